@@ -1,19 +1,22 @@
 import type { Asset } from '~/types/models'
 import { useAsyncState } from '~/utils/async'
 import { generateUUID } from '~/utils/uuid'
+import { useHlc } from '../sync/useHlc'
 import { useTauriSQL } from '../useTauriSQL'
 
 export function useAssetRepository() {
   const { execute, select } = useTauriSQL()
   const { isLoading, error, runAsync } = useAsyncState()
+  const { stampHlc } = useHlc()
 
   const createAsset = (asset: Omit<Asset, 'id' | 'created_at'>) =>
     runAsync(async () => {
       const now = new Date().toISOString()
-      const uuid = generateUUID()
+      // 本机保存的文件以 uuid 命名，记录沿用同一个 uuid
+      const uuid = asset.uuid || generateUUID()
       const result = await execute(
-        'INSERT INTO assets (uuid, url, path, filename, size, mime_type, storage_type, version, updated_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)',
-        [uuid, asset.url, asset.path, asset.filename, asset.size || 0, asset.mime_type || '', asset.storage_type, -Date.now(), now],
+        'INSERT INTO assets (uuid, url, path, filename, size, mime_type, storage_type, version, updated_at, hlc) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)',
+        [uuid, asset.url, asset.path, asset.filename, asset.size || 0, asset.mime_type || '', asset.storage_type, -Date.now(), now, await stampHlc()],
       )
       const assetId = result.lastInsertId as number
 
@@ -61,10 +64,19 @@ export function useAssetRepository() {
       'SELECT * FROM assets WHERE deleted_at IS NULL ORDER BY created_at DESC',
     ), '获取资源列表失败')
 
+  // 保存在本机资源目录的文件（需要上传到同步服务器）
+  const getLocalAssetUuids = () =>
+    runAsync(async () => {
+      const rows = await select<Array<{ uuid: string }>>(
+        `SELECT uuid FROM assets WHERE storage_type = 'local' AND deleted_at IS NULL AND uuid IS NOT NULL`,
+      )
+      return rows.map(row => row.uuid)
+    }, '获取本地资源列表失败')
+
   const deleteAsset = (id: number) =>
-    runAsync(() => execute(
-      'UPDATE assets SET deleted_at = ?, updated_at = ?, version = ? WHERE id = ?',
-      [new Date().toISOString(), new Date().toISOString(), -Date.now(), id],
+    runAsync(async () => execute(
+      'UPDATE assets SET deleted_at = ?, updated_at = ?, version = ?, hlc = ? WHERE id = ?',
+      [new Date().toISOString(), new Date().toISOString(), -Date.now(), await stampHlc(), id],
     ), '删除资源失败')
 
   return {
@@ -72,6 +84,7 @@ export function useAssetRepository() {
     error,
     createAsset,
     getAllAssets,
+    getLocalAssetUuids,
     deleteAsset,
   }
 }
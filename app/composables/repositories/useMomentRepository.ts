@@ -1,19 +1,21 @@
 import type { Moment } from '~/types/models'
 import { useAsyncState } from '~/utils/async'
 import { generateUUID } from '~/utils/uuid'
+import { useHlc } from '../sync/useHlc'
 import { useTauriSQL } from '../useTauriSQL'
 
 export function useMomentRepository() {
   const { execute, select } = useTauriSQL()
   const { isLoading, error, runAsync } = useAsyncState()
+  const { stampHlc } = useHlc()
 
   const createMoment = (content: string, images: string[] = [], tags: string[] = []) =>
     runAsync(async () => {
       const now = new Date().toISOString()
       const uuid = generateUUID()
       const result = await execute(
-        'INSERT INTO moments (uuid, content, images, tags, version, created_at, updated_at) VALUES (?, ?, ?, ?, ?, ?, ?)',
-        [uuid, content, JSON.stringify(images), JSON.stringify(tags), -Date.now(), now, now],
+        'INSERT INTO moments (uuid, content, images, tags, version, created_at, updated_at, hlc) VALUES (?, ?, ?, ?, ?, ?, ?, ?)',
+        [uuid, content, JSON.stringify(images), JSON.stringify(tags), -Date.now(), now, now, await stampHlc()],
       )
       const momentId = result.lastInsertId as number
 
@@ -90,17 +92,17 @@ export function useMomentRepository() {
 
       const now = new Date().toISOString()
       await execute(
-        'UPDATE moments SET content = ?, images = ?, tags = ?, updated_at = ?, version = ? WHERE id = ?',
-        [content, JSON.stringify(images), JSON.stringify(tags), now, -Date.now(), id],
+        'UPDATE moments SET content = ?, images = ?, tags = ?, updated_at = ?, version = ?, hlc = ? WHERE id = ?',
+        [content, JSON.stringify(images), JSON.stringify(tags), now, -Date.now(), await stampHlc(), id],
       )
 
       return { versionChanged: true, newVersion: -Date.now() }
     }, '更新动态失败')
 
   const deleteMoment = (id: number) =>
-    runAsync(() => execute(
-      'UPDATE moments SET deleted_at = ?, updated_at = ?, version = ? WHERE id = ?',
-      [new Date().toISOString(), new Date().toISOString(), -Date.now(), id],
+    runAsync(async () => execute(
+      'UPDATE moments SET deleted_at = ?, updated_at = ?, version = ?, hlc = ? WHERE id = ?',
+      [new Date().toISOString(), new Date().toISOString(), -Date.now(), await stampHlc(), id],
     ), '删除动态失败')
 
   return {
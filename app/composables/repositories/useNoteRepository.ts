@@ -1,19 +1,21 @@
 import type { Note } from '~/types/models'
 import { useAsyncState } from '~/utils/async'
 import { generateUUID } from '~/utils/uuid'
+import { useHlc } from '../sync/useHlc'
 import { useTauriSQL } from '../useTauriSQL'
 
 export function useNoteRepository() {
   const { execute, select } = useTauriSQL()
   const { isLoading, error, runAsync } = useAsyncState()
+  const { stampHlc } = useHlc()
 
   const createNote = (title: string, content: string, tags: string[] = []) =>
     runAsync(async () => {
       const now = new Date().toISOString()
       const uuid = generateUUID()
       const result = await execute(
-        'INSERT INTO notes (uuid, title, content, tags, version, updated_at) VALUES (?, ?, ?, ?, ?, ?)',
-        [uuid, title, content, JSON.stringify(tags), -Date.now(), now],
+        'INSERT INTO notes (uuid, title, content, tags, version, updated_at, hlc) VALUES (?, ?, ?, ?, ?, ?, ?)',
+        [uuid, title, content, JSON.stringify(tags), -Date.now(), now, await stampHlc()],
       )
       const noteId = result.lastInsertId as number
 
@@ -96,13 +98,14 @@ export function useNoteRepository() {
              content = ?,
              tags = ?,
              updated_at = ?,
+             hlc = ?,
          version = CASE
                WHEN title != ? OR content != ? OR tags != ?
                THEN ?
                ELSE version
              END
          WHERE id = ?`,
-        [title, content, JSON.stringify(tags), now, title, content, JSON.stringify(tags), -Date.now(), id],
+        [title, content, JSON.stringify(tags), now, await stampHlc(), title, content, JSON.stringify(tags), -Date.now(), id],
       )
 
       // 查询更新后的版本号
@@ -121,7 +124,7 @@ export function useNoteRepository() {
     }, '更新笔记失败')
 
   const deleteNote = (id: number) =>
-    runAsync(() => execute('UPDATE notes SET deleted_at = ?, updated_at = ?, version = ? WHERE id = ?', [new Date().toISOString(), new Date().toISOString(), -Date.now(), id]), '删除笔记失败')
+    runAsync(async () => execute('UPDATE notes SET deleted_at = ?, updated_at = ?, version = ?, hlc = ? WHERE id = ?', [new Date().toISOString(), new Date().toISOString(), -Date.now(), await stampHlc(), id]), '删除笔记失败')
 
   // 获取最新的一条笔记，用于自动加载
   const getLatestNote = () =>
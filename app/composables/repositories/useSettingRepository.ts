@@ -1,26 +1,32 @@
 import type { AppSetting } from '~/types/models'
 import { useAsyncState } from '~/utils/async'
+import { useHlc } from '../sync/useHlc'
 import { useTauriSQL } from '../useTauriSQL'
 
 export function useSettingRepository() {
   const { execute, select } = useTauriSQL()
   const { isLoading, error, runAsync } = useAsyncState()
+  const { stampHlc } = useHlc()
 
+  // 负数版本号标记本地修改，deleted_at 清空以便覆盖已删除的同名键（同步策略见 config/settings-sync.ts）
   const setSetting = (key: string, value: string, category: string = 'general') =>
-    runAsync(() => execute(
-      'INSERT OR REPLACE INTO settings (key, value, category, updated_at) VALUES (?, ?, ?, CURRENT_TIMESTAMP)',
-      [key, value, category],
+    runAsync(async () => execute(
+      `INSERT INTO settings (key, value, category, updated_at, version, hlc, deleted_at) VALUES (?, ?, ?, ?, ?, ?, NULL)
+       ON CONFLICT(key) DO UPDATE SET
+         value = excluded.value, category = excluded.category, updated_at = excluded.updated_at,
+         version = excluded.version, hlc = excluded.hlc, deleted_at = NULL`,
+      [key, value, category, new Date().toISOString(), -Date.now(), await stampHlc()],
     ), '保存设置失败')
 
   const getSetting = (key: string) =>
     runAsync(async () => {
-      const result = await select<AppSetting[]>('SELECT value FROM settings WHERE key = ?', [key])
+      const result = await select<AppSetting[]>('SELECT value FROM settings WHERE key = ? AND deleted_at IS NULL', [key])
       return result[0]?.value || null
     }, '获取设置失败')
 
   const getAllSettings = () =>
     runAsync(async () => {
-      const result = await select<AppSetting[]>('SELECT key, value FROM settings')
+      const result = await select<AppSetting[]>('SELECT key, value FROM settings WHERE deleted_at IS NULL')
       return result.reduce((acc: Record<string, string>, row) => {
         acc[row.key] = row.value
         return acc
@@ -29,7 +35,7 @@ export function useSettingRepository() {
 
   const getSettingsByCategory = (category: string) =>
     runAsync(async () => {
-      const result = await select<AppSetting[]>('SELECT key, value FROM settings WHERE category = ?', [category])
+      const result = await select<AppSetting[]>('SELECT key, value FROM settings WHERE category = ? AND deleted_at IS NULL', [category])
       return result.reduce((acc: Record<string, string>, row) => {
         acc[row.key] = row.value
         return acc
@@ -37,7 +43,14 @@ export function useSettingRepository() {
     }, '获取分类设置失败')
 
   const deleteSetting = (key: string) =>
-    runAsync(() => execute('DELETE FROM settings WHERE key = ?', [key]), '删除设置失败')
+    runAsync(async () => {
+      // 软删除，删除才能同步到其他设备
+      const now = new Date().toISOString()
+      return execute(
+        'UPDATE settings SET deleted_at = ?, updated_at = ?, version = ?, hlc = ? WHERE key = ?',
+        [now, now, -Date.now(), await stampHlc(), key],
+      )
+    }, '删除设置失败')
 
   return {
     isLoading,
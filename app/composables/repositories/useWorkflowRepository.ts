@@ -1,19 +1,21 @@
 import type { Workflow, WorkflowSchema, WorkflowType } from '~/types/workflow'
 import { useAsyncState } from '~/utils/async'
+import { useHlc } from '../sync/useHlc'
 import { useTauriSQL } from '../useTauriSQL'
 import { generateUUID } from '~/utils/uuid'
 
 export function useWorkflowRepository() {
   const { execute, select } = useTauriSQL()
   const { isLoading, error, runAsync } = useAsyncState()
+  const { stampHlc } = useHlc()
 
   const createWorkflow = (name: string, description: string, steps: any[], schemaId?: number, type: WorkflowType = 'user') =>
     runAsync(async () => {
       const now = new Date().toISOString()
       const uuid = generateUUID()
       const result = await execute(
-        'INSERT INTO workflows (uuid, name, description, steps, schema_id, type, version, updated_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?)',
-        [uuid, name, description, JSON.stringify(steps), schemaId || null, type, -Date.now(), now],
+        'INSERT INTO workflows (uuid, name, description, steps, schema_id, type, version, updated_at, hlc) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)',
+        [uuid, name, description, JSON.stringify(steps), schemaId || null, type, -Date.now(), now, await stampHlc()],
       )
       return result.lastInsertId as number
     }, 'Failed to create workflow')
@@ -142,8 +144,8 @@ export function useWorkflowRepository() {
         // 更新第一个(如果是已删除的则恢复)
         // 确保 steps 被正确更新
         const updateResult = await execute(
-          'UPDATE workflows SET name = ?, description = ?, steps = ?, schema_id = ?, updated_at = ?, version = ?, deleted_at = NULL WHERE id = ?',
-          [name, description, JSON.stringify(steps), schemaId || null, now, -Date.now(), existing[0].id],
+          'UPDATE workflows SET name = ?, description = ?, steps = ?, schema_id = ?, updated_at = ?, version = ?, hlc = ?, deleted_at = NULL WHERE id = ?',
+          [name, description, JSON.stringify(steps), schemaId || null, now, -Date.now(), await stampHlc(), existing[0].id],
         )
         console.log(`[Workflow] Upsert updated workflow ${existing[0].id}, rows affected: ${updateResult.rowsAffected}`)
         return existing[0].id
@@ -152,8 +154,8 @@ export function useWorkflowRepository() {
       // 创建新的
       const uuid = generateUUID()
       const result = await execute(
-        'INSERT INTO workflows (uuid, name, description, steps, schema_id, type, version, updated_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?)',
-        [uuid, name, description, JSON.stringify(steps), schemaId || null, type, -Date.now(), now],
+        'INSERT INTO workflows (uuid, name, description, steps, schema_id, type, version, updated_at, hlc) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)',
+        [uuid, name, description, JSON.stringify(steps), schemaId || null, type, -Date.now(), now, await stampHlc()],
       )
       return result.lastInsertId as number
     }, 'Failed to upsert system workflow')
@@ -162,16 +164,16 @@ export function useWorkflowRepository() {
     runAsync(async () => {
       const now = new Date().toISOString()
       await execute(
-        'UPDATE workflows SET name = ?, description = ?, steps = ?, schema_id = ?, updated_at = ?, version = ? WHERE id = ?',
-        [name, description, JSON.stringify(steps), schemaId || null, now, -Date.now(), id],
+        'UPDATE workflows SET name = ?, description = ?, steps = ?, schema_id = ?, updated_at = ?, version = ?, hlc = ? WHERE id = ?',
+        [name, description, JSON.stringify(steps), schemaId || null, now, -Date.now(), await stampHlc(), id],
       )
       return { versionChanged: true, newVersion: -Date.now() }
     }, 'Failed to update workflow')
 
   const deleteWorkflow = (id: number) =>
-    runAsync(() => execute(
-      'UPDATE workflows SET deleted_at = ?, updated_at = ?, version = ? WHERE id = ?',
-      [new Date().toISOString(), new Date().toISOString(), -Date.now(), id],
+    runAsync(async () => execute(
+      'UPDATE workflows SET deleted_at = ?, updated_at = ?, version = ?, hlc = ? WHERE id = ?',
+      [new Date().toISOString(), new Date().toISOString(), -Date.now(), await stampHlc(), id],
     ), 'Failed to delete workflow')
 
   // 删除所有相同 type 的工作流（用于清理多端同步导致的重复系统流）
@@ -179,8 +181,8 @@ export function useWorkflowRepository() {
     runAsync(async () => {
       const now = new Date().toISOString()
       const result = await execute(
-        'UPDATE workflows SET deleted_at = ?, updated_at = ?, version = ? WHERE type = ? AND deleted_at IS NULL',
-        [now, now, -Date.now(), type],
+        'UPDATE workflows SET deleted_at = ?, updated_at = ?, version = ?, hlc = ? WHERE type = ? AND deleted_at IS NULL',
+        [now, now, -Date.now(), await stampHlc(), type],
       )
       return result.rowsAffected || 0
     }, 'Failed to delete workflows by type')
@@ -1,19 +1,21 @@
 import type { WorkflowSchema } from '~/types/workflow'
 import { useAsyncState } from '~/utils/async'
+import { useHlc } from '../sync/useHlc'
 import { useTauriSQL } from '../useTauriSQL'
 import { generateUUID } from '~/utils/uuid'
 
 export function useWorkflowSchemaRepository() {
   const { execute, select } = useTauriSQL()
   const { isLoading, error, runAsync } = useAsyncState()
+  const { stampHlc } = useHlc()
 
   const createSchema = (name: string, description: string, fields: any[] = []) =>
     runAsync(async () => {
       const now = new Date().toISOString()
       const uuid = generateUUID()
       const result = await execute(
-        'INSERT INTO workflow_schemas (uuid, name, description, fields, version, updated_at) VALUES (?, ?, ?, ?, ?, ?)',
-        [uuid, name, description, JSON.stringify(fields), -Date.now(), now],
+        'INSERT INTO workflow_schemas (uuid, name, description, fields, version, updated_at, hlc) VALUES (?, ?, ?, ?, ?, ?, ?)',
+        [uuid, name, description, JSON.stringify(fields), -Date.now(), now, await stampHlc()],
       )
       return result.lastInsertId as number
     }, 'Failed to create workflow schema')
@@ -28,9 +30,9 @@ export function useWorkflowSchemaRepository() {
     runAsync(() => select<WorkflowSchema[]>('SELECT * FROM workflow_schemas WHERE deleted_at IS NULL ORDER BY updated_at DESC'), 'Failed to list workflow schemas')
 
   const updateSchema = (id: number, name: string, description: string, fields: any[]) =>
-    runAsync(() => execute(
-      'UPDATE workflow_schemas SET name = ?, description = ?, fields = ?, version = ?, updated_at = ? WHERE id = ?',
-      [name, description, JSON.stringify(fields), -Date.now(), new Date().toISOString(), id],
+    runAsync(async () => execute(
+      'UPDATE workflow_schemas SET name = ?, description = ?, fields = ?, version = ?, updated_at = ?, hlc = ? WHERE id = ?',
+      [name, description, JSON.stringify(fields), -Date.now(), new Date().toISOString(), await stampHlc(), id],
     ), 'Failed to update workflow schema')
 
   const deleteSchema = (id: number) =>
@@ -41,8 +43,8 @@ export function useWorkflowSchemaRepository() {
         throw new Error('Cannot delete schema because it is used by one or more workflows')
       }
       return execute(
-        'UPDATE workflow_schemas SET deleted_at = ?, updated_at = ?, version = ? WHERE id = ?',
-        [new Date().toISOString(), new Date().toISOString(), -Date.now(), id],
+        'UPDATE workflow_schemas SET deleted_at = ?, updated_at = ?, version = ?, hlc = ? WHERE id = ?',
+        [new Date().toISOString(), new Date().toISOString(), -Date.now(), await stampHlc(), id],
       )
     }, 'Failed to delete workflow schema')
 
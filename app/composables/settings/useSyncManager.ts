@@ -1,12 +1,20 @@
 import type { ConflictDecision, SyncMode } from '../sync/useSyncConflict'
 import type { RecordMetadata } from '../sync/useSyncMetadata'
+import type { HandshakeResponse } from '~/config/sync-protocol'
+import type { ServerTableSchema } from '~/config/sync-tables'
+import { getVersion } from '@tauri-apps/api/app'
 import { toast } from 'vue-sonner'
+import { useAssetRepository } from '~/composables/repositories/useAssetRepository'
 import { useSettingRepository } from '~/composables/repositories/useSettingRepository'
 import { useWorkflowRepository } from '~/composables/repositories/useWorkflowRepository'
+import { parseAssetRef, useAssetTransfer } from '~/composables/sync/useAssetTransfer'
+import { useCrdtSync } from '~/composables/sync/useCrdtSync'
+import { useDeviceId } from '~/composables/sync/useDeviceId'
 import { useSyncEngine } from '~/composables/sync/useSyncEngine'
 import { useEnvironment } from '~/composables/useEnvironment'
 import { useTauriSQL } from '~/composables/useTauriSQL'
-import { getSyncTableNames, SYNC_TABLES } from '~/config/sync-tables'
+import { buildHandshakeRequest, SYNC_PROTOCOL_HEADER, SYNC_PROTOCOL_VERSION, SyncRefusedError } from '~/config/sync-protocol'
+import { diffServerSchema, getSyncTableNames, SYNC_TABLES } from '~/config/sync-tables'
 
 interface SyncInfoState {
   status: 'idle' | 'ok' | 'error'
@@ -40,6 +48,8 @@ const globalSyncInfo = () => useState<SyncInfoState>('sync_info', () => ({ statu
 const globalSyncWorkflowId = () => useState<number | null>('sync_workflow_id', () => null)
 const globalLastFailedAt = () => useState<number | null>('sync_last_failed_at', () => null) // 上次失败时间戳
 const globalSyncMode = () => useState<SyncMode>('sync_mode', () => 'manual') // 同步模式，默认手动
+const globalDeviceHeaders = () => useState<Record<string, string>>('sync_device_headers', () => ({})) // 本机设备标识请求头
+const globalApiPrefix = () => useState<string | null>('sync_api_prefix', () => null) // 同步接口路由前缀，握手前为 null
 
 // 常量配置
 const FETCH_TIMEOUT_MS = 3000 // fetchSyncState 超时时间 3秒
@@ -49,6 +59,12 @@ export function useSyncManager() {
   const { setSetting, getSetting } = useSettingRepository()
   const { createWorkflow, getAllWorkflows, deleteWorkflow } = useWorkflowRepository()
   const syncEngine = useSyncEngine()
+  const crdtSync = useCrdtSync()
+  const assetTransfer = useAssetTransfer()
+  const { getLocalAssetUuids } = useAssetRepository()
+  const { getDeviceId } = useDeviceId()
+  const deviceHeaders = globalDeviceHeaders()
+  const apiPrefix = globalApiPrefix()
   const { isDesktop } = useEnvironment()
   const router = useRouter()
   const syncMode = globalSyncMode()
@@ -69,13 +85,41 @@ export function useSyncManager() {
 
   const isSavingSyncConfig = ref(false)
 
+  /** 服务器根地址（不含路由前缀） */
+  function getSyncRootUrl() {
+    return (syncServerAddress.value.trim() || serverUrl.value.trim()).replace(/\/+$/, '')
+  }
+
+  /** 同步接口地址：握手成功后为 {根地址}/v1，旧服务端为根地址 */
   function getSyncBaseUrl() {
-    return syncServerAddress.value.trim() || serverUrl.value.trim()
+    const root = getSyncRootUrl()
+    return root ? `${root}${apiPrefix.value ?? ''}` : ''
   }
 
   function buildSyncHeaders() {
     // 局域网环境使用固定 token,安全性由网络隔离保证
-    return { Authorization: 'Bearer zotepad-dev-token' }
+    return { 'Authorization': 'Bearer zotepad-dev-token', [SYNC_PROTOCOL_HEADER]: String(SYNC_PROTOCOL_VERSION), ...deviceHeaders.value }
+  }
+
+  /**
+   * 准备设备标识请求头（服务端据此登记设备和同步进度）
+   * 设备名可在 sync_device_name 设置中修改，默认按平台生成；请求头只能是 ASCII，设备名需编码
+   */
+  async function ensureDeviceHeaders() {
+    if (deviceHeaders.value['X-Device-Id'])
+      return
+
+    const deviceId = await getDeviceId()
+    const ua = navigator.userAgent
+    const defaultName = /android/i.test(ua) ? 'Android' : /iphone|ipad|ipod/i.test(ua) ? 'iOS' : /mac/i.test(ua) ? 'macOS' : /windows/i.test(ua) ? 'Windows' : 'Desktop'
+    const name = (await getSetting('sync_device_name')) || `ZotePad ${defaultName}`
+    const appVersion = await getVersion().catch(() => '')
+
+    deviceHeaders.value = {
+      'X-Device-Id': deviceId,
+      'X-Device-Name': encodeURIComponent(name),
+      ...(appVersion ? { 'X-App-Version': appVersion } : {}),
+    }
   }
 
   function bumpTotalSyncCounts(deltaPulled: number, deltaPushed: number) {
@@ -88,20 +132,58 @@ export function useSyncManager() {
     })
   }
 
+  /**
+   * 与服务端协商协议版本：成功后改用 /v1 路由；旧服务端（404）使用不带前缀的旧路由
+   * 服务端拒绝（版本不兼容 426、表结构冲突 409）时抛出 SyncRefusedError
+   */
+  async function negotiateProtocol(root: string): Promise<HandshakeResponse | null> {
+    const appVersion = await getVersion().catch(() => '')
+    const res = await fetch(`${root}/v1/handshake`, {
+      method: 'POST',
+      headers: { ...buildSyncHeaders(), 'Content-Type': 'application/json' },
+      body: JSON.stringify(buildHandshakeRequest(appVersion)),
+      mode: 'cors',
+      cache: 'no-cache',
+    })
+
+    if (res.status === 404) {
+      apiPrefix.value = ''
+      logger.info('[Sync] 服务端不支持协议握手，使用旧版接口')
+      return null
+    }
+    if (res.status === 409 || res.status === 426) {
+      const data = await res.json().catch(() => null)
+      if (data?.data?.code)
+        throw new SyncRefusedError(data.data)
+      throw new Error(`handshake 请求失败: ${res.status}`)
+    }
+    if (!res.ok)
+      throw new Error(`handshake 请求失败: ${res.status}`)
+
+    const response = (await res.json()).data as HandshakeResponse
+    apiPrefix.value = '/v1'
+    for (const warning of response.warnings) {
+      logger.warn(`[Sync] 表 ${warning.table} 字段与服务端不一致: 服务端会忽略 ${warning.unknown_fields.join(', ') || '无'}，本地缺少 ${warning.missing_fields.join(', ') || '无'}`)
+    }
+    return response
+  }
+
   async function fetchSyncState() {
-    const base = getSyncBaseUrl()
-    logger.info(`[Sync] fetchSyncState 开始, base=${base}`)
-    if (!base)
+    const root = getSyncRootUrl()
+    logger.info(`[Sync] fetchSyncState 开始, root=${root}`)
+    if (!root)
       throw new Error('请先配置服务器地址')
 
-    logger.info(`[Sync] 发送 state 请求到: ${base}/state`)
-
+    await ensureDeviceHeaders()
     // 创建超时 Promise
     const timeoutPromise = new Promise((_, reject) => {
       setTimeout(() => reject(new Error('连接超时，无法连接到服务器')), FETCH_TIMEOUT_MS)
     })
 
     try {
+      // 每次同步前重新握手，服务端升级或降级后能及时切换路由
+      await Promise.race([negotiateProtocol(root), timeoutPromise])
+      const base = getSyncBaseUrl()
       const fetchPromise = fetch(`${base}/state`, {
         headers: buildSyncHeaders(),
         mode: 'cors',
@@ -121,9 +203,23 @@ export function useSyncManager() {
       // 连接成功，清除失败状态
       lastFailedAt.value = null
 
-      return data.data as { version: number, paired?: boolean, server_version?: string }
+      const state = data.data as { version: number, paired?: boolean, server_version?: string, schema?: ServerTableSchema[] }
+
+      // 核对服务端表结构（旧服务端不返回 schema）
+      if (state.schema) {
+        const problems = diffServerSchema(state.schema)
+        if (problems.length)
+          logger.warn(`[Sync] 服务端表结构与本地配置不一致: ${problems.join('; ')}`)
+      }
+
+      return state
     }
     catch (fetchError: any) {
+      // 协议不兼容不是网络问题，不进入重试冷却
+      if (fetchError instanceof SyncRefusedError) {
+        logger.warn(`[Sync] 服务端拒绝同步: ${fetchError.refusal.code} ${fetchError.message}`)
+        throw fetchError
+      }
       console.error('[Sync] fetch 请求失败:', fetchError)
       console.error('[Sync] fetch 错误类型:', fetchError.constructor.name)
       console.error('[Sync] fetch 错误消息:', fetchError.message)
@@ -135,7 +231,7 @@ export function useSyncManager() {
       if (fetchError.message?.includes('Failed to fetch') || fetchError.message?.includes('连接超时')) {
         console.error('[Sync] 这是网络连接失败。可能原因:')
         console.error('[Sync] 1. 设备不在同一 WiFi 网络')
-        console.error('[Sync] 2. 服务器地址错误:', base)
+        console.error('[Sync] 2. 服务器地址错误:', root)
         console.error('[Sync] 3. 服务器未启动')
         console.error('[Sync] 4. 防火墙阻止了连接')
       }
@@ -207,6 +303,7 @@ export function useSyncManager() {
     activity.setSyncState(true)
 
     try {
+      const metadataKey = `sync_metadata_version_${tableName}`
       const result = await syncEngine.syncTableSmart(
         table,
         base,
@@ -216,8 +313,13 @@ export function useSyncManager() {
           // 跳转到合并页面，等待用户决策
           return await navigateToMerge(tableName, conflicts)
         },
+        Number(await getSetting(metadataKey)) || 0,
       )
 
+      // 记录远程元数据版本号，下次只获取此后的墓碑
+      if (result.metadataVersion)
+        await setSetting(metadataKey, String(result.metadataVersion), 'sync')
+
       // 更新统计
       if (result.pulled > 0 || result.pushed > 0) {
         lastSyncSummary.value = { pulled: result.pulled, pushed: result.pushed, at: Date.now() }
@@ -353,11 +455,14 @@ export function useSyncManager() {
     activity.setSyncState(true)
 
     try {
-      // 移动端和桌面端都需要：先升级本地负数版本号（避免版本冲突）
-      const { upgraded, finalVersion } = await syncEngine.upgradeLocalVersions(table, maxVersion)
-      if (upgraded > 0) {
-        logger.info(`[Sync] ${tableName} 升级 ${upgraded} 条本地记录版本号 -> ${finalVersion}`)
-        maxVersion = finalVersion
+      // 移动端：先升级本地负数版本号（避免版本冲突）
+      // 桌面端的版本号由数据库触发器在写入时分配，无需升级
+      if (!isDesktop.value) {
+        const { upgraded, finalVersion } = await syncEngine.upgradeLocalVersions(table, maxVersion)
+        if (upgraded > 0) {
+          logger.info(`[Sync] ${tableName} 升级 ${upgraded} 条本地记录版本号 -> ${finalVersion}`)
+          maxVersion = finalVersion
+        }
       }
 
       // 移动端：推送本地变更到服务器
@@ -403,6 +508,37 @@ export function useSyncManager() {
     }
   }
 
+  /**
+   * 图片地址的可访问形式：zotepad-asset:// 引用换成本地文件地址（本机缺失时从同步服务器下载），
+   * 其他地址原样返回；下载失败时也原样返回
+   */
+  async function resolveAssetUrl(url: string): Promise<string> {
+    const uuid = parseAssetRef(url)
+    if (!uuid)
+      return url
+    await ensureDeviceHeaders()
+    return (await assetTransfer.getAssetUrl(getSyncBaseUrl(), buildSyncHeaders(), uuid)) ?? url
+  }
+
+  /**
+   * 移动端：把保存在本机的资源文件上传到桌面端（分块续传，服务端已完整保存的跳过）
+   * @returns 上传失败的数量
+   */
+  async function uploadLocalAssets(base: string, headers: Record<string, string>): Promise<number> {
+    let failed = 0
+    for (const uuid of await getLocalAssetUuids()) {
+      try {
+        if (await assetTransfer.hasLocalAsset(uuid))
+          await assetTransfer.uploadAsset(base, headers, uuid)
+      }
+      catch (e) {
+        failed++
+        console.warn(`[Sync] 资源 ${uuid} 上传失败:`, e)
+      }
+    }
+    return failed
+  }
+
   /**
    * 同步所有表的本地变更和远程变更
    */
@@ -438,11 +574,14 @@ export function useSyncManager() {
       try {
         logger.info(`[Sync] 同步表: ${tableName}`)
 
-        // 移动端和桌面端都需要：先升级本地负数版本号（避免版本冲突）
-        const { upgraded, finalVersion } = await syncEngine.upgradeLocalVersions(table, maxVersion)
-        if (upgraded > 0) {
-          logger.info(`[Sync] ${tableName} 升级 ${upgraded} 条本地记录版本号 -> ${finalVersion}`)
-          maxVersion = finalVersion
+        // 移动端：先升级本地负数版本号（避免版本冲突）
+        // 桌面端的版本号由数据库触发器在写入时分配，无需升级
+        if (!isDesktop.value) {
+          const { upgraded, finalVersion } = await syncEngine.upgradeLocalVersions(table, maxVersion)
+          if (upgraded > 0) {
+            logger.info(`[Sync] ${tableName} 升级 ${upgraded} 条本地记录版本号 -> ${finalVersion}`)
+            maxVersion = finalVersion
+          }
         }
 
         // 移动端：推送本地变更到服务器
@@ -468,6 +607,31 @@ export function useSyncManager() {
       }
     }
 
+    // 移动端：assets 记录推送后上传对应的文件，其他设备按需下载
+    if (!isDesktop.value) {
+      try {
+        const failed = await uploadLocalAssets(base, headers)
+        if (failed > 0)
+          logger.warn(`[Sync] ${failed} 个资源文件上传失败，下次同步时继续`)
+      }
+      catch (e: any) {
+        console.error('[Sync] 资源文件上传失败:', e)
+      }
+    }
+
+    // 成就统计、积分流水和成就状态按 CRDT 规则合并，与表同步共用版本号游标
+    try {
+      const crdtResult = await crdtSync.syncCrdt(base, headers, currentVersion, !isDesktop.value)
+      totalPushed += crdtResult.pushed
+      totalPulled += crdtResult.pulled
+      maxVersion = Math.max(maxVersion, crdtResult.lastServerVersion)
+      logger.info(`[Sync] 成就数据同步完成: ${JSON.stringify(crdtResult)}`)
+      activity.setSyncCounts(totalPushed, totalPulled)
+    }
+    catch (e: any) {
+      console.error('[Sync] 成就数据同步失败:', e)
+    }
+
     // 结束同步状态指示器
     activity.setSyncState(false)
 
@@ -511,6 +675,19 @@ export function useSyncManager() {
       logger.info(`[Sync] fetchSyncState 成功,服务器状态: ${JSON.stringify(state, null, 2)}`)
       syncInfo.value = { status: 'ok', message: '服务器可用', version: state.version ?? null, paired: state.paired }
 
+      // 本机已在桌面端的设备列表中被移除，服务端会拒绝同步请求
+      if (state.paired === false) {
+        const errorMsg = '此设备已在服务端被移除，无法同步'
+        syncStatus.value = errorMsg
+        syncInfo.value = { status: 'error', message: errorMsg, version: state.version ?? null, paired: false }
+        if (!silent) {
+          toast.error(errorMsg, { id: toastId, duration: 6000 })
+        }
+        isSyncing.value = false
+        setWorking(false)
+        return
+      }
+
       // 检测服务器版本号异常(时间戳污染)
       // 使用 2100000000 作为上限，可以兼容时间戳版本号（当前约1733900000），同时防止真正的异常值
       const MAX_REASONABLE_VERSION = 2100000000
@@ -597,7 +774,10 @@ export function useSyncManager() {
 
       // 用户友好的错误提示
       let userMessage = '同步失败'
-      if (e.message?.includes('Failed to fetch') || e.message?.includes('NetworkError')) {
+      if (e instanceof SyncRefusedError) {
+        userMessage = e.message
+      }
+      else if (e.message?.includes('Failed to fetch') || e.message?.includes('NetworkError')) {
         userMessage = '无法连接到服务器，请检查网络和服务器地址'
       }
       else if (e.message?.includes('401') || e.message?.includes('403')) {
@@ -952,6 +1132,7 @@ export function useSyncManager() {
     syncTable,
     syncTableSmart, // 新增：智能同步
     forcePushRecord, // 新增：强制推送单条记录
+    resolveAssetUrl,
     syncOnce,
     refreshSyncStateCard,
   }
@@ -0,0 +1,196 @@
+/**
+ * 资源文件传输
+ * assets 表只同步记录，文件保存在应用数据目录的 assets/{uuid}。
+ * 笔记引用的资源在本地缺失时从同步服务器按需下载；本地资源分块上传，中断后从已接收的位置继续。
+ * 服务端接口与校验规则见 src-tauri/src/sync_assets.rs
+ *
+ * 笔记中以 `zotepad-asset://{uuid}` 引用本地资源，显示时由 useSyncManager().resolveAssetUrl 替换为可访问的地址
+ */
+
+import { BaseDirectory, exists, mkdir, readFile, writeFile } from '@tauri-apps/plugin-fs'
+
+const ASSET_DIR = 'assets'
+
+/** 上传时每块的大小（低于服务端 axum 默认的 2 MB 请求体上限） */
+const CHUNK_SIZE = 1024 * 1024
+
+/** 本地资源的引用地址前缀 */
+export const ASSET_URL_PREFIX = 'zotepad-asset://'
+
+/**
+ * 从 zotepad-asset:// 引用中取出资源 uuid，其他地址返回 null
+ */
+export function parseAssetRef(url: string): string | null {
+  const match = /^zotepad-asset:\/\/([\w-]+)$/.exec(url.trim())
+  return match ? match[1]! : null
+}
+
+interface UploadStatus {
+  uuid: string
+  received: number
+  complete: boolean
+}
+
+/** 同一资源的并发下载合并为一次 */
+const inflight = new Map<string, Promise<boolean>>()
+
+/** 已解析的资源地址 */
+const objectUrls = new Map<string, string>()
+
+/** 本次运行中已确认服务端完整保存的资源，避免每次同步都查询上传进度 */
+const uploaded = new Set<string>()
+
+async function sha256Hex(bytes: Uint8Array): Promise<string> {
+  const digest = await crypto.subtle.digest('SHA-256', bytes)
+  return Array.from(new Uint8Array(digest), b => b.toString(16).padStart(2, '0')).join('')
+}
+
+function assetPath(uuid: string) {
+  return `${ASSET_DIR}/${uuid}`
+}
+
+export function useAssetTransfer() {
+  /**
+   * 本地是否已有资源文件
+   */
+  async function hasLocalAsset(uuid: string): Promise<boolean> {
+    return exists(assetPath(uuid), { baseDir: BaseDirectory.AppData })
+  }
+
+  /**
+   * 保存资源文件到本地资源目录
+   */
+  async function saveLocalAsset(uuid: string, bytes: Uint8Array): Promise<void> {
+    await mkdir(ASSET_DIR, { baseDir: BaseDirectory.AppData, recursive: true })
+    await writeFile(assetPath(uuid), bytes, { baseDir: BaseDirectory.AppData })
+  }
+
+  /**
+   * 从服务器下载资源文件，校验大小和 sha256 后写入本地
+   */
+  async function downloadAsset(baseUrl: string, headers: Record<string, string>, uuid: string): Promise<void> {
+    const res = await fetch(`${baseUrl}/assets/${uuid}`, { headers })
+    if (!res.ok)
+      throw new Error(`下载资源失败: ${res.status}`)
+
+    const bytes = new Uint8Array(await res.arrayBuffer())
+    const size = Number(res.headers.get('X-Asset-Size'))
+    const sha256 = res.headers.get('X-Asset-Sha256')
+    if (bytes.length !== size || !sha256 || (await sha256Hex(bytes)) !== sha256.toLowerCase())
+      throw new Error(`资源 ${uuid} 校验失败`)
+
+    await saveLocalAsset(uuid, bytes)
+  }
+
+  /**
+   * 确保资源文件在本地存在，缺失时从服务器下载
+   * @returns 是否可用
+   */
+  async function ensureLocalAsset(baseUrl: string, headers: Record<string, string>, uuid: string): Promise<boolean> {
+    if (await hasLocalAsset(uuid))
+      return true
+
+    let task = inflight.get(uuid)
+    if (!task) {
+      task = downloadAsset(baseUrl, headers, uuid)
+        .then(() => true)
+        .catch((e) => {
+          console.warn(`[AssetTransfer] 资源 ${uuid} 下载失败:`, e)
+          return false
+        })
+        .finally(() => inflight.delete(uuid))
+      inflight.set(uuid, task)
+    }
+    return task
+  }
+
+  /**
+   * 获取资源的可访问地址（按需下载），失败时返回 null
+   */
+  async function getAssetUrl(
+    baseUrl: string,
+    headers: Record<string, string>,
+    uuid: string,
+    mimeType?: string,
+  ): Promise<string | null> {
+    const cached = objectUrls.get(uuid)
+    if (cached)
+      return cached
+
+    if (!(await ensureLocalAsset(baseUrl, headers, uuid)))
+      return null
+
+    const bytes = await readFile(assetPath(uuid), { baseDir: BaseDirectory.AppData })
+    const url = URL.createObjectURL(new Blob([bytes], mimeType ? { type: mimeType } : undefined))
+    objectUrls.set(uuid, url)
+    return url
+  }
+
+  /**
+   * 分块上传本地资源文件；服务端已有部分数据时从已接收的位置继续
+   */
+  async function uploadAsset(baseUrl: string, headers: Record<string, string>, uuid: string): Promise<void> {
+    if (uploaded.has(uuid))
+      return
+
+    const bytes = await readFile(assetPath(uuid), { baseDir: BaseDirectory.AppData })
+    const sha256 = await sha256Hex(bytes)
+    const base = `${baseUrl}/assets/${uuid}/upload`
+
+    const getStatus = async (): Promise<UploadStatus> => {
+      const res = await fetch(base, { headers })
+      if (!res.ok)
+        throw new Error(`查询上传进度失败: ${res.status}`)
+      return (await res.json()).data as UploadStatus
+    }
+
+    // 校验失败时服务端会丢弃已接收的数据，从头重试一次
+    for (let attempt = 0; attempt < 2; attempt++) {
+      const status = await getStatus()
+      if (status.complete) {
+        uploaded.add(uuid)
+        return
+      }
+
+      let offset = status.received
+      while (offset < bytes.length) {
+        const chunk = bytes.slice(offset, offset + CHUNK_SIZE)
+        const res = await fetch(`${base}?offset=${offset}`, {
+          method: 'PUT',
+          headers: { ...headers, 'Content-Type': 'application/octet-stream' },
+          body: chunk,
+        })
+        if (res.status === 409) {
+          // 位置不一致（例如上一块的响应丢失），按服务端记录的位置继续
+          offset = (await getStatus()).received
+          continue
+        }
+        if (!res.ok)
+          throw new Error(`上传资源失败: ${res.status}`)
+        offset = ((await res.json()).data as UploadStatus).received
+      }
+
+      const res = await fetch(`${base}/complete`, {
+        method: 'POST',
+        headers: { ...headers, 'Content-Type': 'application/json' },
+        body: JSON.stringify({ size: bytes.length, sha256 }),
+      })
+      if (res.ok) {
+        uploaded.add(uuid)
+        return
+      }
+      if (res.status !== 422)
+        throw new Error(`提交资源失败: ${res.status}`)
+      console.warn(`[AssetTransfer] 资源 ${uuid} 校验失败，重新上传`)
+    }
+    throw new Error(`资源 ${uuid} 上传校验失败`)
+  }
+
+  return {
+    hasLocalAsset,
+    saveLocalAsset,
+    ensureLocalAsset,
+    getAssetUrl,
+    uploadAsset,
+  }
+}
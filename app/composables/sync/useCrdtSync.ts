@@ -0,0 +1,272 @@
+/**
+ * 成就数据的无冲突同步（CRDT）
+ * - user_stat_shards：每台设备一份统计分片，按设备合并后汇总（计数器求和）
+ * - user_points_log：只追加的积分流水，按 operation_id 去重
+ * - user_achievements：按成就取最高等级与最大进度
+ *
+ * 合并规则与服务端 sync_crdt.rs 一致，合并后重新计算 user_stats 和用户档案；
+ * 本地写入时 synced_at 置空，推送成功后记录 synced_at
+ */
+
+import type { StatShard } from '~/composables/useStatsCollector'
+import { usePointsSystem } from '~/composables/usePointsSystem'
+import { useStatsCollector } from '~/composables/useStatsCollector'
+import { useTauriSQL } from '~/composables/useTauriSQL'
+import { encodeJsonBody } from '~/utils/compression'
+
+interface PointsEntry {
+  user_id: number
+  operation_id: string
+  source_type: string
+  source_id: string
+  achievement_key: string | null
+  points: number
+  exp: number
+  reason: string | null
+  created_at: number
+  device_id: string | null
+}
+
+interface AchievementState {
+  user_id: number
+  achievement_key: string
+  level: number
+  progress: number
+  total_points: number
+  total_exp: number
+  unlocked_at: number
+  updated_at: number
+}
+
+export interface CrdtChanges {
+  stats: StatShard[]
+  points: PointsEntry[]
+  achievements: AchievementState[]
+}
+
+export interface CrdtSyncResult {
+  pushed: number
+  pulled: number
+  lastServerVersion: number
+}
+
+/** 统计分片合并：last 按 (updated_at, 值) 取较新者，其余类型取数值较大者；不改动本地 synced_at */
+const MERGE_SHARD_SQL = `
+  INSERT INTO user_stat_shards (user_id, stat_key, device_id, stat_type, stat_value, updated_at, synced_at)
+  VALUES (?, ?, ?, ?, ?, ?, ?)
+  ON CONFLICT(user_id, stat_key, device_id) DO UPDATE SET
+    stat_type = excluded.stat_type,
+    stat_value = excluded.stat_value,
+    updated_at = MAX(updated_at, excluded.updated_at)
+  WHERE (excluded.stat_type = 'last' AND (excluded.updated_at > updated_at
+          OR (excluded.updated_at = updated_at AND excluded.stat_value > stat_value)))
+     OR (excluded.stat_type != 'last' AND CAST(excluded.stat_value AS REAL) > CAST(stat_value AS REAL))`
+
+/** 成就合并：等级、进度、累计积分/经验取最大值，解锁时间取最早 */
+const MERGE_ACHIEVEMENT_SQL = `
+  INSERT INTO user_achievements
+    (user_id, achievement_key, level, progress, total_points, total_exp, unlocked_at, updated_at, synced_at)
+  VALUES (?, ?, ?, ?, ?, ?, NULLIF(?, 0), ?, ?)
+  ON CONFLICT(user_id, achievement_key) DO UPDATE SET
+    level = MAX(level, excluded.level),
+    progress = MAX(progress, excluded.progress),
+    total_points = MAX(total_points, excluded.total_points),
+    total_exp = MAX(total_exp, excluded.total_exp),
+    unlocked_at = CASE WHEN unlocked_at IS NULL THEN excluded.unlocked_at
+                       WHEN excluded.unlocked_at IS NULL THEN unlocked_at
+                       ELSE MIN(unlocked_at, excluded.unlocked_at) END,
+    updated_at = MAX(updated_at, excluded.updated_at)
+  WHERE excluded.level > level OR excluded.progress > progress
+     OR excluded.total_points > total_points OR excluded.total_exp > total_exp
+     OR excluded.unlocked_at < unlocked_at OR (unlocked_at IS NULL AND excluded.unlocked_at IS NOT NULL)`
+
+/** 积分流水按 operation_id 去重 */
+const MERGE_POINTS_SQL = `
+  INSERT OR IGNORE INTO user_points_log
+    (user_id, operation_id, source_type, source_id, achievement_key, points, exp, reason, created_at, device_id, synced_at)
+  VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)`
+
+export function useCrdtSync() {
+  const { select, execute } = useTauriSQL()
+  const { rebuildStat } = useStatsCollector()
+  const { updateProfile } = usePointsSystem()
+
+  /**
+   * 收集尚未推送的本地变更
+   */
+  async function collectUnsynced(): Promise<CrdtChanges> {
+    const [stats, points, achievements] = await Promise.all([
+      select<StatShard[]>(
+        `SELECT user_id, stat_key, device_id, stat_type, stat_value, updated_at
+         FROM user_stat_shards WHERE synced_at IS NULL`,
+        [],
+      ),
+      select<PointsEntry[]>(
+        `SELECT user_id, operation_id, source_type, source_id, achievement_key, points, exp, reason, created_at, device_id
+         FROM user_points_log WHERE synced_at IS NULL AND operation_id IS NOT NULL`,
+        [],
+      ),
+      select<AchievementState[]>(
+        `SELECT user_id, achievement_key, level, progress, total_points, total_exp, unlocked_at, updated_at
+         FROM user_achievements WHERE synced_at IS NULL`,
+        [],
+      ),
+    ])
+    return { stats, points, achievements }
+  }
+
+  /**
+   * 推送本地变更；推送期间又被修改的记录不会被标记为已同步
+   */
+  async function pushCrdtChanges(baseUrl: string, headers: Record<string, string>): Promise<number> {
+    const changes = await collectUnsynced()
+    const total = changes.stats.length + changes.points.length + changes.achievements.length
+    if (total === 0)
+      return 0
+
+    const request = await encodeJsonBody(changes, headers)
+    const res = await fetch(`${baseUrl}/crdt/push`, { method: 'POST', ...request })
+    if (!res.ok)
+      throw new Error(`推送成就数据失败: ${res.status}`)
+
+    const now = Date.now()
+    for (const s of changes.stats) {
+      await execute(
+        `UPDATE user_stat_shards SET synced_at = ?
+         WHERE user_id = ? AND stat_key = ? AND device_id = ? AND stat_value = ? AND updated_at = ?`,
+        [now, s.user_id, s.stat_key, s.device_id, s.stat_value, s.updated_at],
+      )
+    }
+    for (const p of changes.points) {
+      await execute('UPDATE user_points_log SET synced_at = ? WHERE operation_id = ?', [now, p.operation_id])
+    }
+    for (const a of changes.achievements) {
+      await execute(
+        `UPDATE user_achievements SET synced_at = ?
+         WHERE user_id = ? AND achievement_key = ? AND level = ? AND updated_at = ?`,
+        [now, a.user_id, a.achievement_key, a.level, a.updated_at],
+      )
+    }
+
+    console.log('[CrdtSync] 推送完成:', { stats: changes.stats.length, points: changes.points.length, achievements: changes.achievements.length })
+    return total
+  }
+
+  /**
+   * 合并一批远程变更，并重新计算受影响的统计与用户档案
+   * @returns 实际发生变化的记录数
+   */
+  async function applyCrdtChanges(changes: CrdtChanges): Promise<number> {
+    const now = Date.now()
+    const touchedStats = new Map<string, { userId: number, statKey: string }>()
+    const touchedUsers = new Set<number>()
+    let merged = 0
+
+    for (const s of changes.stats ?? []) {
+      const result = await execute(MERGE_SHARD_SQL, [s.user_id, s.stat_key, s.device_id, s.stat_type, s.stat_value, s.updated_at, now])
+      if (result?.rowsAffected) {
+        merged++
+        touchedStats.set(`${s.user_id}:${s.stat_key}`, { userId: s.user_id, statKey: s.stat_key })
+      }
+    }
+
+    for (const p of changes.points ?? []) {
+      const result = await execute(MERGE_POINTS_SQL, [
+        p.user_id,
+        p.operation_id,
+        p.source_type,
+        p.source_id,
+        p.achievement_key,
+        p.points,
+        p.exp,
+        p.reason,
+        p.created_at,
+        p.device_id,
+        now,
+      ])
+      if (result?.rowsAffected) {
+        merged++
+        touchedUsers.add(p.user_id)
+      }
+    }
+
+    for (const a of changes.achievements ?? []) {
+      const result = await execute(MERGE_ACHIEVEMENT_SQL, [
+        a.user_id,
+        a.achievement_key,
+        a.level,
+        a.progress,
+        a.total_points,
+        a.total_exp,
+        a.unlocked_at,
+        a.updated_at,
+        now,
+      ])
+      if (result?.rowsAffected) {
+        merged++
+        touchedUsers.add(a.user_id)
+      }
+    }
+
+    for (const { userId, statKey } of touchedStats.values()) {
+      await rebuildStat(userId, statKey)
+    }
+    for (const userId of touchedUsers) {
+      await updateProfile(userId)
+    }
+
+    return merged
+  }
+
+  /**
+   * 拉取 sinceVersion 之后的远程变更（与表同步共用版本号游标）
+   */
+  async function pullCrdtChanges(
+    baseUrl: string,
+    headers: Record<string, string>,
+    sinceVersion: number,
+  ): Promise<{ pulled: number, lastServerVersion: number }> {
+    let cursor = sinceVersion
+    let pulled = 0
+    let lastServerVersion = 0
+
+    while (true) {
+      const res = await fetch(`${baseUrl}/crdt/pull?since_version=${cursor}&limit=500`, { headers })
+      if (!res.ok)
+        throw new Error(`拉取成就数据失败: ${res.status}`)
+
+      const body = await res.json()
+      const payload = body.data as { changes: CrdtChanges, next_version?: number | null, server_version: number }
+      lastServerVersion = payload.server_version || lastServerVersion
+      pulled += await applyCrdtChanges(payload.changes)
+
+      if (!payload.next_version || payload.next_version <= cursor)
+        break
+      cursor = payload.next_version
+    }
+
+    console.log('[CrdtSync] 拉取完成:', { sinceVersion, pulled, lastServerVersion })
+    return { pulled, lastServerVersion }
+  }
+
+  /**
+   * 同步成就数据：移动端先推送再拉取，桌面端只拉取（本机即服务端）
+   */
+  async function syncCrdt(
+    baseUrl: string,
+    headers: Record<string, string>,
+    sinceVersion: number,
+    push: boolean,
+  ): Promise<CrdtSyncResult> {
+    const pushed = push ? await pushCrdtChanges(baseUrl, headers) : 0
+    const { pulled, lastServerVersion } = await pullCrdtChanges(baseUrl, headers, sinceVersion)
+    return { pushed, pulled, lastServerVersion }
+  }
+
+  return {
+    syncCrdt,
+    pushCrdtChanges,
+    pullCrdtChanges,
+    applyCrdtChanges,
+  }
+}
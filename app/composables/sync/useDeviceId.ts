@@ -0,0 +1,45 @@
+/**
+ * 本机设备 ID
+ * 与桌面端同步服务共用 sync_meta 中的 device_id，移动端首次使用时生成
+ */
+
+import { useTauriSQL } from '~/composables/useTauriSQL'
+
+let cachedDeviceId: string | null = null
+
+export function useDeviceId() {
+  const { select, execute } = useTauriSQL()
+
+  /**
+   * 读取本机设备 ID，不存在时生成并写入 sync_meta
+   */
+  async function getDeviceId(): Promise<string> {
+    if (cachedDeviceId)
+      return cachedDeviceId
+
+    const rows = await select<Array<{ value: string }>>(
+      `SELECT value FROM sync_meta WHERE key = 'device_id'`,
+      [],
+    )
+    const existing = rows[0]?.value?.trim()
+    if (existing) {
+      cachedDeviceId = existing
+      return existing
+    }
+
+    const id = Array.from(crypto.getRandomValues(new Uint8Array(8)), b => b.toString(16).padStart(2, '0')).join('')
+    // 并发首次调用时以先写入者为准
+    await execute(
+      `INSERT OR IGNORE INTO sync_meta (key, value, updated_at) VALUES ('device_id', ?, ?)`,
+      [id, new Date().toISOString()],
+    )
+    const saved = await select<Array<{ value: string }>>(
+      `SELECT value FROM sync_meta WHERE key = 'device_id'`,
+      [],
+    )
+    cachedDeviceId = saved[0]?.value || id
+    return cachedDeviceId
+  }
+
+  return { getDeviceId }
+}
@@ -0,0 +1,61 @@
+/**
+ * 本机混合逻辑时钟
+ * 仓库层的每次写入都用它重打 hlc，同步时据此排序（与桌面端服务共用 sync_meta 中的 device_id）
+ */
+
+import type { Hlc } from '~/utils/hlc'
+import { useDeviceId } from '~/composables/sync/useDeviceId'
+import { useTauriSQL } from '~/composables/useTauriSQL'
+import { getSyncTableNames } from '~/config/sync-tables'
+import { encodeHlc, HybridClock, parseHlc } from '~/utils/hlc'
+
+let clockPromise: Promise<HybridClock> | null = null
+
+export function useHlc() {
+  const { select } = useTauriSQL()
+  const { getDeviceId } = useDeviceId()
+
+  /**
+   * 加载时钟：用已存储的最大 HLC 初始化，保证重启后生成的时间戳不会回退
+   */
+  async function loadClock(): Promise<HybridClock> {
+    const clock = new HybridClock(await getDeviceId())
+    for (const table of getSyncTableNames()) {
+      try {
+        const rows = await select<Array<{ hlc: string | null }>>(
+          `SELECT MAX(hlc) AS hlc FROM ${table} WHERE hlc IS NOT NULL`,
+          [],
+        )
+        const stored = parseHlc(rows[0]?.hlc)
+        if (stored)
+          clock.advance(stored)
+      }
+      catch (e) {
+        console.warn(`[HLC] 读取 ${table} 的 HLC 失败:`, e)
+      }
+    }
+    return clock
+  }
+
+  function getClock(): Promise<HybridClock> {
+    if (!clockPromise) {
+      clockPromise = loadClock().catch((e) => {
+        clockPromise = null
+        throw e
+      })
+    }
+    return clockPromise
+  }
+
+  /** 为一次本地写入生成 HLC */
+  async function stampHlc(): Promise<string> {
+    return encodeHlc((await getClock()).now())
+  }
+
+  /** 观察远程时间戳；超前本机过多时返回 false（调用方应忽略该变更） */
+  async function observeHlc(remote: Hlc): Promise<boolean> {
+    return (await getClock()).observe(remote)
+  }
+
+  return { stampHlc, observeHlc }
+}
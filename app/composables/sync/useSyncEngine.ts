@@ -6,35 +6,121 @@
 import type { ConflictDecision, SyncMode } from './useSyncConflict'
 import type { RecordMetadata } from './useSyncMetadata'
 import type { SyncableTable } from '~/config/sync-tables'
+import { useHlc } from '~/composables/sync/useHlc'
 import { useSyncConflict } from '~/composables/sync/useSyncConflict'
 import { useSyncMetadata } from '~/composables/sync/useSyncMetadata'
 import { useTauriSQL } from '~/composables/useTauriSQL'
+import { isSyncableRecord } from '~/config/sync-tables'
+import { encodeJsonBody } from '~/utils/compression'
+import { compareHlc, effectiveHlc, encodeHlc } from '~/utils/hlc'
 
 export interface SyncChange {
   table: string
-  op: 'upsert' | 'delete'
+  /** delete 为软删除（保留内容），restore 从回收站恢复，scope_out 为记录移出了本设备的同步范围，purge 为从回收站彻底清除（后两者只带主键） */
+  op: 'upsert' | 'delete' | 'restore' | 'scope_out' | 'purge'
   data: Record<string, any>
   version: number
   updated_at: string
   deleted_at: string | null
+  /** 混合逻辑时钟（服务端排序用，旧数据可能缺失） */
+  hlc?: string | null
+  /** 本地编辑所基于的服务端版本号（服务端据此做三方合并） */
+  base_version?: number | null
+  /** 拉取时：自 since_version 以来变化的字段，为空表示整条记录都是新的 */
+  changed_columns?: string[] | null
 }
 
 export interface SyncResult {
   pulled: number
   pushed: number
   conflicts?: Array<{ local: RecordMetadata, remote: RecordMetadata }>
+  /** 远程元数据版本号，下次智能同步从这里增量获取墓碑 */
+  metadataVersion?: number
 }
 
 export interface PullResult {
   lastServerVersion: number
   pulled: number
   maxPulledVersion: number
+  /** 游标早于服务端低水位，本次改为全量对账 */
+  resynced?: boolean
+}
+
+export type ChangeStatus = 'applied' | 'merged' | 'skipped' | 'rejected' | 'conflict' | 'aborted'
+
+export interface ChangeOutcome {
+  /** 在请求 changes 数组中的下标 */
+  index: number
+  table: string
+  pk: string | null
+  status: ChangeStatus
+  reason: string | null
+  /** 服务端分配的版本号（applied / merged） */
+  version: number | null
+  /** 进入服务端冲突队列时的冲突 ID（conflict） */
+  conflict_id?: number | null
 }
 
 export interface PushResult {
   server_version: number
   applied: number
   conflict: boolean
+  /** 原子模式下整体回滚时为 false（旧服务端不返回） */
+  committed?: boolean
+  /** 每条变更的处理结果（旧服务端不返回） */
+  results?: ChangeOutcome[]
+  /** 预览（dry run）：结果仅供查看，服务端未写入 */
+  dry_run?: boolean
+}
+
+/** /pull?dry_run=true 中将会拉取到的一条记录 */
+export interface PreviewItem {
+  pk: string | null
+  /** 标题字段的值（表未配置标题字段时为空） */
+  title: string | null
+  op: SyncChange['op']
+  deleted: boolean
+  updated_at: string
+}
+
+export interface TablePreview {
+  table: string
+  count: number
+  /** 其中的删除数 */
+  deletes: number
+  /** 按版本号顺序的前 50 条 */
+  items: PreviewItem[]
+}
+
+/** /pull?dry_run=true 响应：将会拉取到的变更，服务端不记录拉取进度 */
+export interface PullPreview {
+  tables: TablePreview[]
+  total: number
+  server_version: number
+  /** since_version 早于低水位，实际拉取时需要全量对账 */
+  resync_required: boolean
+  low_water_mark: number
+}
+
+/**
+ * JSON 字段写入本地库前序列化（服务端以真正的 JSON 数组/对象下发，旧服务端下发字符串）
+ */
+function toStoredJson(value: unknown): unknown {
+  return value != null && typeof value === 'object' ? JSON.stringify(value) : value
+}
+
+/**
+ * JSON 字段推送前解析为真正的 JSON；无法解析时原样发送，由服务端拒绝
+ */
+function toWireJson(value: unknown): unknown {
+  if (typeof value !== 'string')
+    return value
+  try {
+    return JSON.parse(value)
+  }
+  catch {
+    return value
+  }
 }
 
 /**
@@ -42,8 +128,9 @@ export interface PushResult {
  */
 export function useSyncEngine() {
   const { select: syncSelect, execute: syncExecute } = useTauriSQL()
-  const { getLocalMetadata, getRemoteMetadata, compareMetadata } = useSyncMetadata()
+  const { getLocalMetadata, getRemoteMetadataSince, reconcileMetadata, compareMetadata } = useSyncMetadata()
   const { detectConflicts } = useSyncConflict()
+  const { stampHlc, observeHlc } = useHlc()
 
   /**
    * 智能同步单个表（基于元数据）
@@ -52,6 +139,7 @@ export function useSyncEngine() {
    * @param headers 请求头
    * @param mode 同步模式（auto | manual）
    * @param onConflict 冲突回调（手动模式时需要用户决策）
+   * @param metadataSince 上次返回的 metadataVersion，只获取此后远程新增的墓碑
    */
   async function syncTableSmart(
     table: SyncableTable,
@@ -59,18 +147,39 @@ export function useSyncEngine() {
     headers: Record<string, string>,
     mode: SyncMode,
     onConflict?: (conflicts: Array<{ local: RecordMetadata, remote: RecordMetadata }>) => Promise<ConflictDecision[]>,
+    metadataSince = 0,
   ): Promise<SyncResult> {
     console.log(`[SyncEngine] 开始智能同步: ${table.name}, mode=${mode}`)
 
-    // 1. 获取元数据
-    const [localMetadata, remoteMetadata] = await Promise.all([
-      getLocalMetadata(table),
-      getRemoteMetadata(baseUrl, table.name, headers),
+    // 1. 获取元数据：未删除的记录用范围哈希对账，只取回哈希不同的桶；远程删除通过增量元数据中的墓碑获得
+    const allLocal = await getLocalMetadata(table, true)
+    const [changed, remoteSince] = await Promise.all([
+      reconcileMetadata(baseUrl, table.name, headers, allLocal.filter(m => !m.deleted_at)),
+      getRemoteMetadataSince(baseUrl, table.name, headers, metadataSince).then(result =>
+        result.resyncRequired ? getRemoteMetadataSince(baseUrl, table.name, headers, 0) : result,
+      ),
     ])
 
+    // 移出同步范围的记录：丢弃本地副本，不参与推送和拉取
+    const scopedOutIds = new Set([...remoteSince.items, ...changed.remote].filter(m => m.scoped_out).map(m => m.uuid))
+    const scopedOut = await dropScopedOut(table, [...scopedOutIds])
+
+    const tombstones = remoteSince.items.filter(m => m.deleted_at && !m.scoped_out)
+    const tombstoneIds = new Set(tombstones.map(m => m.uuid))
+    const remoteMap = new Map([...tombstones, ...changed.remote.filter(m => !m.scoped_out)].map(m => [m.uuid, m]))
+    const localMap = new Map([
+      ...allLocal.filter(m => m.deleted_at || tombstoneIds.has(m.uuid)),
+      ...changed.local,
+    ].filter(m => !scopedOutIds.has(m.uuid)).map(m => [m.uuid, m]))
+    const localMetadata = [...localMap.values()]
+    const remoteMetadata = [...remoteMap.values()]
+
     console.log(`[SyncEngine] ${table.name} 元数据:`, {
       local: localMetadata.length,
       remote: remoteMetadata.length,
+      tombstones: tombstones.length,
+      scopedOut,
+      skipped: allLocal.length - changed.local.length,
     })
 
     // 2. 对比差异
@@ -140,6 +249,7 @@ export function useSyncEngine() {
       pulled,
       pushed,
       conflicts: needManual.length > 0 ? needManual : undefined,
+      metadataVersion: remoteSince.serverVersion || undefined,
     }
   }
 
@@ -160,7 +270,7 @@ export function useSyncEngine() {
     const fieldList = table.fields.join(', ')
     const placeholders = uuids.map(() => '?').join(', ')
     const rows = await syncSelect<any[]>(
-      `SELECT ${fieldList} FROM ${table.name} WHERE uuid IN (${placeholders})`,
+      `SELECT ${fieldList}, base_version FROM ${table.name} WHERE uuid IN (${placeholders})`,
       uuids,
     )
 
@@ -180,11 +290,13 @@ export function useSyncEngine() {
         const data: Record<string, any> = {}
         table.fields.forEach((field) => {
           // 跳过这些由系统管理的字段（会在 SyncChange 的其他字段中传递）
-          if (field === 'updated_at' || field === 'deleted_at' || field === 'version') {
+          if (field === 'updated_at' || field === 'deleted_at' || field === 'version' || field === 'hlc') {
             return
           }
           // 包含所有其他字段，包括 uuid 和 id
-          data[field] = row[field] ?? (table.jsonFields?.includes(field) ? '[]' : '')
+          data[field] = table.jsonFields?.includes(field)
+            ? toWireJson(row[field] ?? '[]')
+            : (row[field] ?? '')
         })
 
         return {
@@ -198,21 +310,37 @@ export function useSyncEngine() {
           version: row.version || 0,
           updated_at: updatedAt,
           deleted_at: deletedAt,
+          hlc: row.hlc || null,
+          base_version: row.base_version || null,
         }
       })
 
     // 发送推送请求
-    const res = await fetch(`${baseUrl}/push`, {
-      method: 'POST',
-      headers: { ...headers, 'Content-Type': 'application/json' },
-      body: JSON.stringify({ table: table.name, changes, client_version: 0 }),
-    })
+    const request = await encodeJsonBody({ table: table.name, changes, client_version: 0 }, headers)
+    const res = await fetch(`${baseUrl}/push`, { method: 'POST', ...request })
 
     if (!res.ok)
       throw new Error(`推送失败: ${res.status}`)
 
     const body = await res.json()
-    return body.data as PushResult
+    const result = body.data as PushResult
+    await recordPushOutcomes(table, result)
+    return result
+  }
+
+  /**
+   * 根据 push 结果更新本地记录的 base_version
+   * 仅处理服务端原样接受（applied）的变更；merged 的内容与本地不同，等待下次拉取
+   */
+  async function recordPushOutcomes(table: SyncableTable, result: PushResult): Promise<void> {
+    for (const outcome of result.results ?? []) {
+      if (outcome.status !== 'applied' || !outcome.pk || !outcome.version)
+        continue
+      await syncExecute(
+        `UPDATE ${table.name} SET base_version = ? WHERE ${table.primaryKey} = ?`,
+        [outcome.version, outcome.pk],
+      )
+    }
   }
 
   /**
@@ -271,8 +399,8 @@ export function useSyncEngine() {
 
     // 强制更新本地版本号为负数（表示待推送）
     await syncExecute(
-      `UPDATE ${table.name} SET version = ?, updated_at = ? WHERE uuid = ?`,
-      [-Date.now(), new Date().toISOString(), recordUuid],
+      `UPDATE ${table.name} SET version = ?, updated_at = ?, hlc = ? WHERE uuid = ?`,
+      [-Date.now(), new Date().toISOString(), await stampHlc(), recordUuid],
     )
 
     // 推送
@@ -322,22 +450,25 @@ export function useSyncEngine() {
     }
 
     const rows = await syncSelect<any[]>(
-      `SELECT ${fieldList} FROM ${table.name} 
+      `SELECT ${fieldList}, base_version FROM ${table.name} 
        WHERE ${whereConditions.join(' AND ')}`,
       [sinceVersion, MAX_REASONABLE_VERSION],
     )
 
-    return rows.map((row) => {
+    // settings 只推送 synced 策略的键（凭据和本机偏好不离开本机）
+    return rows.filter(row => isSyncableRecord(table.name, row)).map((row) => {
       const updatedAt = row.updated_at ? new Date(row.updated_at).toISOString() : new Date().toISOString()
       const deletedAt = row.deleted_at ? new Date(row.deleted_at).toISOString() : null
 
       // 序列化 JSON 字段
       const data: Record<string, any> = {}
       table.fields.forEach((field) => {
-        if (field === 'updated_at' || field === 'deleted_at' || field === 'version') {
+        if (field === 'updated_at' || field === 'deleted_at' || field === 'version' || field === 'hlc') {
           return // 这些字段单独处理
         }
-        data[field] = row[field] ?? (table.jsonFields?.includes(field) ? '[]' : '')
+        data[field] = table.jsonFields?.includes(field)
+          ? toWireJson(row[field] ?? '[]')
+          : (row[field] ?? '')
       })
 
       return {
@@ -351,6 +482,8 @@ export function useSyncEngine() {
         version: row.version || 0,
         updated_at: updatedAt,
         deleted_at: deletedAt,
+        hlc: row.hlc || null,
+        base_version: row.base_version || null,
       }
     })
   }
@@ -368,17 +501,32 @@ export function useSyncEngine() {
       if (change.table !== table.name)
         continue
 
-      // 对于 workflows 表,过滤掉系统流
-      if (table.name === 'workflows' && change.data?.type && change.data.type.startsWith('system:')) {
-        console.log(`[Sync] 跳过系统流: ${change.data.name || change.data.id} (${change.data.type})`)
+      // 过滤掉系统流和非 synced 策略的设置项（服务端不会下发，这里兜底）
+      if (!isSyncableRecord(table.name, change.data)) {
+        console.log(`[Sync] 跳过不参与同步的记录: ${table.name} ${change.data?.[table.primaryKey]}`)
         continue
       }
 
       const pkValue = change.data?.[table.primaryKey]
+
+      // 移出同步范围：丢弃本地副本（不是删除，不记入回收站，也不会推送回去）
+      if (change.op === 'scope_out') {
+        await dropScopedOut(table, [pkValue])
+        continue
+      }
+
       const incomingVersion = change.version || 0
       const updatedAt = change.updated_at || new Date().toISOString()
       const deletedAt = change.deleted_at || null
 
+      // 远程变更的 HLC（旧服务端不携带时由 updated_at 推导）；超前本机过多的时间戳不接受
+      const remoteHlc = effectiveHlc(change.hlc ?? change.data?.hlc, change.updated_at)
+      if (!await observeHlc(remoteHlc)) {
+        console.warn(`[SyncEngine] 跳过时钟超前的远程变更: ${table.name} ${pkValue}, hlc=${encodeHlc(remoteHlc)}`)
+        continue
+      }
+      const remoteHlcValue = encodeHlc(remoteHlc)
+
       // 检查本地是否已有更新的版本
       const existing = await syncSelect<any[]>(
         `SELECT updated_at, ${table.fields.filter(f => f !== 'version' && f !== 'created_at').join(', ')} 
@@ -389,37 +537,81 @@ export function useSyncEngine() {
       let isContentSame = false
       if (existing.length > 0) {
         const local = existing[0]
-        const localUpdatedAt = local.updated_at || ''
-        const remoteUpdatedAt = change.updated_at || ''
+        const localHlc = effectiveHlc(local.hlc, local.updated_at)
 
-        // 本地时间 >= 远程时间，跳过
-        if (localUpdatedAt >= remoteUpdatedAt) {
-          console.log(`[SyncEngine] 跳过较旧的远程变更: ${table.name} ${pkValue}, local=${localUpdatedAt}, remote=${remoteUpdatedAt}`)
+        // 本地 HLC >= 远程 HLC，跳过（与服务端相同的全序，不直接比较时间字符串）
+        if (compareHlc(localHlc, remoteHlc) >= 0) {
+          console.log(`[SyncEngine] 跳过较旧的远程变更: ${table.name} ${pkValue}, local=${encodeHlc(localHlc)}, remote=${remoteHlcValue}`)
           continue
         }
 
-        // 检查内容是否一致（排除版本号和时间戳）
+        // 检查内容是否一致（排除版本号、时间戳和 HLC）
         isContentSame = table.fields
-          .filter(f => !['version', 'updated_at', 'created_at'].includes(f))
+          .filter(f => !['version', 'updated_at', 'created_at', 'hlc'].includes(f))
           .every((field) => {
             const localVal = local[field]
-            const remoteVal = change.data[field]
+            const remoteVal = table.jsonFields?.includes(field) ? toStoredJson(change.data[field]) : change.data[field]
             return localVal === remoteVal || (localVal == null && remoteVal == null)
           })
       }
 
+      // 从回收站彻底清除：删除本地副本，不留回收站记录
+      if (change.op === 'purge') {
+        if (existing.length > 0) {
+          await syncExecute(`DELETE FROM ${table.name} WHERE ${table.primaryKey} = ?`, [pkValue])
+          applied++
+          console.log(`[SyncEngine] 应用远程彻底清除: ${table.name} ${pkValue}`)
+        }
+        continue
+      }
+
+      // 只携带主键的恢复：清除本地删除标记
+      const isBareRestore = change.op === 'restore'
+        && Object.keys(change.data || {}).every(key => key === table.primaryKey)
+      if (isBareRestore) {
+        if (existing.length > 0) {
+          await syncExecute(
+            `UPDATE ${table.name} SET deleted_at = NULL, updated_at = ?, hlc = ?, version = ?, base_version = ? WHERE ${table.primaryKey} = ?`,
+            [updatedAt, remoteHlcValue, incomingVersion, incomingVersion, pkValue],
+          )
+          applied++
+          console.log(`[SyncEngine] 应用远程恢复: ${table.name} ${pkValue}, version=${incomingVersion}`)
+        }
+        continue
+      }
+
+      // 服务端硬删除只携带主键：本地有记录则标记删除，没有则无需处理
+      const isHardDelete = change.op === 'delete'
+        && Object.keys(change.data || {}).every(key => key === table.primaryKey)
+      if (isHardDelete) {
+        if (existing.length > 0) {
+          await syncExecute(
+            `UPDATE ${table.name} SET deleted_at = ?, updated_at = ?, hlc = ?, version = ?, base_version = ? WHERE ${table.primaryKey} = ?`,
+            [deletedAt || updatedAt, updatedAt, remoteHlcValue, incomingVersion, incomingVersion, pkValue],
+          )
+          applied++
+          console.log(`[SyncEngine] 应用远程硬删除: ${table.name} ${pkValue}, version=${incomingVersion}`)
+        }
+        continue
+      }
+
       // 构建 UPSERT SQL
-      const dataFields = table.fields.filter(f => f !== 'created_at') // created_at 由数据库自动管理
+      // base_version 记录本地副本对应的服务端版本，后续推送时作为三方合并的基线
+      const dataFields = [...table.fields.filter(f => f !== 'created_at'), 'base_version'] // created_at 由数据库自动管理
       const placeholders = dataFields.map(() => '?').join(', ')
       const updateSet = dataFields.map(f => `${f} = excluded.${f}`).join(', ')
 
       const values = dataFields.map((field) => {
-        if (field === 'version')
+        if (field === 'version' || field === 'base_version')
           return incomingVersion
         if (field === 'updated_at')
           return updatedAt
+        if (field === 'hlc')
+          return remoteHlcValue
         if (field === 'deleted_at')
           return deletedAt
+        if (table.jsonFields?.includes(field))
+          return toStoredJson(change.data[field]) ?? null
         return change.data[field] ?? null
       })
 
@@ -459,6 +651,9 @@ export function useSyncEngine() {
     let lastServerVersion = 0
     let pulled = 0
     let maxPulledVersion = 0
+    let resynced = false
+    // 全量对账时记录服务端仍存在的主键
+    const remoteKeys = new Set<string>()
 
     console.log(`[SyncEngine] 开始拉取 ${table.name}:`, { sinceVersion, baseUrl })
 
@@ -470,11 +665,35 @@ export function useSyncEngine() {
         throw new Error(`拉取 ${table.name} 失败: ${res.status}`)
 
       const body = await res.json()
-      const payload = body.data as { changes: any[], next_version?: number | null, server_version: number }
+      const payload = body.data as {
+        changes: any[]
+        next_version?: number | null
+        server_version: number
+        resync_required?: boolean
+        low_water_mark?: number
+      }
+
+      // 服务端已回收该游标之后的墓碑，增量拉取会漏掉删除：从 0 开始全量拉取
+      if (payload.resync_required && !resynced) {
+        console.warn(`[SyncEngine] ${table.name} 游标 ${cursor} 早于服务端低水位 ${payload.low_water_mark}，改为全量对账`)
+        resynced = true
+        cursor = 0
+        continue
+      }
 
       if (payload.server_version)
         lastServerVersion = payload.server_version
 
+      if (resynced) {
+        for (const change of payload.changes ?? []) {
+          if (change.op === 'scope_out' || change.op === 'purge')
+            continue
+          const pk = change.data?.[table.primaryKey]
+          if (pk != null)
+            remoteKeys.add(String(pk))
+        }
+      }
+
       if (payload.changes?.length) {
         const applied = await applyRemoteChanges(table, payload.changes)
         pulled += applied
@@ -492,9 +711,65 @@ export function useSyncEngine() {
       cursor = payload.next_version
     }
 
-    console.log(`[SyncEngine] ${table.name} 拉取完成:`, { lastServerVersion, pulled, maxPulledVersion })
+    if (resynced)
+      pulled += await removeMissingRecords(table, remoteKeys)
+
+    console.log(`[SyncEngine] ${table.name} 拉取完成:`, { lastServerVersion, pulled, maxPulledVersion, resynced })
+
+    return { lastServerVersion, pulled, maxPulledVersion, resynced }
+  }
+
+  /**
+   * 全量对账：已同步过（version > 0）但服务端已不存在的记录，说明其删除墓碑已被回收，本地软删除
+   * 版本号保持不变，不会被当作本地变更再推送；未同步的本地编辑不受影响
+   * @param table 表配置
+   * @param remoteKeys 全量拉取中出现过的主键
+   * @returns 删除的记录数
+   */
+  async function removeMissingRecords(table: SyncableTable, remoteKeys: Set<string>): Promise<number> {
+    const rows = await syncSelect<any[]>(
+      `SELECT ${table.fields.join(', ')} FROM ${table.name} WHERE version > 0 AND deleted_at IS NULL`,
+      [],
+    )
+    // 不参与同步的记录（本机设置项等）本来就不在服务端
+    const missing = rows
+      .filter(row => isSyncableRecord(table.name, row))
+      .map(row => String(row[table.primaryKey]))
+      .filter(pk => !remoteKeys.has(pk))
+
+    const now = new Date().toISOString()
+    for (const pk of missing) {
+      await syncExecute(
+        `UPDATE ${table.name} SET deleted_at = ?, updated_at = ? WHERE ${table.primaryKey} = ?`,
+        [now, now, pk],
+      )
+    }
+
+    if (missing.length)
+      console.log(`[SyncEngine] ${table.name} 全量对账：软删除 ${missing.length} 条服务端已回收的记录`)
+
+    return missing.length
+  }
+
+  /**
+   * 丢弃移出同步范围的记录的本地副本
+   * 只删除已同步过（version > 0）的副本；未推送的本地编辑保留，推送后由服务端决定是否仍在范围内
+   * @returns 删除的记录数
+   */
+  async function dropScopedOut(table: SyncableTable, pks: string[]): Promise<number> {
+    let dropped = 0
+    for (const pk of pks) {
+      const result = await syncExecute(
+        `DELETE FROM ${table.name} WHERE ${table.primaryKey} = ? AND version > 0`,
+        [pk],
+      )
+      dropped += result.rowsAffected ?? 0
+    }
+
+    if (dropped)
+      console.log(`[SyncEngine] ${table.name} 丢弃 ${dropped} 条移出同步范围的记录`)
 
-    return { lastServerVersion, pulled, maxPulledVersion }
+    return dropped
   }
 
   /**
@@ -519,11 +794,8 @@ export function useSyncEngine() {
       return { server_version: sinceVersion, applied: 0, conflict: false }
     }
 
-    const res = await fetch(`${baseUrl}/push`, {
-      method: 'POST',
-      headers: { ...headers, 'Content-Type': 'application/json' },
-      body: JSON.stringify({ table: table.name, changes, client_version: sinceVersion }),
-    })
+    const request = await encodeJsonBody({ table: table.name, changes, client_version: sinceVersion }, headers)
+    const res = await fetch(`${baseUrl}/push`, { method: 'POST', ...request })
 
     if (!res.ok)
       throw new Error(`推送 ${table.name} 失败: ${res.status}`)
@@ -531,7 +803,59 @@ export function useSyncEngine() {
     const body = await res.json()
     console.log(`[SyncEngine] ${table.name} 推送响应:`, body.data)
 
-    return body.data as PushResult
+    const result = body.data as PushResult
+    await recordPushOutcomes(table, result)
+    return result
+  }
+
+  /**
+   * 预览推送：服务端照常判断每条变更，最后回滚，不写入任何数据
+   * 不更新本地 base_version
+   * @returns 每条变更将会得到的结果（results）
+   */
+  async function previewPushTableChanges(
+    table: SyncableTable,
+    baseUrl: string,
+    headers: Record<string, string>,
+    sinceVersion: number,
+  ): Promise<PushResult> {
+    const changes = await collectLocalChanges(table, sinceVersion)
+    if (!changes.length) {
+      return { server_version: sinceVersion, applied: 0, conflict: false, results: [], dry_run: true }
+    }
+
+    const request = await encodeJsonBody({ table: table.name, changes, client_version: sinceVersion, dry_run: true }, headers)
+    const res = await fetch(`${baseUrl}/push?dry_run=true`, { method: 'POST', ...request })
+
+    if (!res.ok)
+      throw new Error(`预览推送 ${table.name} 失败: ${res.status}`)
+
+    const body = await res.json()
+    const result = body.data as PushResult
+    // 旧服务端不认识 dry_run，会真正写入
+    if (!result.dry_run)
+      console.warn(`[SyncEngine] 服务端不支持预览，${table.name} 已实际推送`)
+    return result
+  }
+
+  /**
+   * 预览拉取：按表统计将会拉取到的变更
+   * @param tableName 只看一张表，缺省为全部同步表
+   */
+  async function previewPull(
+    baseUrl: string,
+    headers: Record<string, string>,
+    sinceVersion: number,
+    tableName?: string,
+  ): Promise<PullPreview> {
+    const table = tableName ? `&table=${tableName}` : ''
+    const res = await fetch(`${baseUrl}/pull?since_version=${sinceVersion}&dry_run=true${table}`, { headers })
+
+    if (!res.ok)
+      throw new Error(`预览拉取失败: ${res.status}`)
+
+    const body = await res.json()
+    return body.data as PullPreview
   }
 
   /**
@@ -623,6 +947,9 @@ export function useSyncEngine() {
     applyRemoteChanges,
     pullTableChanges,
     pushTableChanges,
+    // 预览（dry run）
+    previewPushTableChanges,
+    previewPull,
     upgradeLocalVersions,
     resetSyncedVersions,
   }
@@ -4,13 +4,17 @@
  */
 
 import type { SyncableTable } from '~/config/sync-tables'
+import type { MerkleNode } from '~/utils/merkle'
 import { useTauriSQL } from '~/composables/useTauriSQL'
+import { MerkleIndex, reconcile } from '~/utils/merkle'
 
 export interface RecordMetadata {
   uuid: string
   version: number
   updated_at: string
   deleted_at: string | null
+  /** 记录不在本设备的同步范围内：删除本地副本，但不是用户删除 */
+  scoped_out?: boolean
 }
 
 export interface SyncDiff {
@@ -18,7 +22,7 @@ export interface SyncDiff {
   localOnly: RecordMetadata[]
   /** 仅远程有的记录 */
   remoteOnly: RecordMetadata[]
-  /** 冲突的记录（两端都有且不同） */
+  /** 冲突的记录（两端都有且不同，包括一端已删除、另一端仍存在的记录） */
   conflicts: Array<{
     local: RecordMetadata
     remote: RecordMetadata
@@ -32,12 +36,13 @@ export function useSyncMetadata() {
 
   /**
    * 获取本地表的元数据
+   * @param includeDeleted 是否包含已软删除的记录（墓碑）
    */
-  async function getLocalMetadata(table: SyncableTable): Promise<RecordMetadata[]> {
+  async function getLocalMetadata(table: SyncableTable, includeDeleted = false): Promise<RecordMetadata[]> {
     const rows = await select<RecordMetadata[]>(
       `SELECT uuid, version, updated_at, deleted_at 
        FROM ${table.name} 
-       WHERE deleted_at IS NULL AND uuid IS NOT NULL AND uuid != ''
+       WHERE ${includeDeleted ? '1 = 1' : 'deleted_at IS NULL'} AND uuid IS NOT NULL AND uuid != ''
        ORDER BY updated_at DESC`,
       [],
     )
@@ -62,6 +67,85 @@ export function useSyncMetadata() {
     return body.data as RecordMetadata[]
   }
 
+  /**
+   * 分页获取远程表在 sinceVersion 之后有变化的元数据（包含墓碑）
+   * resyncRequired 为 true 时部分墓碑已在远程回收，需要从 0 重新获取
+   */
+  async function getRemoteMetadataSince(
+    baseUrl: string,
+    table: string,
+    headers: Record<string, string>,
+    sinceVersion: number,
+  ): Promise<{ items: RecordMetadata[], serverVersion: number, resyncRequired: boolean }> {
+    const items: RecordMetadata[] = []
+    let cursor: string | null = null
+    let serverVersion = 0
+
+    do {
+      const query = cursor ? `cursor=${cursor}` : `since_version=${sinceVersion}`
+      const res = await fetch(`${baseUrl}/metadata?table=${table}&${query}&limit=500`, { headers })
+
+      if (!res.ok)
+        throw new Error(`获取远程元数据失败: ${res.status}`)
+
+      const body = await res.json()
+      // 旧版本服务端忽略分页参数，直接返回未删除记录的数组（没有墓碑）
+      if (Array.isArray(body.data))
+        return { items: [], serverVersion: 0, resyncRequired: false }
+
+      const page = body.data as {
+        items: RecordMetadata[]
+        next_cursor: string | null
+        server_version: number
+        resync_required: boolean
+      }
+      if (page.resync_required)
+        return { items: [], serverVersion: page.server_version, resyncRequired: true }
+
+      items.push(...page.items)
+      cursor = page.next_cursor
+      serverVersion = page.server_version
+    } while (cursor)
+
+    return { items, serverVersion, resyncRequired: false }
+  }
+
+  /**
+   * 范围哈希对账：只取回两端哈希不同的桶中的元数据
+   * 返回的 local / remote 可直接交给 compareMetadata，哈希相同的桶中的记录两端一致，不在结果中
+   * 服务端不支持 /merkle 时退回全量元数据
+   */
+  async function reconcileMetadata(
+    baseUrl: string,
+    table: string,
+    headers: Record<string, string>,
+    local: RecordMetadata[],
+  ): Promise<{ local: RecordMetadata[], remote: RecordMetadata[] }> {
+    let fallback = false
+    const result = await reconcile(new MerkleIndex(local), async (prefixes) => {
+      const res = await fetch(`${baseUrl}/merkle`, {
+        method: 'POST',
+        headers: { ...headers, 'Content-Type': 'application/json' },
+        body: JSON.stringify({ table, prefixes }),
+      })
+      if (res.status === 404) {
+        fallback = true
+        return []
+      }
+      if (!res.ok)
+        throw new Error(`范围哈希对账失败: ${res.status}`)
+
+      const body = await res.json()
+      return body.data as MerkleNode<RecordMetadata>[]
+    })
+
+    if (fallback)
+      return { local, remote: await getRemoteMetadata(baseUrl, table, headers) }
+
+    console.log(`[SyncMetadata] ${table} 范围哈希对账: ${result.rounds} 轮, 差异桶中本地 ${result.local.length} 条 / 远程 ${result.remote.length} 条`)
+    return { local: result.local, remote: result.remote }
+  }
+
   /**
    * 对比本地和远程元数据，找出差异
    */
@@ -84,12 +168,17 @@ export function useSyncMetadata() {
       const remoteItem = remoteMap.get(localItem.uuid)
 
       if (!remoteItem) {
-        // 仅本地有
-        diff.localOnly.push(localItem)
+        // 仅本地有（本地墓碑在远程不存在时无需处理）
+        if (!localItem.deleted_at)
+          diff.localOnly.push(localItem)
       }
       else {
-        // 两端都有，检查是否相同（基于 updated_at）
-        if (localItem.updated_at === remoteItem.updated_at) {
+        // 两端都已删除：无需处理
+        if (localItem.deleted_at && remoteItem.deleted_at) {
+          diff.identical.push(localItem)
+        }
+        // 两端都有，检查是否相同（基于 updated_at 和删除状态）
+        else if (localItem.updated_at === remoteItem.updated_at && !localItem.deleted_at === !remoteItem.deleted_at) {
           diff.identical.push(localItem)
         }
         else {
@@ -99,9 +188,9 @@ export function useSyncMetadata() {
       }
     }
 
-    // 检查远程独有的记录
+    // 检查远程独有的记录（远程墓碑在本地不存在时无需处理）
     for (const remoteItem of remote) {
-      if (!localMap.has(remoteItem.uuid)) {
+      if (!localMap.has(remoteItem.uuid) && !remoteItem.deleted_at) {
         diff.remoteOnly.push(remoteItem)
       }
     }
@@ -112,6 +201,8 @@ export function useSyncMetadata() {
   return {
     getLocalMetadata,
     getRemoteMetadata,
+    getRemoteMetadataSince,
+    reconcileMetadata,
     compareMetadata,
   }
 }
@@ -0,0 +1,93 @@
+/**
+ * 同步会话日志（仅桌面端）
+ * 服务端把每台设备连续的 /pull、/push 请求归入一个会话，主动同步另一台桌面端时每次同步记录为一个会话。
+ * 每次更新会话后后端发出 sync:session 事件，数据结构与 list_sync_sessions 返回的会话一致，
+ * 字段含义见 src-tauri/src/sync_sessions.rs
+ */
+
+export interface TableCounts {
+  pulled: number
+  pushed: number
+  conflicts: number
+  rejected: number
+}
+
+export interface SyncSession {
+  id: number
+  /** server：对端同步本机；client：本机主动同步对端 */
+  role: 'server' | 'client'
+  /** 服务端会话为对端设备 ID，客户端会话为对端地址 */
+  peer_id: string | null
+  peer_name: string | null
+  started_at: string
+  ended_at: string
+  duration_ms: number
+  requests: number
+  pulled: number
+  pushed: number
+  conflicts: number
+  rejected: number
+  bytes_in: number
+  bytes_out: number
+  tables: Record<string, TableCounts>
+  errors: string[]
+  status: 'ok' | 'error'
+}
+
+/** 一次请求（或一次主动同步）的统计 */
+export interface SyncExchange {
+  tables: Record<string, TableCounts>
+  bytes_in: number
+  bytes_out: number
+  duration_ms: number
+  error: string | null
+}
+
+/** sync:session 事件 */
+export interface SyncSessionEvent {
+  session: SyncSession
+  exchange: SyncExchange
+}
+
+export interface SyncSessionPage {
+  items: SyncSession[]
+  /** 下一页的 before 参数，为空表示没有更早的会话 */
+  next_before: number | null
+}
+
+/** 各表变更数之和 */
+export function sumTableCounts(tables: Record<string, TableCounts>): TableCounts {
+  const total: TableCounts = { pulled: 0, pushed: 0, conflicts: 0, rejected: 0 }
+  for (const counts of Object.values(tables)) {
+    total.pulled += counts.pulled
+    total.pushed += counts.pushed
+    total.conflicts += counts.conflicts
+    total.rejected += counts.rejected
+  }
+  return total
+}
+
+export function useSyncSessions() {
+  /**
+   * 按时间倒序分页列出会话
+   * @param before 上一页的 next_before
+   * @param peerId 只看某个对端
+   */
+  async function listSessions(before?: number | null, limit = 50, peerId?: string): Promise<SyncSessionPage> {
+    const { invoke } = await import('@tauri-apps/api/core')
+    return invoke<SyncSessionPage>('list_sync_sessions', { before: before ?? null, limit, peerId: peerId ?? null })
+  }
+
+  /**
+   * 查看单条会话
+   */
+  async function getSession(id: number): Promise<SyncSession | null> {
+    const { invoke } = await import('@tauri-apps/api/core')
+    return invoke<SyncSession | null>('get_sync_session', { id })
+  }
+
+  return {
+    listSessions,
+    getSession,
+  }
+}
@@ -196,8 +196,8 @@ export function useAchievementSystem() {
       // 首次解锁
       await execute(
         `INSERT INTO user_achievements 
-        (user_id, achievement_key, level, progress, total_points, total_exp, unlocked_at, updated_at)
-        VALUES (?, ?, ?, 0, ?, ?, ?, ?)`,
+        (user_id, achievement_key, level, progress, total_points, total_exp, unlocked_at, updated_at, synced_at)
+        VALUES (?, ?, ?, 0, ?, ?, ?, ?, NULL)`,
         [
           userId,
           achievement.key,
@@ -218,23 +218,27 @@ export function useAchievementSystem() {
 
       await execute(
         `UPDATE user_achievements 
-        SET level = ?, total_points = ?, total_exp = ?, updated_at = ?
+        SET level = ?, total_points = ?, total_exp = ?, updated_at = ?, synced_at = NULL
         WHERE user_id = ? AND achievement_key = ?`,
         [level, newTotalPoints, newTotalExp, now, userId, achievement.key],
       )
     }
 
-    // 发放奖励
-    const levelDiff = existing.length === 0 ? level : level - existing[0]!.level
-    await addPoints(
-      userId,
-      'achievement',
-      achievement.key,
-      achievement.points * levelDiff,
-      achievement.exp * levelDiff,
-      `解锁成就: ${achievement.name} (Lv.${level})`,
-      achievement.key,
-    )
+    // 发放奖励：每级一笔，operation_id 由成就和等级决定，
+    // 多台设备各自解锁同一等级时同步后只计一次
+    const oldLevel = existing.length === 0 ? 0 : existing[0]!.level
+    for (let lv = oldLevel + 1; lv <= level; lv++) {
+      await addPoints(
+        userId,
+        'achievement',
+        achievement.key,
+        achievement.points,
+        achievement.exp,
+        `解锁成就: ${achievement.name} (Lv.${lv})`,
+        achievement.key,
+        `achievement_${userId}_${achievement.key}_${lv}`,
+      )
+    }
   }
 
   /**
@@ -1,3 +1,6 @@
+import type { SyncSessionEvent } from '~/composables/sync/useSyncSessions'
+import { sumTableCounts } from '~/composables/sync/useSyncSessions'
+
 // Using useState for global singleton behavior in Nuxt
 export const useActivityStatus = () => {
   const syncState = useState('activity_sync_state', () => ({
@@ -5,6 +8,10 @@ export const useActivityStatus = () => {
     pulling: 0,
     isSyncing: false,
     lastUpdated: 0,
+    // 最近一次 sync:session 事件（会话与本次请求的统计）
+    lastSession: null as SyncSessionEvent | null,
+    // 与 ActionStatusIndicator 的状态一致
+    status: 'idle' as 'idle' | 'loading' | 'success' | 'error',
   }))
 
   const workflows = useState<Map<string, {
@@ -32,6 +39,18 @@ export const useActivityStatus = () => {
     syncState.value.lastUpdated = Date.now()
   }
 
+  /**
+   * 根据 sync:session 事件更新同步状态
+   * 计数以本机视角显示：上行为本机发出的变更，下行为本机收到的变更
+   */
+  function applySyncSession(event: SyncSessionEvent) {
+    const counts = sumTableCounts(event.exchange.tables)
+    const isServer = event.session.role === 'server'
+    syncState.value.lastSession = event
+    syncState.value.status = event.exchange.error ? 'error' : 'success'
+    setSyncCounts(isServer ? counts.pulled : counts.pushed, isServer ? counts.pushed : counts.pulled)
+  }
+
   function startWorkflow(id: string, title: string, totalSteps: number) {
     workflows.value.set(id, {
       id,
@@ -72,6 +91,7 @@ export const useActivityStatus = () => {
     workflowList,
     setSyncState,
     setSyncCounts,
+    applySyncSession,
     startWorkflow,
     updateWorkflowStep,
     finishWorkflow,
@@ -0,0 +1,106 @@
+/**
+ * 笔记历史版本（仅桌面端）
+ * 笔记的标题或正文被覆盖前（编辑器保存、同步写入），数据库触发器会保存旧内容；
+ * 连续编辑的中间状态按保留策略清理。字段含义见 src-tauri/src/note_revisions.rs
+ */
+
+/** edit：本机编辑覆盖；sync：同步或合并覆盖；restore：恢复历史版本前的内容 */
+export type RevisionSource = 'edit' | 'sync' | 'restore'
+
+export interface NoteRevisionSummary {
+  id: number
+  note_uuid: string
+  title: string | null
+  source: RevisionSource
+  /** 被覆盖时笔记的版本号 */
+  version: number | null
+  /** 这份内容写入的时间 */
+  edited_at: string | null
+  /** 被覆盖（保存快照）的时间 */
+  created_at: string
+  /** 正文字数 */
+  chars: number
+}
+
+export interface NoteRevision extends Omit<NoteRevisionSummary, 'chars'> {
+  content: string | null
+}
+
+export interface DiffOp {
+  kind: 'equal' | 'insert' | 'delete'
+  text: string
+}
+
+/** 两个版本之间的字符级差异（旧 -> 新） */
+export interface NoteRevisionDiff {
+  from_id: number
+  /** 为空表示与笔记当前内容比较 */
+  to_id: number | null
+  title: DiffOp[]
+  content: DiffOp[]
+  inserted: number
+  deleted: number
+}
+
+export interface NoteRevisionRetention {
+  /** 保留天数，0 表示不按时间清理 */
+  retention_days: number
+  /** 每篇笔记最多保留的版本数（上限 500，超出时按 500 保存） */
+  max_per_note: number
+  /** 存在时间短于该秒数的编辑中间状态会被清理 */
+  merge_secs: number
+}
+
+export interface RevisionCompactReport {
+  orphaned: number
+  expired: number
+  merged: number
+  trimmed: number
+}
+
+export function useNoteRevisions() {
+  async function invokeCommand<T>(command: string, args?: Record<string, unknown>): Promise<T> {
+    const { invoke } = await import('@tauri-apps/api/core')
+    return invoke<T>(command, args)
+  }
+
+  /**
+   * 列出一篇笔记的历史版本（最新的在前，不含正文）
+   */
+  const listRevisions = (noteUuid: string) =>
+    invokeCommand<NoteRevisionSummary[]>('list_note_revisions', { noteUuid })
+
+  const getRevision = (id: number) =>
+    invokeCommand<NoteRevision | null>('get_note_revision', { id })
+
+  /**
+   * 比较历史版本与另一个版本
+   * @param against 为空时与笔记当前内容比较
+   */
+  const diffRevision = (id: number, against?: number) =>
+    invokeCommand<NoteRevisionDiff | null>('diff_note_revision', { id, against: against ?? null })
+
+  /**
+   * 把笔记恢复为该版本（作为一次新的编辑同步到其他设备）；内容与当前一致时返回 false
+   */
+  const restoreRevision = (id: number) =>
+    invokeCommand<boolean>('restore_note_revision', { id })
+
+  const getRetention = () =>
+    invokeCommand<NoteRevisionRetention>('get_note_revision_retention')
+
+  /**
+   * 保存保留策略，并立即按新策略清理
+   */
+  const setRetention = (retention: NoteRevisionRetention) =>
+    invokeCommand<RevisionCompactReport>('set_note_revision_retention', { retention })
+
+  return {
+    listRevisions,
+    getRevision,
+    diffRevision,
+    restoreRevision,
+    getRetention,
+    setRetention,
+  }
+}
@@ -5,8 +5,12 @@
  */
 
 import { useAsyncState } from '~/utils/async'
+import { useDeviceId } from './sync/useDeviceId'
 import { useTauriSQL } from './useTauriSQL'
 
+// 同一毫秒内多次记账时区分 operation_id
+let operationCounter = 0
+
 export interface PointsLogEntry {
   id: number
   user_id: number
@@ -36,6 +40,7 @@ export interface UserProfile {
 export function usePointsSystem() {
   const { execute, select } = useTauriSQL()
   const { isLoading, error, runAsync } = useAsyncState()
+  const { getDeviceId } = useDeviceId()
 
   /**
    * 初始化用户档案
@@ -149,8 +154,8 @@ export function usePointsSystem() {
 
   /**
    * 添加积分和经验
-   * Phase 1: operation_id 留空
-   * Phase 3: 生成 operation_id = `${deviceId}_${timestamp}_${counter}`
+   * operation_id 默认为 `${deviceId}_${timestamp}_${counter}`，同步时按它去重；
+   * 多台设备可能各自发放的同一笔奖励（如成就升级）应传入确定性的 operationId，合并后只计一次
    */
   const addPoints = (
     userId: number,
@@ -160,9 +165,11 @@ export function usePointsSystem() {
     exp: number,
     reason: string | null = null,
     achievementKey: string | null = null,
+    operationId: string | null = null,
   ) =>
     runAsync(async () => {
       const now = Date.now()
+      const deviceId = await getDeviceId()
 
       console.log('[积分系统] 准备添加积分:', {
         userId,
@@ -174,16 +181,14 @@ export function usePointsSystem() {
         achievementKey,
       })
 
-      // Phase 1: 生成临时唯一 ID（使用时间戳+随机数）
-      // Phase 3: 改为 `${deviceId}_${timestamp}_${counter}`
-      const operationId = `${userId}_${Date.now()}_${Math.random().toString(36).substr(2, 9)}`
+      const opId = operationId ?? `${deviceId}_${now}_${operationCounter++}`
 
-      // 插入积分日志
+      // 插入积分日志（只追加；同一 operation_id 已存在时忽略，例如已从其他设备同步过来）
       await execute(
-        `INSERT INTO user_points_log 
-        (user_id, operation_id, source_type, source_id, achievement_key, points, exp, reason, created_at)
-        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)`,
-        [userId, operationId, sourceType, sourceId, achievementKey, points, exp, reason, now],
+        `INSERT OR IGNORE INTO user_points_log 
+        (user_id, operation_id, source_type, source_id, achievement_key, points, exp, reason, created_at, device_id)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)`,
+        [userId, opId, sourceType, sourceId, achievementKey, points, exp, reason, now, deviceId],
       )
 
       console.log('[积分系统] 积分已记录到数据库')
@@ -3,9 +3,13 @@
  * 负责收集和更新用户统计数据（可扩展键值对存储）
  * Phase 1: 本地功能
  * Phase 3: 添加 device_id 和 synced_at
+ *
+ * 每台设备只写自己的分片（user_stat_shards），user_stats 为各设备分片的汇总，
+ * 同步时分片按设备合并，汇总结果在每台设备上都相同（见 sync/useCrdtSync.ts）
  */
 
 import { useAsyncState } from '~/utils/async'
+import { useDeviceId } from './sync/useDeviceId'
 import { useTauriSQL } from './useTauriSQL'
 
 export interface UserStat {
@@ -19,6 +23,18 @@ export interface UserStat {
   synced_at: number | null
 }
 
+/**
+ * 某台设备上的一项统计
+ */
+export interface StatShard {
+  user_id: number
+  stat_key: string
+  device_id: string
+  stat_type: StatType
+  stat_value: string
+  updated_at: number
+}
+
 /**
  * 统计指标类型定义
  */
@@ -61,6 +77,47 @@ export const STATS_METRICS = {
 export function useStatsCollector() {
   const { execute, select } = useTauriSQL()
   const { isLoading, error, runAsync } = useAsyncState()
+  const { getDeviceId } = useDeviceId()
+
+  /**
+   * 从各设备的分片重新计算 user_stats 中的一项统计
+   * counter 求和，max/date 取最大值，last 取最近一次写入的值（与服务端 sync_crdt::rebuild_stat 一致）
+   */
+  const rebuildStat = async (userId: number, statKey: string) => {
+    const shards = await select<StatShard[]>(
+      `SELECT stat_type, stat_value, updated_at FROM user_stat_shards
+       WHERE user_id = ? AND stat_key = ?
+       ORDER BY updated_at, stat_value`,
+      [userId, statKey],
+    )
+    const latest = shards[shards.length - 1]
+    if (!latest)
+      return
+
+    const numbers = shards.map(shard => Number(shard.stat_value) || 0)
+    let value: string
+    switch (latest.stat_type) {
+      case 'last':
+        value = latest.stat_value
+        break
+      case 'max':
+      case 'date':
+        value = String(Math.max(...numbers))
+        break
+      default:
+        value = String(numbers.reduce((sum, n) => sum + n, 0))
+    }
+
+    await execute(
+      `INSERT INTO user_stats (user_id, stat_key, stat_value, stat_type, updated_at)
+       VALUES (?, ?, ?, ?, ?)
+       ON CONFLICT(user_id, stat_key) DO UPDATE SET
+         stat_value = excluded.stat_value,
+         stat_type = excluded.stat_type,
+         updated_at = excluded.updated_at`,
+      [userId, statKey, value, latest.stat_type, latest.updated_at],
+    )
+  }
 
   /**
    * 更新统计数据
@@ -78,25 +135,18 @@ export function useStatsCollector() {
     runAsync(async () => {
       const now = Date.now()
       const valueStr = String(value)
+      const deviceId = await getDeviceId()
 
-      // 查询当前值
-      const current = await select<UserStat[]>(
-        'SELECT * FROM user_stats WHERE user_id = ? AND stat_key = ?',
-        [userId, statKey],
+      // 查询本机分片的当前值
+      const current = await select<StatShard[]>(
+        'SELECT * FROM user_stat_shards WHERE user_id = ? AND stat_key = ? AND device_id = ?',
+        [userId, statKey, deviceId],
       )
 
-      if (current.length === 0) {
-        // 插入新记录
-        await execute(
-          `INSERT INTO user_stats (user_id, stat_key, stat_value, stat_type, updated_at)
-          VALUES (?, ?, ?, ?, ?)`,
-          [userId, statKey, valueStr, statType, now],
-        )
-      }
-      else {
+      let newValue = valueStr
+      if (current.length > 0) {
         // 根据类型更新
         const oldValue = current[0]!.stat_value
-        let newValue = valueStr
 
         switch (statType) {
           case 'counter':
@@ -116,13 +166,21 @@ export function useStatsCollector() {
             newValue = Math.max(Number(oldValue), Number(value)).toString()
             break
         }
-
-        await execute(
-          'UPDATE user_stats SET stat_value = ?, updated_at = ? WHERE user_id = ? AND stat_key = ?',
-          [newValue, now, userId, statKey],
-        )
       }
 
+      // synced_at 清空，下次同步时推送
+      await execute(
+        `INSERT INTO user_stat_shards (user_id, stat_key, device_id, stat_type, stat_value, updated_at, synced_at)
+        VALUES (?, ?, ?, ?, ?, ?, NULL)
+        ON CONFLICT(user_id, stat_key, device_id) DO UPDATE SET
+          stat_type = excluded.stat_type,
+          stat_value = excluded.stat_value,
+          updated_at = excluded.updated_at,
+          synced_at = NULL`,
+        [userId, statKey, deviceId, statType, newValue, now],
+      )
+      await rebuildStat(userId, statKey)
+
       return { statKey, value: valueStr }
     }, '更新统计数据失败')
 
@@ -203,6 +261,7 @@ export function useStatsCollector() {
     isLoading,
     error,
     updateStat,
+    rebuildStat,
     incrementCounter,
     updateMax,
     getStat,
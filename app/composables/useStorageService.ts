@@ -1,12 +1,30 @@
-import type { IStorageAdapter, StorageConfig } from '~/lib/storage/types'
+import type { IStorageAdapter, StorageConfig, UploadResult } from '~/lib/storage/types'
 import { COSAdapter } from '~/lib/storage/adapters/cos'
+import { generateUUID } from '~/utils/uuid'
 import { useSettingRepository } from './repositories/useSettingRepository'
+import { ASSET_URL_PREFIX, useAssetTransfer } from './sync/useAssetTransfer'
 import { useCurrentUser } from './useCurrentUser'
 import { useImageCompressor } from './useImageCompressor'
 import { useLog } from './useLog'
 import { useStatsCollector } from './useStatsCollector'
 import { useStorageSelector } from './useStorageSelector'
 
+/** 本机存储（assets/{uuid}），不是图床服务 */
+const LOCAL_PROVIDER = 'local'
+
+interface UploadOptions {
+  provider?: string
+  /** 没有可用图床时保存到本机（需要调用方登记 assets 记录，文件才会同步到其他设备） */
+  allowLocal?: boolean
+}
+
+export interface StoredFile extends UploadResult {
+  /** 存储位置：图床服务商或 local */
+  storage_type: string
+  /** 本机保存时的资源 uuid（assets 记录须使用同一个 uuid） */
+  uuid?: string
+}
+
 // 存储适配器工厂
 class StorageFactory {
   static createAdapter(provider: string): IStorageAdapter {
@@ -29,6 +47,7 @@ export function useStorageService() {
   const { compressImage, loadSettings: loadImageSettings } = useImageCompressor()
   const { incrementCounter } = useStatsCollector()
   const { currentUserId } = useCurrentUser()
+  const { saveLocalAsset } = useAssetTransfer()
 
   /**
    * 获取所有已配置可用的存储服务商
@@ -55,7 +74,7 @@ export function useStorageService() {
   /**
    * 确定要使用的存储服务商
    */
-  const determineProvider = async (options?: { provider?: string }): Promise<string> => {
+  const determineProvider = async (options?: UploadOptions): Promise<string> => {
     if (options?.provider) {
       return options.provider
     }
@@ -67,6 +86,9 @@ export function useStorageService() {
     const available = await getAvailableProviders()
 
     if (available.length === 0) {
+      // 没有图床时保存在本机，文件随局域网同步传到其他设备
+      if (options?.allowLocal)
+        return LOCAL_PROVIDER
       throw new Error('未配置任何可用的图床服务，请先在设置中启用并配置')
     }
 
@@ -87,7 +109,25 @@ export function useStorageService() {
     }
   }
 
-  const uploadFile = async (file: File, options?: { provider?: string }) => {
+  /**
+   * 保存到本机资源目录，以 zotepad-asset:// 引用
+   */
+  const saveLocalFile = async (file: File) => {
+    const uuid = generateUUID()
+    await saveLocalAsset(uuid, new Uint8Array(await file.arrayBuffer()))
+    logger.info(`[Storage] Saved locally. uuid: ${uuid}`)
+    return {
+      url: `${ASSET_URL_PREFIX}${uuid}`,
+      path: `assets/${uuid}`,
+      uuid,
+      storage_type: LOCAL_PROVIDER,
+      size: file.size,
+      filename: file.name,
+      mime_type: file.type,
+    }
+  }
+
+  const uploadFile = async (file: File, options?: UploadOptions): Promise<StoredFile> => {
     const provider = await determineProvider(options)
 
     logger.info(`[Storage] Starting upload. Provider: ${provider}, File: ${file.name}, Size: ${file.size}`)
@@ -116,6 +156,9 @@ export function useStorageService() {
     }
 
     try {
+      if (provider === LOCAL_PROVIDER)
+        return await saveLocalFile(fileToUpload)
+
       // 2. 获取该 Provider 的配置
       const rawSettings = await getSettingsByCategory(provider)
 
@@ -160,6 +203,7 @@ export function useStorageService() {
       logger.info(`[Storage] Upload success. URL: ${result.url}`)
       return {
         ...result,
+        storage_type: provider,
         size: fileToUpload.size,
         filename: fileToUpload.name,
         mime_type: fileToUpload.type,
@@ -171,7 +215,7 @@ export function useStorageService() {
     }
   }
 
-  const uploadFiles = async (files: File[], options?: { provider?: string }) => {
+  const uploadFiles = async (files: File[], options?: UploadOptions) => {
     if (files.length === 0)
       return []
 
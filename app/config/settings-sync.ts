@@ -0,0 +1,48 @@
+/**
+ * 设置项同步策略
+ * 与服务端 sync_settings.rs 保持一致：只有 synced 的键会被推送和接收，
+ * secret（凭据，如 COS 密钥）无论如何都不会离开本机
+ */
+
+export type SettingPolicy = 'synced' | 'device_local' | 'secret'
+
+/** 键名包含这些片段时一律视为凭据 */
+const SECRET_MARKERS = ['secret', 'token', 'password', 'credential', 'api_key']
+
+/** 按键名指定的策略，优先于分类 */
+const KEY_POLICIES: Record<string, SettingPolicy> = {
+  secret_id: 'secret',
+  secret_key: 'secret',
+  default_storage_provider: 'synced',
+  custom_css: 'synced',
+  sidebar_open: 'device_local',
+  last_active_menu: 'device_local',
+  notes_active_tab: 'device_local',
+}
+
+/** 按 category 的默认策略；未列出的分类（包括 general）默认只属于本机 */
+const CATEGORY_POLICIES: Record<string, SettingPolicy> = {
+  cos: 'synced',
+  editor: 'synced',
+  sync: 'device_local',
+  ui: 'device_local',
+}
+
+/**
+ * 计算设置键的同步策略
+ */
+export function settingPolicy(key: string, category?: string | null): SettingPolicy {
+  const lower = key.toLowerCase()
+  if (SECRET_MARKERS.some(marker => lower.includes(marker)))
+    return 'secret'
+  if (key in KEY_POLICIES)
+    return KEY_POLICIES[key]!
+  return (category && CATEGORY_POLICIES[category]) || 'device_local'
+}
+
+/**
+ * 设置项是否参与同步
+ */
+export function isSyncedSetting(key: string, category?: string | null): boolean {
+  return settingPolicy(key, category) === 'synced'
+}
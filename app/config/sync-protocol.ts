@@ -0,0 +1,65 @@
+import { SYNC_TABLES } from './sync-tables'
+
+/**
+ * 同步协议版本与能力
+ * 与服务端 sync_protocol.rs 保持一致：同步前先 POST /v1/handshake，之后的请求走 /v1 路由并带上 X-Sync-Protocol 请求头；
+ * 旧服务端没有 /v1/handshake（404）时退回不带前缀的旧路由
+ */
+
+/** 当前协议版本 */
+export const SYNC_PROTOCOL_VERSION = 1
+
+/** 仍然接受的最低协议版本 */
+export const SYNC_MIN_PROTOCOL_VERSION = 1
+
+/** 声明协议版本的请求头 */
+export const SYNC_PROTOCOL_HEADER = 'X-Sync-Protocol'
+
+/** 本端支持的可选能力 */
+export const SYNC_CAPABILITIES = ['compression', 'stream_pull', 'merkle', 'metadata_pages', 'assets', 'version_vectors', 'crdt', 'scopes']
+
+export type RefusalCode = 'client_too_old' | 'server_too_old' | 'missing_protocol' | 'schema_mismatch'
+
+/** 服务端拒绝同步时返回的原因 */
+export interface SyncRefusal {
+  code: RefusalCode
+  message: string
+  server_protocol_version: number
+  min_protocol_version: number
+}
+
+export interface TableWarning {
+  table: string
+  unknown_fields: string[]
+  missing_fields: string[]
+}
+
+export interface HandshakeResponse {
+  protocol_version: number
+  server_protocol_version: number
+  min_protocol_version: number
+  server_version: string
+  capabilities: string[]
+  warnings: TableWarning[]
+}
+
+/** 握手被拒绝（协议版本不兼容或同步表结构冲突），不应继续同步 */
+export class SyncRefusedError extends Error {
+  constructor(public refusal: SyncRefusal) {
+    super(refusal.message)
+    this.name = 'SyncRefusedError'
+  }
+}
+
+/**
+ * POST /v1/handshake 请求体
+ */
+export function buildHandshakeRequest(appVersion?: string) {
+  return {
+    protocol_version: SYNC_PROTOCOL_VERSION,
+    min_protocol_version: SYNC_MIN_PROTOCOL_VERSION,
+    app_version: appVersion || null,
+    tables: Object.values(SYNC_TABLES).map(t => ({ name: t.name, primary_key: t.primaryKey, fields: t.fields })),
+    capabilities: SYNC_CAPABILITIES,
+  }
+}
@@ -1,3 +1,5 @@
+import { isSyncedSetting } from './settings-sync'
+
 /**
  * 同步表配置
  * 定义了所有需要同步的数据表的元信息
@@ -22,13 +24,13 @@ export interface SyncableTable {
 
 /**
  * 所有可同步的表配置
- * 排除了 Settings（敏感信息）
+ * settings 只同步 synced 策略的键（见 settings-sync.ts），凭据永不同步
  */
 export const SYNC_TABLES: Record<string, SyncableTable> = {
   notes: {
     name: 'notes',
     primaryKey: 'uuid',
-    fields: ['uuid', 'title', 'content', 'tags', 'created_at', 'updated_at', 'deleted_at', 'version'],
+    fields: ['uuid', 'title', 'content', 'tags', 'created_at', 'updated_at', 'deleted_at', 'version', 'hlc'],
     jsonFields: ['tags'],
     hasVersion: true,
     hasSoftDelete: true,
@@ -37,7 +39,7 @@ export const SYNC_TABLES: Record<string, SyncableTable> = {
   moments: {
     name: 'moments',
     primaryKey: 'uuid',
-    fields: ['uuid', 'content', 'images', 'tags', 'created_at', 'updated_at', 'deleted_at', 'version'],
+    fields: ['uuid', 'content', 'images', 'tags', 'created_at', 'updated_at', 'deleted_at', 'version', 'hlc'],
     jsonFields: ['images', 'tags'],
     hasVersion: true,
     hasSoftDelete: true,
@@ -46,7 +48,7 @@ export const SYNC_TABLES: Record<string, SyncableTable> = {
   assets: {
     name: 'assets',
     primaryKey: 'uuid',
-    fields: ['uuid', 'url', 'path', 'filename', 'size', 'mime_type', 'storage_type', 'created_at', 'updated_at', 'deleted_at', 'version'],
+    fields: ['uuid', 'url', 'path', 'filename', 'size', 'mime_type', 'storage_type', 'created_at', 'updated_at', 'deleted_at', 'version', 'hlc'],
     jsonFields: [],
     hasVersion: true,
     hasSoftDelete: true,
@@ -55,7 +57,7 @@ export const SYNC_TABLES: Record<string, SyncableTable> = {
   workflows: {
     name: 'workflows',
     primaryKey: 'uuid',
-    fields: ['uuid', 'name', 'description', 'steps', 'schema_id', 'type', 'created_at', 'updated_at', 'deleted_at', 'version'],
+    fields: ['uuid', 'name', 'description', 'steps', 'schema_id', 'type', 'created_at', 'updated_at', 'deleted_at', 'version', 'hlc'],
     jsonFields: ['steps'],
     hasVersion: true,
     hasSoftDelete: true,
@@ -64,12 +66,21 @@ export const SYNC_TABLES: Record<string, SyncableTable> = {
   workflow_schemas: {
     name: 'workflow_schemas',
     primaryKey: 'uuid',
-    fields: ['uuid', 'name', 'description', 'fields', 'created_at', 'updated_at', 'deleted_at', 'version'],
+    fields: ['uuid', 'name', 'description', 'fields', 'created_at', 'updated_at', 'deleted_at', 'version', 'hlc'],
     jsonFields: ['fields'],
     hasVersion: true,
     hasSoftDelete: true,
     hasUpdatedAt: true,
   },
+  settings: {
+    name: 'settings',
+    primaryKey: 'key',
+    fields: ['key', 'value', 'category', 'created_at', 'updated_at', 'deleted_at', 'version', 'hlc'],
+    jsonFields: [],
+    hasVersion: true,
+    hasSoftDelete: true,
+    hasUpdatedAt: true,
+  },
 }
 
 /**
@@ -79,6 +90,19 @@ export function getSyncTableNames(): string[] {
   return Object.keys(SYNC_TABLES)
 }
 
+/**
+ * 表中的某条记录是否参与同步（系统工作流、非 synced 策略的设置项除外）
+ */
+export function isSyncableRecord(tableName: string, data: Record<string, any> | null | undefined): boolean {
+  if (!data)
+    return true
+  if (tableName === 'workflows')
+    return !(typeof data.type === 'string' && data.type.startsWith('system:'))
+  if (tableName === 'settings')
+    return isSyncedSetting(String(data.key ?? ''), data.category)
+  return true
+}
+
 /**
  * 检查表是否可同步
  */
@@ -92,3 +116,46 @@ export function isSyncable(tableName: string): boolean {
 export function getTableConfig(tableName: string): SyncableTable | null {
   return SYNC_TABLES[tableName] || null
 }
+
+/**
+ * 服务端 /state 返回的同步表结构
+ */
+export interface ServerTableSchema {
+  name: string
+  primary_key: string
+  fields: string[]
+  json_fields: string[]
+  merge_fields: string[]
+  columns: { name: string, type: string }[]
+  errors: { kind: string, [key: string]: unknown }[]
+}
+
+/**
+ * 核对服务端注册的表结构与本地配置，返回不一致之处（为空表示一致）
+ */
+export function diffServerSchema(schema: ServerTableSchema[]): string[] {
+  const problems: string[] = []
+  const serverTables = new Map(schema.map(t => [t.name, t]))
+
+  for (const local of Object.values(SYNC_TABLES)) {
+    const remote = serverTables.get(local.name)
+    if (!remote) {
+      problems.push(`服务端未注册表 ${local.name}`)
+      continue
+    }
+    if (remote.primary_key !== local.primaryKey)
+      problems.push(`${local.name} 主键不一致: 本地 ${local.primaryKey}, 服务端 ${remote.primary_key}`)
+
+    const missing = local.fields.filter(f => !remote.fields.includes(f))
+    if (missing.length)
+      problems.push(`${local.name} 服务端缺少字段: ${missing.join(', ')}`)
+    const extra = remote.fields.filter(f => !local.fields.includes(f))
+    if (extra.length)
+      problems.push(`${local.name} 本地缺少字段: ${extra.join(', ')}`)
+
+    for (const error of remote.errors)
+      problems.push(`${local.name} 服务端表结构错误: ${error.kind}`)
+  }
+
+  return problems
+}
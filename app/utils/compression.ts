@@ -0,0 +1,23 @@
+/**
+ * 同步请求体压缩
+ * 较大的 JSON 请求体用 gzip 压缩后发送（服务端按 Content-Encoding 解压）
+ * 降级方案：运行环境不支持 CompressionStream 时原样发送
+ */
+
+/** 超过该大小（字节）才压缩，小请求压缩收益不大 */
+const COMPRESS_THRESHOLD = 16 * 1024
+
+export async function encodeJsonBody(
+  payload: unknown,
+  headers: Record<string, string>,
+): Promise<{ body: BodyInit, headers: Record<string, string> }> {
+  const json = JSON.stringify(payload)
+  const jsonHeaders = { ...headers, 'Content-Type': 'application/json' }
+
+  if (json.length < COMPRESS_THRESHOLD || typeof CompressionStream === 'undefined')
+    return { body: json, headers: jsonHeaders }
+
+  const stream = new Blob([json]).stream().pipeThrough(new CompressionStream('gzip'))
+  const body = await new Response(stream).arrayBuffer()
+  return { body, headers: { ...jsonHeaders, 'Content-Encoding': 'gzip' } }
+}
@@ -0,0 +1,108 @@
+/**
+ * 混合逻辑时钟（Hybrid Logical Clock）
+ * 与桌面端 src-tauri/src/hlc.rs 使用相同的编码：`{physical:013}:{counter:05}:{device_id}`
+ * 定宽编码保证字符串字典序与时间戳顺序一致
+ */
+
+/** 计数器上限（编码宽度为 5 位），溢出时借位到物理时间 */
+const MAX_COUNTER = 99_999
+
+/** 远程时间戳允许超前本机时钟的最大值（与服务端 MAX_DRIFT_MS 一致） */
+export const MAX_DRIFT_MS = 10 * 60 * 1000
+
+export interface Hlc {
+  physical: number
+  counter: number
+  deviceId: string
+}
+
+export function encodeHlc(hlc: Hlc): string {
+  return `${String(hlc.physical).padStart(13, '0')}:${String(hlc.counter).padStart(5, '0')}:${hlc.deviceId}`
+}
+
+/** 解析 encodeHlc 生成的字符串，格式不对时返回 null */
+export function parseHlc(value: string | null | undefined): Hlc | null {
+  const match = /^(\d+):(\d+):?(.*)$/.exec((value || '').trim())
+  if (!match)
+    return null
+  return { physical: Number(match[1]), counter: Number(match[2]), deviceId: match[3] || '' }
+}
+
+/**
+ * 记录的有效时间戳：已存储的 HLC，没有时由 updated_at 推导（设备 ID 为空串）
+ * 与服务端 Hlc::effective 保持一致
+ */
+export function effectiveHlc(hlc: string | null | undefined, updatedAt: string | null | undefined): Hlc {
+  const stamped = parseHlc(hlc)
+  if (stamped)
+    return stamped
+  const physical = updatedAt ? Date.parse(updatedAt) : Number.NaN
+  return { physical: Number.isNaN(physical) ? 0 : physical, counter: 0, deviceId: '' }
+}
+
+/** 比较顺序：物理时间 → 计数器 → 设备 ID */
+export function compareHlc(a: Hlc, b: Hlc): number {
+  if (a.physical !== b.physical)
+    return a.physical - b.physical
+  if (a.counter !== b.counter)
+    return a.counter - b.counter
+  return a.deviceId < b.deviceId ? -1 : a.deviceId > b.deviceId ? 1 : 0
+}
+
+/** 本机时钟 */
+export class HybridClock {
+  private lastPhysical = 0
+  private lastCounter = 0
+
+  constructor(readonly deviceId: string) {}
+
+  /** 为本地写入生成新的时间戳 */
+  now(): Hlc {
+    const wall = Date.now()
+    if (wall > this.lastPhysical) {
+      this.lastPhysical = wall
+      this.lastCounter = 0
+    }
+    else {
+      this.bump()
+    }
+    return { physical: this.lastPhysical, counter: this.lastCounter, deviceId: this.deviceId }
+  }
+
+  /**
+   * 收到远程时间戳时推进本地时钟，保证之后的本地写入排在其后
+   * 超前本机墙钟超过 MAX_DRIFT_MS 时不推进并返回 false
+   */
+  observe(remote: Hlc): boolean {
+    if (remote.physical - Date.now() > MAX_DRIFT_MS) {
+      console.warn(`[HLC] 远程时钟超前本地 ${remote.physical - Date.now()} ms，忽略 (device=${remote.deviceId})`)
+      return false
+    }
+    this.advance(remote)
+    return true
+  }
+
+  /** 无条件推进本地时钟到不小于 remote（用于本机已存储的时间戳） */
+  advance(remote: Hlc): void {
+    const physical = Math.max(Date.now(), this.lastPhysical, remote.physical)
+    let counter = 0
+    if (physical === this.lastPhysical && physical === remote.physical)
+      counter = Math.max(this.lastCounter, remote.counter)
+    else if (physical === this.lastPhysical)
+      counter = this.lastCounter
+    else if (physical === remote.physical)
+      counter = remote.counter
+    this.lastPhysical = physical
+    this.lastCounter = counter
+  }
+
+  private bump(): void {
+    if (this.lastCounter >= MAX_COUNTER) {
+      this.lastPhysical += 1
+      this.lastCounter = 0
+    }
+    else {
+      this.lastCounter += 1
+    }
+  }
+}
@@ -0,0 +1,171 @@
+/**
+ * 范围哈希对账（与服务端 src-tauri/src/sync_merkle.rs 的分桶和哈希规则一致）
+ * 两端把未删除记录按主键哈希的十六进制前缀分桶，客户端只向下展开哈希不同的桶，
+ * 哈希相同的桶中的记录两端完全一致，不需要传输元数据
+ *
+ * 两端的哈希和桶哈希由 tests/fixtures/merkle-parity.json 固定（pnpm test 与 cargo test 都会检查）
+ */
+
+/** 64 位无符号整数，[高 32 位, 低 32 位] */
+type U64 = [number, number]
+
+export interface MerkleRecord {
+  uuid: string
+  updated_at: string
+}
+
+export interface MerkleBucket {
+  prefix: string
+  count: number
+  hash: string
+}
+
+export interface MerkleNode<T> extends MerkleBucket {
+  children: MerkleBucket[]
+  /** 记录较少的桶直接返回记录的元数据 */
+  leaves: T[] | null
+}
+
+const HEX = '0123456789abcdef'
+const encoder = new TextEncoder()
+
+/** 64 位乘法（取低 64 位），按 16 位分段计算以避免超出 JS 的安全整数范围 */
+function mul64(a: U64, b: U64): U64 {
+  const a0 = a[1] & 0xFFFF
+  const a1 = a[1] >>> 16
+  const a2 = a[0] & 0xFFFF
+  const a3 = a[0] >>> 16
+  const b0 = b[1] & 0xFFFF
+  const b1 = b[1] >>> 16
+  const b2 = b[0] & 0xFFFF
+  const b3 = b[0] >>> 16
+
+  let c = a0 * b0
+  const r0 = c % 0x10000
+  c = Math.floor(c / 0x10000) + a1 * b0 + a0 * b1
+  const r1 = c % 0x10000
+  c = Math.floor(c / 0x10000) + a2 * b0 + a1 * b1 + a0 * b2
+  const r2 = c % 0x10000
+  c = Math.floor(c / 0x10000) + a3 * b0 + a2 * b1 + a1 * b2 + a0 * b3
+  const r3 = c % 0x10000
+  return [(r3 * 0x10000 + r2) >>> 0, (r1 * 0x10000 + r0) >>> 0]
+}
+
+/** h ^= h >> 33 */
+function xorShift33(h: U64): U64 {
+  return [h[0], (h[1] ^ (h[0] >>> 1)) >>> 0]
+}
+
+function toHex(h: U64): string {
+  return h[0].toString(16).padStart(8, '0') + h[1].toString(16).padStart(8, '0')
+}
+
+/** FNV-1a 64 位哈希，再经 fmix64 打散 */
+function hash64Raw(text: string): U64 {
+  let h: U64 = [0xCBF29CE4, 0x84222325]
+  for (const byte of encoder.encode(text)) {
+    h = mul64([h[0], (h[1] ^ byte) >>> 0], [0x00000100, 0x000001B3])
+  }
+  h = mul64(xorShift33(h), [0xFF51AFD7, 0xED558CCD])
+  h = mul64(xorShift33(h), [0xC4CEB9FE, 0x1A85EC53])
+  return xorShift33(h)
+}
+
+/** 64 位哈希的 16 位十六进制表示 */
+export function hash64(text: string): string {
+  return toHex(hash64Raw(text))
+}
+
+/** 本地记录的哈希索引 */
+export class MerkleIndex<T extends MerkleRecord> {
+  private entries: Array<{ key: string, digest: U64, record: T }>
+
+  constructor(records: T[]) {
+    this.entries = records
+      .map(record => ({
+        key: hash64(record.uuid),
+        digest: hash64Raw(`${record.uuid}\n${record.updated_at ?? ''}`),
+        record,
+      }))
+      .sort((a, b) => (a.key < b.key ? -1 : a.key > b.key ? 1 : 0))
+  }
+
+  private range(prefix: string) {
+    let lo = 0
+    let hi = this.entries.length
+    while (lo < hi) {
+      const mid = (lo + hi) >>> 1
+      if (this.entries[mid]!.key < prefix)
+        lo = mid + 1
+      else
+        hi = mid
+    }
+    let end = lo
+    while (end < this.entries.length && this.entries[end]!.key.startsWith(prefix))
+      end++
+    return this.entries.slice(lo, end)
+  }
+
+  summary(prefix: string): { count: number, hash: string } {
+    const range = this.range(prefix)
+    let hi = 0
+    let lo = 0
+    for (const { digest } of range) {
+      hi ^= digest[0]
+      lo ^= digest[1]
+    }
+    return { count: range.length, hash: toHex([hi >>> 0, lo >>> 0]) }
+  }
+
+  records(prefix: string): T[] {
+    return this.range(prefix).map(e => e.record)
+  }
+}
+
+/**
+ * 与远程逐层比较桶哈希，返回两端哈希不同的桶中的记录
+ * @param fetchNodes 请求远程展开指定的桶
+ */
+export async function reconcile<T extends MerkleRecord, R>(
+  index: MerkleIndex<T>,
+  fetchNodes: (prefixes: string[]) => Promise<MerkleNode<R>[]>,
+): Promise<{ local: T[], remote: R[], rounds: number }> {
+  const local: T[] = []
+  const remote: R[] = []
+  let pending = ['']
+  let rounds = 0
+
+  while (pending.length > 0) {
+    const nodes = await fetchNodes(pending)
+    rounds++
+    const next: string[] = []
+
+    for (const node of nodes) {
+      const mine = index.summary(node.prefix)
+      if (mine.count === node.count && mine.hash === node.hash)
+        continue
+
+      if (node.leaves) {
+        remote.push(...node.leaves)
+        local.push(...index.records(node.prefix))
+        continue
+      }
+
+      for (const c of HEX) {
+        const prefix = node.prefix + c
+        const child = index.summary(prefix)
+        const theirs = node.children.find(b => b.prefix === prefix)
+        if (!theirs) {
+          // 远程没有这个桶：本地记录都是本地独有的
+          local.push(...index.records(prefix))
+        }
+        else if (theirs.count !== child.count || theirs.hash !== child.hash) {
+          next.push(prefix)
+        }
+      }
+    }
+    pending = next
+  }
+
+  return { local, remote, rounds }
+}
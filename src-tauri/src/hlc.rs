@@ -0,0 +1,262 @@
+//! 混合逻辑时钟（Hybrid Logical Clock）
+//! 物理时间（毫秒）+ 逻辑计数器 + 设备 ID，为跨设备写入提供确定性的全序
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Mutex;
+
+/// 计数器上限（编码宽度为 5 位），溢出时借位到物理时间
+const MAX_COUNTER: u32 = 99_999;
+
+/// 远程时间戳允许超前本机时钟的最大值；超出的时间戳不会被观察，对应的变更被拒绝，
+/// 避免一台时钟错误的设备把所有设备的时钟永久推向未来
+pub const MAX_DRIFT_MS: i64 = 10 * 60 * 1000;
+
+/// 一个 HLC 时间戳
+///
+/// 比较顺序：物理时间 → 计数器 → 设备 ID，保证任意两个时间戳都可比较且结果在各端一致
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Hlc {
+    pub physical: i64,
+    pub counter: u32,
+    pub device_id: String,
+}
+
+impl Hlc {
+    /// 编码为定宽字符串：`{physical:013}:{counter:05}:{device_id}`
+    /// 定宽保证字符串的字典序与 `Ord` 一致，可直接用于 SQL 的 MAX/ORDER BY
+    pub fn encode(&self) -> String {
+        format!("{:013}:{:05}:{}", self.physical, self.counter, self.device_id)
+    }
+
+    /// 解析 `encode` 生成的字符串
+    pub fn parse(s: &str) -> Option<Hlc> {
+        let mut parts = s.trim().splitn(3, ':');
+        let physical = parts.next()?.parse::<i64>().ok()?;
+        let counter = parts.next()?.parse::<u32>().ok()?;
+        let device_id = parts.next().unwrap_or("").to_string();
+        Some(Hlc { physical, counter, device_id })
+    }
+
+    /// 从 updated_at 推导时间戳（兼容没有 HLC 的旧数据和旧客户端）
+    /// 设备 ID 为空串，因此同一毫秒内真正打过 HLC 的写入总是更大
+    pub fn from_timestamp(ts: &str) -> Hlc {
+        Hlc {
+            physical: parse_timestamp_ms(ts).unwrap_or(0),
+            counter: 0,
+            device_id: String::new(),
+        }
+    }
+
+    /// 记录的有效时间戳：已存储的 HLC，没有时由 updated_at 推导
+    ///
+    /// 前端的每次写入都会重打 HLC，因此 HLC 存在时不再参考 updated_at，
+    /// 否则一台墙钟超前的设备写下的 updated_at 会绕过 HLC 的全序
+    pub fn effective(hlc: Option<&str>, updated_at: &str) -> Hlc {
+        hlc.and_then(Hlc::parse)
+            .unwrap_or_else(|| Hlc::from_timestamp(updated_at))
+    }
+}
+
+/// 解析各种时间格式为 Unix 毫秒
+/// 支持 RFC 3339（`now_iso()` / 前端 `toISOString()`）、
+/// SQLite `CURRENT_TIMESTAMP`（`YYYY-MM-DD HH:MM:SS`，UTC）以及纯数字的秒/毫秒
+pub fn parse_timestamp_ms(ts: &str) -> Option<i64> {
+    let ts = ts.trim();
+    if ts.is_empty() {
+        return None;
+    }
+
+    if let Ok(dt) = DateTime::parse_from_rfc3339(ts) {
+        return Some(dt.timestamp_millis());
+    }
+
+    for fmt in ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S"] {
+        if let Ok(naive) = NaiveDateTime::parse_from_str(ts, fmt) {
+            return Some(naive.and_utc().timestamp_millis());
+        }
+    }
+
+    if let Ok(n) = ts.parse::<i64>() {
+        // 10 位及以下视为秒
+        return Some(if n.abs() < 100_000_000_000 { n * 1000 } else { n });
+    }
+
+    None
+}
+
+/// 本机的混合逻辑时钟
+pub struct HybridClock {
+    device_id: String,
+    last: Mutex<(i64, u32)>,
+}
+
+impl HybridClock {
+    pub fn new(device_id: String) -> Self {
+        Self {
+            device_id,
+            last: Mutex::new((0, 0)),
+        }
+    }
+
+    /// 从数据库加载：读取（或生成）本机设备 ID，并用已存储的最大 HLC 初始化时钟，
+    /// 保证重启后生成的时间戳不会回退
+    pub fn load(conn: &Connection, tables: &[&str]) -> Self {
+        let device_id = load_or_create_device_id(conn);
+        let clock = Self::new(device_id);
+
+        let mut max_seen: Option<Hlc> = None;
+        for table in tables {
+            let query = format!("SELECT MAX(hlc) FROM {} WHERE hlc IS NOT NULL", table);
+            let value: Option<String> = conn
+                .query_row(&query, [], |row| row.get(0))
+                .ok()
+                .flatten();
+            if let Some(hlc) = value.as_deref().and_then(Hlc::parse) {
+                if max_seen.as_ref().is_none_or(|m| hlc > *m) {
+                    max_seen = Some(hlc);
+                }
+            }
+        }
+        // 本机已存储的时间戳不受漂移上限约束，保证重启后不回退
+        if let Some(hlc) = max_seen {
+            clock.advance(&hlc);
+        }
+
+        clock
+    }
+
+    /// 复制当前状态的独立时钟（预演用，不影响本机时钟）
+    pub fn snapshot(&self) -> HybridClock {
+        let last = *self.last.lock().unwrap_or_else(|e| e.into_inner());
+        HybridClock {
+            device_id: self.device_id.clone(),
+            last: Mutex::new(last),
+        }
+    }
+
+    pub fn device_id(&self) -> &str {
+        &self.device_id
+    }
+
+    /// 为本地写入生成新的时间戳
+    pub fn now(&self) -> Hlc {
+        let wall = Utc::now().timestamp_millis();
+        let mut last = self.last.lock().unwrap_or_else(|e| e.into_inner());
+
+        let (physical, counter) = if wall > last.0 {
+            (wall, 0)
+        } else {
+            bump(last.0, last.1)
+        };
+        *last = (physical, counter);
+
+        Hlc {
+            physical,
+            counter,
+            device_id: self.device_id.clone(),
+        }
+    }
+
+    /// 收到远程时间戳时推进本地时钟，保证之后的本地写入排在其后
+    ///
+    /// 远程时间戳超前本机墙钟超过 `MAX_DRIFT_MS` 时不推进并返回 false，调用方应拒绝该变更
+    pub fn observe(&self, remote: &Hlc) -> bool {
+        let drift = remote.physical - Utc::now().timestamp_millis();
+        if drift > MAX_DRIFT_MS {
+            log::warn!("[HLC] 远程时钟超前本地 {} ms，拒绝 (device={})", drift, remote.device_id);
+            return false;
+        }
+        self.advance(remote);
+        true
+    }
+
+    /// 无条件推进本地时钟到不小于 `remote`（用于本机已存储的时间戳）
+    pub fn advance(&self, remote: &Hlc) {
+        let wall = Utc::now().timestamp_millis();
+        let mut last = self.last.lock().unwrap_or_else(|e| e.into_inner());
+        let physical = wall.max(last.0).max(remote.physical);
+
+        // 只推进不生成：下一次 now() 会在此基础上递增，严格大于 remote
+        let counter = if physical == last.0 && physical == remote.physical {
+            last.1.max(remote.counter)
+        } else if physical == last.0 {
+            last.1
+        } else if physical == remote.physical {
+            remote.counter
+        } else {
+            0
+        };
+
+        *last = (physical, counter);
+    }
+}
+
+/// 逻辑计数器 +1，溢出时推进物理时间
+fn bump(physical: i64, counter: u32) -> (i64, u32) {
+    if counter >= MAX_COUNTER {
+        (physical + 1, 0)
+    } else {
+        (physical, counter + 1)
+    }
+}
+
+/// 读取 sync_meta 中的本机设备 ID，不存在时生成并写入
+fn load_or_create_device_id(conn: &Connection) -> String {
+    let existing: Option<String> = conn
+        .query_row("SELECT value FROM sync_meta WHERE key = 'device_id'", [], |row| row.get(0))
+        .optional()
+        .unwrap_or_else(|e| {
+            log::warn!("[HLC] 读取 device_id 失败: {}", e);
+            None
+        });
+
+    if let Some(id) = existing.filter(|s| !s.trim().is_empty()) {
+        return id;
+    }
+
+    let id = generate_device_id();
+    if let Err(e) = conn.execute(
+        "INSERT OR REPLACE INTO sync_meta (key, value, updated_at) VALUES ('device_id', ?1, ?2)",
+        params![id, Utc::now().to_rfc3339()],
+    ) {
+        log::warn!("[HLC] 保存 device_id 失败，本次运行使用临时 ID: {}", e);
+    }
+    id
+}
+
+/// 生成随机设备 ID（16 位十六进制）
+fn generate_device_id() -> String {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_i64(Utc::now().timestamp_nanos_opt().unwrap_or_default());
+    hasher.write_u32(std::process::id());
+    format!("{:016x}", hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stamped_hlc_wins_over_updated_at() {
+        let stamped = "0000000001000:00003:dev";
+        let effective = Hlc::effective(Some(stamped), "2099-01-01T00:00:00Z");
+        assert_eq!(effective.encode(), stamped);
+        assert_eq!(Hlc::effective(None, "1970-01-01T00:00:01Z").physical, 1000);
+    }
+
+    #[test]
+    fn observe_rejects_far_future_timestamps() {
+        let clock = HybridClock::new("local".to_string());
+        let wall = Utc::now().timestamp_millis();
+        let future = Hlc { physical: wall + MAX_DRIFT_MS * 2, counter: 0, device_id: "peer".to_string() };
+        assert!(!clock.observe(&future));
+        assert!(clock.now() < future);
+
+        let near = Hlc { physical: wall + 1000, counter: 7, device_id: "peer".to_string() };
+        assert!(clock.observe(&near));
+        assert!(clock.now() > near);
+    }
+}
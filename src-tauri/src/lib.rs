@@ -93,18 +93,110 @@ async fn compress_image(buffer: Vec<u8>, quality: u8, target_format: Option<Stri
 #[cfg(not(mobile))]
 mod sync_engine;
 
+// 混合逻辑时钟（同步冲突排序）
+#[cfg(not(mobile))]
+mod hlc;
+
+// 三方文本合并
+#[cfg(not(mobile))]
+mod merge;
+
+// 同步冲突队列
+#[cfg(not(mobile))]
+mod sync_conflicts;
+
+// 同步表注册与表结构校验
+#[cfg(not(mobile))]
+mod sync_registry;
+
+// 触发器维护的同步变更日志
+#[cfg(not(mobile))]
+mod sync_changelog;
+
+// 墓碑回收与低水位
+#[cfg(not(mobile))]
+mod sync_gc;
+
+// 持久化版本号序列
+#[cfg(not(mobile))]
+mod sync_sequence;
+
+// 同步字段编解码
+#[cfg(not(mobile))]
+mod sync_codec;
+
+// 回收站
+#[cfg(not(mobile))]
+mod sync_trash;
+
+// 设置项同步策略
+#[cfg(not(mobile))]
+mod sync_settings;
+
+// 成就数据的无冲突同步（CRDT）
+#[cfg(not(mobile))]
+mod sync_crdt;
+
+// 同步设备登记
+#[cfg(not(mobile))]
+mod sync_devices;
+
+// 原生同步客户端（桌面端之间互相同步）
+#[cfg(not(mobile))]
+mod sync_client;
+
+// 版本向量（多主同步）
+#[cfg(not(mobile))]
+mod sync_vector;
+
+// 流式拉取（NDJSON）
+#[cfg(not(mobile))]
+mod sync_stream;
+
+// 范围哈希对账（替代全量元数据）
+#[cfg(not(mobile))]
+mod sync_merkle;
+
+// 资源文件传输（局域网内设备之间）
+#[cfg(not(mobile))]
+mod sync_assets;
+
+// 同步协议版本与能力协商
+#[cfg(not(mobile))]
+mod sync_protocol;
+
+// 同步会话日志
+#[cfg(not(mobile))]
+mod sync_sessions;
+
+// 同步预览（dry run）
+#[cfg(not(mobile))]
+mod sync_preview;
+
+// 笔记历史版本
+#[cfg(not(mobile))]
+mod note_revisions;
+
+// 设备同步范围
+#[cfg(not(mobile))]
+mod sync_scopes;
+
+// 测试辅助（内存数据库）
+#[cfg(all(test, not(mobile)))]
+mod test_support;
+
 // HTTP Server 只在桌面端编译
 #[cfg(not(mobile))]
 use axum::{
-    extract::{Query, State},
+    extract::{Path, Query, State},
     http::StatusCode,
-    response::Json,
+    response::{IntoResponse, Json},
     routing::{get, post},
     Router,
 };
 use serde::{Deserialize, Serialize};
 #[cfg(not(mobile))]
-use std::sync::{atomic::{AtomicI64, Ordering}, Arc};
+use std::sync::{atomic::{AtomicBool, Ordering}, Arc};
 #[cfg(not(mobile))]
 use rusqlite::Connection;
 #[cfg(not(mobile))]
@@ -114,13 +206,83 @@ use tokio::sync::Mutex;
 #[cfg(not(mobile))]
 use tower_http::cors::CorsLayer;
 #[cfg(not(mobile))]
+use tower_http::compression::CompressionLayer;
+#[cfg(not(mobile))]
+use tower_http::decompression::RequestDecompressionLayer;
+#[cfg(not(mobile))]
 use crate::sync_engine::SyncChange;
 
+// 同步运行时：HTTP 服务与 Tauri 命令共享的 HLC（版本号由数据库序列分配）
+#[cfg(not(mobile))]
+struct SyncRuntime {
+    clock: hlc::HybridClock,  // 本机混合逻辑时钟
+    changelog_ready: AtomicBool,  // 变更日志触发器是否已安装
+    merkle_cache: sync_merkle::MerkleCache,  // /merkle 的哈希索引缓存
+}
+
+#[cfg(not(mobile))]
+impl SyncRuntime {
+    // 读取本机设备 ID 与已存储的最大 HLC，并校验注册的同步表结构
+    fn load(app_handle: &AppHandle) -> Self {
+        match open_db(app_handle) {
+            Ok(mut conn) => {
+                if let Err(errors) = sync_registry::registry().validate(&conn) {
+                    for e in errors {
+                        log::error!("[SyncRegistry] 表结构校验失败: {}", e);
+                    }
+                }
+                let runtime = SyncRuntime {
+                    clock: hlc::HybridClock::load(&conn, &sync_engine::table_names()),
+                    changelog_ready: AtomicBool::new(false),
+                    merkle_cache: sync_merkle::MerkleCache::default(),
+                };
+                runtime.ensure_changelog(&mut conn);
+
+                // 回收过期墓碑
+                let retention_days = sync_gc::retention_days(&conn);
+                match sync_gc::purge_tombstones(&mut conn, retention_days) {
+                    Ok(report) if report.purged_rows > 0 || report.purged_log_entries > 0 => log::info!(
+                        "[SyncGC] 清除 {} 条软删除记录、{} 条变更日志，低水位版本号 {}",
+                        report.purged_rows, report.purged_log_entries, report.low_water_mark
+                    ),
+                    Ok(_) => {}
+                    Err(e) => log::warn!("[SyncGC] 墓碑回收失败: {}", e),
+                }
+
+                // 按保留策略清理笔记历史版本
+                let retention = note_revisions::Retention::load(&conn);
+                match note_revisions::compact(&mut conn, &retention) {
+                    Ok(report) if report.removed() > 0 => log::info!("[NoteRevisions] 清理 {} 个历史版本", report.removed()),
+                    Ok(_) => {}
+                    Err(e) => log::warn!("[NoteRevisions] 清理历史版本失败: {}", e),
+                }
+                runtime
+            }
+            Err(_) => SyncRuntime {
+                clock: hlc::HybridClock::new(format!("ephemeral-{}", std::process::id())),
+                changelog_ready: AtomicBool::new(false),
+                merkle_cache: sync_merkle::MerkleCache::default(),
+            },
+        }
+    }
+
+    // 安装变更日志与版本号触发器；首次启动时迁移可能还未执行，失败后在下一次同步请求时重试
+    fn ensure_changelog(&self, conn: &mut Connection) {
+        if self.changelog_ready.load(Ordering::Acquire) {
+            return;
+        }
+        match sync_changelog::install(conn).and_then(|_| sync_crdt::install(conn)) {
+            Ok(()) => self.changelog_ready.store(true, Ordering::Release),
+            Err(e) => log::warn!("[SyncChangelog] 安装触发器失败，稍后重试: {}", e),
+        }
+    }
+}
+
 // HTTP Server 状态，持有 Tauri AppHandle
 #[cfg(not(mobile))]
 struct HttpServerState {
     app_handle: AppHandle,
-    version_counter: AtomicI64,  // 全局版本号计数器
+    sync: Arc<SyncRuntime>,
     token: String,
 }
 
@@ -136,35 +298,97 @@ struct ApiResponse<T> {
 // ============ Sync 数据结构 ============
 
 #[cfg(not(mobile))]
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Debug, Clone)]
 struct SyncStateData {
     version: i64,  // 服务器当前最大版本号
     server_version: String,  // 服务器软件版本
     paired: bool,
+    schema: Vec<sync_registry::TableSchema>,  // 注册的同步表结构及校验结果，供客户端核对
+    low_water_mark: i64,  // 已回收墓碑的最大版本号，早于它的增量游标需要全量对账
+    replica_id: String,  // 本机副本 ID（版本向量中的键）
+    knowledge: sync_vector::VersionVector,  // 本机已完整接收到的各副本版本号
+    protocol_version: u32,  // 同步协议版本（/v1 路由）
+    capabilities: Vec<String>,  // 支持的可选能力，详见 /v1/handshake
 }
 
 #[cfg(not(mobile))]
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct PullResponse {
     changes: Vec<SyncChange>,
-    next_version: Option<i64>,  // 分页时的下一个版本号
+    next_version: Option<i64>,  // 分页时的下一个版本号（单表拉取）
+    next_cursor: Option<String>,  // 跨表拉取的下一页游标，为空表示已拉取完
     server_version: i64,  // 服务器当前最大版本号
+    resync_required: bool,  // since_version 早于低水位：墓碑已回收，客户端需要全量对账
+    low_water_mark: i64,
+    scoped: bool,  // 按请求方设备的同步范围过滤过：对端的 knowledge 不能代表本机持有的数据
 }
 
 #[cfg(not(mobile))]
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct PullQuery {
-    table: Option<String>,  // 新增：指定要拉取的表
+    table: Option<String>,  // 指定要拉取的表；为空时跨表拉取所有同步表
     since_version: Option<i64>,  // 客户端上次同步的版本号
+    cursor: Option<String>,  // 跨表拉取：上一页返回的 next_cursor（优先于 since_version）
+    limit: Option<usize>,
+    knowledge: Option<String>,  // 客户端的 knowledge（版本向量，URL 安全 base64），已见过的记录不再返回
+    dry_run: Option<bool>,  // true：只返回按表统计的预览，不返回变更、不更新拉取进度
+}
+
+#[cfg(not(mobile))]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct PushQuery {
+    dry_run: Option<bool>,  // 与请求体中的 dry_run 相同
+}
+
+#[cfg(not(mobile))]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct MetadataQuery {
+    table: Option<String>,
+    since_version: Option<i64>,  // 只返回该版本号之后有变化的记录
+    cursor: Option<String>,  // 上一页返回的 next_cursor（优先于 since_version）
     limit: Option<usize>,
 }
 
+#[cfg(not(mobile))]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct MetadataPage {
+    items: Vec<serde_json::Value>,  // 含墓碑（deleted_at 不为空）
+    next_cursor: Option<String>,  // 为空表示已取完
+    server_version: i64,
+    resync_required: bool,  // since_version 早于低水位：部分墓碑已回收，客户端需要从 0 重新获取
+    low_water_mark: i64,
+}
+
 #[cfg(not(mobile))]
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct PushRequest {
     table: Option<String>,  // 新增：指定要推送的表
     changes: Vec<SyncChange>,
-    client_version: Option<i64>,  // 客户端当前的版本号
+    client_version: Option<i64>,  // 旧客户端携带的版本号，已不使用（冲突按记录的 base_version 检测）
+    atomic: Option<bool>,  // true：任意一条失败则整体回滚；默认逐条 savepoint
+    dry_run: Option<bool>,  // true：照常判断每条变更后回滚，只返回将会得到的结果
+}
+
+#[cfg(not(mobile))]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CrdtPullQuery {
+    since_version: Option<i64>,
+    limit: Option<usize>,
+}
+
+#[cfg(not(mobile))]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CrdtPullResponse {
+    changes: sync_crdt::CrdtChanges,
+    next_version: Option<i64>,  // 还有下一页时的 since_version
+    server_version: i64,
+}
+
+#[cfg(not(mobile))]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CrdtPushResponse {
+    merged: sync_crdt::CrdtReport,  // 每张表实际发生变化的记录数
+    server_version: i64,
 }
 
 #[cfg(not(mobile))]
@@ -173,6 +397,10 @@ struct PushResponse {
     applied: usize,
     server_version: i64,  // 服务器最新版本号
     conflict: bool,
+    committed: bool,  // 原子模式下整体回滚时为 false
+    results: Vec<sync_engine::ChangeOutcome>,  // 每条变更的处理结果（与请求 changes 一一对应）
+    #[serde(default)]
+    dry_run: bool,  // 预演结果，没有写入任何数据
 }
 
 // ============ Sync Helpers ============
@@ -302,6 +530,53 @@ fn check_auth(headers: &axum::http::HeaderMap, token: &str) -> Result<(), Status
     Err(StatusCode::UNAUTHORIZED)
 }
 
+// 识别请求方设备并刷新最近访问时间；已移除的设备返回 403，不带设备 ID 的旧客户端照常放行
+#[cfg(not(mobile))]
+fn identify_device(conn: &Connection, headers: &axum::http::HeaderMap) -> Result<Option<String>, StatusCode> {
+    let Some(info) = sync_devices::DeviceInfo::from_headers(headers) else {
+        return Ok(None);
+    };
+    match sync_devices::touch(conn, &info) {
+        Ok(true) => Ok(Some(info.device_id)),
+        Ok(false) => {
+            log::warn!("[SyncDevices] 拒绝已移除设备的同步请求: {}", info.device_id);
+            Err(StatusCode::FORBIDDEN)
+        }
+        Err(e) => {
+            // 设备表不可用（迁移未执行等）不影响同步本身
+            log::warn!("[SyncDevices] 记录设备失败: {}", e);
+            Ok(Some(info.device_id))
+        }
+    }
+}
+
+// 把一次同步请求记入对端的会话，并通过 sync:session 事件通知前端
+#[cfg(not(mobile))]
+fn record_session(
+    app_handle: &AppHandle,
+    conn: &Connection,
+    device_id: Option<&str>,
+    started: std::time::Instant,
+    mut exchange: sync_sessions::Exchange,
+) {
+    exchange.duration_ms = started.elapsed().as_millis() as u64;
+    match sync_sessions::record(conn, sync_sessions::SessionRole::Server, device_id, &exchange) {
+        Ok(session) => {
+            let _ = app_handle.emit("sync:session", sync_sessions::SessionEvent { session, exchange });
+        }
+        Err(e) => log::warn!("[SyncSessions] 记录同步会话失败: {}", e),
+    }
+}
+
+// 失败的同步请求只记录错误
+#[cfg(not(mobile))]
+fn failed_exchange(error: String) -> sync_sessions::Exchange {
+    sync_sessions::Exchange {
+        error: Some(error),
+        ..Default::default()
+    }
+}
+
 // /state: 返回当前版本号与配对状态
 #[cfg(not(mobile))]
 async fn sync_state(
@@ -313,25 +588,37 @@ async fn sync_state(
     let app_handle = state_guard.app_handle.clone();
     drop(state_guard);
 
-    // 读取所有表的最大版本号
-    let db_version = match open_db(&app_handle) {
-        Ok(conn) => sync_engine::max_version_all_tables(&conn),
-        Err(e) => return Err(e),
-    };
+    // 读取所有表的最大版本号，同时按实际表结构重新校验（首次启动时迁移可能晚于服务启动）
+    let conn = open_db(&app_handle)?;
+    let version = sync_sequence::current(&conn);
+    let schema = sync_registry::registry().describe(&conn);
+    let low_water_mark = sync_gc::low_water_mark(&conn);
+    let replica_id = sync_vector::replica_id(&conn).map_err(|e| {
+        log::error!("sync_state replica_id error: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let knowledge = sync_vector::knowledge(&conn).map_err(|e| {
+        log::error!("sync_state knowledge error: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
 
-    let version = {
-        let guard = state.lock().await;
-        // 若数据库版本更大，则更新全局 version_counter
-        if db_version > guard.version_counter.load(Ordering::Relaxed) {
-            guard.version_counter.store(db_version, Ordering::Relaxed);
-        }
-        guard.version_counter.load(Ordering::Relaxed)
+    // 已移除的设备仍可查询状态，由 paired = false 得知自己需要重新配对
+    let paired = match identify_device(&conn, &headers) {
+        Ok(Some(device_id)) => sync_devices::is_paired(&conn, &device_id).unwrap_or(true),
+        Ok(None) => true,
+        Err(_) => false,
     };
 
     let data = SyncStateData {
         version,
         server_version: env!("CARGO_PKG_VERSION").to_string(),
-        paired: true,
+        paired,
+        schema,
+        low_water_mark,
+        replica_id,
+        knowledge,
+        protocol_version: sync_protocol::PROTOCOL_VERSION,
+        capabilities: sync_protocol::CAPABILITIES.iter().map(|c| c.to_string()).collect(),
     };
 
     Ok(Json(ApiResponse {
@@ -341,211 +628,1043 @@ async fn sync_state(
     }))
 }
 
+// /v1/handshake: 交换协议版本、同步表结构与可选能力；不兼容时返回类型化的拒绝
+#[cfg(not(mobile))]
+async fn sync_handshake(
+    State(state): State<Arc<Mutex<HttpServerState>>>,
+    headers: axum::http::HeaderMap,
+    Json(body): Json<sync_protocol::HandshakeRequest>,
+) -> Result<axum::response::Response, StatusCode> {
+    let state_guard = state.lock().await;
+    check_auth(&headers, &state_guard.token)?;
+    let app_handle = state_guard.app_handle.clone();
+    drop(state_guard);
+
+    let conn = open_db(&app_handle)?;
+    let device_id = identify_device(&conn, &headers)?;
+
+    match sync_protocol::negotiate(&body) {
+        Ok(response) => {
+            for warning in &response.warnings {
+                log::info!(
+                    "[SyncProtocol] 表 {} 字段不一致：客户端多出 {:?}，缺少 {:?}",
+                    warning.table,
+                    warning.unknown_fields,
+                    warning.missing_fields
+                );
+            }
+            Ok(Json(ApiResponse {
+                success: true,
+                data: Some(response),
+                message: None,
+            })
+            .into_response())
+        }
+        Err(refusal) => {
+            log::warn!(
+                "[SyncProtocol] 拒绝设备 {:?}（协议 v{}，{:?}）: {}",
+                device_id,
+                body.protocol_version,
+                body.app_version,
+                refusal.message
+            );
+            Ok(refusal_response(refusal))
+        }
+    }
+}
+
+// 拒绝响应：版本不兼容返回 426，表结构冲突返回 409，响应体携带拒绝原因
+#[cfg(not(mobile))]
+fn refusal_response(refusal: sync_protocol::Refusal) -> axum::response::Response {
+    let status = match refusal.code {
+        sync_protocol::RefusalCode::SchemaMismatch => StatusCode::CONFLICT,
+        _ => StatusCode::UPGRADE_REQUIRED,
+    };
+    let message = refusal.message.clone();
+    (
+        status,
+        Json(ApiResponse {
+            success: false,
+            data: Some(refusal),
+            message: Some(message),
+        }),
+    )
+        .into_response()
+}
+
+// /v1 路由的协议版本校验：请求头缺失或版本不兼容时直接拒绝，不进入处理函数
+#[cfg(not(mobile))]
+async fn require_protocol(request: axum::extract::Request, next: axum::middleware::Next) -> axum::response::Response {
+    let header = request
+        .headers()
+        .get(sync_protocol::PROTOCOL_HEADER)
+        .and_then(|v| v.to_str().ok());
+    match sync_protocol::check_header(header) {
+        Ok(_) => next.run(request).await,
+        Err(refusal) => refusal_response(refusal),
+    }
+}
+
+// 不带前缀的旧路由：旧客户端只能读取，写入（除 /merkle 这类只读的 POST 外）需要协议请求头
+#[cfg(not(mobile))]
+async fn legacy_protocol(request: axum::extract::Request, next: axum::middleware::Next) -> axum::response::Response {
+    let header = request
+        .headers()
+        .get(sync_protocol::PROTOCOL_HEADER)
+        .and_then(|v| v.to_str().ok());
+    let write = !matches!(*request.method(), axum::http::Method::GET | axum::http::Method::HEAD)
+        && request.uri().path() != "/merkle";
+    match sync_protocol::check_legacy(header, write) {
+        Ok(()) => next.run(request).await,
+        Err(refusal) => refusal_response(refusal),
+    }
+}
+
 // /pull: 按版本号拉取增量变更
 #[cfg(not(mobile))]
 async fn sync_pull(
     State(state): State<Arc<Mutex<HttpServerState>>>,
     headers: axum::http::HeaderMap,
     Query(query): Query<PullQuery>,
-) -> Result<Json<ApiResponse<PullResponse>>, StatusCode> {
+) -> Result<axum::response::Response, StatusCode> {
     let state_guard = state.lock().await;
     check_auth(&headers, &state_guard.token)?;
     let app_handle = state_guard.app_handle.clone();
+    let sync = state_guard.sync.clone();
     drop(state_guard);
 
+    let started = std::time::Instant::now();
     let since_version = query.since_version.unwrap_or(0);
-    let limit = query.limit.unwrap_or(500).min(1000);
-    let table_name = query.table.as_deref().unwrap_or("notes"); // 默认 notes
-
-    let conn = open_db(&app_handle)?;
-    
-    // 使用泛型引擎加载表变更
-    let changes = sync_engine::load_table_changes(&conn, table_name, since_version, limit)
-        .map_err(|e| {
-            log::error!("sync_pull load_table_changes error for {}: {}", table_name, e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+    let limit = query.limit.unwrap_or(500).clamp(1, 1000);
+
+    let mut conn = open_db(&app_handle)?;
+    sync.ensure_changelog(&mut conn);
+    let device_id = identify_device(&conn, &headers)?;
+    let scope = sync_scopes::for_device(&conn, device_id.as_deref());
+
+    // 预览：只统计将会拉取到的变更，不返回变更、不更新拉取进度
+    if query.dry_run.unwrap_or(false) {
+        let knowledge = match query.knowledge.as_deref() {
+            Some(raw) => Some(sync_vector::VersionVector::decode_param(raw).ok_or(StatusCode::BAD_REQUEST)?),
+            None => None,
+        };
+        let preview = sync_preview::preview_pull(&conn, since_version, query.table.as_deref(), knowledge.as_ref(), scope.as_ref())
+            .map_err(|e| {
+                log::error!("sync_pull preview error: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+        return Ok(Json(ApiResponse {
+            success: true,
+            data: Some(preview),
+            message: None,
+        })
+        .into_response());
+    }
 
-    // 获取当前数据库最大版本号（所有表）
-    let server_version = sync_engine::max_version_all_tables(&conn);
-    {
-        let guard = state.lock().await;
-        guard.version_counter.store(server_version, Ordering::Relaxed);
+    // 客户端游标早于低水位：期间的删除已被回收，增量拉取会漏掉它们
+    // 设备的同步范围修改后尚未重新拉取：增量拉取收不到新进入范围的记录
+    let low_water_mark = sync_gc::low_water_mark(&conn);
+    let scope_resync = device_id
+        .as_deref()
+        .is_some_and(|d| sync_scopes::requires_resync(&conn, d, query.table.as_deref(), since_version));
+    if query.cursor.is_none() && (scope_resync || sync_gc::requires_resync(&conn, since_version)) {
+        if scope_resync {
+            log::info!("[SyncScopes] 设备同步范围已修改，要求客户端全量对账");
+        } else {
+            log::info!("[SyncGC] since_version {} 早于低水位 {}，要求客户端全量对账", since_version, low_water_mark);
+        }
+        return Ok(Json(ApiResponse {
+            success: true,
+            data: Some(PullResponse {
+                changes: Vec::new(),
+                next_version: None,
+                next_cursor: None,
+                server_version: sync_sequence::current(&conn),
+                resync_required: true,
+                low_water_mark,
+                scoped: scope.is_some(),
+            }),
+            message: Some("resync required".to_string()),
+        })
+        .into_response());
     }
 
-    // 若变化达到 limit，则需要分页，next_version 为最后一条的 version + 1
-    let next_version = if changes.len() >= limit {
-        changes.last().map(|c| c.version + 1)
-    } else {
-        None
+    // 单表拉取读满 limit 条变更日志时需要分页，next_version 为本页读到的最后一条的版本号
+    let (mut changes, next_version, next_cursor) = match query.table.as_deref() {
+        // 使用泛型引擎加载单表变更
+        Some(table_name) => {
+            let (changes, next) = sync_engine::load_table_page(&conn, table_name, since_version, limit, scope.as_ref())
+                .map_err(|e| {
+                    log::error!("sync_pull load_table_changes error for {}: {}", table_name, e);
+                    let error = format!("读取 {} 的变更失败: {}", table_name, e);
+                    record_session(&app_handle, &conn, device_id.as_deref(), started, failed_exchange(error));
+                    StatusCode::INTERNAL_SERVER_ERROR
+                })?;
+            (changes, next, None)
+        }
+        // 未指定表：所有同步表按版本号交错返回，用游标分页
+        None => {
+            let cursor = match query.cursor.as_deref() {
+                Some(raw) => Some(sync_engine::PullCursor::decode(raw).ok_or(StatusCode::BAD_REQUEST)?),
+                None => None,
+            };
+            let (changes, next) = sync_engine::load_all_changes(&conn, since_version, cursor.as_ref(), limit, scope.as_ref())
+                .map_err(|e| {
+                    log::error!("sync_pull load_all_changes error: {}", e);
+                    let error = format!("读取变更失败: {}", e);
+                    record_session(&app_handle, &conn, device_id.as_deref(), started, failed_exchange(error));
+                    StatusCode::INTERNAL_SERVER_ERROR
+                })?;
+            (changes, None, next.map(|c| c.encode()))
+        }
     };
 
+    // 当前已分配的最大版本号
+    let server_version = sync_sequence::current(&conn);
+
+    // 拉取完最后一页后客户端持有 server_version，否则只到本页最后一条
+    if let Some(device_id) = device_id.as_deref() {
+        let pulled_to = if next_version.is_none() && next_cursor.is_none() {
+            server_version
+        } else {
+            changes.iter().map(|c| c.version).max().unwrap_or(since_version)
+        };
+        if let Err(e) = sync_devices::record_pull(&conn, device_id, pulled_to) {
+            log::warn!("[SyncDevices] 记录拉取进度失败: {}", e);
+        }
+    }
+
+    // 客户端已从其他副本收到的写入不再重复发送（分页游标按过滤前的结果计算）
+    // 从 0 全量拉取时不过滤：见过某次写入不代表仍持有该记录（例如移出同步范围后已丢弃的本地副本）
+    if let Some(raw) = query.knowledge.as_deref() {
+        let knowledge = sync_vector::VersionVector::decode_param(raw).ok_or(StatusCode::BAD_REQUEST)?;
+        if since_version > 0 {
+            changes.retain(|c| !c.vv.as_ref().is_some_and(|vv| vv.seen_by(&knowledge)));
+        }
+    }
+
+    // 第一页附带上次拉取以来超出最长天数的笔记（不影响分页）
+    if query.cursor.is_none() {
+        if let Some(device_id) = device_id.as_deref() {
+            changes.extend(sync_scopes::begin_pull(&conn, device_id, scope.as_ref(), query.table.as_deref(), since_version));
+        }
+    }
+
     let resp = PullResponse {
         changes,
         next_version,
+        next_cursor,
         server_version,
+        resync_required: false,
+        low_water_mark,
+        scoped: scope.is_some(),
     };
 
+    let mut exchange = sync_sessions::Exchange::default();
+    for change in &resp.changes {
+        exchange.add_pulled(&change.table);
+    }
+    exchange.bytes_out = sync_sessions::json_size(&resp);
+    record_session(&app_handle, &conn, device_id.as_deref(), started, exchange);
+
     Ok(Json(ApiResponse {
         success: true,
         data: Some(resp),
         message: None,
-    }))
+    })
+    .into_response())
 }
 
-// /metadata: 获取指定表的元数据列表（用于智能合并）
+// /pull/stream: 跨表拉取的 NDJSON 流式版本，边读边写；limit 为空时一次拉取全部
 #[cfg(not(mobile))]
-async fn sync_metadata(
+async fn sync_pull_stream(
     State(state): State<Arc<Mutex<HttpServerState>>>,
     headers: axum::http::HeaderMap,
-    Query(query): Query<std::collections::HashMap<String, String>>,
-) -> Result<Json<ApiResponse<Vec<serde_json::Value>>>, StatusCode> {
+    Query(query): Query<PullQuery>,
+) -> Result<axum::response::Response, StatusCode> {
     let state_guard = state.lock().await;
     check_auth(&headers, &state_guard.token)?;
     let app_handle = state_guard.app_handle.clone();
+    let sync = state_guard.sync.clone();
     drop(state_guard);
 
-    let table_name = query.get("table").map(|s| s.as_str()).unwrap_or("notes");
+    let started = std::time::Instant::now();
+    let mut conn = open_db(&app_handle)?;
+    sync.ensure_changelog(&mut conn);
+    let device_id = identify_device(&conn, &headers)?;
+    let scope = sync_scopes::for_device(&conn, device_id.as_deref());
+    let since_version = query.since_version.unwrap_or(0);
 
-    let conn = open_db(&app_handle)?;
-    
-    let metadata = sync_engine::load_table_metadata(&conn, table_name)
-        .map_err(|e| {
-            log::error!("sync_metadata error for {}: {}", table_name, e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+    let request = sync_stream::StreamRequest {
+        since_version,
+        after: match query.cursor.as_deref() {
+            Some(raw) => Some(sync_engine::PullCursor::decode(raw).ok_or(StatusCode::BAD_REQUEST)?),
+            None => None,
+        },
+        // 从 0 全量拉取时不按 knowledge 过滤，同 /pull
+        knowledge: match query.knowledge.as_deref() {
+            Some(raw) => Some(sync_vector::VersionVector::decode_param(raw).ok_or(StatusCode::BAD_REQUEST)?),
+            None => None,
+        }
+        .filter(|_| since_version > 0),
+        limit: query.limit,
+        scope_resync: device_id
+            .as_deref()
+            .is_some_and(|d| sync_scopes::requires_resync(&conn, d, None, since_version)),
+        scope,
+    };
 
-    Ok(Json(ApiResponse {
-        success: true,
-        data: Some(metadata),
-        message: None,
-    }))
+    // 在阻塞线程中读取 SQLite，通过有界通道逐行写出（客户端读得慢时读取也会暂停）
+    let (tx, rx) = tokio::sync::mpsc::channel::<Result<Vec<u8>, std::io::Error>>(16);
+    tokio::task::spawn_blocking(move || {
+        let mut bytes_out = 0u64;
+        let aged_out = match device_id.as_deref() {
+            Some(device_id) if request.after.is_none() && !request.scope_resync => {
+                sync_scopes::begin_pull(&conn, device_id, request.scope.as_ref(), None, request.since_version)
+            }
+            _ => Vec::new(),
+        };
+        let result = sync_stream::write_changes(&conn, &request, aged_out, |line| {
+            bytes_out += line.len() as u64;
+            tx.blocking_send(Ok(line)).is_ok()
+        });
+        let mut exchange = sync_sessions::Exchange::default();
+        match result {
+            Ok(summary) => {
+                if summary.next_cursor.is_none() && !summary.resync_required {
+                    if let Some(device_id) = device_id.as_deref() {
+                        if let Err(e) = sync_devices::record_pull(&conn, device_id, summary.server_version) {
+                            log::warn!("[SyncDevices] 记录拉取进度失败: {}", e);
+                        }
+                    }
+                }
+                for (table, count) in summary.tables {
+                    exchange.tables.entry(table).or_default().pulled = count;
+                }
+            }
+            Err(e) => {
+                // 已经开始写出响应，只能中断连接；客户端收不到 end 行即知道需要重试
+                log::error!("sync_pull_stream error: {}", e);
+                exchange.error = Some(format!("流式拉取中断: {}", e));
+                let _ = tx.blocking_send(Err(std::io::Error::other(e.to_string())));
+            }
+        }
+        exchange.bytes_out = bytes_out;
+        record_session(&app_handle, &conn, device_id.as_deref(), started, exchange);
+    });
+
+    Ok((
+        [(axum::http::header::CONTENT_TYPE, sync_stream::CONTENT_TYPE)],
+        axum::body::Body::from_stream(tokio_stream::wrappers::ReceiverStream::new(rx)),
+    )
+        .into_response())
 }
 
-// /push: 接受增量，分配新版本号并应用
+// /metadata: 获取指定表的元数据列表（用于智能合并）
+// 带 since_version / cursor / limit 时分页返回增量元数据（含墓碑），否则返回全部未删除记录（旧客户端）
 #[cfg(not(mobile))]
-async fn sync_push(
+async fn sync_metadata(
     State(state): State<Arc<Mutex<HttpServerState>>>,
     headers: axum::http::HeaderMap,
-    Json(body): Json<PushRequest>,
-) -> Result<Json<ApiResponse<PushResponse>>, StatusCode> {
+    Query(query): Query<MetadataQuery>,
+) -> Result<axum::response::Response, StatusCode> {
     let state_guard = state.lock().await;
     check_auth(&headers, &state_guard.token)?;
     let app_handle = state_guard.app_handle.clone();
+    let sync = state_guard.sync.clone();
     drop(state_guard);
 
-    let conn = open_db(&app_handle)?;
-    
-    // 获取当前服务器版本号（所有表）
-    let _server_version_before = sync_engine::max_version_all_tables(&conn);
-    let _client_version = body.client_version.unwrap_or(0);
-    
-    // 移除全局版本检查，改用记录级冲突检测
-    // 因为客户端可能只同步了部分表，或者 client_version 传递不准确
-    // 我们信任客户端的 push 决策（客户端已完成 diff 和冲突解决）
-
-    let mut applied = 0usize;
-    let table_name = body.table.as_deref(); // 可选的表名过滤
-
-    for change in body.changes.iter() {
-        // 如果请求中指定了表名，只处理该表；否则根据 change.table 判断
-        let target_table = if let Some(t) = table_name {
-            if change.table != t {
-                continue; // 跳过非目标表
-            }
-            t
-        } else {
-            &change.table
-        };
+    let table_name = query.table.as_deref().unwrap_or("notes");
 
-        // 检查表是否支持
-        if sync_engine::get_table_config(target_table).is_none() {
-            log::warn!("Unsupported table: {}", target_table);
-            continue;
-        }
-        
-        // 为每个变更分配新的版本号（原子递增）
-        let new_version = {
-            let guard = state.lock().await;
-            guard.version_counter.fetch_add(1, Ordering::Relaxed) + 1
-        };
-        
-        // 使用泛型引擎应用变更
-        match sync_engine::apply_table_change(&conn, target_table, change, new_version) {
-            Ok(applied_one) => {
-                if applied_one {
-                    applied += 1;
-                }
-            }
-            Err(e) => {
-                log::error!("apply_table_change error for {}: {}", target_table, e);
-            }
-        }
-    }
+    let mut conn = open_db(&app_handle)?;
+    let device_id = identify_device(&conn, &headers)?;
+    let scope = sync_scopes::for_device(&conn, device_id.as_deref());
 
-    // 获取应用后的最新版本号
-    let server_version = sync_engine::max_version_all_tables(&conn);
-    {
-        let guard = state.lock().await;
-        guard.version_counter.store(server_version, Ordering::Relaxed);
+    if query.since_version.is_none() && query.cursor.is_none() && query.limit.is_none() {
+        let metadata = sync_engine::load_table_metadata(&conn, table_name, scope.as_ref())
+            .map_err(|e| {
+                log::error!("sync_metadata error for {}: {}", table_name, e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+
+        return Ok(Json(ApiResponse {
+            success: true,
+            data: Some(metadata),
+            message: None,
+        })
+        .into_response());
     }
 
-    let resp = PushResponse {
-        applied,
-        server_version,
-        conflict: false,
+    sync.ensure_changelog(&mut conn);
+    let since_version = query.since_version.unwrap_or(0);
+    let limit = query.limit.unwrap_or(500).clamp(1, 1000);
+    let cursor = match query.cursor.as_deref() {
+        Some(raw) => Some(sync_engine::PullCursor::decode(raw).ok_or(StatusCode::BAD_REQUEST)?),
+        None => None,
     };
+    let low_water_mark = sync_gc::low_water_mark(&conn);
 
-    // 如果有变更应用成功，通知前端显示"接收"状态
-    if applied > 0 {
-        let guard = state.lock().await;
-        let _ = guard.app_handle.emit("sync:incoming", applied);
+    // 游标早于低水位：期间的墓碑已被回收，增量元数据会漏掉这些删除；同步范围修改后同样需要全量对账
+    let scope_resync = device_id
+        .as_deref()
+        .is_some_and(|d| sync_scopes::requires_resync(&conn, d, Some(table_name), since_version));
+    if cursor.is_none() && (scope_resync || sync_gc::requires_resync(&conn, since_version)) {
+        return Ok(Json(ApiResponse {
+            success: true,
+            data: Some(MetadataPage {
+                items: Vec::new(),
+                next_cursor: None,
+                server_version: sync_sequence::current(&conn),
+                resync_required: true,
+                low_water_mark,
+            }),
+            message: Some("resync required".to_string()),
+        })
+        .into_response());
+    }
+
+    let (mut items, next) = sync_engine::load_metadata_page(&conn, table_name, since_version, cursor.as_ref(), limit, scope.as_ref())
+        .map_err(|e| {
+            log::error!("sync_metadata page error for {}: {}", table_name, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if let (None, Some(device_id)) = (cursor.as_ref(), device_id.as_deref()) {
+        let aged_out = sync_scopes::begin_pull(&conn, device_id, scope.as_ref(), Some(table_name), since_version);
+        items.extend(aged_out.iter().map(sync_scopes::scope_out_metadata));
     }
 
     Ok(Json(ApiResponse {
         success: true,
-        data: Some(resp),
+        data: Some(MetadataPage {
+            items,
+            next_cursor: next.map(|c| c.encode()),
+            server_version: sync_sequence::current(&conn),
+            resync_required: false,
+            low_water_mark,
+        }),
         message: None,
-    }))
+    })
+    .into_response())
 }
 
-// 启动 HTTP 服务器 (仅桌面端)
+// /merkle: 范围哈希对账，展开指定的桶（客户端只请求两端哈希不同的桶）
 #[cfg(not(mobile))]
-async fn start_http_server(app_handle: AppHandle, port: u16) {
-    // 简易令牌（后续可改为持久化/用户配置）
-    let token = std::env::var("ZOTEPAD_SYNC_TOKEN").unwrap_or_else(|_| "zotepad-dev-token".to_string());
-
-    let state = Arc::new(Mutex::new(HttpServerState {
-        app_handle,
-        version_counter: AtomicI64::new(0),
-        token,
-    }));
+async fn sync_merkle_nodes(
+    State(state): State<Arc<Mutex<HttpServerState>>>,
+    headers: axum::http::HeaderMap,
+    Json(body): Json<sync_merkle::MerkleRequest>,
+) -> Result<Json<ApiResponse<Vec<sync_merkle::MerkleNode>>>, StatusCode> {
+    let state_guard = state.lock().await;
+    check_auth(&headers, &state_guard.token)?;
+    let app_handle = state_guard.app_handle.clone();
+    let sync = state_guard.sync.clone();
+    drop(state_guard);
 
-    // 初始化 version_counter 为 DB 最新版本号
+    if sync_engine::get_table_config(&body.table).is_none()
+        || body.prefixes.len() > sync_merkle::MAX_PREFIXES
+        || !body.prefixes.iter().all(|p| sync_merkle::is_valid_prefix(p))
     {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let conn = open_db(&app_handle)?;
+    let device_id = identify_device(&conn, &headers)?;
+    let scope = sync_scopes::for_device(&conn, device_id.as_deref());
+
+    // 一次对账的多轮请求复用同一个索引，有新的写入（序列号变化）后才重新加载
+    let sequence = sync_sequence::current(&conn);
+    let index = sync
+        .merkle_cache
+        .get_or_build(&body.table, scope.as_ref(), sequence, || {
+            let mut metadata = sync_engine::load_table_metadata(&conn, &body.table, scope.as_ref())?;
+            // 范围外的记录不在该设备上，不参与哈希
+            metadata.retain(|m| m.get("scoped_out").is_none());
+            Ok(sync_merkle::MerkleIndex::build(metadata))
+        })
+        .map_err(|e: rusqlite::Error| {
+            log::error!("sync_merkle error for {}: {}", body.table, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    let nodes = body.prefixes.iter().map(|p| index.node(p)).collect();
+
+    Ok(Json(ApiResponse {
+        success: true,
+        data: Some(nodes),
+        message: None,
+    }))
+}
+
+// /push: 接受增量，在单个事务中应用（版本号由触发器在写入时分配）
+#[cfg(not(mobile))]
+async fn sync_push(
+    State(state): State<Arc<Mutex<HttpServerState>>>,
+    headers: axum::http::HeaderMap,
+    Query(query): Query<PushQuery>,
+    Json(body): Json<PushRequest>,
+) -> Result<Json<ApiResponse<PushResponse>>, StatusCode> {
+    let state_guard = state.lock().await;
+    check_auth(&headers, &state_guard.token)?;
+    let app_handle = state_guard.app_handle.clone();
+    let sync = state_guard.sync.clone();
+    drop(state_guard);
+
+    let started = std::time::Instant::now();
+    let mut conn = open_db(&app_handle)?;
+    sync.ensure_changelog(&mut conn);
+    let device_id = identify_device(&conn, &headers)?;
+
+    // 预演：照常判断每条变更后回滚，不记录设备进度和会话，也不通知前端
+    if body.dry_run.or(query.dry_run).unwrap_or(false) {
+        let report = sync_engine::preview_changes(
+            &mut conn,
+            &body.changes,
+            body.table.as_deref(),
+            &sync.clock,
+            body.atomic.unwrap_or(false),
+        )
+        .map_err(|e| {
+            log::error!("sync_push dry run error: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        let conflict = report.outcomes.iter().any(|o| {
+            o.status == sync_engine::ChangeStatus::Conflict
+                || (o.status == sync_engine::ChangeStatus::Merged && o.reason.is_some())
+        });
+        return Ok(Json(ApiResponse {
+            success: true,
+            data: Some(PushResponse {
+                applied: report.applied_count(),
+                server_version: sync_sequence::current(&conn),
+                conflict,
+                committed: false,
+                results: report.outcomes,
+                dry_run: true,
+            }),
+            message: None,
+        }));
+    }
+
+    let bytes_in = sync_sessions::json_size(&body.changes);
+    let report = match sync_engine::apply_changes(
+        &mut conn,
+        &body.changes,
+        body.table.as_deref(),
+        &sync.clock,
+        body.atomic.unwrap_or(false),
+    ) {
+        Ok(report) => report,
+        Err(e) => {
+            log::error!("sync_push transaction error: {}", e);
+            let mut exchange = failed_exchange(format!("写入推送的变更失败: {}", e));
+            exchange.bytes_in = bytes_in;
+            record_session(&app_handle, &conn, device_id.as_deref(), started, exchange);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    let applied = report.applied_count();
+    // 进入冲突队列，或 merged 且带 reason（写入了冲突标记）
+    let queued = report
+        .outcomes
+        .iter()
+        .filter(|o| o.status == sync_engine::ChangeStatus::Conflict)
+        .count();
+    let conflict = queued > 0
+        || report
+            .outcomes
+            .iter()
+            .any(|o| o.status == sync_engine::ChangeStatus::Merged && o.reason.is_some());
+
+    // 获取应用后的最新版本号
+    let server_version = sync_sequence::current(&conn);
+
+    if let (Some(device_id), true) = (device_id.as_deref(), applied > 0) {
+        if let Err(e) = sync_devices::record_push(&conn, device_id, server_version) {
+            log::warn!("[SyncDevices] 记录推送进度失败: {}", e);
+        }
+    }
+
+    let mut exchange = sync_sessions::Exchange {
+        bytes_in,
+        ..Default::default()
+    };
+    exchange.add_outcomes(&report.outcomes, false);
+
+    let resp = PushResponse {
+        applied,
+        server_version,
+        conflict,
+        committed: report.committed,
+        results: report.outcomes,
+        dry_run: false,
+    };
+    exchange.bytes_out = sync_sessions::json_size(&resp);
+    record_session(&app_handle, &conn, device_id.as_deref(), started, exchange);
+
+    // 如果有变更应用成功，通知前端显示"接收"状态
+    if applied > 0 {
+        let guard = state.lock().await;
+        let _ = guard.app_handle.emit("sync:incoming", applied);
+    }
+
+    // 有新的冲突入队，通知前端提示用户处理
+    if queued > 0 {
         let guard = state.lock().await;
-        let app_handle = guard.app_handle.clone();
-        drop(guard);
-        if let Ok(conn) = open_db(&app_handle) {
-            let latest_version = sync_engine::max_version_all_tables(&conn);
-            let guard = state.lock().await;
-            guard.version_counter.store(latest_version, Ordering::Relaxed);
+        let _ = guard.app_handle.emit("sync:conflict", queued);
+    }
+
+    Ok(Json(ApiResponse {
+        success: true,
+        data: Some(resp),
+        message: None,
+    }))
+}
+
+// /crdt/pull: 拉取成就统计分片、积分流水和成就状态（与 /pull 共用版本号游标）
+#[cfg(not(mobile))]
+async fn sync_crdt_pull(
+    State(state): State<Arc<Mutex<HttpServerState>>>,
+    headers: axum::http::HeaderMap,
+    Query(query): Query<CrdtPullQuery>,
+) -> Result<Json<ApiResponse<CrdtPullResponse>>, StatusCode> {
+    let state_guard = state.lock().await;
+    check_auth(&headers, &state_guard.token)?;
+    let app_handle = state_guard.app_handle.clone();
+    let sync = state_guard.sync.clone();
+    drop(state_guard);
+
+    let mut conn = open_db(&app_handle)?;
+    sync.ensure_changelog(&mut conn);
+    let device_id = identify_device(&conn, &headers)?;
+
+    let limit = query.limit.unwrap_or(500).clamp(1, 1000);
+    let (changes, next_version) = sync_crdt::load_changes(&conn, query.since_version.unwrap_or(0), limit)
+        .map_err(|e| {
+            log::error!("sync_crdt_pull error: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    let server_version = sync_sequence::current(&conn);
+
+    if let (Some(device_id), None) = (device_id.as_deref(), next_version) {
+        if let Err(e) = sync_devices::record_pull(&conn, device_id, server_version) {
+            log::warn!("[SyncDevices] 记录拉取进度失败: {}", e);
         }
     }
 
+    Ok(Json(ApiResponse {
+        success: true,
+        data: Some(CrdtPullResponse {
+            changes,
+            next_version,
+            server_version,
+        }),
+        message: None,
+    }))
+}
+
+// /crdt/push: 合并客户端的成就数据（幂等，可重复推送）
+#[cfg(not(mobile))]
+async fn sync_crdt_push(
+    State(state): State<Arc<Mutex<HttpServerState>>>,
+    headers: axum::http::HeaderMap,
+    Json(body): Json<sync_crdt::CrdtChanges>,
+) -> Result<Json<ApiResponse<CrdtPushResponse>>, StatusCode> {
+    let state_guard = state.lock().await;
+    check_auth(&headers, &state_guard.token)?;
+    let app_handle = state_guard.app_handle.clone();
+    let sync = state_guard.sync.clone();
+    drop(state_guard);
+
+    let mut conn = open_db(&app_handle)?;
+    sync.ensure_changelog(&mut conn);
+    let device_id = identify_device(&conn, &headers)?;
+
+    let merged = sync_crdt::apply_changes(&mut conn, &body).map_err(|e| {
+        log::error!("sync_crdt_push error: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let server_version = sync_sequence::current(&conn);
+
+    if merged.total() > 0 {
+        let _ = app_handle.emit("sync:incoming", merged.total());
+        if let Some(device_id) = device_id.as_deref() {
+            if let Err(e) = sync_devices::record_push(&conn, device_id, server_version) {
+                log::warn!("[SyncDevices] 记录推送进度失败: {}", e);
+            }
+        }
+    }
+
+    Ok(Json(ApiResponse {
+        success: true,
+        data: Some(CrdtPushResponse {
+            merged,
+            server_version,
+        }),
+        message: None,
+    }))
+}
+
+#[cfg(not(mobile))]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ConflictListQuery {
+    status: Option<String>,  // open | resolved，为空返回全部
+}
+
+#[cfg(not(mobile))]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ResolveConflictRequest {
+    resolution: sync_conflicts::Resolution,
+}
+
+// /conflicts: 列出冲突队列
+#[cfg(not(mobile))]
+async fn sync_conflicts_list(
+    State(state): State<Arc<Mutex<HttpServerState>>>,
+    headers: axum::http::HeaderMap,
+    Query(query): Query<ConflictListQuery>,
+) -> Result<Json<ApiResponse<Vec<sync_conflicts::SyncConflict>>>, StatusCode> {
+    let state_guard = state.lock().await;
+    check_auth(&headers, &state_guard.token)?;
+    let app_handle = state_guard.app_handle.clone();
+    drop(state_guard);
+
+    let conn = open_db(&app_handle)?;
+    identify_device(&conn, &headers)?;
+    let conflicts = sync_conflicts::list_conflicts(&conn, query.status.as_deref()).map_err(|e| {
+        log::error!("sync_conflicts_list error: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(ApiResponse {
+        success: true,
+        data: Some(conflicts),
+        message: None,
+    }))
+}
+
+// /conflicts/{id}: 查看单条冲突的两端副本
+#[cfg(not(mobile))]
+async fn sync_conflicts_get(
+    State(state): State<Arc<Mutex<HttpServerState>>>,
+    headers: axum::http::HeaderMap,
+    Path(id): Path<i64>,
+) -> Result<Json<ApiResponse<sync_conflicts::SyncConflict>>, StatusCode> {
+    let state_guard = state.lock().await;
+    check_auth(&headers, &state_guard.token)?;
+    let app_handle = state_guard.app_handle.clone();
+    drop(state_guard);
+
+    let conn = open_db(&app_handle)?;
+    identify_device(&conn, &headers)?;
+    let conflict = sync_conflicts::get_conflict(&conn, id)
+        .map_err(|e| {
+            log::error!("sync_conflicts_get error: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(Json(ApiResponse {
+        success: true,
+        data: Some(conflict),
+        message: None,
+    }))
+}
+
+// /conflicts/{id}/resolve: 解决冲突（keep_local | keep_remote | keep_both）
+#[cfg(not(mobile))]
+async fn sync_conflicts_resolve(
+    State(state): State<Arc<Mutex<HttpServerState>>>,
+    headers: axum::http::HeaderMap,
+    Path(id): Path<i64>,
+    Json(body): Json<ResolveConflictRequest>,
+) -> Result<Json<ApiResponse<sync_conflicts::SyncConflict>>, StatusCode> {
+    let state_guard = state.lock().await;
+    check_auth(&headers, &state_guard.token)?;
+    let app_handle = state_guard.app_handle.clone();
+    let sync = state_guard.sync.clone();
+    drop(state_guard);
+
+    let mut conn = open_db(&app_handle)?;
+    sync.ensure_changelog(&mut conn);
+    identify_device(&conn, &headers)?;
+    let conflict = sync_conflicts::resolve_conflict(&mut conn, id, body.resolution, &sync.clock)
+        .map_err(|e| match e {
+            sync_conflicts::ResolveError::NotApplied(reason) => {
+                log::warn!("sync_conflicts_resolve #{} not applied: {}", id, reason);
+                StatusCode::CONFLICT
+            }
+            sync_conflicts::ResolveError::Db(e) => {
+                log::error!("sync_conflicts_resolve error: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let _ = app_handle.emit("sync:incoming", 1);
+
+    Ok(Json(ApiResponse {
+        success: true,
+        data: Some(conflict),
+        message: None,
+    }))
+}
+
+// /devices: 列出登记过的同步设备及其同步进度
+#[cfg(not(mobile))]
+async fn sync_devices_list(
+    State(state): State<Arc<Mutex<HttpServerState>>>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<ApiResponse<Vec<sync_devices::SyncDevice>>>, StatusCode> {
+    let state_guard = state.lock().await;
+    check_auth(&headers, &state_guard.token)?;
+    let app_handle = state_guard.app_handle.clone();
+    drop(state_guard);
+
+    let conn = open_db(&app_handle)?;
+    identify_device(&conn, &headers)?;
+    let devices = sync_devices::list(&conn, sync_sequence::current(&conn)).map_err(|e| {
+        log::error!("sync_devices_list error: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(ApiResponse {
+        success: true,
+        data: Some(devices),
+        message: None,
+    }))
+}
+
+// 启动 HTTP 服务器 (仅桌面端)
+// 资源目录：{app_data_dir}/assets
+#[cfg(not(mobile))]
+fn asset_dir(app_handle: &AppHandle) -> Result<std::path::PathBuf, StatusCode> {
+    let path = app_handle.path().app_data_dir().map_err(|e| {
+        log::error!("resolve app_data_dir failed: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(sync_assets::asset_dir(&path))
+}
+
+#[cfg(not(mobile))]
+fn asset_status(e: sync_assets::AssetError) -> StatusCode {
+    match e {
+        sync_assets::AssetError::InvalidId => StatusCode::BAD_REQUEST,
+        sync_assets::AssetError::NotFound => StatusCode::NOT_FOUND,
+        sync_assets::AssetError::OffsetMismatch(_) => StatusCode::CONFLICT,
+        sync_assets::AssetError::TooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+        sync_assets::AssetError::Checksum => StatusCode::UNPROCESSABLE_ENTITY,
+        sync_assets::AssetError::Io(e) => {
+            log::error!("[SyncAssets] 读写失败: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+// /assets/{uuid}: 下载资源文件，响应头携带大小和 sha256 供接收方校验
+#[cfg(not(mobile))]
+async fn sync_asset_download(
+    State(state): State<Arc<Mutex<HttpServerState>>>,
+    headers: axum::http::HeaderMap,
+    Path(uuid): Path<String>,
+) -> Result<axum::response::Response, StatusCode> {
+    let state_guard = state.lock().await;
+    check_auth(&headers, &state_guard.token)?;
+    let app_handle = state_guard.app_handle.clone();
+    drop(state_guard);
+
+    let conn = open_db(&app_handle)?;
+    identify_device(&conn, &headers)?;
+    let path = sync_assets::local_path(&asset_dir(&app_handle)?, &uuid).map_err(asset_status)?;
+    if !path.is_file() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let mime_type = conn
+        .query_row("SELECT mime_type FROM assets WHERE uuid = ?1", rusqlite::params![uuid], |row| {
+            row.get::<_, Option<String>>(0)
+        })
+        .ok()
+        .flatten()
+        .filter(|m| !m.is_empty())
+        .unwrap_or_else(|| "application/octet-stream".to_string());
+    drop(conn);
+
+    // 先完整读一遍计算 sha256，再从同一个文件句柄分块写出
+    let (file, size, sha256) = tokio::task::spawn_blocking(move || sync_assets::open_for_download(&path))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|e| asset_status(e.into()))?;
+
+    let (tx, rx) = tokio::sync::mpsc::channel::<Result<Vec<u8>, std::io::Error>>(8);
+    tokio::task::spawn_blocking(move || {
+        use std::io::Read;
+        let mut file = file.take(size);
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            match file.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    if tx.blocking_send(Ok(buf[..n].to_vec())).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    let _ = tx.blocking_send(Err(e));
+                    break;
+                }
+            }
+        }
+    });
+
+    Ok((
+        [
+            (axum::http::header::CONTENT_TYPE, mime_type),
+            (axum::http::HeaderName::from_static(sync_assets::SIZE_HEADER), size.to_string()),
+            (axum::http::HeaderName::from_static(sync_assets::SHA256_HEADER), sha256),
+        ],
+        axum::body::Body::from_stream(tokio_stream::wrappers::ReceiverStream::new(rx)),
+    )
+        .into_response())
+}
+
+// /assets/{uuid}/upload: 查询上传进度（已接收的字节数）
+#[cfg(not(mobile))]
+async fn sync_asset_upload_status(
+    State(state): State<Arc<Mutex<HttpServerState>>>,
+    headers: axum::http::HeaderMap,
+    Path(uuid): Path<String>,
+) -> Result<Json<ApiResponse<sync_assets::UploadStatus>>, StatusCode> {
+    let state_guard = state.lock().await;
+    check_auth(&headers, &state_guard.token)?;
+    let app_handle = state_guard.app_handle.clone();
+    drop(state_guard);
+
+    let conn = open_db(&app_handle)?;
+    identify_device(&conn, &headers)?;
+    let status = sync_assets::upload_status(&asset_dir(&app_handle)?, &uuid).map_err(asset_status)?;
+
+    Ok(Json(ApiResponse {
+        success: true,
+        data: Some(status),
+        message: None,
+    }))
+}
+
+#[cfg(not(mobile))]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct AssetChunkQuery {
+    offset: u64,  // 本块在文件中的起始位置，必须等于已接收的字节数
+}
+
+// PUT /assets/{uuid}/upload?offset=N: 追加一块（请求体为原始字节）
+#[cfg(not(mobile))]
+async fn sync_asset_upload_chunk(
+    State(state): State<Arc<Mutex<HttpServerState>>>,
+    headers: axum::http::HeaderMap,
+    Path(uuid): Path<String>,
+    Query(query): Query<AssetChunkQuery>,
+    body: axum::body::Bytes,
+) -> Result<Json<ApiResponse<sync_assets::UploadStatus>>, StatusCode> {
+    let state_guard = state.lock().await;
+    check_auth(&headers, &state_guard.token)?;
+    let app_handle = state_guard.app_handle.clone();
+    drop(state_guard);
+
+    let conn = open_db(&app_handle)?;
+    identify_device(&conn, &headers)?;
+    let received = sync_assets::append_chunk(&asset_dir(&app_handle)?, &uuid, query.offset, &body).map_err(|e| {
+        if let sync_assets::AssetError::OffsetMismatch(received) = e {
+            log::warn!("[SyncAssets] {} 分块位置 {} 与已接收的 {} 字节不一致", uuid, query.offset, received);
+        }
+        asset_status(e)
+    })?;
+
+    Ok(Json(ApiResponse {
+        success: true,
+        data: Some(sync_assets::UploadStatus { uuid, received, complete: false }),
+        message: None,
+    }))
+}
+
+// /assets/{uuid}/upload/complete: 校验大小与 sha256，通过后写入资源目录
+#[cfg(not(mobile))]
+async fn sync_asset_upload_complete(
+    State(state): State<Arc<Mutex<HttpServerState>>>,
+    headers: axum::http::HeaderMap,
+    Path(uuid): Path<String>,
+    Json(body): Json<sync_assets::CompleteUpload>,
+) -> Result<Json<ApiResponse<sync_assets::UploadStatus>>, StatusCode> {
+    let state_guard = state.lock().await;
+    check_auth(&headers, &state_guard.token)?;
+    let app_handle = state_guard.app_handle.clone();
+    drop(state_guard);
+
+    let conn = open_db(&app_handle)?;
+    identify_device(&conn, &headers)?;
+    let dir = asset_dir(&app_handle)?;
+    let target = uuid.clone();
+    tokio::task::spawn_blocking(move || sync_assets::finish_upload(&dir, &target, &body))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(asset_status)?;
+    log::info!("[SyncAssets] 已接收资源文件 {}", uuid);
+
+    Ok(Json(ApiResponse {
+        success: true,
+        data: Some(sync_assets::UploadStatus { uuid, received: 0, complete: true }),
+        message: None,
+    }))
+}
+
+#[cfg(not(mobile))]
+async fn start_http_server(app_handle: AppHandle, port: u16, sync: Arc<SyncRuntime>) {
+    // 简易令牌（后续可改为持久化/用户配置）
+    let token = std::env::var("ZOTEPAD_SYNC_TOKEN").unwrap_or_else(|_| "zotepad-dev-token".to_string());
+
+    log::info!("Sync device id: {}", sync.clock.device_id());
+
+    let state = Arc::new(Mutex::new(HttpServerState {
+        app_handle,
+        sync,
+        token,
+    }));
+
     // 配置 CORS - 使用 permissive() 完全开放
     let cors = CorsLayer::permissive();
 
-    // 构建路由
-    let app = Router::new()
-        .route("/", get(health_check))
-        .route("/health", get(health_check))
+    // 同步接口：挂在 /v1 下（要求 X-Sync-Protocol 请求头），不带前缀的旧路由保留给旧版本客户端（只读）
+    let api = Router::new()
         .route("/state", get(sync_state))
         .route("/metadata", get(sync_metadata))
+        .route("/merkle", post(sync_merkle_nodes))
         .route("/pull", get(sync_pull))
+        .route("/pull/stream", get(sync_pull_stream))
         .route("/push", post(sync_push))
+        .route("/crdt/pull", get(sync_crdt_pull))
+        .route("/crdt/push", post(sync_crdt_push))
+        .route("/conflicts", get(sync_conflicts_list))
+        .route("/conflicts/{id}", get(sync_conflicts_get))
+        .route("/conflicts/{id}/resolve", post(sync_conflicts_resolve))
+        .route("/devices", get(sync_devices_list))
+        .route("/assets/{uuid}", get(sync_asset_download))
+        .route("/assets/{uuid}/upload", get(sync_asset_upload_status).put(sync_asset_upload_chunk))
+        .route("/assets/{uuid}/upload/complete", post(sync_asset_upload_complete));
+    let legacy = api.clone().route_layer(axum::middleware::from_fn(legacy_protocol));
+    let v1 = api
+        .layer(axum::middleware::from_fn(require_protocol))
+        // 握手本身在请求体中声明版本，不经过请求头校验
+        .route("/handshake", post(sync_handshake));
+
+    // 构建路由
+    let app = Router::new()
+        .route("/", get(health_check))
+        .route("/health", get(health_check))
+        .merge(legacy)
+        .nest("/v1", v1)
         // .route("/api/notification", post(send_notification))
         // .route("/api/emit", post(emit_event))
+        // 按 Accept-Encoding 协商 gzip / zstd 压缩响应，按 Content-Encoding 解压请求体（/push）
+        .layer(CompressionLayer::new())
+        .layer(RequestDecompressionLayer::new())
         .layer(cors)
         .with_state(state);
 
@@ -629,7 +1748,810 @@ fn get_http_server_port() -> u16 {
     54577
 }
 
+// Tauri 命令：立即回收过期墓碑（retention_days 为空时使用配置的保留天数）
+#[cfg(not(mobile))]
+#[tauri::command]
+fn purge_sync_tombstones(app_handle: AppHandle, retention_days: Option<i64>) -> Result<sync_gc::GcReport, String> {
+    let mut conn = open_db(&app_handle).map_err(|e| e.to_string())?;
+    let days = retention_days.filter(|d| *d > 0).unwrap_or_else(|| sync_gc::retention_days(&conn));
+    sync_gc::purge_tombstones(&mut conn, days).map_err(|e| e.to_string())
+}
+
+// Tauri 命令：列出回收站中的记录（table 为空时包含所有同步表）
+#[cfg(not(mobile))]
+#[tauri::command]
+fn list_trash(app_handle: AppHandle, table: Option<String>) -> Result<Vec<sync_trash::TrashItem>, String> {
+    let conn = open_db(&app_handle).map_err(|e| e.to_string())?;
+    sync_trash::list_trash(&conn, table.as_deref()).map_err(|e| e.to_string())
+}
+
+// Tauri 命令：从回收站恢复一条记录
+#[cfg(not(mobile))]
+#[tauri::command]
+fn restore_trash_item(
+    app_handle: AppHandle,
+    sync: tauri::State<'_, Arc<SyncRuntime>>,
+    table: String,
+    pk: String,
+) -> Result<bool, String> {
+    let mut conn = open_db(&app_handle).map_err(|e| e.to_string())?;
+    sync.ensure_changelog(&mut conn);
+    sync_trash::restore(&conn, &table, &pk, &sync.clock).map_err(|e| e.to_string())
+}
+
+// Tauri 命令：彻底清除回收站中的一条记录
+#[cfg(not(mobile))]
+#[tauri::command]
+fn purge_trash_item(
+    app_handle: AppHandle,
+    sync: tauri::State<'_, Arc<SyncRuntime>>,
+    table: String,
+    pk: String,
+) -> Result<bool, String> {
+    let mut conn = open_db(&app_handle).map_err(|e| e.to_string())?;
+    sync.ensure_changelog(&mut conn);
+    sync_trash::purge(&mut conn, &table, &pk, &sync.clock).map_err(|e| e.to_string())
+}
+
+// Tauri 命令：列出一篇笔记的历史版本（最新的在前，不含正文）
+#[cfg(not(mobile))]
+#[tauri::command]
+fn list_note_revisions(app_handle: AppHandle, note_uuid: String) -> Result<Vec<note_revisions::RevisionSummary>, String> {
+    let conn = open_db(&app_handle).map_err(|e| e.to_string())?;
+    note_revisions::list(&conn, &note_uuid).map_err(|e| e.to_string())
+}
+
+// Tauri 命令：查看单个历史版本
+#[cfg(not(mobile))]
+#[tauri::command]
+fn get_note_revision(app_handle: AppHandle, id: i64) -> Result<Option<note_revisions::NoteRevision>, String> {
+    let conn = open_db(&app_handle).map_err(|e| e.to_string())?;
+    note_revisions::get(&conn, id).map_err(|e| e.to_string())
+}
 
+// Tauri 命令：比较历史版本与另一个版本（against 为空时与笔记当前内容比较）
+#[cfg(not(mobile))]
+#[tauri::command]
+fn diff_note_revision(
+    app_handle: AppHandle,
+    id: i64,
+    against: Option<i64>,
+) -> Result<Option<note_revisions::RevisionDiff>, String> {
+    let conn = open_db(&app_handle).map_err(|e| e.to_string())?;
+    note_revisions::diff_revision(&conn, id, against).map_err(|e| e.to_string())
+}
+
+// Tauri 命令：把笔记恢复为某个历史版本（作为一次新的写入同步到其他设备）
+#[cfg(not(mobile))]
+#[tauri::command]
+fn restore_note_revision(
+    app_handle: AppHandle,
+    sync: tauri::State<'_, Arc<SyncRuntime>>,
+    id: i64,
+) -> Result<bool, String> {
+    let mut conn = open_db(&app_handle).map_err(|e| e.to_string())?;
+    sync.ensure_changelog(&mut conn);
+    note_revisions::restore(&mut conn, id, &sync.clock).map_err(|e| e.to_string())
+}
+
+// Tauri 命令：读取历史版本保留策略
+#[cfg(not(mobile))]
+#[tauri::command]
+fn get_note_revision_retention(app_handle: AppHandle) -> Result<note_revisions::Retention, String> {
+    let conn = open_db(&app_handle).map_err(|e| e.to_string())?;
+    Ok(note_revisions::Retention::load(&conn))
+}
+
+// Tauri 命令：保存历史版本保留策略并立即按新策略清理
+#[cfg(not(mobile))]
+#[tauri::command]
+fn set_note_revision_retention(
+    app_handle: AppHandle,
+    retention: note_revisions::Retention,
+) -> Result<note_revisions::CompactReport, String> {
+    let mut conn = open_db(&app_handle).map_err(|e| e.to_string())?;
+    retention.save(&conn).map_err(|e| e.to_string())?;
+    let retention = note_revisions::Retention::load(&conn);
+    note_revisions::compact(&mut conn, &retention).map_err(|e| e.to_string())
+}
+
+// Tauri 命令：列出登记过的同步设备
+#[cfg(not(mobile))]
+#[tauri::command]
+fn list_sync_devices(app_handle: AppHandle) -> Result<Vec<sync_devices::SyncDevice>, String> {
+    let conn = open_db(&app_handle).map_err(|e| e.to_string())?;
+    sync_devices::list(&conn, sync_sequence::current(&conn)).map_err(|e| e.to_string())
+}
+
+// Tauri 命令：读取设备的同步范围（为空表示同步全部数据）
+#[cfg(not(mobile))]
+#[tauri::command]
+fn get_sync_device_scope(app_handle: AppHandle, device_id: String) -> Result<Option<sync_scopes::SyncScope>, String> {
+    let conn = open_db(&app_handle).map_err(|e| e.to_string())?;
+    sync_scopes::load(&conn, &device_id).map_err(|e| e.to_string())
+}
+
+// Tauri 命令：设置设备的同步范围，设备下一次同步时重新拉取；设备不存在时返回 false
+#[cfg(not(mobile))]
+#[tauri::command]
+fn set_sync_device_scope(
+    app_handle: AppHandle,
+    device_id: String,
+    scope: Option<sync_scopes::SyncScope>,
+) -> Result<bool, String> {
+    let conn = open_db(&app_handle).map_err(|e| e.to_string())?;
+    sync_scopes::save(&conn, &device_id, scope.as_ref()).map_err(|e| e.to_string())
+}
+
+// Tauri 命令：移除同步设备，之后它的同步请求会被拒绝
+#[cfg(not(mobile))]
+#[tauri::command]
+fn remove_sync_device(app_handle: AppHandle, device_id: String) -> Result<bool, String> {
+    let conn = open_db(&app_handle).map_err(|e| e.to_string())?;
+    sync_devices::revoke(&conn, &device_id).map_err(|e| e.to_string())
+}
+
+// Tauri 命令：作为客户端与另一台桌面端同步，进度通过 sync:progress 事件通知前端
+#[cfg(not(mobile))]
+#[tauri::command]
+async fn sync_with_peer(
+    app_handle: AppHandle,
+    sync: tauri::State<'_, Arc<SyncRuntime>>,
+    base_url: String,
+    token: Option<String>,
+) -> Result<sync_client::SyncRunReport, String> {
+    let sync = sync.inner().clone();
+    let mut conn = open_db(&app_handle).map_err(|e| e.to_string())?;
+    sync.ensure_changelog(&mut conn);
+
+    // 与前端一致：设备名优先使用用户设置的 sync_device_name
+    let name = conn
+        .query_row(
+            "SELECT value FROM settings WHERE key = 'sync_device_name' AND deleted_at IS NULL",
+            [],
+            |row| row.get::<_, String>(0),
+        )
+        .ok()
+        .filter(|v| !v.trim().is_empty())
+        .unwrap_or_else(|| format!("ZotePad {}", std::env::consts::OS));
+    let device = sync_devices::DeviceInfo {
+        device_id: sync.clock.device_id().to_string(),
+        name: Some(name),
+        app_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+    };
+
+    let client = sync_client::SyncClient::new(&base_url, token, device).map_err(|e| e.to_string())?;
+    let emitter = app_handle.clone();
+    let on_progress = move |progress: sync_client::SyncProgress| {
+        let _ = emitter.emit("sync:progress", progress);
+    };
+    let started = std::time::Instant::now();
+    let result = sync_client::run(&client, &mut conn, &sync.clock, &on_progress).await;
+
+    // 每次主动同步记录为一个会话，成功与失败都记录
+    let (bytes_in, bytes_out) = client.transferred();
+    let mut exchange = sync_sessions::Exchange {
+        bytes_in,
+        bytes_out,
+        duration_ms: started.elapsed().as_millis() as u64,
+        ..Default::default()
+    };
+    match &result {
+        Ok(report) => exchange.tables = report.tables.clone(),
+        Err(e) => exchange.error = Some(e.to_string()),
+    }
+    match sync_sessions::insert(&conn, sync_sessions::SessionRole::Client, Some(client.base_url()), &exchange) {
+        Ok(session) => {
+            let _ = app_handle.emit("sync:session", sync_sessions::SessionEvent { session, exchange });
+        }
+        Err(e) => log::warn!("[SyncSessions] 记录同步会话失败: {}", e),
+    }
+
+    let report = result.map_err(|e| {
+        log::error!("[SyncClient] 与 {} 同步失败: {}", base_url, e);
+        e.to_string()
+    })?;
+
+    if report.pulled > 0 {
+        let _ = app_handle.emit("sync:incoming", report.pulled);
+    }
+    Ok(report)
+}
+
+// Tauri 命令：按时间倒序分页查看同步会话；before 为上一页的 next_before
+#[cfg(not(mobile))]
+#[tauri::command]
+fn list_sync_sessions(
+    app_handle: AppHandle,
+    before: Option<i64>,
+    limit: Option<usize>,
+    peer_id: Option<String>,
+) -> Result<sync_sessions::SessionPage, String> {
+    let conn = open_db(&app_handle).map_err(|e| e.to_string())?;
+    sync_sessions::list(&conn, before, limit.unwrap_or(50), peer_id.as_deref()).map_err(|e| e.to_string())
+}
+
+// Tauri 命令：查看单条同步会话
+#[cfg(not(mobile))]
+#[tauri::command]
+fn get_sync_session(app_handle: AppHandle, id: i64) -> Result<Option<sync_sessions::SyncSession>, String> {
+    let conn = open_db(&app_handle).map_err(|e| e.to_string())?;
+    sync_sessions::get(&conn, id).map_err(|e| e.to_string())
+}
+
+// Tauri 命令：列出同步冲突
+#[cfg(not(mobile))]
+#[tauri::command]
+fn list_sync_conflicts(app_handle: AppHandle, status: Option<String>) -> Result<Vec<sync_conflicts::SyncConflict>, String> {
+    let conn = open_db(&app_handle).map_err(|e| e.to_string())?;
+    sync_conflicts::list_conflicts(&conn, status.as_deref()).map_err(|e| e.to_string())
+}
+
+// Tauri 命令：查看单条同步冲突
+#[cfg(not(mobile))]
+#[tauri::command]
+fn get_sync_conflict(app_handle: AppHandle, id: i64) -> Result<Option<sync_conflicts::SyncConflict>, String> {
+    let conn = open_db(&app_handle).map_err(|e| e.to_string())?;
+    sync_conflicts::get_conflict(&conn, id).map_err(|e| e.to_string())
+}
+
+// Tauri 命令：解决同步冲突
+#[cfg(not(mobile))]
+#[tauri::command]
+fn resolve_sync_conflict(
+    app_handle: AppHandle,
+    sync: tauri::State<'_, Arc<SyncRuntime>>,
+    id: i64,
+    resolution: sync_conflicts::Resolution,
+) -> Result<Option<sync_conflicts::SyncConflict>, String> {
+    let mut conn = open_db(&app_handle).map_err(|e| e.to_string())?;
+    sync.ensure_changelog(&mut conn);
+    sync_conflicts::resolve_conflict(&mut conn, id, resolution, &sync.clock)
+        .map_err(|e| e.to_string())
+}
+
+
+
+// 数据库迁移（按版本号顺序执行）
+fn migrations() -> Vec<Migration> {
+    vec![
+        // Migration 1: Init minimal tables (users, settings)
+        Migration {
+            version: 1,
+            description: "init_minimal_tables",
+            sql: "\
+CREATE TABLE IF NOT EXISTS users (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  name TEXT NOT NULL,
+  email TEXT NOT NULL UNIQUE,
+  created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+  updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS settings (
+  key TEXT PRIMARY KEY,
+  value TEXT NOT NULL,
+  category TEXT DEFAULT 'general',
+  created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+  updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+              ",
+            kind: MigrationKind::Up,
+        },
+        Migration {
+            version: 2,
+            description: "create_notes_table_with_sync_fields",
+            sql: "\
+CREATE TABLE IF NOT EXISTS notes (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  uuid TEXT UNIQUE NOT NULL,
+  title TEXT,
+  content TEXT,
+  tags TEXT DEFAULT '[]',
+  version INTEGER DEFAULT 0,
+  deleted_at DATETIME,
+  created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+  updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX IF NOT EXISTS idx_notes_version ON notes(version);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_notes_uuid ON notes(uuid);
+              ",
+            kind: MigrationKind::Up,
+        },
+        Migration {
+            version: 3,
+            description: "create_workflows_table_with_sync_fields",
+            sql: "\
+CREATE TABLE IF NOT EXISTS workflow_schemas (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  uuid TEXT UNIQUE NOT NULL,
+  name TEXT NOT NULL,
+  description TEXT,
+  fields TEXT DEFAULT '[]',
+  version INTEGER DEFAULT 0,
+  deleted_at DATETIME,
+  created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+  updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX IF NOT EXISTS idx_workflow_schemas_version ON workflow_schemas(version);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_workflow_schemas_uuid ON workflow_schemas(uuid);
+
+CREATE TABLE IF NOT EXISTS workflow_envs (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  key TEXT NOT NULL UNIQUE,
+  value TEXT NOT NULL,
+  created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+  updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS workflows (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  uuid TEXT UNIQUE NOT NULL,
+  name TEXT NOT NULL,
+  description TEXT,
+  steps TEXT NOT NULL DEFAULT '[]',
+  schema_id INTEGER,
+  type TEXT DEFAULT 'user',
+  version INTEGER DEFAULT 0,
+  deleted_at DATETIME,
+  created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+  updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX IF NOT EXISTS idx_workflows_version ON workflows(version);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_workflows_uuid ON workflows(uuid);
+              ",
+            kind: MigrationKind::Up,
+        },
+        Migration {
+            version: 4,
+            description: "create_moments_table_with_sync_fields",
+            sql: "\
+CREATE TABLE IF NOT EXISTS moments (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  uuid TEXT UNIQUE NOT NULL,
+  content TEXT,
+  images TEXT DEFAULT '[]',
+  tags TEXT DEFAULT '[]',
+  version INTEGER DEFAULT 0,
+  deleted_at DATETIME,
+  created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+  updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX IF NOT EXISTS idx_moments_version ON moments(version);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_moments_uuid ON moments(uuid);
+              ",
+            kind: MigrationKind::Up,
+        },
+        Migration {
+            version: 5,
+            description: "create_assets_with_sync_fields",
+            sql: "
+                CREATE TABLE IF NOT EXISTS assets (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    uuid TEXT UNIQUE NOT NULL,
+                    url TEXT NOT NULL,
+                    path TEXT NOT NULL,
+                    filename TEXT NOT NULL,
+                    size INTEGER,
+                    mime_type TEXT,
+                    storage_type TEXT DEFAULT 'cos',
+                    version INTEGER DEFAULT 0,
+                    deleted_at DATETIME,
+                    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                    created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+                );
+                CREATE INDEX IF NOT EXISTS idx_assets_version ON assets(version);
+                CREATE UNIQUE INDEX IF NOT EXISTS idx_assets_uuid ON assets(uuid);
+            ",
+            kind: MigrationKind::Up,
+        },
+        // Migration 6: Achievement system tables
+        Migration {
+            version: 6,
+            description: "create_achievement_system_tables",
+            sql: "\
+                CREATE TABLE IF NOT EXISTS achievements (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    key TEXT NOT NULL UNIQUE,
+                    name TEXT NOT NULL,
+                    description TEXT,
+                    type TEXT NOT NULL,
+                    category TEXT NOT NULL,
+                    points INTEGER DEFAULT 0,
+                    exp INTEGER DEFAULT 0,
+                    icon TEXT,
+                    rule_config TEXT,
+                    max_level INTEGER DEFAULT 1,
+                    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+                );
+                CREATE INDEX IF NOT EXISTS idx_achievements_type ON achievements(type);
+                CREATE INDEX IF NOT EXISTS idx_achievements_category ON achievements(category);
+
+                CREATE TABLE IF NOT EXISTS user_achievements (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    user_id INTEGER NOT NULL,
+                    achievement_key TEXT NOT NULL,
+                    level INTEGER DEFAULT 1,
+                    progress INTEGER DEFAULT 0,
+                    total_points INTEGER DEFAULT 0,
+                    total_exp INTEGER DEFAULT 0,
+                    unlocked_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                    device_id TEXT,
+                    synced_at DATETIME,
+                    UNIQUE(user_id, achievement_key)
+                );
+                CREATE INDEX IF NOT EXISTS idx_user_achievements_user ON user_achievements(user_id);
+                CREATE INDEX IF NOT EXISTS idx_user_achievements_synced ON user_achievements(synced_at);
+
+                CREATE TABLE IF NOT EXISTS user_stats (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    user_id INTEGER NOT NULL,
+                    stat_key TEXT NOT NULL,
+                    stat_value TEXT NOT NULL,
+                    stat_type TEXT DEFAULT 'counter',
+                    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                    device_id TEXT,
+                    synced_at DATETIME,
+                    UNIQUE(user_id, stat_key)
+                );
+                CREATE INDEX IF NOT EXISTS idx_user_stats_user ON user_stats(user_id);
+                CREATE INDEX IF NOT EXISTS idx_user_stats_key ON user_stats(stat_key);
+                CREATE INDEX IF NOT EXISTS idx_user_stats_synced ON user_stats(synced_at);
+
+                CREATE TABLE IF NOT EXISTS user_points_log (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    user_id INTEGER NOT NULL,
+                    operation_id TEXT NOT NULL UNIQUE,
+                    source_type TEXT NOT NULL,
+                    source_id TEXT NOT NULL,
+                    achievement_key TEXT,
+                    points INTEGER NOT NULL,
+                    exp INTEGER NOT NULL,
+                    reason TEXT,
+                    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                    device_id TEXT,
+                    synced_at DATETIME
+                );
+                CREATE INDEX IF NOT EXISTS idx_points_log_user ON user_points_log(user_id);
+                CREATE INDEX IF NOT EXISTS idx_points_log_operation ON user_points_log(operation_id);
+                CREATE INDEX IF NOT EXISTS idx_points_log_synced ON user_points_log(synced_at);
+                CREATE INDEX IF NOT EXISTS idx_points_log_created ON user_points_log(created_at);
+
+                CREATE TABLE IF NOT EXISTS user_achievement_profile (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    user_id INTEGER NOT NULL UNIQUE,
+                    total_points INTEGER DEFAULT 0,
+                    total_exp INTEGER DEFAULT 0,
+                    current_level INTEGER DEFAULT 1,
+                    title TEXT,
+                    achievements_count INTEGER DEFAULT 0,
+                    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+                );
+                CREATE INDEX IF NOT EXISTS idx_profile_user ON user_achievement_profile(user_id);
+
+                INSERT OR IGNORE INTO achievements (key, name, description, type, category, points, exp, icon, rule_config, max_level) VALUES
+                ('writing_first_note', '初出茅庐', '创建第一篇笔记', 'milestone', 'writing', 10, 5, '📝', '{\"metric\":\"content.notes_total\",\"target\":1}', 1),
+                ('writing_10_notes', '勤奋笔者', '创建10篇笔记', 'milestone', 'writing', 50, 20, '✍️', '{\"metric\":\"content.notes_total\",\"target\":10}', 1),
+                ('writing_50_notes', '笔记达人', '创建50篇笔记', 'milestone', 'writing', 200, 100, '📚', '{\"metric\":\"content.notes_total\",\"target\":50}', 1),
+                ('writing_words', '文字工匠', '累计书写字数（可升级）', 'progressive', 'writing', 10, 5, '✨', '{\"metric\":\"content.words_total\",\"baseTarget\":1000,\"rate\":2}', 999),
+                ('social_first_moment', '分享时刻', '发布第一条动态', 'milestone', 'social', 10, 5, '💬', '{\"metric\":\"content.moments_total\",\"target\":1}', 1),
+                ('social_10_moments', '活跃用户', '发布10条动态', 'milestone', 'social', 50, 20, '🎉', '{\"metric\":\"content.moments_total\",\"target\":10}', 1),
+                ('asset_first_image', '摄影起步', '上传第一张图片', 'milestone', 'asset', 10, 5, '📷', '{\"metric\":\"asset.images_total\",\"target\":1}', 1),
+                ('asset_collector', '素材收藏家', '累计上传素材（可升级）', 'progressive', 'asset', 10, 5, '🗂️', '{\"metric\":\"asset.total\",\"baseTarget\":10,\"rate\":2}', 999);
+            ",
+            kind: MigrationKind::Up,
+        },
+        // Migration 7: HLC columns for sync conflict ordering
+        Migration {
+            version: 7,
+            description: "add_hlc_columns_and_sync_meta",
+            sql: "\
+                ALTER TABLE notes ADD COLUMN hlc TEXT;
+                ALTER TABLE moments ADD COLUMN hlc TEXT;
+                ALTER TABLE assets ADD COLUMN hlc TEXT;
+                ALTER TABLE workflows ADD COLUMN hlc TEXT;
+                ALTER TABLE workflow_schemas ADD COLUMN hlc TEXT;
+
+                CREATE TABLE IF NOT EXISTS sync_meta (
+                    key TEXT PRIMARY KEY,
+                    value TEXT NOT NULL,
+                    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+                );
+            ",
+            kind: MigrationKind::Up,
+        },
+        // Migration 8: Three-way merge bases + client-side base version
+        Migration {
+            version: 8,
+            description: "add_sync_merge_bases",
+            sql: "\
+                CREATE TABLE IF NOT EXISTS sync_merge_bases (
+                    table_name TEXT NOT NULL,
+                    pk TEXT NOT NULL,
+                    version INTEGER NOT NULL,
+                    snapshot TEXT NOT NULL,
+                    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                    PRIMARY KEY (table_name, pk, version)
+                );
+
+                ALTER TABLE notes ADD COLUMN base_version INTEGER DEFAULT 0;
+                ALTER TABLE moments ADD COLUMN base_version INTEGER DEFAULT 0;
+                ALTER TABLE assets ADD COLUMN base_version INTEGER DEFAULT 0;
+                ALTER TABLE workflows ADD COLUMN base_version INTEGER DEFAULT 0;
+                ALTER TABLE workflow_schemas ADD COLUMN base_version INTEGER DEFAULT 0;
+            ",
+            kind: MigrationKind::Up,
+        },
+        // Migration 9: Server-side sync conflict queue
+        Migration {
+            version: 9,
+            description: "create_sync_conflicts",
+            sql: "\
+                CREATE TABLE IF NOT EXISTS sync_conflicts (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    table_name TEXT NOT NULL,
+                    pk TEXT NOT NULL,
+                    base_version INTEGER NOT NULL,
+                    local_version INTEGER NOT NULL,
+                    local_data TEXT NOT NULL,
+                    remote_change TEXT NOT NULL,
+                    remote_device TEXT,
+                    status TEXT NOT NULL DEFAULT 'open',
+                    resolution TEXT,
+                    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                    resolved_at DATETIME
+                );
+                CREATE INDEX IF NOT EXISTS idx_sync_conflicts_status ON sync_conflicts(status);
+                CREATE INDEX IF NOT EXISTS idx_sync_conflicts_record ON sync_conflicts(table_name, pk);
+            ",
+            kind: MigrationKind::Up,
+        },
+        // Migration 10: Trigger-maintained sync change log (triggers are installed by the desktop server)
+        Migration {
+            version: 10,
+            description: "create_sync_changelog",
+            sql: "\
+                CREATE TABLE IF NOT EXISTS sync_changelog (
+                    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+                    table_name TEXT NOT NULL,
+                    pk TEXT NOT NULL,
+                    op TEXT NOT NULL,
+                    changed_columns TEXT,
+                    version INTEGER,
+                    hlc TEXT,
+                    created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+                );
+                CREATE INDEX IF NOT EXISTS idx_sync_changelog_record ON sync_changelog(table_name, pk, seq);
+                CREATE INDEX IF NOT EXISTS idx_sync_changelog_version ON sync_changelog(table_name, version);
+            ",
+            kind: MigrationKind::Up,
+        },
+        // Migration 11: Durable version sequence (seeded and bumped by the desktop server's triggers)
+        Migration {
+            version: 11,
+            description: "create_sync_sequence",
+            sql: "\
+                CREATE TABLE IF NOT EXISTS sync_sequence (
+                    name TEXT PRIMARY KEY,
+                    value INTEGER NOT NULL DEFAULT 0
+                );
+            ",
+            kind: MigrationKind::Up,
+        },
+        // Migration 12: Sync columns for settings (synced keys are chosen per key, see sync_settings)
+        Migration {
+            version: 12,
+            description: "add_sync_columns_to_settings",
+            sql: "\
+                ALTER TABLE settings ADD COLUMN version INTEGER DEFAULT 0;
+                ALTER TABLE settings ADD COLUMN deleted_at DATETIME;
+                ALTER TABLE settings ADD COLUMN hlc TEXT;
+                ALTER TABLE settings ADD COLUMN base_version INTEGER DEFAULT 0;
+            ",
+            kind: MigrationKind::Up,
+        },
+        // Migration 13: CRDT sync for achievement data (per-device stat shards, ledger and achievement versions)
+        Migration {
+            version: 13,
+            description: "add_crdt_achievement_sync",
+            sql: "\
+                CREATE TABLE IF NOT EXISTS user_stat_shards (
+                    user_id INTEGER NOT NULL,
+                    stat_key TEXT NOT NULL,
+                    device_id TEXT NOT NULL,
+                    stat_type TEXT NOT NULL DEFAULT 'counter',
+                    stat_value TEXT NOT NULL,
+                    updated_at INTEGER NOT NULL DEFAULT 0,
+                    version INTEGER DEFAULT 0,
+                    synced_at DATETIME,
+                    PRIMARY KEY (user_id, stat_key, device_id)
+                );
+                CREATE INDEX IF NOT EXISTS idx_user_stat_shards_version ON user_stat_shards(version);
+
+                INSERT OR IGNORE INTO user_stat_shards (user_id, stat_key, device_id, stat_type, stat_value, updated_at)
+                SELECT user_id, stat_key,
+                       COALESCE(device_id, (SELECT value FROM sync_meta WHERE key = 'device_id'), 'legacy-' || lower(hex(randomblob(8)))),
+                       COALESCE(stat_type, 'counter'), stat_value,
+                       CASE WHEN typeof(updated_at) = 'integer' THEN updated_at
+                            ELSE COALESCE(CAST(strftime('%s', updated_at) AS INTEGER) * 1000, 0) END
+                FROM user_stats;
+
+                ALTER TABLE user_points_log ADD COLUMN version INTEGER DEFAULT 0;
+                ALTER TABLE user_achievements ADD COLUMN version INTEGER DEFAULT 0;
+                CREATE INDEX IF NOT EXISTS idx_points_log_version ON user_points_log(version);
+                CREATE INDEX IF NOT EXISTS idx_user_achievements_version ON user_achievements(version);
+            ",
+            kind: MigrationKind::Up,
+        },
+        // Migration 14: Devices seen by the desktop sync server
+        Migration {
+            version: 14,
+            description: "create_sync_devices",
+            sql: "\
+                CREATE TABLE IF NOT EXISTS sync_devices (
+                    device_id TEXT PRIMARY KEY,
+                    name TEXT,
+                    app_version TEXT,
+                    first_seen_at DATETIME,
+                    last_seen_at DATETIME,
+                    last_pulled_version INTEGER DEFAULT 0,
+                    last_pushed_version INTEGER DEFAULT 0,
+                    revoked_at DATETIME
+                );
+            ",
+            kind: MigrationKind::Up,
+        },
+        // Migration 15: Per-record version vectors and per-replica knowledge for multi-master sync
+        Migration {
+            version: 15,
+            description: "add_version_vectors",
+            sql: "\
+                ALTER TABLE notes ADD COLUMN vv TEXT;
+                ALTER TABLE moments ADD COLUMN vv TEXT;
+                ALTER TABLE assets ADD COLUMN vv TEXT;
+                ALTER TABLE workflows ADD COLUMN vv TEXT;
+                ALTER TABLE workflow_schemas ADD COLUMN vv TEXT;
+                ALTER TABLE settings ADD COLUMN vv TEXT;
+
+                CREATE TABLE IF NOT EXISTS sync_knowledge (
+                    replica_id TEXT PRIMARY KEY,
+                    counter INTEGER NOT NULL DEFAULT 0,
+                    updated_at DATETIME
+                );
+            ",
+            kind: MigrationKind::Up,
+        },
+        // Migration 16: Sync session journal (one row per peer exchange)
+        Migration {
+            version: 16,
+            description: "create_sync_sessions",
+            sql: "\
+                CREATE TABLE IF NOT EXISTS sync_sessions (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    role TEXT NOT NULL,
+                    peer_id TEXT,
+                    started_at DATETIME NOT NULL,
+                    ended_at DATETIME NOT NULL,
+                    duration_ms INTEGER NOT NULL DEFAULT 0,
+                    requests INTEGER NOT NULL DEFAULT 0,
+                    bytes_in INTEGER NOT NULL DEFAULT 0,
+                    bytes_out INTEGER NOT NULL DEFAULT 0,
+                    tables TEXT NOT NULL DEFAULT '{}',
+                    errors TEXT NOT NULL DEFAULT '[]'
+                );
+                CREATE INDEX IF NOT EXISTS idx_sync_sessions_peer ON sync_sessions(peer_id, id);
+            ",
+            kind: MigrationKind::Up,
+        },
+        // Migration 17: Note revision history (snapshots taken by trigger before title/content are overwritten)
+        Migration {
+            version: 17,
+            description: "create_note_revisions",
+            sql: "\
+                CREATE TABLE IF NOT EXISTS note_revisions (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    note_uuid TEXT NOT NULL,
+                    title TEXT,
+                    content TEXT,
+                    source TEXT NOT NULL DEFAULT 'edit',
+                    version INTEGER,
+                    edited_at TEXT,
+                    created_at TEXT NOT NULL
+                );
+                CREATE INDEX IF NOT EXISTS idx_note_revisions_note ON note_revisions(note_uuid, id);
+
+                DROP TRIGGER IF EXISTS note_revisions_capture;
+                CREATE TRIGGER note_revisions_capture AFTER UPDATE OF title, content ON notes
+                WHEN OLD.title IS NOT NEW.title OR OLD.content IS NOT NEW.content
+                BEGIN
+                    INSERT INTO note_revisions (note_uuid, title, content, source, version, edited_at, created_at)
+                    VALUES (
+                        OLD.uuid, OLD.title, OLD.content,
+                        CASE WHEN NEW.hlc IS NOT OLD.hlc THEN 'sync' ELSE 'edit' END,
+                        OLD.version, OLD.updated_at,
+                        strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+                    );
+                    DELETE FROM note_revisions WHERE note_uuid = OLD.uuid AND id <= (
+                        SELECT id FROM note_revisions WHERE note_uuid = OLD.uuid ORDER BY id DESC LIMIT 1 OFFSET 500
+                    );
+                END;
+            ",
+            kind: MigrationKind::Up,
+        },
+        // Migration 18: Per-device sync scopes (tags / tables / note age) and pending resync marks
+        Migration {
+            version: 18,
+            description: "add_sync_device_scopes",
+            sql: "\
+                ALTER TABLE sync_devices ADD COLUMN scope TEXT;
+                ALTER TABLE sync_devices ADD COLUMN scope_pending TEXT;
+                ALTER TABLE sync_devices ADD COLUMN scope_checked_at TEXT;
+            ",
+            kind: MigrationKind::Up,
+        },
+        // Migration 19: Remember each setting key's category (decides its sync policy, survives hard deletes)
+        Migration {
+            version: 19,
+            description: "create_setting_categories",
+            sql: "\
+                CREATE TABLE IF NOT EXISTS setting_categories (
+                    key TEXT PRIMARY KEY,
+                    category TEXT
+                );
+                INSERT OR IGNORE INTO setting_categories (key, category) SELECT key, category FROM settings;
+
+                DROP TRIGGER IF EXISTS setting_categories_insert;
+                CREATE TRIGGER setting_categories_insert AFTER INSERT ON settings
+                BEGIN
+                    INSERT OR REPLACE INTO setting_categories (key, category) VALUES (NEW.key, NEW.category);
+                END;
+
+                DROP TRIGGER IF EXISTS setting_categories_update;
+                CREATE TRIGGER setting_categories_update AFTER UPDATE OF key, category ON settings
+                BEGIN
+                    INSERT OR REPLACE INTO setting_categories (key, category) VALUES (NEW.key, NEW.category);
+                END;
+            ",
+            kind: MigrationKind::Up,
+        },
+        // Migration 20: Label note revisions by the writing device (local edits also stamp a new HLC)
+        Migration {
+            version: 20,
+            description: "label_note_revisions_by_device",
+            sql: "\
+                DROP TRIGGER IF EXISTS note_revisions_capture;
+                CREATE TRIGGER note_revisions_capture AFTER UPDATE OF title, content ON notes
+                WHEN OLD.title IS NOT NEW.title OR OLD.content IS NOT NEW.content
+                BEGIN
+                    INSERT INTO note_revisions (note_uuid, title, content, source, version, edited_at, created_at)
+                    VALUES (
+                        OLD.uuid, OLD.title, OLD.content,
+                        CASE
+                            WHEN NEW.hlc IS OLD.hlc THEN 'edit'
+                            WHEN substr(NEW.hlc, 21) = (SELECT value FROM sync_meta WHERE key = 'device_id') THEN 'edit'
+                            ELSE 'sync'
+                        END,
+                        OLD.version, OLD.updated_at,
+                        strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+                    );
+                    DELETE FROM note_revisions WHERE note_uuid = OLD.uuid AND id <= (
+                        SELECT id FROM note_revisions WHERE note_uuid = OLD.uuid ORDER BY id DESC LIMIT 1 OFFSET 500
+                    );
+                END;
+            ",
+            kind: MigrationKind::Up,
+        },
+        // Migration 21: Mark hard deletes that purged a trashed record (peers hard-delete instead of soft-deleting)
+        Migration {
+            version: 21,
+            description: "add_sync_changelog_purged",
+            sql: "ALTER TABLE sync_changelog ADD COLUMN purged INTEGER NOT NULL DEFAULT 0;",
+            kind: MigrationKind::Up,
+        },
+    ]
+}
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
@@ -648,237 +2570,7 @@ pub fn run() {
             tauri_plugin_sql::Builder::default()
                 .add_migrations(
                     "sqlite:app_v5.db",
-                    vec![
-                        // Migration 1: Init minimal tables (users, settings)
-                        Migration {
-                            version: 1,
-                            description: "init_minimal_tables",
-                            sql: "\
-                CREATE TABLE IF NOT EXISTS users (
-                  id INTEGER PRIMARY KEY AUTOINCREMENT,
-                  name TEXT NOT NULL,
-                  email TEXT NOT NULL UNIQUE,
-                  created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-                  updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
-                );
-                
-                CREATE TABLE IF NOT EXISTS settings (
-                  key TEXT PRIMARY KEY,
-                  value TEXT NOT NULL,
-                  category TEXT DEFAULT 'general',
-                  created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-                  updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
-                );
-              ",
-                            kind: MigrationKind::Up,
-                        },
-                        Migration {
-                            version: 2,
-                            description: "create_notes_table_with_sync_fields",
-                            sql: "\
-                CREATE TABLE IF NOT EXISTS notes (
-                  id INTEGER PRIMARY KEY AUTOINCREMENT,
-                  uuid TEXT UNIQUE NOT NULL,
-                  title TEXT,
-                  content TEXT,
-                  tags TEXT DEFAULT '[]',
-                  version INTEGER DEFAULT 0,
-                  deleted_at DATETIME,
-                  created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-                  updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
-                );
-                CREATE INDEX IF NOT EXISTS idx_notes_version ON notes(version);
-                CREATE UNIQUE INDEX IF NOT EXISTS idx_notes_uuid ON notes(uuid);
-              ",
-                            kind: MigrationKind::Up,
-                        },
-                        Migration {
-                            version: 3,
-                            description: "create_workflows_table_with_sync_fields",
-                            sql: "\
-                CREATE TABLE IF NOT EXISTS workflow_schemas (
-                  id INTEGER PRIMARY KEY AUTOINCREMENT,
-                  uuid TEXT UNIQUE NOT NULL,
-                  name TEXT NOT NULL,
-                  description TEXT,
-                  fields TEXT DEFAULT '[]',
-                  version INTEGER DEFAULT 0,
-                  deleted_at DATETIME,
-                  created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-                  updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
-                );
-                CREATE INDEX IF NOT EXISTS idx_workflow_schemas_version ON workflow_schemas(version);
-                CREATE UNIQUE INDEX IF NOT EXISTS idx_workflow_schemas_uuid ON workflow_schemas(uuid);
-
-                CREATE TABLE IF NOT EXISTS workflow_envs (
-                  id INTEGER PRIMARY KEY AUTOINCREMENT,
-                  key TEXT NOT NULL UNIQUE,
-                  value TEXT NOT NULL,
-                  created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-                  updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
-                );
-
-                CREATE TABLE IF NOT EXISTS workflows (
-                  id INTEGER PRIMARY KEY AUTOINCREMENT,
-                  uuid TEXT UNIQUE NOT NULL,
-                  name TEXT NOT NULL,
-                  description TEXT,
-                  steps TEXT NOT NULL DEFAULT '[]',
-                  schema_id INTEGER,
-                  type TEXT DEFAULT 'user',
-                  version INTEGER DEFAULT 0,
-                  deleted_at DATETIME,
-                  created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-                  updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
-                );
-                CREATE INDEX IF NOT EXISTS idx_workflows_version ON workflows(version);
-                CREATE UNIQUE INDEX IF NOT EXISTS idx_workflows_uuid ON workflows(uuid);
-              ",
-                            kind: MigrationKind::Up,
-                        },
-                        Migration {
-                            version: 4,
-                            description: "create_moments_table_with_sync_fields",
-                            sql: "\
-                CREATE TABLE IF NOT EXISTS moments (
-                  id INTEGER PRIMARY KEY AUTOINCREMENT,
-                  uuid TEXT UNIQUE NOT NULL,
-                  content TEXT,
-                  images TEXT DEFAULT '[]',
-                  tags TEXT DEFAULT '[]',
-                  version INTEGER DEFAULT 0,
-                  deleted_at DATETIME,
-                  created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-                  updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
-                );
-                CREATE INDEX IF NOT EXISTS idx_moments_version ON moments(version);
-                CREATE UNIQUE INDEX IF NOT EXISTS idx_moments_uuid ON moments(uuid);
-              ",
-                            kind: MigrationKind::Up,
-                        },
-                        Migration {
-                            version: 5,
-                            description: "create_assets_with_sync_fields",
-                            sql: "
-                                CREATE TABLE IF NOT EXISTS assets (
-                                    id INTEGER PRIMARY KEY AUTOINCREMENT,
-                                    uuid TEXT UNIQUE NOT NULL,
-                                    url TEXT NOT NULL,
-                                    path TEXT NOT NULL,
-                                    filename TEXT NOT NULL,
-                                    size INTEGER,
-                                    mime_type TEXT,
-                                    storage_type TEXT DEFAULT 'cos',
-                                    version INTEGER DEFAULT 0,
-                                    deleted_at DATETIME,
-                                    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-                                    created_at DATETIME DEFAULT CURRENT_TIMESTAMP
-                                );
-                                CREATE INDEX IF NOT EXISTS idx_assets_version ON assets(version);
-                                CREATE UNIQUE INDEX IF NOT EXISTS idx_assets_uuid ON assets(uuid);
-                            ",
-                            kind: MigrationKind::Up,
-                        },
-                        // Migration 6: Achievement system tables
-                        Migration {
-                            version: 6,
-                            description: "create_achievement_system_tables",
-                            sql: "\
-                                CREATE TABLE IF NOT EXISTS achievements (
-                                    id INTEGER PRIMARY KEY AUTOINCREMENT,
-                                    key TEXT NOT NULL UNIQUE,
-                                    name TEXT NOT NULL,
-                                    description TEXT,
-                                    type TEXT NOT NULL,
-                                    category TEXT NOT NULL,
-                                    points INTEGER DEFAULT 0,
-                                    exp INTEGER DEFAULT 0,
-                                    icon TEXT,
-                                    rule_config TEXT,
-                                    max_level INTEGER DEFAULT 1,
-                                    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-                                    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
-                                );
-                                CREATE INDEX IF NOT EXISTS idx_achievements_type ON achievements(type);
-                                CREATE INDEX IF NOT EXISTS idx_achievements_category ON achievements(category);
-
-                                CREATE TABLE IF NOT EXISTS user_achievements (
-                                    id INTEGER PRIMARY KEY AUTOINCREMENT,
-                                    user_id INTEGER NOT NULL,
-                                    achievement_key TEXT NOT NULL,
-                                    level INTEGER DEFAULT 1,
-                                    progress INTEGER DEFAULT 0,
-                                    total_points INTEGER DEFAULT 0,
-                                    total_exp INTEGER DEFAULT 0,
-                                    unlocked_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-                                    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-                                    device_id TEXT,
-                                    synced_at DATETIME,
-                                    UNIQUE(user_id, achievement_key)
-                                );
-                                CREATE INDEX IF NOT EXISTS idx_user_achievements_user ON user_achievements(user_id);
-                                CREATE INDEX IF NOT EXISTS idx_user_achievements_synced ON user_achievements(synced_at);
-
-                                CREATE TABLE IF NOT EXISTS user_stats (
-                                    id INTEGER PRIMARY KEY AUTOINCREMENT,
-                                    user_id INTEGER NOT NULL,
-                                    stat_key TEXT NOT NULL,
-                                    stat_value TEXT NOT NULL,
-                                    stat_type TEXT DEFAULT 'counter',
-                                    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-                                    device_id TEXT,
-                                    synced_at DATETIME,
-                                    UNIQUE(user_id, stat_key)
-                                );
-                                CREATE INDEX IF NOT EXISTS idx_user_stats_user ON user_stats(user_id);
-                                CREATE INDEX IF NOT EXISTS idx_user_stats_key ON user_stats(stat_key);
-                                CREATE INDEX IF NOT EXISTS idx_user_stats_synced ON user_stats(synced_at);
-
-                                CREATE TABLE IF NOT EXISTS user_points_log (
-                                    id INTEGER PRIMARY KEY AUTOINCREMENT,
-                                    user_id INTEGER NOT NULL,
-                                    operation_id TEXT NOT NULL UNIQUE,
-                                    source_type TEXT NOT NULL,
-                                    source_id TEXT NOT NULL,
-                                    achievement_key TEXT,
-                                    points INTEGER NOT NULL,
-                                    exp INTEGER NOT NULL,
-                                    reason TEXT,
-                                    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-                                    device_id TEXT,
-                                    synced_at DATETIME
-                                );
-                                CREATE INDEX IF NOT EXISTS idx_points_log_user ON user_points_log(user_id);
-                                CREATE INDEX IF NOT EXISTS idx_points_log_operation ON user_points_log(operation_id);
-                                CREATE INDEX IF NOT EXISTS idx_points_log_synced ON user_points_log(synced_at);
-                                CREATE INDEX IF NOT EXISTS idx_points_log_created ON user_points_log(created_at);
-
-                                CREATE TABLE IF NOT EXISTS user_achievement_profile (
-                                    id INTEGER PRIMARY KEY AUTOINCREMENT,
-                                    user_id INTEGER NOT NULL UNIQUE,
-                                    total_points INTEGER DEFAULT 0,
-                                    total_exp INTEGER DEFAULT 0,
-                                    current_level INTEGER DEFAULT 1,
-                                    title TEXT,
-                                    achievements_count INTEGER DEFAULT 0,
-                                    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
-                                );
-                                CREATE INDEX IF NOT EXISTS idx_profile_user ON user_achievement_profile(user_id);
-
-                                INSERT OR IGNORE INTO achievements (key, name, description, type, category, points, exp, icon, rule_config, max_level) VALUES
-                                ('writing_first_note', '初出茅庐', '创建第一篇笔记', 'milestone', 'writing', 10, 5, '📝', '{\"metric\":\"content.notes_total\",\"target\":1}', 1),
-                                ('writing_10_notes', '勤奋笔者', '创建10篇笔记', 'milestone', 'writing', 50, 20, '✍️', '{\"metric\":\"content.notes_total\",\"target\":10}', 1),
-                                ('writing_50_notes', '笔记达人', '创建50篇笔记', 'milestone', 'writing', 200, 100, '📚', '{\"metric\":\"content.notes_total\",\"target\":50}', 1),
-                                ('writing_words', '文字工匠', '累计书写字数（可升级）', 'progressive', 'writing', 10, 5, '✨', '{\"metric\":\"content.words_total\",\"baseTarget\":1000,\"rate\":2}', 999),
-                                ('social_first_moment', '分享时刻', '发布第一条动态', 'milestone', 'social', 10, 5, '💬', '{\"metric\":\"content.moments_total\",\"target\":1}', 1),
-                                ('social_10_moments', '活跃用户', '发布10条动态', 'milestone', 'social', 50, 20, '🎉', '{\"metric\":\"content.moments_total\",\"target\":10}', 1),
-                                ('asset_first_image', '摄影起步', '上传第一张图片', 'milestone', 'asset', 10, 5, '📷', '{\"metric\":\"asset.images_total\",\"target\":1}', 1),
-                                ('asset_collector', '素材收藏家', '累计上传素材（可升级）', 'progressive', 'asset', 10, 5, '🗂️', '{\"metric\":\"asset.total\",\"baseTarget\":10,\"rate\":2}', 999);
-                            ",
-                            kind: MigrationKind::Up,
-                        },
-
-                    ],
+                    migrations(),
                 )
                 .build(),
         )
@@ -900,6 +2592,46 @@ pub fn run() {
             get_local_ip,
             #[cfg(not(mobile))]
             get_http_server_port,
+            #[cfg(not(mobile))]
+            list_sync_conflicts,
+            #[cfg(not(mobile))]
+            get_sync_conflict,
+            #[cfg(not(mobile))]
+            resolve_sync_conflict,
+            #[cfg(not(mobile))]
+            purge_sync_tombstones,
+            #[cfg(not(mobile))]
+            list_trash,
+            #[cfg(not(mobile))]
+            restore_trash_item,
+            #[cfg(not(mobile))]
+            purge_trash_item,
+            #[cfg(not(mobile))]
+            list_sync_devices,
+            #[cfg(not(mobile))]
+            get_sync_device_scope,
+            #[cfg(not(mobile))]
+            set_sync_device_scope,
+            #[cfg(not(mobile))]
+            remove_sync_device,
+            #[cfg(not(mobile))]
+            sync_with_peer,
+            #[cfg(not(mobile))]
+            list_sync_sessions,
+            #[cfg(not(mobile))]
+            get_sync_session,
+            #[cfg(not(mobile))]
+            list_note_revisions,
+            #[cfg(not(mobile))]
+            get_note_revision,
+            #[cfg(not(mobile))]
+            diff_note_revision,
+            #[cfg(not(mobile))]
+            restore_note_revision,
+            #[cfg(not(mobile))]
+            get_note_revision_retention,
+            #[cfg(not(mobile))]
+            set_note_revision_retention,
             compress_image
         ])
         .setup(|app| {
@@ -908,10 +2640,19 @@ pub fn run() {
             {
                 let app_handle = app.handle().clone();
                 let port = 54577; // HTTP 服务器端口
+
+                // 注册可同步的表（SyncRuntime::load 会按实际表结构校验）
+                if !sync_registry::SyncRegistry::with_builtin_tables().install() {
+                    log::warn!("[SyncRegistry] 注册表已初始化，忽略重复注册");
+                }
+
+                // 同步运行时由 HTTP 服务与 Tauri 命令共享
+                let sync = Arc::new(SyncRuntime::load(&app_handle));
+                app.manage(sync.clone());
                 
                 std::thread::spawn(move || {
                     let rt = tokio::runtime::Runtime::new().unwrap();
-                    rt.block_on(start_http_server(app_handle, port));
+                    rt.block_on(start_http_server(app_handle, port, sync));
                 });
                 
                 log::info!("HTTP server will start on port {}", port);
@@ -0,0 +1,336 @@
+//! 三方文本合并
+//! 以行（段落）为单位做 diff3：只有两端修改了同一区域时才输出冲突标记
+
+/// 冲突标记（本机 = 服务端当前内容，远程 = 推送方内容）
+const MARKER_LOCAL: &str = "<<<<<<< 本机";
+const MARKER_SEP: &str = "=======";
+const MARKER_REMOTE: &str = ">>>>>>> 远程";
+
+/// LCS 动态规划表的最大单元数，超过时退化为整体比较，避免超长文本占用过多内存
+const MAX_LCS_CELLS: usize = 4_000_000;
+
+/// 合并结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeResult {
+    pub text: String,
+    /// 是否存在重叠修改（已写入冲突标记）
+    pub conflicted: bool,
+}
+
+/// 三方合并：base 为共同祖先，local / remote 为两端各自的修改
+pub fn merge3(base: &str, local: &str, remote: &str) -> MergeResult {
+    // 快速路径：任意一端未修改，直接取另一端
+    if local == remote || remote == base {
+        return MergeResult { text: local.to_string(), conflicted: false };
+    }
+    if local == base {
+        return MergeResult { text: remote.to_string(), conflicted: false };
+    }
+
+    let base_lines = split_lines(base);
+    let local_lines = split_lines(local);
+    let remote_lines = split_lines(remote);
+
+    let to_local = lcs_matches(&base_lines, &local_lines);
+    let to_remote = lcs_matches(&base_lines, &remote_lines);
+
+    let mut out = String::with_capacity(local.len().max(remote.len()));
+    let mut conflicted = false;
+
+    let (mut i, mut il, mut ir) = (0usize, 0usize, 0usize);
+    let n = base_lines.len();
+
+    while i < n || il < local_lines.len() || ir < remote_lines.len() {
+        // 稳定行：三端在此处一致
+        if i < n && to_local[i] == Some(il) && to_remote[i] == Some(ir) {
+            out.push_str(base_lines[i]);
+            i += 1;
+            il += 1;
+            ir += 1;
+            continue;
+        }
+
+        // 找到下一个三端都匹配的同步点
+        let mut j = i;
+        while j < n && !(to_local[j].is_some() && to_remote[j].is_some()) {
+            j += 1;
+        }
+        let (jl, jr) = if j < n {
+            (to_local[j].unwrap_or(il), to_remote[j].unwrap_or(ir))
+        } else {
+            (local_lines.len(), remote_lines.len())
+        };
+
+        let base_chunk = &base_lines[i..j];
+        let local_chunk = &local_lines[il..jl];
+        let remote_chunk = &remote_lines[ir..jr];
+
+        if local_chunk == base_chunk {
+            push_lines(&mut out, remote_chunk);
+        } else if remote_chunk == base_chunk || local_chunk == remote_chunk {
+            push_lines(&mut out, local_chunk);
+        } else if let Some(merged) = merge_disjoint_hunks(
+            &base_lines,
+            (i, j),
+            hunks(&to_local, (i, j), (il, jl), &local_lines),
+            hunks(&to_remote, (i, j), (ir, jr), &remote_lines),
+        ) {
+            // 相邻但不重叠的修改（如一端改第 1 行、另一端改第 2 行）
+            out.push_str(&merged);
+        } else {
+            conflicted = true;
+            push_marker(&mut out, MARKER_LOCAL);
+            push_lines(&mut out, local_chunk);
+            push_marker(&mut out, MARKER_SEP);
+            push_lines(&mut out, remote_chunk);
+            push_marker(&mut out, MARKER_REMOTE);
+        }
+
+        i = j;
+        il = jl;
+        ir = jr;
+    }
+
+    MergeResult { text: out, conflicted }
+}
+
+/// 一端相对 base 的一处修改：替换 base[start..end]（start == end 表示纯插入）
+struct Hunk<'a> {
+    start: usize,
+    end: usize,
+    lines: &'a [&'a str],
+}
+
+impl Hunk<'_> {
+    fn overlaps(&self, other: &Hunk) -> bool {
+        let self_insert = self.start == self.end;
+        let other_insert = other.start == other.end;
+        match (self_insert, other_insert) {
+            (true, true) => self.start == other.start,
+            (true, false) => other.start < self.start && self.start < other.end,
+            (false, true) => self.start < other.start && other.start < self.end,
+            (false, false) => self.start < other.end && other.start < self.end,
+        }
+    }
+}
+
+/// 在 [base_start, base_end) 区间内，根据匹配关系提取一端的修改块
+fn hunks<'a>(
+    matches: &[Option<usize>],
+    (base_start, base_end): (usize, usize),
+    (side_start, side_end): (usize, usize),
+    side: &'a [&'a str],
+) -> Vec<Hunk<'a>> {
+    let mut result = Vec::new();
+    let (mut k, mut p) = (base_start, side_start);
+
+    while k < base_end || p < side_end {
+        if k < base_end && matches[k] == Some(p) {
+            k += 1;
+            p += 1;
+            continue;
+        }
+        let start = k;
+        while k < base_end && matches[k].is_none() {
+            k += 1;
+        }
+        let side_stop = if k < base_end { matches[k].unwrap_or(side_end) } else { side_end };
+        result.push(Hunk { start, end: k, lines: &side[p..side_stop] });
+        p = side_stop;
+    }
+
+    result
+}
+
+/// 两端修改互不重叠时，把它们一起应用到 base 上；存在重叠返回 None
+fn merge_disjoint_hunks(
+    base_lines: &[&str],
+    (base_start, base_end): (usize, usize),
+    local: Vec<Hunk>,
+    remote: Vec<Hunk>,
+) -> Option<String> {
+    let mut all: Vec<Hunk> = Vec::with_capacity(local.len() + remote.len());
+    for hunk in remote {
+        // 两端完全相同的修改只保留一份
+        if local.iter().any(|l| l.start == hunk.start && l.end == hunk.end && l.lines == hunk.lines) {
+            continue;
+        }
+        if local.iter().any(|l| l.overlaps(&hunk)) {
+            return None;
+        }
+        all.push(hunk);
+    }
+    all.extend(local);
+    // 同一位置：纯插入排在替换之前
+    all.sort_by_key(|h| (h.start, h.end != h.start));
+
+    let mut out = String::new();
+    let mut k = base_start;
+    for hunk in all {
+        push_lines(&mut out, &base_lines[k..hunk.start]);
+        push_lines(&mut out, hunk.lines);
+        k = hunk.end;
+    }
+    push_lines(&mut out, &base_lines[k..base_end]);
+
+    Some(out)
+}
+
+/// 计算 a 中每个元素在 b 中对应的位置（基于最长公共子序列），未匹配为 None
+///
+/// 先去掉公共前后缀再做 DP；规模超过 MAX_LCS_CELLS 时中间部分视为整体替换
+pub fn lcs_matches<T: PartialEq>(a: &[T], b: &[T]) -> Vec<Option<usize>> {
+    let mut matches = vec![None; a.len()];
+
+    let mut prefix = 0;
+    while prefix < a.len() && prefix < b.len() && a[prefix] == b[prefix] {
+        matches[prefix] = Some(prefix);
+        prefix += 1;
+    }
+
+    let mut suffix = 0;
+    while suffix < a.len() - prefix
+        && suffix < b.len() - prefix
+        && a[a.len() - 1 - suffix] == b[b.len() - 1 - suffix]
+    {
+        matches[a.len() - 1 - suffix] = Some(b.len() - 1 - suffix);
+        suffix += 1;
+    }
+
+    let a_mid = &a[prefix..a.len() - suffix];
+    let b_mid = &b[prefix..b.len() - suffix];
+    let (rows, cols) = (a_mid.len(), b_mid.len());
+    if rows == 0 || cols == 0 || rows.saturating_mul(cols) > MAX_LCS_CELLS {
+        return matches;
+    }
+
+    // dp[x][y] = a_mid[x..] 与 b_mid[y..] 的 LCS 长度
+    let width = cols + 1;
+    let mut dp = vec![0u32; (rows + 1) * width];
+    for x in (0..rows).rev() {
+        for y in (0..cols).rev() {
+            dp[x * width + y] = if a_mid[x] == b_mid[y] {
+                dp[(x + 1) * width + y + 1] + 1
+            } else {
+                dp[(x + 1) * width + y].max(dp[x * width + y + 1])
+            };
+        }
+    }
+
+    let (mut x, mut y) = (0, 0);
+    while x < rows && y < cols {
+        if a_mid[x] == b_mid[y] {
+            matches[prefix + x] = Some(prefix + y);
+            x += 1;
+            y += 1;
+        } else if dp[(x + 1) * width + y] >= dp[x * width + y + 1] {
+            x += 1;
+        } else {
+            y += 1;
+        }
+    }
+
+    matches
+}
+
+/// 按行切分并保留换行符，保证拼接后与原文一致
+fn split_lines(text: &str) -> Vec<&str> {
+    text.split_inclusive('\n').collect()
+}
+
+fn push_lines(out: &mut String, lines: &[&str]) {
+    for line in lines {
+        out.push_str(line);
+    }
+}
+
+/// 写入冲突标记，必要时先补齐上一行的换行
+fn push_marker(out: &mut String, marker: &str) {
+    if !out.is_empty() && !out.ends_with('\n') {
+        out.push('\n');
+    }
+    out.push_str(marker);
+    out.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_sided_edits_take_the_other_side() {
+        let base = "a\nb\n";
+        assert_eq!(merge3(base, base, "a\nB\n").text, "a\nB\n");
+        assert_eq!(merge3(base, "A\nb\n", base).text, "A\nb\n");
+        assert_eq!(merge3("", "x\n", "x\n"), MergeResult { text: "x\n".to_string(), conflicted: false });
+    }
+
+    #[test]
+    fn merges_non_overlapping_edits() {
+        let base = "one\ntwo\nthree\nfour\nfive\n";
+        let merged = merge3(base, "ONE\ntwo\nthree\nfour\nfive\n", "one\ntwo\nthree\nfour\nFIVE\n");
+        assert_eq!(merged, MergeResult { text: "ONE\ntwo\nthree\nfour\nFIVE\n".to_string(), conflicted: false });
+
+        // 相邻行：一端改第 1 行，另一端改第 2 行
+        let merged = merge3("a\nb\n", "A\nb\n", "a\nB\n");
+        assert_eq!(merged, MergeResult { text: "A\nB\n".to_string(), conflicted: false });
+
+        // 两端在不同位置插入
+        let merged = merge3("a\nb\nc\n", "a\nx\nb\nc\n", "a\nb\nc\ny\n");
+        assert_eq!(merged.text, "a\nx\nb\nc\ny\n");
+        assert!(!merged.conflicted);
+    }
+
+    #[test]
+    fn identical_edits_are_kept_once() {
+        let merged = merge3("a\nb\nc\n", "a\nB\nc\n", "a\nB\nc\n");
+        assert_eq!(merged, MergeResult { text: "a\nB\nc\n".to_string(), conflicted: false });
+    }
+
+    #[test]
+    fn overlapping_edits_get_conflict_markers() {
+        let merged = merge3("a\nb\nc\n", "a\nlocal\nc\n", "a\nremote\nc\n");
+        assert!(merged.conflicted);
+        assert_eq!(
+            merged.text,
+            format!("a\n{}\nlocal\n{}\nremote\n{}\nc\n", MARKER_LOCAL, MARKER_SEP, MARKER_REMOTE)
+        );
+
+        // 最后一行没有换行时，标记另起一行
+        let merged = merge3("x", "local", "remote");
+        assert!(merged.conflicted);
+        assert_eq!(merged.text, format!("{}\nlocal\n{}\nremote\n{}\n", MARKER_LOCAL, MARKER_SEP, MARKER_REMOTE));
+    }
+
+    #[test]
+    fn lcs_matches_prefix_suffix_and_middle() {
+        let a = ["a", "b", "c", "d", "e"];
+        let b = ["a", "x", "c", "y", "e"];
+        assert_eq!(lcs_matches(&a, &b), vec![Some(0), None, Some(2), None, Some(4)]);
+
+        assert_eq!(lcs_matches(&["a", "b"], &["b"]), vec![None, Some(0)]);
+        assert_eq!(lcs_matches::<&str>(&[], &["a"]), Vec::<Option<usize>>::new());
+        assert_eq!(lcs_matches(&["a"], &[]), vec![None]);
+    }
+
+    #[test]
+    fn lcs_falls_back_to_whole_replacement_past_cell_limit() {
+        // 去掉公共前后缀后中间部分为 2001 x 2001，超过 MAX_LCS_CELLS
+        let size = 2001;
+        assert!(size * size > MAX_LCS_CELLS);
+        let a: Vec<i64> = (0..size as i64).collect();
+        let mut b = a.clone();
+        b[0] = -1;
+        b[size - 1] = -2;
+        assert!(lcs_matches(&a, &b).iter().all(|m| m.is_none()));
+
+        // 规模在上限内时中间部分正常匹配
+        let small_a: Vec<i64> = (0..100).collect();
+        let mut small_b = small_a.clone();
+        small_b[0] = -1;
+        small_b[99] = -2;
+        let matches = lcs_matches(&small_a, &small_b);
+        assert_eq!(matches[50], Some(50));
+        assert_eq!(matches[0], None);
+    }
+}
@@ -0,0 +1,709 @@
+//! 笔记历史版本
+//! notes 的标题或正文被覆盖前，由触发器 `note_revisions_capture` 把旧的标题和正文保存到 note_revisions，
+//! 编辑器保存与同步写入（`apply_table_change`、前端拉取）都会经过它，因此任何一次覆盖都能找回。
+//!
+//! - source 区分覆盖来源：edit（本机编辑）、sync（同步写入）、restore（恢复历史版本前的内容）。
+//!   触发器按新 HLC 的设备判断：本机设备写入记为 edit，其他设备的 HLC 记为 sync；
+//!   用本机时钟完成的同步写入（三方合并、采用远程副本）由调用方通过 `relabel_since` 改为 sync
+//! - 编辑器每秒自动保存，连续编辑会留下大量只存在了几秒的中间状态，由 `compact` 按保留策略清理
+//! - 历史版本只保存在本机，不参与同步
+//!
+//! 差异按字符计算（中文没有词边界，按词切分会把整句视为一个词），先按行对齐再逐字比较
+
+use crate::hlc::{parse_timestamp_ms, HybridClock};
+use crate::merge;
+use crate::sync_engine;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// 默认保留天数（0 表示不按时间清理）
+pub const DEFAULT_RETENTION_DAYS: i64 = 30;
+
+/// 默认每篇笔记最多保留的版本数
+pub const DEFAULT_MAX_PER_NOTE: i64 = 100;
+
+/// 每篇笔记保留版本数的上限：触发器 `note_revisions_capture` 写入时只保留最新的 500 条，更大的配置不会生效
+pub const MAX_PER_NOTE_LIMIT: i64 = 500;
+
+/// 默认合并间隔：本机编辑产生的、存在时间短于该秒数的中间状态会被清理
+pub const DEFAULT_MERGE_SECS: i64 = 300;
+
+const RETENTION_DAYS_KEY: &str = "note_revision_retention_days";
+const MAX_PER_NOTE_KEY: &str = "note_revision_max_per_note";
+const MERGE_SECS_KEY: &str = "note_revision_merge_secs";
+
+/// 两段文字之间夹着的相同片段不超过该字数时并入替换，避免单字巧合匹配把一次改写切碎
+const MIN_EQUAL_CHARS: usize = 2;
+
+/// 覆盖来源
+pub const SOURCE_EDIT: &str = "edit";
+pub const SOURCE_SYNC: &str = "sync";
+pub const SOURCE_RESTORE: &str = "restore";
+
+/// 保留策略（存放在 sync_meta）
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retention {
+    /// 早于该天数的版本被删除，0 表示不按时间清理
+    pub retention_days: i64,
+    /// 每篇笔记最多保留的版本数（不超过 MAX_PER_NOTE_LIMIT）
+    pub max_per_note: i64,
+    /// 本机编辑的中间状态存在时间短于该秒数时被清理（sync / restore 版本始终保留）
+    pub merge_secs: i64,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Retention {
+            retention_days: DEFAULT_RETENTION_DAYS,
+            max_per_note: DEFAULT_MAX_PER_NOTE,
+            merge_secs: DEFAULT_MERGE_SECS,
+        }
+    }
+}
+
+impl Retention {
+    /// 读取保留策略，未配置或非法的项使用默认值
+    pub fn load(conn: &Connection) -> Retention {
+        Retention {
+            retention_days: read_meta(conn, RETENTION_DAYS_KEY).filter(|d| *d >= 0).unwrap_or(DEFAULT_RETENTION_DAYS),
+            max_per_note: read_meta(conn, MAX_PER_NOTE_KEY)
+                .filter(|n| *n > 0)
+                .map(|n| n.min(MAX_PER_NOTE_LIMIT))
+                .unwrap_or(DEFAULT_MAX_PER_NOTE),
+            merge_secs: read_meta(conn, MERGE_SECS_KEY).filter(|s| *s >= 0).unwrap_or(DEFAULT_MERGE_SECS),
+        }
+    }
+
+    pub fn save(&self, conn: &Connection) -> rusqlite::Result<()> {
+        let now = sync_engine::now_iso();
+        for (key, value) in [
+            (RETENTION_DAYS_KEY, self.retention_days.max(0)),
+            (MAX_PER_NOTE_KEY, self.max_per_note.clamp(1, MAX_PER_NOTE_LIMIT)),
+            (MERGE_SECS_KEY, self.merge_secs.max(0)),
+        ] {
+            conn.execute(
+                "INSERT OR REPLACE INTO sync_meta (key, value, updated_at) VALUES (?1, ?2, ?3)",
+                params![key, value.to_string(), now],
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// 列表中的一个版本（不含正文）
+#[derive(Serialize, Debug, Clone)]
+pub struct RevisionSummary {
+    pub id: i64,
+    pub note_uuid: String,
+    pub title: Option<String>,
+    pub source: String,
+    /// 被覆盖时笔记的版本号
+    pub version: Option<i64>,
+    /// 这份内容写入的时间（被覆盖前笔记的 updated_at）
+    pub edited_at: Option<String>,
+    /// 被覆盖（保存快照）的时间
+    pub created_at: String,
+    /// 正文字数
+    pub chars: usize,
+}
+
+/// 一个完整的版本
+#[derive(Serialize, Debug, Clone)]
+pub struct NoteRevision {
+    pub id: i64,
+    pub note_uuid: String,
+    pub title: Option<String>,
+    pub content: Option<String>,
+    pub source: String,
+    pub version: Option<i64>,
+    pub edited_at: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffKind {
+    Equal,
+    Insert,
+    Delete,
+}
+
+/// 差异中的一段文字
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct DiffOp {
+    pub kind: DiffKind,
+    pub text: String,
+}
+
+/// 两个版本之间的差异（旧 -> 新）
+#[derive(Serialize, Debug, Clone)]
+pub struct RevisionDiff {
+    pub from_id: i64,
+    /// 比较对象的版本 ID，为空表示笔记当前内容
+    pub to_id: Option<i64>,
+    pub title: Vec<DiffOp>,
+    pub content: Vec<DiffOp>,
+    /// 新增字数
+    pub inserted: usize,
+    /// 删除字数
+    pub deleted: usize,
+}
+
+/// 一次清理的结果
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct CompactReport {
+    /// 笔记已被彻底清除的版本
+    pub orphaned: usize,
+    /// 超过保留天数的版本
+    pub expired: usize,
+    /// 连续编辑的中间状态
+    pub merged: usize,
+    /// 超过每篇笔记上限的版本
+    pub trimmed: usize,
+}
+
+impl CompactReport {
+    pub fn removed(&self) -> usize {
+        self.orphaned + self.expired + self.merged + self.trimmed
+    }
+}
+
+const SELECT_REVISION: &str =
+    "SELECT id, note_uuid, title, content, source, version, edited_at, created_at FROM note_revisions";
+
+fn map_revision(row: &rusqlite::Row) -> rusqlite::Result<NoteRevision> {
+    Ok(NoteRevision {
+        id: row.get(0)?,
+        note_uuid: row.get(1)?,
+        title: row.get(2)?,
+        content: row.get(3)?,
+        source: row.get(4)?,
+        version: row.get(5)?,
+        edited_at: row.get(6)?,
+        created_at: row.get(7)?,
+    })
+}
+
+/// 列出一篇笔记的历史版本，最新的在前
+pub fn list(conn: &Connection, note_uuid: &str) -> rusqlite::Result<Vec<RevisionSummary>> {
+    let mut stmt = conn.prepare(&format!("{} WHERE note_uuid = ?1 ORDER BY id DESC", SELECT_REVISION))?;
+    let rows = stmt.query_map(params![note_uuid], map_revision)?;
+    rows.map(|r| {
+        r.map(|rev| RevisionSummary {
+            chars: rev.content.as_deref().map(|c| c.chars().count()).unwrap_or(0),
+            id: rev.id,
+            note_uuid: rev.note_uuid,
+            title: rev.title,
+            source: rev.source,
+            version: rev.version,
+            edited_at: rev.edited_at,
+            created_at: rev.created_at,
+        })
+    })
+    .collect()
+}
+
+/// 查看单个版本
+pub fn get(conn: &Connection, id: i64) -> rusqlite::Result<Option<NoteRevision>> {
+    conn.query_row(&format!("{} WHERE id = ?1", SELECT_REVISION), params![id], map_revision)
+        .optional()
+}
+
+/// 比较版本 `id` 与版本 `against`（为空时与笔记当前内容比较）；任一方不存在时返回 None
+pub fn diff_revision(conn: &Connection, id: i64, against: Option<i64>) -> rusqlite::Result<Option<RevisionDiff>> {
+    let Some(from) = get(conn, id)? else {
+        return Ok(None);
+    };
+    let to = match against {
+        Some(other) => get(conn, other)?.map(|r| (r.title, r.content)),
+        None => current(conn, &from.note_uuid)?,
+    };
+    let Some((to_title, to_content)) = to else {
+        return Ok(None);
+    };
+
+    let title = diff(from.title.as_deref().unwrap_or(""), to_title.as_deref().unwrap_or(""));
+    let content = diff(from.content.as_deref().unwrap_or(""), to_content.as_deref().unwrap_or(""));
+    let count = |kind: DiffKind| {
+        title
+            .iter()
+            .chain(content.iter())
+            .filter(|op| op.kind == kind)
+            .map(|op| op.text.chars().count())
+            .sum()
+    };
+    Ok(Some(RevisionDiff {
+        from_id: id,
+        to_id: against,
+        inserted: count(DiffKind::Insert),
+        deleted: count(DiffKind::Delete),
+        title,
+        content,
+    }))
+}
+
+/// 把笔记恢复为某个版本的内容，作为一次新的写入（版本号由触发器分配，并同步到其他设备）
+///
+/// 恢复前的内容会留下一个 restore 版本；内容与当前一致或笔记已被彻底清除时返回 false
+pub fn restore(conn: &mut Connection, id: i64, clock: &HybridClock) -> rusqlite::Result<bool> {
+    let Some(revision) = get(conn, id)? else {
+        return Ok(false);
+    };
+    let Some((title, content)) = current(conn, &revision.note_uuid)? else {
+        return Ok(false);
+    };
+    if title == revision.title && content == revision.content {
+        return Ok(false);
+    }
+    let Some(config) = sync_engine::get_table_config("notes") else {
+        return Ok(false);
+    };
+
+    let tx = conn.transaction()?;
+    let mark = latest_id(&tx, &revision.note_uuid)?;
+    tx.execute(
+        "UPDATE notes SET title = ?1, content = ?2, updated_at = ?3, hlc = ?4, version = 0 WHERE uuid = ?5",
+        params![revision.title, revision.content, sync_engine::now_iso(), clock.now().encode(), revision.note_uuid],
+    )?;
+    // 本机时钟写入，触发器记为 edit，这里改为 restore
+    relabel_since(&tx, &revision.note_uuid, mark, SOURCE_RESTORE)?;
+    sync_engine::snapshot_merge_base(&tx, config.name, config, &revision.note_uuid)?;
+    tx.commit()?;
+
+    log::info!("[NoteRevisions] 笔记 {} 恢复为版本 #{}", revision.note_uuid, id);
+    Ok(true)
+}
+
+/// 笔记最新一个版本的 ID，没有版本时为 0
+pub fn latest_id(conn: &Connection, note_uuid: &str) -> rusqlite::Result<i64> {
+    conn.query_row(
+        "SELECT COALESCE(MAX(id), 0) FROM note_revisions WHERE note_uuid = ?1",
+        params![note_uuid],
+        |row| row.get(0),
+    )
+}
+
+/// 把 `after` 之后产生的版本改记为 source（写入前用 `latest_id` 取得 `after`）
+pub fn relabel_since(conn: &Connection, note_uuid: &str, after: i64, source: &str) -> rusqlite::Result<usize> {
+    conn.execute(
+        "UPDATE note_revisions SET source = ?1 WHERE note_uuid = ?2 AND id > ?3",
+        params![source, note_uuid, after],
+    )
+}
+
+/// 按保留策略清理历史版本
+pub fn compact(conn: &mut Connection, retention: &Retention) -> rusqlite::Result<CompactReport> {
+    let tx = conn.transaction()?;
+    let orphaned = tx.execute(
+        "DELETE FROM note_revisions WHERE note_uuid NOT IN (SELECT uuid FROM notes)",
+        [],
+    )?;
+
+    let expired = if retention.retention_days > 0 {
+        tx.execute(
+            "DELETE FROM note_revisions WHERE created_at < strftime('%Y-%m-%dT%H:%M:%fZ', 'now', ?1)",
+            params![format!("-{} days", retention.retention_days)],
+        )?
+    } else {
+        0
+    };
+
+    // 本机编辑的中间状态：从写入到被覆盖不足 merge_secs（每段连续编辑只留下编辑开始前的内容）
+    let mut merged = 0;
+    if retention.merge_secs > 0 {
+        let short_lived: Vec<i64> = {
+            let mut stmt = tx.prepare("SELECT id, edited_at, created_at FROM note_revisions WHERE source = ?1")?;
+            let rows = stmt.query_map(params![SOURCE_EDIT], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, Option<String>>(1)?, row.get::<_, String>(2)?))
+            })?;
+            let mut ids = Vec::new();
+            for row in rows {
+                let (id, edited_at, created_at) = row?;
+                let lived = edited_at
+                    .as_deref()
+                    .and_then(parse_timestamp_ms)
+                    .zip(parse_timestamp_ms(&created_at))
+                    .map(|(edited, replaced)| replaced - edited);
+                if lived.is_some_and(|ms| ms < retention.merge_secs * 1000) {
+                    ids.push(id);
+                }
+            }
+            ids
+        };
+        for id in short_lived {
+            merged += tx.execute("DELETE FROM note_revisions WHERE id = ?1", params![id])?;
+        }
+    }
+
+    let trimmed = tx.execute(
+        "DELETE FROM note_revisions WHERE id IN (
+             SELECT id FROM (
+                 SELECT id, ROW_NUMBER() OVER (PARTITION BY note_uuid ORDER BY id DESC) AS n FROM note_revisions
+             ) WHERE n > ?1
+         )",
+        params![retention.max_per_note.clamp(1, MAX_PER_NOTE_LIMIT)],
+    )?;
+
+    tx.commit()?;
+    Ok(CompactReport { orphaned, expired, merged, trimmed })
+}
+
+/// 字符级差异：先按行对齐，再在改动的行块内逐字比较
+pub fn diff(old: &str, new: &str) -> Vec<DiffOp> {
+    let mut ops = Vec::new();
+    let old_lines: Vec<&str> = old.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = new.split_inclusive('\n').collect();
+
+    for segment in align(&old_lines, &new_lines) {
+        match segment {
+            Segment::Equal(a, _) => push(&mut ops, DiffKind::Equal, &old_lines[a].concat()),
+            Segment::Change(a, b) => {
+                let removed: Vec<char> = old_lines[a].concat().chars().collect();
+                let added: Vec<char> = new_lines[b].concat().chars().collect();
+                for op in cleanup(diff_chars(&removed, &added)) {
+                    push(&mut ops, op.kind, &op.text);
+                }
+            }
+        }
+    }
+    align_boundaries(ops)
+}
+
+fn diff_chars(old: &[char], new: &[char]) -> Vec<DiffOp> {
+    let mut ops = Vec::new();
+    for segment in align(old, new) {
+        match segment {
+            Segment::Equal(a, _) => push(&mut ops, DiffKind::Equal, &old[a].iter().collect::<String>()),
+            Segment::Change(a, b) => {
+                push(&mut ops, DiffKind::Delete, &old[a].iter().collect::<String>());
+                push(&mut ops, DiffKind::Insert, &new[b].iter().collect::<String>());
+            }
+        }
+    }
+    ops
+}
+
+/// 两侧都是改动的短相同片段并入替换：删除 A、保留 x、删除 B 变为删除 AxB、插入 x
+fn cleanup(ops: Vec<DiffOp>) -> Vec<DiffOp> {
+    let mut deleted = String::new();
+    let mut inserted = String::new();
+    let mut out: Vec<DiffOp> = Vec::new();
+
+    for (i, op) in ops.iter().enumerate() {
+        match op.kind {
+            DiffKind::Delete => deleted.push_str(&op.text),
+            DiffKind::Insert => inserted.push_str(&op.text),
+            DiffKind::Equal => {
+                let between_changes = (!deleted.is_empty() || !inserted.is_empty())
+                    && ops.get(i + 1).is_some_and(|next| next.kind != DiffKind::Equal);
+                if between_changes && op.text.chars().count() <= MIN_EQUAL_CHARS {
+                    deleted.push_str(&op.text);
+                    inserted.push_str(&op.text);
+                } else {
+                    flush(&mut out, &mut deleted, &mut inserted);
+                    push(&mut out, DiffKind::Equal, &op.text);
+                }
+            }
+        }
+    }
+    flush(&mut out, &mut deleted, &mut inserted);
+    out
+}
+
+/// 夹在两段相同文字之间的单纯插入或删除，在等价的位置中挪到换行、标点处，
+/// 例如 `第二|行\n第三|行` 调整为 `第二行|\n第三行|`
+fn align_boundaries(mut ops: Vec<DiffOp>) -> Vec<DiffOp> {
+    for i in 1..ops.len().saturating_sub(1) {
+        if ops[i].kind == DiffKind::Equal || ops[i - 1].kind != DiffKind::Equal || ops[i + 1].kind != DiffKind::Equal {
+            continue;
+        }
+        let mut prev: Vec<char> = ops[i - 1].text.chars().collect();
+        let mut edit: Vec<char> = ops[i].text.chars().collect();
+        let mut next: Vec<char> = ops[i + 1].text.chars().collect();
+
+        // 先尽量左移，再逐字右移，记录得分最高的位置
+        while let (Some(&p), Some(&e)) = (prev.last(), edit.last()) {
+            if p != e {
+                break;
+            }
+            prev.pop();
+            edit.pop();
+            edit.insert(0, p);
+            next.insert(0, p);
+        }
+        let score = |prev: &[char], edit: &[char], next: &[char]| {
+            boundary_score(prev.last(), edit.first()) + boundary_score(edit.last(), next.first())
+        };
+        let mut best = (score(&prev, &edit, &next), prev.clone(), edit.clone(), next.clone());
+        while !next.is_empty() && edit.first() == next.first() {
+            let c = next.remove(0);
+            edit.remove(0);
+            edit.push(c);
+            prev.push(c);
+            let current = score(&prev, &edit, &next);
+            if current >= best.0 {
+                best = (current, prev.clone(), edit.clone(), next.clone());
+            }
+        }
+
+        let (_, prev, edit, next) = best;
+        ops[i - 1].text = prev.into_iter().collect();
+        ops[i].text = edit.into_iter().collect();
+        ops[i + 1].text = next.into_iter().collect();
+    }
+
+    let mut out = Vec::with_capacity(ops.len());
+    for op in ops {
+        push(&mut out, op.kind, &op.text);
+    }
+    out
+}
+
+/// 在字符 left 与 right 之间断开的合适程度：文本首尾 > 换行 > 句读标点 > 空白
+fn boundary_score(left: Option<&char>, right: Option<&char>) -> u8 {
+    let (Some(&left), Some(&right)) = (left, right) else {
+        return 6;
+    };
+    if left == '\n' || right == '\n' {
+        4
+    } else if left.is_ascii_punctuation() || "，。！？；：、”’）》】…".contains(left) {
+        3
+    } else if left.is_whitespace() || right.is_whitespace() {
+        2
+    } else {
+        0
+    }
+}
+
+fn flush(out: &mut Vec<DiffOp>, deleted: &mut String, inserted: &mut String) {
+    push(out, DiffKind::Delete, deleted);
+    push(out, DiffKind::Insert, inserted);
+    deleted.clear();
+    inserted.clear();
+}
+
+/// 对齐结果中的一段：两侧相同，或一侧的区间被替换为另一侧的区间（任一侧可以为空）
+enum Segment {
+    Equal(Range<usize>, Range<usize>),
+    Change(Range<usize>, Range<usize>),
+}
+
+/// 按最长公共子序列把两个序列切分为相同段与改动段
+fn align<T: PartialEq>(a: &[T], b: &[T]) -> Vec<Segment> {
+    let matches = merge::lcs_matches(a, b);
+    let mut segments = Vec::new();
+    let (mut i, mut j) = (0, 0);
+
+    while i < a.len() || j < b.len() {
+        if i < a.len() && matches[i] == Some(j) {
+            let (start_a, start_b) = (i, j);
+            while i < a.len() && matches[i] == Some(j) {
+                i += 1;
+                j += 1;
+            }
+            segments.push(Segment::Equal(start_a..i, start_b..j));
+            continue;
+        }
+        let start_a = i;
+        while i < a.len() && matches[i].is_none() {
+            i += 1;
+        }
+        let end_b = if i < a.len() { matches[i].unwrap_or(b.len()) } else { b.len() };
+        segments.push(Segment::Change(start_a..i, j..end_b));
+        j = end_b;
+    }
+    segments
+}
+
+/// 追加一段文字，与前一段同类时合并
+fn push(ops: &mut Vec<DiffOp>, kind: DiffKind, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    match ops.last_mut() {
+        Some(last) if last.kind == kind => last.text.push_str(text),
+        _ => ops.push(DiffOp { kind, text: text.to_string() }),
+    }
+}
+
+/// 笔记当前的标题和正文；笔记不存在时返回 None
+fn current(conn: &Connection, note_uuid: &str) -> rusqlite::Result<Option<(Option<String>, Option<String>)>> {
+    conn.query_row(
+        "SELECT title, content FROM notes WHERE uuid = ?1",
+        params![note_uuid],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )
+    .optional()
+}
+
+fn read_meta(conn: &Connection, key: &str) -> Option<i64> {
+    conn.query_row("SELECT value FROM sync_meta WHERE key = ?1", params![key], |row| row.get::<_, String>(0))
+        .optional()
+        .ok()
+        .flatten()
+        .and_then(|v| v.trim().parse::<i64>().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::mem_db;
+
+    fn op(kind: DiffKind, text: &str) -> DiffOp {
+        DiffOp { kind, text: text.to_string() }
+    }
+
+    #[test]
+    fn diff_handles_empty_inputs() {
+        assert!(diff("", "").is_empty());
+        assert_eq!(diff("", "新内容"), vec![op(DiffKind::Insert, "新内容")]);
+        assert_eq!(diff("旧内容", ""), vec![op(DiffKind::Delete, "旧内容")]);
+        assert_eq!(diff("不变", "不变"), vec![op(DiffKind::Equal, "不变")]);
+    }
+
+    #[test]
+    fn diff_compares_cjk_by_char() {
+        assert_eq!(
+            diff("今天天气很好", "今天天气不好"),
+            vec![
+                op(DiffKind::Equal, "今天天气"),
+                op(DiffKind::Delete, "很"),
+                op(DiffKind::Insert, "不"),
+                op(DiffKind::Equal, "好"),
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_reports_pure_insertions_and_deletions() {
+        assert_eq!(
+            diff("第一行\n第二行\n", "第一行\n新增一行\n第二行\n"),
+            vec![op(DiffKind::Equal, "第一行\n"), op(DiffKind::Insert, "新增一行\n"), op(DiffKind::Equal, "第二行\n")]
+        );
+        assert_eq!(
+            diff("第一行\n删掉\n第二行\n", "第一行\n第二行\n"),
+            vec![op(DiffKind::Equal, "第一行\n"), op(DiffKind::Delete, "删掉\n"), op(DiffKind::Equal, "第二行\n")]
+        );
+    }
+
+    #[test]
+    fn cleanup_folds_short_equal_runs_into_the_replacement() {
+        assert_eq!(
+            cleanup(vec![op(DiffKind::Delete, "甲"), op(DiffKind::Equal, "的"), op(DiffKind::Delete, "乙")]),
+            vec![op(DiffKind::Delete, "甲的乙"), op(DiffKind::Insert, "的")]
+        );
+        // 超过 MIN_EQUAL_CHARS 的相同片段保留
+        let kept = vec![op(DiffKind::Delete, "甲"), op(DiffKind::Equal, "相同的字"), op(DiffKind::Insert, "乙")];
+        assert_eq!(cleanup(kept.clone()), kept);
+        assert!(cleanup(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn align_boundaries_moves_edits_to_line_breaks() {
+        let ops = vec![
+            op(DiffKind::Equal, "第一行\n第二"),
+            op(DiffKind::Insert, "行\n第三"),
+            op(DiffKind::Equal, "行\n"),
+        ];
+        assert_eq!(
+            align_boundaries(ops),
+            vec![op(DiffKind::Equal, "第一行\n第二行\n"), op(DiffKind::Insert, "第三行\n")]
+        );
+        // 没有夹在两段相同文字之间的改动不调整
+        let edge = vec![op(DiffKind::Insert, "开头"), op(DiffKind::Equal, "正文")];
+        assert_eq!(align_boundaries(edge.clone()), edge);
+        assert!(align_boundaries(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn max_per_note_is_capped_by_the_trigger_limit() {
+        let conn = mem_db();
+        Retention { max_per_note: 10_000, ..Retention::default() }.save(&conn).unwrap();
+        assert_eq!(Retention::load(&conn).max_per_note, MAX_PER_NOTE_LIMIT);
+
+        conn.execute(
+            "UPDATE sync_meta SET value = '10000' WHERE key = ?1",
+            params![MAX_PER_NOTE_KEY],
+        )
+        .unwrap();
+        assert_eq!(Retention::load(&conn).max_per_note, MAX_PER_NOTE_LIMIT);
+    }
+
+    #[test]
+    fn local_edits_are_labelled_edit_and_compacted() {
+        let mut conn = mem_db();
+        let clock = HybridClock::load(&conn, &["notes"]);
+        conn.execute(
+            "INSERT INTO notes (uuid, title, content, tags, updated_at, hlc) VALUES ('n1', '标题', '初稿', '[]', ?1, ?2)",
+            params![sync_engine::now_iso(), clock.now().encode()],
+        )
+        .unwrap();
+
+        // 前端自动保存：每次写入都带本机设备的新 HLC
+        for content in ["第二稿", "第三稿"] {
+            conn.execute(
+                "UPDATE notes SET content = ?1, updated_at = ?2, hlc = ?3 WHERE uuid = 'n1'",
+                params![content, sync_engine::now_iso(), clock.now().encode()],
+            )
+            .unwrap();
+        }
+        // 其他设备的写入
+        conn.execute(
+            "UPDATE notes SET content = '远程稿', hlc = ?1 WHERE uuid = 'n1'",
+            params![format!("{:013}:{:05}:{}", 1, 0, "other-device")],
+        )
+        .unwrap();
+
+        let sources: Vec<String> = list(&conn, "n1").unwrap().into_iter().map(|r| r.source).collect();
+        assert_eq!(sources, vec![SOURCE_SYNC, SOURCE_EDIT, SOURCE_EDIT]);
+
+        let retention = Retention { retention_days: 0, max_per_note: MAX_PER_NOTE_LIMIT, merge_secs: 300 };
+        let report = compact(&mut conn, &retention).unwrap();
+        assert_eq!(report.merged, 2);
+        let kept: Vec<Option<String>> =
+            list(&conn, "n1").unwrap().iter().map(|r| get(&conn, r.id).unwrap().unwrap().content).collect();
+        assert_eq!(kept, vec![Some("第三稿".to_string())]);
+    }
+
+    #[test]
+    fn restore_is_labelled_restore() {
+        let mut conn = mem_db();
+        let clock = HybridClock::load(&conn, &["notes"]);
+        conn.execute(
+            "INSERT INTO notes (uuid, title, content, tags, updated_at, hlc) VALUES ('n1', '标题', '旧', '[]', ?1, ?2)",
+            params![sync_engine::now_iso(), clock.now().encode()],
+        )
+        .unwrap();
+        conn.execute("UPDATE notes SET content = '新', hlc = ?1 WHERE uuid = 'n1'", params![clock.now().encode()])
+            .unwrap();
+        let old = list(&conn, "n1").unwrap()[0].id;
+
+        assert!(restore(&mut conn, old, &clock).unwrap());
+        let revisions = list(&conn, "n1").unwrap();
+        assert_eq!(revisions[0].source, SOURCE_RESTORE);
+        assert_eq!(revisions[1].source, SOURCE_EDIT);
+        assert_eq!(current(&conn, "n1").unwrap().unwrap().1.as_deref(), Some("旧"));
+    }
+
+    #[test]
+    fn compact_trims_to_max_per_note() {
+        let mut conn = mem_db();
+        conn.execute(
+            "INSERT INTO notes (uuid, title, content, tags, updated_at) VALUES ('n1', '标题', '0', '[]', '2024-01-01T00:00:00Z')",
+            [],
+        )
+        .unwrap();
+        for i in 1..=5 {
+            conn.execute("UPDATE notes SET content = ?1 WHERE uuid = 'n1'", params![i.to_string()]).unwrap();
+        }
+        assert_eq!(list(&conn, "n1").unwrap().len(), 5);
+
+        let retention = Retention { retention_days: 0, max_per_note: 2, merge_secs: 0 };
+        let report = compact(&mut conn, &retention).unwrap();
+        assert_eq!(report.trimmed, 3);
+        let kept: Vec<Option<String>> =
+            list(&conn, "n1").unwrap().iter().map(|r| get(&conn, r.id).unwrap().unwrap().content).collect();
+        assert_eq!(kept, vec![Some("4".to_string()), Some("3".to_string())]);
+    }
+}
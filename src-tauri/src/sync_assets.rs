@@ -0,0 +1,350 @@
+//! 资源文件传输
+//! assets 表只同步记录，文件本身保存在各设备的 `{app_data_dir}/assets/{uuid}`。
+//! 局域网内的设备之间通过 HTTP 服务直接传输文件：
+//!
+//! - 下载：`GET /assets/{uuid}`，响应头 `X-Asset-Size` / `X-Asset-Sha256` 供接收方校验
+//! - 上传（分块、可续传）：
+//!   1. `GET /assets/{uuid}/upload` 查询已接收的字节数
+//!   2. `PUT /assets/{uuid}/upload?offset=N` 从 offset 处追加一块（offset 与已接收字节数不一致时返回 409）
+//!   3. `POST /assets/{uuid}/upload/complete` 提交 sha256 与大小，校验通过后移入资源目录
+//!
+//! 未完成的上传保存在 `assets/.partial/{uuid}.part`，中断后从已接收的位置继续；
+//! 同一资源的分块写入与提交按 uuid 串行执行，客户端重试造成的并发请求不会写乱文件
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// 单个资源的大小上限
+pub const MAX_ASSET_SIZE: u64 = 512 * 1024 * 1024;
+
+/// 下载响应中携带文件 sha256 的响应头
+pub const SHA256_HEADER: &str = "x-asset-sha256";
+
+/// 下载响应中携带文件大小的响应头（响应体可能被压缩，不能依赖 Content-Length）
+pub const SIZE_HEADER: &str = "x-asset-size";
+
+const PARTIAL_DIR: &str = ".partial";
+
+/// 各资源的上传锁
+static UPLOAD_LOCKS: OnceLock<Mutex<HashMap<String, Arc<Mutex<()>>>>> = OnceLock::new();
+
+#[derive(Debug)]
+pub enum AssetError {
+    /// uuid 含有不允许的字符（防止路径穿越）
+    InvalidId,
+    NotFound,
+    /// 分块的 offset 与已接收的字节数不一致，携带已接收的字节数
+    OffsetMismatch(u64),
+    TooLarge,
+    /// 大小或 sha256 与声明的不一致（未完成的上传已丢弃）
+    Checksum,
+    Io(io::Error),
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssetError::InvalidId => write!(f, "资源 ID 不合法"),
+            AssetError::NotFound => write!(f, "资源文件不存在"),
+            AssetError::OffsetMismatch(received) => write!(f, "分块位置不一致，已接收 {} 字节", received),
+            AssetError::TooLarge => write!(f, "资源文件超过大小上限"),
+            AssetError::Checksum => write!(f, "资源文件校验失败"),
+            AssetError::Io(e) => write!(f, "资源文件读写失败: {}", e),
+        }
+    }
+}
+
+impl From<io::Error> for AssetError {
+    fn from(e: io::Error) -> Self {
+        AssetError::Io(e)
+    }
+}
+
+/// 上传进度
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct UploadStatus {
+    pub uuid: String,
+    /// 已接收的字节数，下一块从这里开始
+    pub received: u64,
+    /// 资源文件已存在，无需上传
+    pub complete: bool,
+}
+
+/// POST /assets/{uuid}/upload/complete 请求体
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CompleteUpload {
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// 资源目录
+pub fn asset_dir(app_data_dir: &Path) -> PathBuf {
+    app_data_dir.join("assets")
+}
+
+/// uuid 只允许字母、数字、`-` 和 `_`
+pub fn is_valid_id(uuid: &str) -> bool {
+    !uuid.is_empty() && uuid.len() <= 64 && uuid.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// 资源文件路径
+pub fn local_path(dir: &Path, uuid: &str) -> Result<PathBuf, AssetError> {
+    if !is_valid_id(uuid) {
+        return Err(AssetError::InvalidId);
+    }
+    Ok(dir.join(uuid))
+}
+
+fn partial_path(dir: &Path, uuid: &str) -> Result<PathBuf, AssetError> {
+    if !is_valid_id(uuid) {
+        return Err(AssetError::InvalidId);
+    }
+    Ok(dir.join(PARTIAL_DIR).join(format!("{}.part", uuid)))
+}
+
+/// 计算文件的大小与 sha256（小写十六进制）
+pub fn sha256_file(path: &Path) -> io::Result<(u64, String)> {
+    sha256_reader(&mut File::open(path)?)
+}
+
+/// 打开资源文件用于下载：计算大小与 sha256 后回到文件开头，返回同一个文件句柄
+///
+/// 响应头中的校验值与写出的内容来自同一个句柄，期间文件被新的上传替换也不会不一致
+pub fn open_for_download(path: &Path) -> io::Result<(File, u64, String)> {
+    let mut file = File::open(path)?;
+    let (size, sha256) = sha256_reader(&mut file)?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok((file, size, sha256))
+}
+
+fn sha256_reader(file: &mut impl Read) -> io::Result<(u64, String)> {
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut size = 0u64;
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        size += n as u64;
+    }
+    Ok((size, to_hex(&hasher.finalize())))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// 查询上传进度
+pub fn upload_status(dir: &Path, uuid: &str) -> Result<UploadStatus, AssetError> {
+    let complete = local_path(dir, uuid)?.is_file();
+    let received = if complete {
+        0
+    } else {
+        fs::metadata(partial_path(dir, uuid)?).map(|m| m.len()).unwrap_or(0)
+    };
+    Ok(UploadStatus { uuid: uuid.to_string(), received, complete })
+}
+
+/// 同一 uuid 共用的锁；顺带清理已没有请求持有的锁
+fn upload_lock(uuid: &str) -> Arc<Mutex<()>> {
+    let mut locks = UPLOAD_LOCKS.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner());
+    // 锁只在这里被克隆，只剩表中一份引用的锁此刻不会被任何请求持有
+    locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+    locks.entry(uuid.to_string()).or_default().clone()
+}
+
+/// 在 offset 处追加一块，返回追加后已接收的字节数
+pub fn append_chunk(dir: &Path, uuid: &str, offset: u64, chunk: &[u8]) -> Result<u64, AssetError> {
+    let path = partial_path(dir, uuid)?;
+    let lock = upload_lock(uuid);
+    let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // 在锁内从同一个句柄读取长度并定位写入，检查与写入之间文件不会被其他请求改动；只有第一块会创建文件
+    let mut file = match OpenOptions::new().create(offset == 0).write(true).truncate(false).open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(AssetError::OffsetMismatch(0)),
+        Err(e) => return Err(e.into()),
+    };
+    let received = file.metadata()?.len();
+    if offset != received {
+        return Err(AssetError::OffsetMismatch(received));
+    }
+    if received + chunk.len() as u64 > MAX_ASSET_SIZE {
+        return Err(AssetError::TooLarge);
+    }
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(chunk)?;
+    file.sync_data()?;
+    Ok(received + chunk.len() as u64)
+}
+
+/// 校验已接收的文件并移入资源目录；校验失败时丢弃，客户端需要从头上传
+pub fn finish_upload(dir: &Path, uuid: &str, expected: &CompleteUpload) -> Result<(), AssetError> {
+    let partial = partial_path(dir, uuid)?;
+    let lock = upload_lock(uuid);
+    let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+    if !partial.is_file() {
+        return Err(AssetError::NotFound);
+    }
+    commit(&partial, &local_path(dir, uuid)?, expected.size, &expected.sha256)
+}
+
+/// 校验临时文件的大小与 sha256，通过后原子地移动到目标位置
+fn commit(temp: &Path, target: &Path, size: u64, sha256: &str) -> Result<(), AssetError> {
+    let (actual_size, actual_sha) = sha256_file(temp)?;
+    if actual_size != size || !actual_sha.eq_ignore_ascii_case(sha256.trim()) {
+        log::warn!(
+            "[SyncAssets] 校验失败: {:?} 期望 {} 字节 / {}，实际 {} 字节 / {}",
+            target,
+            size,
+            sha256,
+            actual_size,
+            actual_sha
+        );
+        let _ = fs::remove_file(temp);
+        return Err(AssetError::Checksum);
+    }
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(temp, target)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 测试用的临时资源目录，结束时删除
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(name: &str) -> Scratch {
+            let dir = std::env::temp_dir().join(format!("zotepad-assets-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            Scratch(dir)
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn sha256(bytes: &[u8]) -> String {
+        to_hex(&Sha256::digest(bytes))
+    }
+
+    #[test]
+    fn chunks_must_continue_at_the_received_offset() {
+        let dir = Scratch::new("offset");
+        let dir = &dir.0;
+        assert!(matches!(append_chunk(dir, "a1", 4, b"late"), Err(AssetError::OffsetMismatch(0))));
+        assert!(!partial_path(dir, "a1").unwrap().exists());
+
+        assert_eq!(append_chunk(dir, "a1", 0, b"hello ").unwrap(), 6);
+        // 重发的块和跳过的块都被拒绝，并告知已接收的字节数
+        assert!(matches!(append_chunk(dir, "a1", 0, b"hello "), Err(AssetError::OffsetMismatch(6))));
+        assert!(matches!(append_chunk(dir, "a1", 9, b"!"), Err(AssetError::OffsetMismatch(6))));
+        assert_eq!(append_chunk(dir, "a1", 6, b"world").unwrap(), 11);
+
+        let status = upload_status(dir, "a1").unwrap();
+        assert_eq!((status.received, status.complete), (11, false));
+        assert!(matches!(append_chunk(dir, "../a1", 0, b"x"), Err(AssetError::InvalidId)));
+    }
+
+    #[test]
+    fn concurrent_chunks_at_the_same_offset_write_once() {
+        let dir = Scratch::new("concurrent");
+        let results: Vec<_> = std::thread::scope(|s| {
+            let handles: Vec<_> = (0..8)
+                .map(|i| {
+                    let dir = &dir.0;
+                    s.spawn(move || append_chunk(dir, "a1", 0, &[i as u8; 1024]))
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1);
+        assert!(results.iter().all(|r| matches!(r, Ok(1024) | Err(AssetError::OffsetMismatch(1024)))));
+        let written = fs::read(partial_path(&dir.0, "a1").unwrap()).unwrap();
+        assert_eq!(written.len(), 1024);
+        assert!(written.iter().all(|b| *b == written[0]));
+    }
+
+    #[test]
+    fn uploads_over_the_size_limit_are_rejected() {
+        let dir = Scratch::new("limit");
+        let dir = &dir.0;
+        append_chunk(dir, "a1", 0, b"x").unwrap();
+        // 稀疏文件，不实际占用 512 MB
+        let part = partial_path(dir, "a1").unwrap();
+        OpenOptions::new().write(true).open(&part).unwrap().set_len(MAX_ASSET_SIZE - 2).unwrap();
+
+        assert_eq!(append_chunk(dir, "a1", MAX_ASSET_SIZE - 2, b"ab").unwrap(), MAX_ASSET_SIZE);
+        assert!(matches!(append_chunk(dir, "a1", MAX_ASSET_SIZE, b"c"), Err(AssetError::TooLarge)));
+        assert_eq!(fs::metadata(&part).unwrap().len(), MAX_ASSET_SIZE);
+    }
+
+    #[test]
+    fn finishing_verifies_size_and_checksum() {
+        let dir = Scratch::new("checksum");
+        let dir = &dir.0;
+        let body = b"asset body";
+        assert!(matches!(
+            finish_upload(dir, "a1", &CompleteUpload { size: 0, sha256: String::new() }),
+            Err(AssetError::NotFound)
+        ));
+
+        append_chunk(dir, "a1", 0, body).unwrap();
+        let wrong = CompleteUpload { size: body.len() as u64, sha256: sha256(b"other body") };
+        assert!(matches!(finish_upload(dir, "a1", &wrong), Err(AssetError::Checksum)));
+        // 校验失败的上传被丢弃，需要从头上传
+        assert_eq!(upload_status(dir, "a1").unwrap().received, 0);
+
+        append_chunk(dir, "a1", 0, body).unwrap();
+        let short = CompleteUpload { size: 3, sha256: sha256(body) };
+        assert!(matches!(finish_upload(dir, "a1", &short), Err(AssetError::Checksum)));
+
+        append_chunk(dir, "a1", 0, body).unwrap();
+        let expected = CompleteUpload { size: body.len() as u64, sha256: sha256(body).to_uppercase() };
+        finish_upload(dir, "a1", &expected).unwrap();
+        assert!(upload_status(dir, "a1").unwrap().complete);
+        assert_eq!(fs::read(local_path(dir, "a1").unwrap()).unwrap(), body);
+        assert!(!partial_path(dir, "a1").unwrap().exists());
+    }
+
+    /// Windows 上不能替换已打开的文件
+    #[cfg(unix)]
+    #[test]
+    fn download_hashes_and_streams_the_same_handle() {
+        let dir = Scratch::new("download");
+        fs::create_dir_all(&dir.0).unwrap();
+        let path = local_path(&dir.0, "a1").unwrap();
+        fs::write(&path, b"first version").unwrap();
+
+        let (mut file, size, hash) = open_for_download(&path).unwrap();
+        // 打开后文件被新的上传替换，写出的仍是校验过的内容
+        let replacement = dir.0.join("replacement");
+        fs::write(&replacement, b"second version, longer").unwrap();
+        fs::rename(&replacement, &path).unwrap();
+
+        let mut streamed = Vec::new();
+        file.read_to_end(&mut streamed).unwrap();
+        assert_eq!(streamed, b"first version");
+        assert_eq!((size, hash), (streamed.len() as u64, sha256(&streamed)));
+    }
+}
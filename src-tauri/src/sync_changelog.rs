@@ -0,0 +1,399 @@
+//! 同步变更日志
+//! 由 SQLite 触发器维护 sync_changelog：每张同步表的 INSERT / UPDATE / DELETE 都记录一条，
+//! 包括直接 DELETE 的硬删除和主键重命名，/pull 从这里发现变更而不是扫描各表的 version。
+//! 版本号也在写入时由触发器从 sync_sequence 分配，拉取只读不写
+
+use crate::hlc::Hlc;
+use crate::sync_engine::TableConfig;
+use crate::sync_registry;
+use crate::sync_sequence::{self, BUMP_SQL, CURRENT_SQL};
+use crate::sync_vector;
+use rusqlite::{params, Connection, OptionalExtension};
+
+/// 不计入 changed_columns 的同步元数据列（每次写入都会变化）
+const UNTRACKED_COLUMNS: &[&str] = &["version", "updated_at", "hlc"];
+
+/// 删除时间使用与 now_iso() / 前端 toISOString() 一致的 ISO 8601 格式，客户端按字符串比较 updated_at
+const NOW_ISO: &str = "strftime('%Y-%m-%dT%H:%M:%fZ', 'now')";
+
+/// 日志操作类型
+pub const OP_INSERT: &str = "insert";
+pub const OP_UPDATE: &str = "update";
+pub const OP_DELETE: &str = "delete";
+
+/// 某条记录在日志中的最新状态
+#[derive(Debug, Clone)]
+pub struct LogEntry {
+    pub pk: String,
+    pub op: String,
+    /// 生效版本号：硬删除取日志中的版本号，其余取记录当前的版本号
+    pub version: i64,
+    pub hlc: Option<String>,
+    pub created_at: String,
+    /// 删除来自回收站的彻底清除（接收方直接删除本地副本）
+    pub purged: bool,
+}
+
+/// 为所有注册表（重新）创建触发器，为尚未分配版本号的记录分配版本号，
+/// 并为尚无日志的已有记录补一条 insert
+///
+/// 表结构校验未通过的表会被跳过；字段列表变化后重启即可重建触发器
+pub fn install(conn: &mut Connection) -> rusqlite::Result<()> {
+    let registry = sync_registry::registry();
+    let schema = registry.describe(conn);
+
+    let tx = conn.transaction()?;
+    sync_sequence::seed(&tx)?;
+    for config in registry.tables() {
+        if let Some(table) = schema.iter().find(|t| t.name == config.name && !t.is_valid()) {
+            log::warn!("[SyncChangelog] 跳过 {} 表：表结构校验未通过 ({} 个错误)", table.name, table.errors.len());
+            continue;
+        }
+        tx.execute_batch(&trigger_sql(config))?;
+
+
+        let backfilled = tx.execute(
+            &format!(
+                "INSERT INTO sync_changelog (table_name, pk, op, version, created_at)
+                 SELECT ?1, {pk}, ?2, version, COALESCE(updated_at, CURRENT_TIMESTAMP) FROM {table}
+                 WHERE {pk} IS NOT NULL
+                   AND NOT EXISTS (SELECT 1 FROM sync_changelog l WHERE l.table_name = ?1 AND l.pk = {table}.{pk})",
+                table = config.name,
+                pk = config.primary_key
+            ),
+            params![config.name, OP_INSERT],
+        )?;
+        if backfilled > 0 {
+            log::info!("[SyncChangelog] {} 表补录 {} 条已有记录", config.name, backfilled);
+        }
+
+        // 迁移前的旧数据和触发器安装前的本地编辑（version <= 0）：经由 update 触发器分配版本号
+        let stamped = tx.execute(
+            &format!("UPDATE {} SET version = 0 WHERE version IS NULL OR version <= 0", config.name),
+            [],
+        )?;
+        if stamped > 0 {
+            log::info!("[SyncChangelog] {} 表为 {} 条记录分配版本号", config.name, stamped);
+        }
+
+        // 版本向量上线前的记录：视为本机写入
+        let vectored = sync_vector::backfill(&tx, config)?;
+        if vectored > 0 {
+            log::info!("[SyncChangelog] {} 表为 {} 条记录补录版本向量", config.name, vectored);
+        }
+    }
+    tx.commit()
+}
+
+/// 生成一张表的触发器 DDL
+fn trigger_sql(config: &TableConfig) -> String {
+    let table = config.name;
+    let pk = config.primary_key;
+
+    // 只改同步元数据的 UPDATE 不算变更：触发器自己分配版本号，以及内容未变的自动保存（只刷新 updated_at / hlc）；
+    // 需要强制重新发布时把 version 置为 0
+    let any_changed = config
+        .fields
+        .iter()
+        .filter(|f| !UNTRACKED_COLUMNS.contains(f))
+        .map(|f| format!("OLD.{f} IS NOT NEW.{f}"))
+        .collect::<Vec<_>>()
+        .join(" OR ");
+    let changed_columns = config
+        .fields
+        .iter()
+        .filter(|f| !UNTRACKED_COLUMNS.contains(f))
+        .map(|f| format!("SELECT '{f}' AS c WHERE OLD.{f} IS NOT NEW.{f}"))
+        .collect::<Vec<_>>()
+        .join(" UNION ALL ");
+    let stamp = sync_vector::stamp_sql();
+
+    format!(
+        "DROP TRIGGER IF EXISTS sync_changelog_{table}_insert;
+         DROP TRIGGER IF EXISTS sync_changelog_{table}_update;
+         DROP TRIGGER IF EXISTS sync_changelog_{table}_rekey;
+         DROP TRIGGER IF EXISTS sync_changelog_{table}_delete;
+
+         -- 每次写入都从序列分配新版本号，覆盖写入方给出的 version（前端的负数版本号、同步写入的占位值），
+         -- 并记入版本向量的本机条目（应用远程变更时由同步引擎改回合并后的向量）
+         CREATE TRIGGER sync_changelog_{table}_insert AFTER INSERT ON {table}
+         BEGIN
+             {BUMP_SQL};
+             UPDATE {table} SET version = {CURRENT_SQL}, vv = {stamp} WHERE {pk} = NEW.{pk};
+             INSERT INTO sync_changelog (table_name, pk, op, version)
+             VALUES ('{table}', NEW.{pk}, '{OP_INSERT}', {CURRENT_SQL});
+         END;
+
+         CREATE TRIGGER sync_changelog_{table}_update AFTER UPDATE ON {table}
+         WHEN {any_changed} OR NEW.version IS NULL OR NEW.version <= 0
+         BEGIN
+             {BUMP_SQL};
+             UPDATE {table} SET version = {CURRENT_SQL}, vv = {stamp} WHERE {pk} = NEW.{pk};
+             INSERT INTO sync_changelog (table_name, pk, op, changed_columns, version)
+             VALUES (
+                 '{table}',
+                 NEW.{pk},
+                 CASE WHEN OLD.{pk} IS NOT NEW.{pk} THEN '{OP_INSERT}' ELSE '{OP_UPDATE}' END,
+                 (SELECT json_group_array(c) FROM ({changed_columns})),
+                 {CURRENT_SQL}
+             );
+         END;
+
+         -- 主键重命名：旧主键记为硬删除（新主键由 update 触发器记为 insert）
+         CREATE TRIGGER sync_changelog_{table}_rekey AFTER UPDATE OF {pk} ON {table}
+         WHEN OLD.{pk} IS NOT NEW.{pk}
+         BEGIN
+             {BUMP_SQL};
+             INSERT INTO sync_changelog (table_name, pk, op, version, created_at)
+             VALUES ('{table}', OLD.{pk}, '{OP_DELETE}', {CURRENT_SQL}, {NOW_ISO});
+         END;
+
+         CREATE TRIGGER sync_changelog_{table}_delete AFTER DELETE ON {table}
+         BEGIN
+             {BUMP_SQL};
+             INSERT INTO sync_changelog (table_name, pk, op, version, created_at)
+             VALUES ('{table}', OLD.{pk}, '{OP_DELETE}', {CURRENT_SQL}, {NOW_ISO});
+         END;"
+    )
+}
+
+/// 记录一条不对应任何本地记录的删除（收到了本机从未见过的记录的删除）
+///
+/// 不再插入空白占位记录，删除只保留在日志中，并随墓碑回收一起清除
+pub fn record_tombstone(
+    conn: &Connection,
+    table_name: &str,
+    pk: &str,
+    hlc: &str,
+    deleted_at: &str,
+) -> rusqlite::Result<()> {
+    let version = sync_sequence::next(conn)?;
+    conn.execute(
+        "INSERT INTO sync_changelog (table_name, pk, op, version, hlc, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![table_name, pk, OP_DELETE, version, hlc, deleted_at],
+    )?;
+    Ok(())
+}
+
+/// 把记录刚写入的删除日志标记为彻底清除，并记下清除时的 HLC
+pub fn mark_purged(conn: &Connection, table_name: &str, pk: &str, hlc: &str) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE sync_changelog SET purged = 1, hlc = ?1
+         WHERE seq = (SELECT MAX(seq) FROM sync_changelog WHERE table_name = ?2 AND pk = ?3) AND op = ?4",
+        params![hlc, table_name, pk, OP_DELETE],
+    )?;
+    Ok(())
+}
+
+/// 一条记录最近一次写入分配到的版本号（变更日志中最新一条）
+pub fn latest_version(conn: &Connection, table_name: &str, pk: &str) -> rusqlite::Result<Option<i64>> {
+    conn.query_row(
+        "SELECT version FROM sync_changelog WHERE table_name = ?1 AND pk = ?2 ORDER BY seq DESC LIMIT 1",
+        params![table_name, pk],
+        |row| row.get(0),
+    )
+    .optional()
+    .map(Option::flatten)
+}
+
+/// 记录已被删除（日志中最新一条为 delete）时返回删除的时间戳
+pub fn tombstone_hlc(conn: &Connection, table_name: &str, pk: &str) -> rusqlite::Result<Option<Hlc>> {
+    let latest: Option<(String, Option<String>, Option<String>)> = conn
+        .query_row(
+            "SELECT op, hlc, created_at FROM sync_changelog WHERE table_name = ?1 AND pk = ?2 ORDER BY seq DESC LIMIT 1",
+            params![table_name, pk],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()?;
+
+    Ok(latest
+        .filter(|(op, _, _)| op == OP_DELETE)
+        .map(|(_, hlc, created_at)| Hlc::effective(hlc.as_deref(), created_at.as_deref().unwrap_or(""))))
+}
+
+/// 按 (版本号, 主键) 顺序列出 since_version 之后有变更的记录，每条记录只取最新状态
+///
+/// `after_pk` 的语义与 `load_table_changes` 相同
+pub fn latest_entries(
+    conn: &Connection,
+    config: &TableConfig,
+    since_version: i64,
+    after_pk: Option<&str>,
+    limit: usize,
+) -> rusqlite::Result<Vec<LogEntry>> {
+    let query = format!(
+        "SELECT pk, op, v, hlc, created_at, purged FROM (
+             SELECT l.pk AS pk, l.op AS op, l.hlc AS hlc, l.created_at AS created_at, l.purged AS purged,
+                    CASE WHEN l.op = ?1 THEN l.version ELSE t.version END AS v
+             FROM sync_changelog l
+             LEFT JOIN {table} t ON t.{pk} = l.pk
+             WHERE l.table_name = ?2
+               AND l.seq = (SELECT MAX(seq) FROM sync_changelog m WHERE m.table_name = ?2 AND m.pk = l.pk)
+         )
+         WHERE v > ?3 OR (v = ?3 AND ?4 IS NOT NULL AND pk > ?4)
+         ORDER BY v ASC, pk ASC
+         LIMIT ?5",
+        table = config.name,
+        pk = config.primary_key
+    );
+
+    let mut stmt = conn.prepare(&query)?;
+    let rows = stmt.query_map(
+        params![OP_DELETE, config.name, since_version, after_pk, limit as i64],
+        |row| {
+            Ok(LogEntry {
+                pk: row.get(0)?,
+                op: row.get(1)?,
+                version: row.get::<_, Option<i64>>(2)?.unwrap_or(0),
+                hlc: row.get(3)?,
+                created_at: row.get::<_, Option<String>>(4)?.unwrap_or_default(),
+                purged: row.get(5)?,
+            })
+        },
+    )?;
+    rows.collect()
+}
+
+/// 客户端在 since_version 之后尚未见过的字段变更（并集）
+///
+/// 期间有 insert（新建或主键重命名）时返回 None，表示整条记录都是新的
+pub fn changed_columns_since(
+    conn: &Connection,
+    table_name: &str,
+    pk: &str,
+    since_version: i64,
+) -> rusqlite::Result<Option<Vec<String>>> {
+    let mut stmt = conn.prepare(
+        "SELECT op, changed_columns FROM sync_changelog
+         WHERE table_name = ?1 AND pk = ?2
+           AND seq > COALESCE((
+               SELECT MAX(seq) FROM sync_changelog
+               WHERE table_name = ?1 AND pk = ?2 AND version > 0 AND version <= ?3
+           ), 0)
+         ORDER BY seq ASC",
+    )?;
+    let mut rows = stmt.query(params![table_name, pk, since_version])?;
+
+    let mut columns: Vec<String> = Vec::new();
+    while let Some(row) = rows.next()? {
+        let op: String = row.get(0)?;
+        if op == OP_INSERT {
+            return Ok(None);
+        }
+        let changed: Option<String> = row.get(1)?;
+        let changed: Vec<String> = changed
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default();
+        for column in changed {
+            if !columns.contains(&column) {
+                columns.push(column);
+            }
+        }
+    }
+    Ok(Some(columns))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::mem_db;
+
+    fn version(conn: &Connection, uuid: &str) -> i64 {
+        conn.query_row("SELECT version FROM notes WHERE uuid = ?1", params![uuid], |row| row.get(0)).unwrap()
+    }
+
+    /// (op, changed_columns, version)
+    fn log(conn: &Connection, pk: &str) -> Vec<(String, Option<String>, i64)> {
+        conn.prepare("SELECT op, changed_columns, version FROM sync_changelog WHERE table_name = 'notes' AND pk = ?1 ORDER BY seq")
+            .unwrap()
+            .query_map(params![pk], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap()
+    }
+
+    fn insert_note(conn: &Connection, uuid: &str) {
+        // 前端写入的负数版本号
+        conn.execute(
+            "INSERT INTO notes (uuid, title, content, tags, version, updated_at, hlc) VALUES (?1, 't', 'c', '[]', -1, '2024-01-01T00:00:00Z', 'h0')",
+            params![uuid],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn insert_and_update_assign_versions_from_the_sequence() {
+        let conn = mem_db();
+        insert_note(&conn, "n1");
+        let inserted = version(&conn, "n1");
+        assert!(inserted > 0);
+        assert_eq!(log(&conn, "n1"), vec![(OP_INSERT.to_string(), None, inserted)]);
+
+        conn.execute("UPDATE notes SET content = 'c2', version = -5, hlc = 'h1' WHERE uuid = 'n1'", []).unwrap();
+        let updated = version(&conn, "n1");
+        assert!(updated > inserted);
+        assert_eq!(log(&conn, "n1")[1], (OP_UPDATE.to_string(), Some("[\"content\"]".to_string()), updated));
+        assert_eq!(latest_version(&conn, "notes", "n1").unwrap(), Some(updated));
+    }
+
+    #[test]
+    fn metadata_only_updates_are_not_changes() {
+        let conn = mem_db();
+        insert_note(&conn, "n1");
+        let before = version(&conn, "n1");
+
+        // 内容未变的自动保存
+        conn.execute("UPDATE notes SET updated_at = '2024-01-02T00:00:00Z', hlc = 'h1' WHERE uuid = 'n1'", []).unwrap();
+        assert_eq!(version(&conn, "n1"), before);
+        assert_eq!(log(&conn, "n1").len(), 1);
+
+        // version 置 0 强制重新发布
+        conn.execute("UPDATE notes SET version = 0, hlc = 'h2' WHERE uuid = 'n1'", []).unwrap();
+        let restamped = version(&conn, "n1");
+        assert!(restamped > before);
+        assert_eq!(log(&conn, "n1")[1], (OP_UPDATE.to_string(), Some("[]".to_string()), restamped));
+    }
+
+    #[test]
+    fn rekey_and_delete_record_tombstones() {
+        let conn = mem_db();
+        insert_note(&conn, "old");
+        conn.execute("UPDATE notes SET uuid = 'new' WHERE uuid = 'old'", []).unwrap();
+
+        let old = log(&conn, "old");
+        assert_eq!(old.len(), 2);
+        assert_eq!(old[1].0, OP_DELETE);
+        let new = log(&conn, "new");
+        assert_eq!(new.len(), 1);
+        assert_eq!(new[0].0, OP_INSERT);
+        assert_eq!(new[0].2, version(&conn, "new"));
+        assert!(new[0].2 > old[1].2);
+        assert!(tombstone_hlc(&conn, "notes", "old").unwrap().is_some());
+        assert!(tombstone_hlc(&conn, "notes", "new").unwrap().is_none());
+
+        let before = version(&conn, "new");
+        conn.execute("DELETE FROM notes WHERE uuid = 'new'", []).unwrap();
+        let deleted = log(&conn, "new");
+        assert_eq!(deleted[1].0, OP_DELETE);
+        assert!(deleted[1].2 > before);
+        assert!(tombstone_hlc(&conn, "notes", "new").unwrap().is_some());
+    }
+
+    #[test]
+    fn changed_columns_since_unions_updates_and_stops_at_insert() {
+        let conn = mem_db();
+        insert_note(&conn, "n1");
+        let inserted = version(&conn, "n1");
+        conn.execute("UPDATE notes SET title = 't2' WHERE uuid = 'n1'", []).unwrap();
+        let titled = version(&conn, "n1");
+        conn.execute("UPDATE notes SET content = 'c2', tags = '[\"a\"]' WHERE uuid = 'n1'", []).unwrap();
+
+        assert_eq!(changed_columns_since(&conn, "notes", "n1", inserted - 1).unwrap(), None);
+        let mut columns = changed_columns_since(&conn, "notes", "n1", inserted).unwrap().unwrap();
+        columns.sort();
+        assert_eq!(columns, vec!["content", "tags", "title"]);
+        let mut columns = changed_columns_since(&conn, "notes", "n1", titled).unwrap().unwrap();
+        columns.sort();
+        assert_eq!(columns, vec!["content", "tags"]);
+    }
+}
@@ -0,0 +1,830 @@
+//! 原生同步客户端
+//! 以客户端身份通过 /state、/merkle、/pull、/push 与另一台 ZotePad 桌面端同步，
+//! 不依赖前端 webview；流程与移动端一致：先推送本地变更，再拉取远程变更。
+//!
+//! 同步前先调用 /v1/handshake 协商协议版本，之后的请求都走 /v1 路由；
+//! 对端不支持握手（旧版本）时退回不带前缀的旧路由
+//!
+//! 每个对端的进度保存在 sync_meta 中：
+//! - `peer:<地址>:pushed`：已推送到的本地版本号
+//! - `peer:<地址>:pulled`：已拉取到的对端版本号
+//!
+//! 两端交换 knowledge（版本向量）：对端已见过的本地写入不再推送，本机已见过的远程写入对端不再返回
+
+use crate::hlc::HybridClock;
+use crate::sync_devices::{self, DeviceInfo};
+use crate::sync_engine::{self, ChangeOutcome, ChangeStatus, PullCursor, SyncChange, SyncOp};
+use crate::sync_merkle::{self, MerkleIndex, MerkleNode, Reconciler};
+use crate::sync_protocol::{self, HandshakeRequest, HandshakeResponse, Refusal};
+use crate::sync_scopes;
+use crate::sync_sequence;
+use crate::sync_sessions::{self, TableCounts};
+use crate::sync_stream::{PullStreamItem, StreamSummary};
+use crate::sync_vector::{self, VersionVector};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fmt;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri_plugin_http::reqwest;
+
+/// 每页拉取 / 每批推送 / 每次应用的变更数
+const BATCH_SIZE: usize = 200;
+
+/// 超过该大小的推送请求体用 gzip 压缩
+const COMPRESS_THRESHOLD: usize = 16 * 1024;
+
+/// 同步客户端错误
+#[derive(Debug)]
+pub enum ClientError {
+    /// 网络错误或连接失败
+    Http(reqwest::Error),
+    /// 对端返回非 2xx 状态码
+    Status(u16),
+    /// 对端返回 success = false 或响应无法解析
+    Protocol(String),
+    /// 本设备已被对端移除
+    Unpaired,
+    /// 对端拒绝同步（协议版本不兼容或同步表结构冲突）
+    Refused(Refusal),
+    Db(rusqlite::Error),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Http(e) => write!(f, "连接对端失败: {}", e),
+            ClientError::Status(401) => write!(f, "对端拒绝访问：令牌无效"),
+            ClientError::Status(403) => write!(f, "此设备已在对端被移除，无法同步"),
+            ClientError::Status(code) => write!(f, "对端返回错误状态码 {}", code),
+            ClientError::Protocol(msg) => write!(f, "对端响应无效: {}", msg),
+            ClientError::Unpaired => write!(f, "此设备已在对端被移除，无法同步"),
+            ClientError::Refused(refusal) => write!(f, "对端拒绝同步: {}", refusal.message),
+            ClientError::Db(e) => write!(f, "本地数据库错误: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(e: reqwest::Error) -> Self {
+        ClientError::Http(e)
+    }
+}
+
+impl From<rusqlite::Error> for ClientError {
+    fn from(e: rusqlite::Error) -> Self {
+        ClientError::Db(e)
+    }
+}
+
+/// 对端 API 的统一响应外壳
+#[derive(Deserialize)]
+struct Envelope<T> {
+    success: bool,
+    data: Option<T>,
+    message: Option<String>,
+}
+
+/// /state 响应（只取客户端需要的字段）
+#[derive(Deserialize, Debug, Clone)]
+pub struct RemoteState {
+    pub version: i64,
+    #[serde(default)]
+    pub server_version: String,
+    pub paired: bool,
+    #[serde(default)]
+    pub low_water_mark: i64,
+    /// 对端已完整接收到的各副本版本号（旧版本服务端不返回）
+    #[serde(default)]
+    pub knowledge: VersionVector,
+}
+
+/// /metadata 中的一条记录（对端不支持 /merkle 时全量对账，只需要主键）
+#[derive(Deserialize, Debug, Clone)]
+pub struct RemoteMetadata {
+    pub uuid: String,
+}
+
+/// /pull 响应
+#[derive(Deserialize, Debug, Clone)]
+pub struct RemotePull {
+    pub changes: Vec<SyncChange>,
+    pub next_cursor: Option<String>,
+    pub server_version: i64,
+    #[serde(default)]
+    pub resync_required: bool,
+    #[serde(default)]
+    pub low_water_mark: i64,
+    #[serde(default)]
+    pub scoped: bool,
+}
+
+/// /push 响应
+#[derive(Deserialize, Debug, Clone)]
+pub struct RemotePush {
+    pub applied: usize,
+    #[serde(default)]
+    pub results: Vec<ChangeOutcome>,
+}
+
+#[derive(Serialize)]
+struct PushBody<'a> {
+    changes: &'a [SyncChange],
+    atomic: bool,
+}
+
+/// 同步进度（通过 sync:progress 事件发给前端）
+#[derive(Serialize, Debug, Clone)]
+pub struct SyncProgress {
+    /// state / push / pull / reconcile / done
+    pub phase: &'static str,
+    /// 本阶段已处理的变更数
+    pub processed: usize,
+    pub peer: String,
+}
+
+/// 一次同步的结果
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct SyncRunReport {
+    /// 对端实际写入的本地变更数
+    pub pushed: usize,
+    /// 本地实际写入的远程变更数
+    pub pulled: usize,
+    /// 被对端放入冲突队列的变更数
+    pub conflicts: usize,
+    /// 被对端拒绝的变更数
+    pub rejected: usize,
+    /// 全量对账时软删除的本地记录数
+    pub removed: usize,
+    /// 移出了本机在对端的同步范围、已删除本地副本的记录数
+    pub scoped_out: usize,
+    /// 对端墓碑已回收或本机的同步范围已修改，本次做了全量对账
+    pub resynced: bool,
+    /// 同步完成时对端的版本号
+    pub remote_version: i64,
+    /// 各表的拉取 / 推送 / 冲突 / 拒绝数
+    pub tables: BTreeMap<String, TableCounts>,
+}
+
+/// 访问另一台 ZotePad 同步服务的 HTTP 客户端
+pub struct SyncClient {
+    base_url: String,
+    token: Option<String>,
+    device: DeviceInfo,
+    http: reqwest::Client,
+    /// 路由前缀：握手成功后为 /v1，对端不支持握手时为空
+    api_prefix: Mutex<&'static str>,
+    /// 已收到 / 已发出的字节数（响应体按解压后计算）
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+}
+
+impl SyncClient {
+    pub fn new(base_url: &str, token: Option<String>, device: DeviceInfo) -> Result<Self, ClientError> {
+        let http = reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(5))
+            .timeout(Duration::from_secs(60))
+            .build()?;
+        Ok(SyncClient {
+            base_url: base_url.trim().trim_end_matches('/').to_string(),
+            token: token.filter(|t| !t.is_empty()),
+            device,
+            http,
+            api_prefix: Mutex::new(""),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+        })
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// 已收到、已发出的字节数
+    pub fn transferred(&self) -> (u64, u64) {
+        (self.bytes_in.load(Ordering::Relaxed), self.bytes_out.load(Ordering::Relaxed))
+    }
+
+    fn count_in(&self, bytes: usize) {
+        self.bytes_in.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn count_out(&self, bytes: usize) {
+        self.bytes_out.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// 附加鉴权、设备标识与协议版本请求头
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let prefix = *self.api_prefix.lock().unwrap();
+        self.request_url(method, format!("{}{}{}", self.base_url, prefix, path))
+    }
+
+    fn request_url(&self, method: reqwest::Method, url: String) -> reqwest::RequestBuilder {
+        let mut req = self
+            .http
+            .request(method, url)
+            .header(sync_protocol::PROTOCOL_HEADER, sync_protocol::PROTOCOL_VERSION.to_string())
+            .header(sync_devices::DEVICE_ID_HEADER, sync_devices::percent_encode(&self.device.device_id));
+        if let Some(token) = &self.token {
+            req = req.header("Authorization", format!("Bearer {}", token));
+        }
+        if let Some(name) = &self.device.name {
+            req = req.header(sync_devices::DEVICE_NAME_HEADER, sync_devices::percent_encode(name));
+        }
+        if let Some(version) = &self.device.app_version {
+            req = req.header(sync_devices::APP_VERSION_HEADER, sync_devices::percent_encode(version));
+        }
+        req
+    }
+
+    async fn send<T: DeserializeOwned>(&self, req: reqwest::RequestBuilder) -> Result<T, ClientError> {
+        let res = req.send().await?;
+        let status = res.status();
+        if !status.is_success() {
+            return Err(ClientError::Status(status.as_u16()));
+        }
+        let bytes = res.bytes().await?;
+        self.count_in(bytes.len());
+        let envelope: Envelope<T> = serde_json::from_slice(&bytes).map_err(|e| ClientError::Protocol(e.to_string()))?;
+        match (envelope.success, envelope.data) {
+            (true, Some(data)) => Ok(data),
+            _ => Err(ClientError::Protocol(envelope.message.unwrap_or_else(|| "missing data".to_string()))),
+        }
+    }
+
+    /// POST /v1/handshake：协商协议版本与能力，成功后改用 /v1 路由
+    ///
+    /// 对端不支持握手（404）时返回 None，继续使用旧路由；对端拒绝时返回 `ClientError::Refused`
+    pub async fn handshake(&self) -> Result<Option<HandshakeResponse>, ClientError> {
+        let body = HandshakeRequest {
+            protocol_version: sync_protocol::PROTOCOL_VERSION,
+            min_protocol_version: Some(sync_protocol::MIN_PROTOCOL_VERSION),
+            app_version: self.device.app_version.clone(),
+            tables: sync_protocol::server_tables(),
+            capabilities: sync_protocol::CAPABILITIES.iter().map(|c| c.to_string()).collect(),
+        };
+        let body = serde_json::to_string(&body).map_err(|e| ClientError::Protocol(e.to_string()))?;
+        self.count_out(body.len());
+        let res = self
+            .request_url(reqwest::Method::POST, format!("{}/v1/handshake", self.base_url))
+            .header("Content-Type", "application/json")
+            .body(body)
+            .send()
+            .await?;
+        let status = res.status().as_u16();
+        if status == 404 {
+            *self.api_prefix.lock().unwrap() = "";
+            return Ok(None);
+        }
+        if status == 409 || status == 426 {
+            let bytes = res.bytes().await?;
+            self.count_in(bytes.len());
+            let envelope: Envelope<Refusal> =
+                serde_json::from_slice(&bytes).map_err(|e| ClientError::Protocol(e.to_string()))?;
+            return Err(match envelope.data {
+                Some(refusal) => ClientError::Refused(refusal),
+                None => ClientError::Status(status),
+            });
+        }
+        if !(200..300).contains(&status) {
+            return Err(ClientError::Status(status));
+        }
+        let bytes = res.bytes().await?;
+        self.count_in(bytes.len());
+        let envelope: Envelope<HandshakeResponse> =
+            serde_json::from_slice(&bytes).map_err(|e| ClientError::Protocol(e.to_string()))?;
+        match (envelope.success, envelope.data) {
+            (true, Some(response)) => {
+                *self.api_prefix.lock().unwrap() = "/v1";
+                Ok(Some(response))
+            }
+            _ => Err(ClientError::Protocol(envelope.message.unwrap_or_else(|| "missing data".to_string()))),
+        }
+    }
+
+    /// GET /state
+    pub async fn state(&self) -> Result<RemoteState, ClientError> {
+        self.send(self.request(reqwest::Method::GET, "/state")).await
+    }
+
+    /// GET /metadata：对端指定表中未删除记录的元数据
+    pub async fn metadata(&self, table: &str) -> Result<Vec<RemoteMetadata>, ClientError> {
+        self.send(self.request(reqwest::Method::GET, &format!("/metadata?table={}", table))).await
+    }
+
+    /// POST /merkle：展开对端指定表中的桶
+    pub async fn merkle(&self, table: &str, prefixes: &[String]) -> Result<Vec<MerkleNode>, ClientError> {
+        let body = serde_json::json!({ "table": table, "prefixes": prefixes }).to_string();
+        self.count_out(body.len());
+        self.send(
+            self.request(reqwest::Method::POST, "/merkle")
+                .header("Content-Type", "application/json")
+                .body(body),
+        )
+        .await
+    }
+
+    /// 与对端逐层比较桶哈希，返回两端哈希不同的桶中对端记录的主键（本地这些桶中的记录在 `reconciler.local_rows`）
+    ///
+    /// 对端是不支持范围哈希对账的旧版本时返回 `ClientError::Status(404)`
+    pub async fn reconcile(&self, table: &str, reconciler: &mut Reconciler<'_>) -> Result<HashSet<String>, ClientError> {
+        while !reconciler.pending().is_empty() {
+            let mut nodes = Vec::new();
+            for chunk in reconciler.pending().chunks(sync_merkle::MAX_PREFIXES) {
+                nodes.extend(self.merkle(table, chunk).await?);
+            }
+            reconciler.feed(nodes);
+        }
+        Ok(reconciler
+            .remote
+            .iter()
+            .filter_map(|m| m["uuid"].as_str().map(|s| s.to_string()))
+            .collect())
+    }
+
+    /// GET /pull：跨表拉取一页变更；cursor 为上一页的 next_cursor，knowledge 为本机已见过的写入
+    pub async fn pull(
+        &self,
+        since_version: i64,
+        cursor: Option<&str>,
+        knowledge: &VersionVector,
+    ) -> Result<RemotePull, ClientError> {
+        let mut path = format!("/pull?since_version={}&limit={}", since_version, BATCH_SIZE);
+        // 游标和 knowledge 都是 URL 安全的 base64，无需转义
+        if let Some(cursor) = cursor {
+            path.push_str(&format!("&cursor={}", cursor));
+        }
+        if !knowledge.is_empty() {
+            path.push_str(&format!("&knowledge={}", knowledge.encode_param()));
+        }
+        self.send(self.request(reqwest::Method::GET, &path)).await
+    }
+
+    /// GET /pull/stream：逐行读取 NDJSON，每攒够一批交给 `on_batch`，返回最后的 end 行
+    ///
+    /// 对端是不支持流式拉取的旧版本时返回 `ClientError::Status(404)`
+    pub async fn pull_stream(
+        &self,
+        since_version: i64,
+        knowledge: &VersionVector,
+        mut on_batch: impl FnMut(Vec<SyncChange>) -> Result<(), ClientError>,
+    ) -> Result<StreamSummary, ClientError> {
+        let mut path = format!("/pull/stream?since_version={}", since_version);
+        if !knowledge.is_empty() {
+            path.push_str(&format!("&knowledge={}", knowledge.encode_param()));
+        }
+        let mut res = self.request(reqwest::Method::GET, &path).send().await?;
+        let status = res.status();
+        if !status.is_success() {
+            return Err(ClientError::Status(status.as_u16()));
+        }
+
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut batch = Vec::with_capacity(BATCH_SIZE);
+        while let Some(chunk) = res.chunk().await? {
+            self.count_in(chunk.len());
+            buffer.extend_from_slice(&chunk);
+            while let Some(pos) = buffer.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=pos).collect();
+                let item: PullStreamItem =
+                    serde_json::from_slice(&line).map_err(|e| ClientError::Protocol(e.to_string()))?;
+                match item {
+                    PullStreamItem::Change(change) => {
+                        batch.push(change);
+                        if batch.len() >= BATCH_SIZE {
+                            on_batch(std::mem::take(&mut batch))?;
+                        }
+                    }
+                    PullStreamItem::End(summary) => {
+                        if !batch.is_empty() {
+                            on_batch(batch)?;
+                        }
+                        return Ok(summary);
+                    }
+                }
+            }
+        }
+        // 没有 end 行：对端在写出途中出错或连接被中断，已应用的变更不受影响，下次从原游标重新拉取
+        Err(ClientError::Protocol("stream ended before end line".to_string()))
+    }
+
+    /// POST /push：逐条应用，单条失败不影响其他变更；较大的请求体用 gzip 压缩
+    pub async fn push(&self, changes: &[SyncChange]) -> Result<RemotePush, ClientError> {
+        let body = serde_json::to_vec(&PushBody { changes, atomic: false })
+            .map_err(|e| ClientError::Protocol(e.to_string()))?;
+        let mut req = self
+            .request(reqwest::Method::POST, "/push")
+            .header("Content-Type", "application/json");
+        if body.len() > COMPRESS_THRESHOLD {
+            let mut encoder = GzEncoder::new(Vec::with_capacity(body.len() / 4), Compression::fast());
+            let compressed = encoder
+                .write_all(&body)
+                .and_then(|_| encoder.finish())
+                .map_err(|e| ClientError::Protocol(e.to_string()))?;
+            self.count_out(compressed.len());
+            req = req.header("Content-Encoding", "gzip").body(compressed);
+        } else {
+            self.count_out(body.len());
+            req = req.body(body);
+        }
+        self.send(req).await
+    }
+}
+
+/// 与对端完成一次双向同步
+///
+/// 拉取回来的变更在本地会分配新的版本号，下次同步时会再推送给对端；
+/// 对端按 HLC 判断为相同写入并跳过，不会产生冲突。
+pub async fn run(
+    client: &SyncClient,
+    conn: &mut Connection,
+    clock: &HybridClock,
+    on_progress: &(dyn Fn(SyncProgress) + Send + Sync),
+) -> Result<SyncRunReport, ClientError> {
+    let progress = |phase: &'static str, processed: usize| {
+        on_progress(SyncProgress { phase, processed, peer: client.base_url().to_string() })
+    };
+    let mut report = SyncRunReport::default();
+
+    // 1. 协商协议版本，确认本设备仍被对端接受
+    progress("state", 0);
+    match client.handshake().await? {
+        Some(handshake) => {
+            for warning in &handshake.warnings {
+                log::warn!(
+                    "[SyncClient] 表 {} 与对端字段不一致：对端会忽略 {:?}，对端多出 {:?}",
+                    warning.table,
+                    warning.unknown_fields,
+                    warning.missing_fields
+                );
+            }
+        }
+        None => log::info!("[SyncClient] {} 不支持协议握手，使用旧版接口", client.base_url()),
+    }
+    let state = client.state().await?;
+    if !state.paired {
+        return Err(ClientError::Unpaired);
+    }
+    log::info!(
+        "[SyncClient] 开始与 {} 同步：对端版本 {}（{}）",
+        client.base_url(),
+        state.version,
+        state.server_version
+    );
+
+    // 2. 推送本地变更
+    let pushed_key = cursor_key(client.base_url(), "pushed");
+    let pushed_before = read_cursor(conn, &pushed_key)?;
+    let local_head = sync_sequence::current(conn);
+    let mut after: Option<PullCursor> = None;
+    loop {
+        let (mut changes, next) = sync_engine::load_all_changes(conn, pushed_before, after.as_ref(), BATCH_SIZE, None)?;
+        if changes.is_empty() {
+            break;
+        }
+        // 对端已从其他副本收到的写入不再推送
+        changes.retain(|c| !c.vv.as_ref().is_some_and(|vv| vv.seen_by(&state.knowledge)));
+        let result = if changes.is_empty() {
+            RemotePush { applied: 0, results: Vec::new() }
+        } else {
+            client.push(&changes).await?
+        };
+        report.pushed += result.applied;
+        sync_sessions::count_outcomes(&mut report.tables, &result.results, false);
+        for outcome in &result.results {
+            match outcome.status {
+                ChangeStatus::Conflict => report.conflicts += 1,
+                ChangeStatus::Rejected => {
+                    report.rejected += 1;
+                    log::warn!(
+                        "[SyncClient] 对端拒绝 {} {:?}: {}",
+                        outcome.table,
+                        outcome.pk,
+                        outcome.reason.as_deref().unwrap_or("")
+                    );
+                }
+                _ => {}
+            }
+        }
+        progress("push", report.pushed);
+        match next {
+            Some(cursor) => after = Some(cursor),
+            None => break,
+        }
+    }
+    write_cursor(conn, &pushed_key, local_head.max(pushed_before))?;
+
+    // 3. 拉取远程变更；对端墓碑已回收时从 0 全量拉取并对账
+    let pulled_key = cursor_key(client.base_url(), "pulled");
+    let since = read_cursor(conn, &pulled_key)?;
+    // 从 0 全量拉取时不带 knowledge：见过某次写入不代表仍持有该记录（例如移出同步范围后丢弃的副本）
+    let knowledge = if since > 0 { sync_vector::knowledge(conn)? } else { VersionVector::default() };
+    let mut summary = pull_all(client, conn, clock, since, &knowledge, &mut report, &progress).await?;
+    if summary.resync_required {
+        log::warn!("[SyncClient] 对端要求全量对账（游标 {}，对端低水位 {}）", since, summary.low_water_mark);
+        report.resynced = true;
+        summary = pull_all(client, conn, clock, 0, &VersionVector::default(), &mut report, &progress).await?;
+    }
+    report.remote_version = summary.server_version;
+
+    if report.resynced {
+        progress("reconcile", 0);
+        report.removed = remove_missing(client, conn, clock, pushed_before).await?;
+    }
+    write_cursor(conn, &pulled_key, report.remote_version)?;
+    // 已完整拉取对端：对端见过的写入本机也都见过了（对端按同步范围过滤过时不成立）
+    if !summary.scoped {
+        sync_vector::observe(conn, &state.knowledge)?;
+    }
+
+    progress("done", report.pushed + report.pulled);
+    log::info!(
+        "[SyncClient] 与 {} 同步完成：推送 {}，拉取 {}，冲突 {}，拒绝 {}",
+        client.base_url(),
+        report.pushed,
+        report.pulled,
+        report.conflicts,
+        report.rejected
+    );
+    Ok(report)
+}
+
+/// 拉取 since 之后的全部远程变更并分批应用；对端不支持流式拉取时改用分页拉取
+async fn pull_all(
+    client: &SyncClient,
+    conn: &mut Connection,
+    clock: &HybridClock,
+    since: i64,
+    knowledge: &VersionVector,
+    report: &mut SyncRunReport,
+    progress: &(dyn Fn(&'static str, usize) + Send + Sync),
+) -> Result<StreamSummary, ClientError> {
+    let mut apply = |changes: Vec<SyncChange>| -> Result<(), ClientError> {
+        // scope_out 不是删除：直接丢弃本地副本，不留墓碑，也不会作为删除推送回去
+        let (scoped_out, changes): (Vec<SyncChange>, Vec<SyncChange>) =
+            changes.into_iter().partition(|c| matches!(c.op, SyncOp::ScopeOut));
+        if !scoped_out.is_empty() {
+            let tx = conn.transaction()?;
+            for change in &scoped_out {
+                if sync_scopes::drop_local(&tx, change)? {
+                    report.scoped_out += 1;
+                }
+            }
+            tx.commit()?;
+        }
+        let applied = sync_engine::apply_changes(conn, &changes, None, clock, false)?;
+        report.pulled += applied.applied_count();
+        sync_sessions::count_outcomes(&mut report.tables, &applied.outcomes, true);
+        progress("pull", report.pulled);
+        Ok(())
+    };
+
+    match client.pull_stream(since, knowledge, &mut apply).await {
+        Err(ClientError::Status(404)) => log::info!("[SyncClient] 对端不支持流式拉取，改用分页拉取"),
+        result => return result,
+    }
+
+    let mut cursor: Option<String> = None;
+    loop {
+        let page = client.pull(since, cursor.as_deref(), knowledge).await?;
+        let summary = StreamSummary {
+            server_version: page.server_version,
+            resync_required: page.resync_required,
+            low_water_mark: page.low_water_mark,
+            scoped: page.scoped,
+            ..Default::default()
+        };
+        if page.resync_required {
+            return Ok(summary);
+        }
+        if !page.changes.is_empty() {
+            apply(page.changes)?;
+        }
+        match page.next_cursor {
+            Some(next) => cursor = Some(next),
+            None => return Ok(summary),
+        }
+    }
+}
+
+/// 全量对账：已推送过（版本号不超过推送游标）但对端已不存在的记录，说明对端已删除且墓碑已回收，本地软删除
+async fn remove_missing(
+    client: &SyncClient,
+    conn: &mut Connection,
+    clock: &HybridClock,
+    pushed_before: i64,
+) -> Result<usize, ClientError> {
+    let mut removed = 0;
+    for table in sync_engine::table_names() {
+        // 只比较两端哈希不同的桶；对端不支持时退回全量元数据
+        let local = MerkleIndex::build(sync_engine::load_table_metadata(conn, table, None)?);
+        let mut reconciler = Reconciler::new(&local);
+        let (remote, candidates) = match client.reconcile(table, &mut reconciler).await {
+            Ok(remote) => {
+                log::info!(
+                    "[SyncClient] {} 范围哈希对账：{} 轮请求，{} 条本地记录待核对",
+                    table,
+                    reconciler.rounds,
+                    reconciler.local_rows.len()
+                );
+                (remote, std::mem::take(&mut reconciler.local_rows))
+            }
+            Err(ClientError::Status(404)) => {
+                let remote = client.metadata(table).await?.into_iter().map(|m| m.uuid).collect();
+                (remote, local.rows(""))
+            }
+            Err(e) => return Err(e),
+        };
+        let missing: Vec<String> = candidates
+            .into_iter()
+            .filter(|m| m["version"].as_i64().is_some_and(|v| v > 0 && v <= pushed_before))
+            .filter_map(|m| m["uuid"].as_str().map(|s| s.to_string()))
+            .filter(|pk| !remote.contains(pk))
+            .collect();
+        if missing.is_empty() {
+            continue;
+        }
+
+        let config = sync_engine::get_table_config(table).expect("registered table");
+        let tx = conn.transaction()?;
+        let now = sync_engine::now_iso();
+        for pk in &missing {
+            tx.execute(
+                &format!(
+                    "UPDATE {} SET deleted_at = ?1, updated_at = ?1, hlc = ?2 WHERE {} = ?3",
+                    table, config.primary_key
+                ),
+                params![now, clock.now().encode(), pk],
+            )?;
+        }
+        tx.commit()?;
+        log::info!("[SyncClient] {} 全量对账：软删除 {} 条对端已回收的记录", table, missing.len());
+        removed += missing.len();
+    }
+    Ok(removed)
+}
+
+fn cursor_key(base_url: &str, kind: &str) -> String {
+    format!("peer:{}:{}", base_url, kind)
+}
+
+fn read_cursor(conn: &Connection, key: &str) -> rusqlite::Result<i64> {
+    let value: Option<String> = conn
+        .query_row("SELECT value FROM sync_meta WHERE key = ?1", params![key], |row| row.get(0))
+        .optional()?;
+    Ok(value.and_then(|v| v.parse().ok()).unwrap_or(0))
+}
+
+fn write_cursor(conn: &Connection, key: &str, version: i64) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO sync_meta (key, value, updated_at) VALUES (?1, ?2, ?3)",
+        params![key, version.to_string(), sync_engine::now_iso()],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::mem_db;
+    use axum::extract::{Query, State};
+    use axum::routing::{get, post};
+    use axum::{Json, Router};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    type Peer = (Arc<Mutex<Connection>>, Arc<HybridClock>);
+
+    fn ok(data: serde_json::Value) -> Json<serde_json::Value> {
+        Json(serde_json::json!({ "success": true, "data": data, "message": null }))
+    }
+
+    async fn handshake(Json(body): Json<HandshakeRequest>) -> Json<serde_json::Value> {
+        ok(serde_json::to_value(sync_protocol::negotiate(&body).unwrap()).unwrap())
+    }
+
+    async fn state(State((db, _)): State<Peer>) -> Json<serde_json::Value> {
+        let conn = db.lock().unwrap();
+        ok(serde_json::json!({
+            "version": sync_sequence::current(&conn),
+            "server_version": "test",
+            "paired": true,
+            "low_water_mark": 0,
+            "knowledge": sync_vector::knowledge(&conn).unwrap(),
+        }))
+    }
+
+    async fn pull(State((db, _)): State<Peer>, Query(query): Query<HashMap<String, String>>) -> Json<serde_json::Value> {
+        let conn = db.lock().unwrap();
+        let since: i64 = query["since_version"].parse().unwrap();
+        let after = query.get("cursor").map(|c| PullCursor::decode(c).unwrap());
+        // 每页两条，覆盖分页续读
+        let (mut changes, next) = sync_engine::load_all_changes(&conn, since, after.as_ref(), 2, None).unwrap();
+        if let Some(knowledge) = query.get("knowledge").and_then(|k| VersionVector::decode_param(k)) {
+            changes.retain(|c| !c.vv.as_ref().is_some_and(|vv| vv.seen_by(&knowledge)));
+        }
+        ok(serde_json::json!({
+            "changes": changes,
+            "next_cursor": next.map(|n| n.encode()),
+            "server_version": sync_sequence::current(&conn),
+        }))
+    }
+
+    async fn push(State((db, clock)): State<Peer>, Json(body): Json<serde_json::Value>) -> Json<serde_json::Value> {
+        let mut conn = db.lock().unwrap();
+        let changes: Vec<SyncChange> = serde_json::from_value(body["changes"].clone()).unwrap();
+        let report = sync_engine::apply_changes(&mut conn, &changes, None, &clock, false).unwrap();
+        ok(serde_json::json!({ "applied": report.applied_count(), "results": report.outcomes }))
+    }
+
+    /// 在 127.0.0.1 的随机端口上启动只有同步接口的对端，返回地址
+    async fn serve(db: Arc<Mutex<Connection>>) -> String {
+        let api = Router::new()
+            .route("/state", get(state))
+            .route("/pull", get(pull))
+            .route("/push", post(push));
+        let app = Router::new()
+            .nest("/v1", api.route("/handshake", post(handshake)))
+            .with_state((db, Arc::new(HybridClock::new("server".to_string()))));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}", addr)
+    }
+
+    fn replica(device_id: &str) -> Connection {
+        let conn = mem_db();
+        conn.execute(
+            "INSERT INTO sync_meta (key, value, updated_at) VALUES ('device_id', ?1, '')",
+            params![device_id],
+        )
+        .unwrap();
+        conn
+    }
+
+    fn add_note(conn: &Connection, uuid: &str, title: &str, updated_at: &str) {
+        conn.execute(
+            "INSERT INTO notes (uuid, title, content, tags, updated_at) VALUES (?1, ?2, 'body', '[]', ?3)",
+            params![uuid, title, updated_at],
+        )
+        .unwrap();
+    }
+
+    fn notes(conn: &Connection) -> Vec<(String, String, Option<String>)> {
+        let mut stmt = conn.prepare("SELECT uuid, title, deleted_at FROM notes ORDER BY uuid").unwrap();
+        stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn converges_with_in_process_peer() {
+        let server = Arc::new(Mutex::new(replica("server")));
+        for (uuid, day) in [("s1", "01"), ("s2", "02"), ("s3", "03")] {
+            add_note(&server.lock().unwrap(), uuid, "server", &format!("2024-01-{}T00:00:00Z", day));
+        }
+        let base_url = serve(server.clone()).await;
+
+        let mut local = replica("local");
+        add_note(&local, "l1", "local", "2024-02-01T00:00:00Z");
+        let clock = HybridClock::new("local".to_string());
+        let device = DeviceInfo { device_id: "local".to_string(), name: Some("测试设备".to_string()), app_version: None };
+        let client = SyncClient::new(&base_url, None, device).unwrap();
+        let phases = Mutex::new(Vec::new());
+        let on_progress = |p: SyncProgress| phases.lock().unwrap().push(p.phase);
+
+        let first = run(&client, &mut local, &clock, &on_progress).await.unwrap();
+        assert_eq!((first.pushed, first.pulled, first.rejected), (1, 3, 0), "{:?}", first);
+        assert_eq!(notes(&local), notes(&server.lock().unwrap()));
+        assert_eq!(notes(&local).len(), 4);
+        assert_eq!(phases.lock().unwrap().last(), Some(&"done"));
+
+        // 对端编辑、本地删除后再同步一次，两端仍一致
+        server
+            .lock()
+            .unwrap()
+            .execute("UPDATE notes SET title = 'edited', updated_at = ?1, hlc = NULL WHERE uuid = 's2'", params![sync_engine::now_iso()])
+            .unwrap();
+        local
+            .execute("UPDATE notes SET deleted_at = ?1, updated_at = ?1, hlc = ?2 WHERE uuid = 's1'", params![sync_engine::now_iso(), clock.now().encode()])
+            .unwrap();
+        let second = run(&client, &mut local, &clock, &on_progress).await.unwrap();
+        assert_eq!((second.pushed, second.pulled), (1, 1), "{:?}", second);
+        assert_eq!(notes(&local), notes(&server.lock().unwrap()));
+        assert!(notes(&local).iter().any(|(uuid, title, _)| uuid == "s2" && title == "edited"));
+        assert!(notes(&local).iter().any(|(uuid, _, deleted)| uuid == "s1" && deleted.is_some()));
+
+        // 没有新写入时，回显的变更两端都会跳过
+        let third = run(&client, &mut local, &clock, &on_progress).await.unwrap();
+        assert_eq!((third.pushed, third.pulled, third.rejected), (0, 0, 0), "{:?}", third);
+    }
+}
@@ -0,0 +1,238 @@
+//! 同步字段编解码
+//! 按 SQLite 列声明类型在列值与 SyncChange 的 JSON 之间转换：
+//! REAL 保留小数，BLOB 使用 base64，json_fields 以真正的 JSON 数组/对象传输
+
+use crate::sync_engine::TableConfig;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use rusqlite::types::{Value as SqlValue, ValueRef};
+use rusqlite::Connection;
+use serde_json::Value;
+
+/// 列的编码方式（由声明类型按 SQLite 类型亲和性规则推导，json_fields 优先）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnKind {
+    Integer,
+    Real,
+    Text,
+    Blob,
+    /// 未声明类型或 NUMERIC 亲和性：按存储类型原样传输
+    Numeric,
+    Json,
+}
+
+impl ColumnKind {
+    fn from_declared(declared: &str) -> ColumnKind {
+        let t = declared.to_ascii_uppercase();
+        if t.contains("INT") {
+            ColumnKind::Integer
+        } else if t.contains("CHAR") || t.contains("CLOB") || t.contains("TEXT") {
+            ColumnKind::Text
+        } else if t.contains("BLOB") {
+            ColumnKind::Blob
+        } else if t.contains("REAL") || t.contains("FLOA") || t.contains("DOUB") {
+            ColumnKind::Real
+        } else {
+            ColumnKind::Numeric
+        }
+    }
+}
+
+/// 按 config.fields 的顺序返回每个字段的编码方式
+pub fn column_kinds(conn: &Connection, config: &TableConfig) -> rusqlite::Result<Vec<ColumnKind>> {
+    let mut stmt = conn.prepare("SELECT name, type FROM pragma_table_info(?1)")?;
+    let declared = stmt
+        .query_map([config.name], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    Ok(config
+        .fields
+        .iter()
+        .map(|field| {
+            if config.json_fields.contains(field) {
+                return ColumnKind::Json;
+            }
+            declared
+                .iter()
+                .find(|(name, _)| name == field)
+                .map(|(_, t)| ColumnKind::from_declared(t))
+                .unwrap_or(ColumnKind::Numeric)
+        })
+        .collect())
+}
+
+/// 读取一列并转换为 JSON
+pub fn read_value(row: &rusqlite::Row, i: usize, kind: ColumnKind) -> rusqlite::Result<Value> {
+    Ok(match row.get_ref(i)? {
+        ValueRef::Null => Value::Null,
+        ValueRef::Integer(n) => Value::from(n),
+        ValueRef::Real(f) => serde_json::Number::from_f64(f).map(Value::Number).unwrap_or(Value::Null),
+        ValueRef::Blob(bytes) => Value::String(STANDARD.encode(bytes)),
+        ValueRef::Text(bytes) => {
+            let text = String::from_utf8_lossy(bytes);
+            match kind {
+                ColumnKind::Blob => Value::String(STANDARD.encode(bytes)),
+                ColumnKind::Json if text.trim().is_empty() => Value::Null,
+                // 库里已有的非法 JSON 原样发出，由接收端拒绝，而不是悄悄丢掉
+                ColumnKind::Json => serde_json::from_str(&text).unwrap_or_else(|e| {
+                    log::warn!("[SyncCodec] JSON 字段内容无法解析，按字符串发送: {}", e);
+                    Value::String(text.into_owned())
+                }),
+                _ => Value::String(text.into_owned()),
+            }
+        }
+    })
+}
+
+/// 把 JSON 值转换为写入该列的 SQL 值；类型不匹配或 JSON 非法时返回原因
+pub fn to_sql(value: &Value, kind: ColumnKind) -> Result<SqlValue, String> {
+    match (kind, value) {
+        (_, Value::Null) => Ok(SqlValue::Null),
+
+        // 旧客户端以字符串传 JSON：必须能解析
+        (ColumnKind::Json, Value::String(s)) if s.trim().is_empty() => Ok(SqlValue::Null),
+        (ColumnKind::Json, Value::String(s)) => serde_json::from_str::<Value>(s)
+            .map(|v| SqlValue::Text(v.to_string()))
+            .map_err(|e| format!("malformed JSON: {}", e)),
+        (ColumnKind::Json, v) => Ok(SqlValue::Text(v.to_string())),
+
+        (ColumnKind::Blob, Value::String(s)) => STANDARD
+            .decode(s.trim())
+            .map(SqlValue::Blob)
+            .map_err(|e| format!("invalid base64: {}", e)),
+        (ColumnKind::Blob, _) => Err("expected base64 string".to_string()),
+
+        (_, Value::Bool(b)) => Ok(SqlValue::Integer(*b as i64)),
+        (ColumnKind::Real, Value::Number(n)) => Ok(SqlValue::Real(n.as_f64().unwrap_or_default())),
+        (_, Value::Number(n)) => Ok(match n.as_i64() {
+            Some(i) => SqlValue::Integer(i),
+            None => SqlValue::Real(n.as_f64().unwrap_or_default()),
+        }),
+        (_, Value::String(s)) => Ok(SqlValue::Text(s.clone())),
+        (_, v) => Ok(SqlValue::Text(v.to_string())),
+    }
+}
+
+/// 检查一条变更的数据能否无损写入（写入前调用，失败时整条变更被拒绝）
+pub fn validate(config: &TableConfig, kinds: &[ColumnKind], data: &Value) -> Result<(), String> {
+    for (field, kind) in config.fields.iter().zip(kinds) {
+        if let Some(value) = data.get(*field) {
+            to_sql(value, *kind).map_err(|reason| format!("{}: {}", field, reason))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const FIELDS: &[&str] = &["id", "count", "ratio", "label", "payload", "loose", "meta"];
+
+    fn config() -> TableConfig {
+        TableConfig {
+            name: "codec_items",
+            primary_key: "id",
+            fields: FIELDS,
+            json_fields: &["meta"],
+            merge_fields: &[],
+            title_field: None,
+        }
+    }
+
+    fn codec_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE codec_items (
+                 id TEXT PRIMARY KEY, count BIGINT, ratio DOUBLE PRECISION, label VARCHAR(20),
+                 payload BLOB, loose, meta TEXT
+             )",
+        )
+        .unwrap();
+        conn
+    }
+
+    #[test]
+    fn kinds_follow_declared_affinity() {
+        let conn = codec_db();
+        let kinds = column_kinds(&conn, &config()).unwrap();
+        use ColumnKind::*;
+        assert_eq!(kinds, vec![Text, Integer, Real, Text, Blob, Numeric, Json]);
+        assert_eq!(ColumnKind::from_declared("DATETIME"), Numeric);
+        assert_eq!(ColumnKind::from_declared("float"), Real);
+        assert_eq!(ColumnKind::from_declared("CLOB"), Text);
+    }
+
+    #[test]
+    fn values_round_trip_through_sqlite() {
+        let conn = codec_db();
+        let config = config();
+        let kinds = column_kinds(&conn, &config).unwrap();
+        let data = json!({
+            "id": "c1",
+            "count": 9_007_199_254_740_993i64,
+            "ratio": 0.5,
+            "label": "标签",
+            "payload": STANDARD.encode([0u8, 159, 146, 150]),
+            "loose": 1.25,
+            "meta": { "tags": ["a", "b"], "pinned": true },
+        });
+        validate(&config, &kinds, &data).unwrap();
+
+        let values = FIELDS
+            .iter()
+            .zip(&kinds)
+            .map(|(field, kind)| to_sql(&data[*field], *kind).unwrap())
+            .collect::<Vec<_>>();
+        conn.execute(
+            "INSERT INTO codec_items VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            rusqlite::params_from_iter(values),
+        )
+        .unwrap();
+
+        let read = conn
+            .query_row("SELECT * FROM codec_items", [], |row| {
+                kinds.iter().enumerate().map(|(i, kind)| read_value(row, i, *kind)).collect::<rusqlite::Result<Vec<_>>>()
+            })
+            .unwrap();
+        for (field, value) in FIELDS.iter().zip(read) {
+            assert_eq!(value, data[*field], "{}", field);
+        }
+    }
+
+    #[test]
+    fn legacy_string_json_and_empty_values() {
+        assert_eq!(to_sql(&json!("[1, 2]"), ColumnKind::Json), Ok(SqlValue::Text("[1,2]".into())));
+        assert_eq!(to_sql(&json!("  "), ColumnKind::Json), Ok(SqlValue::Null));
+        assert_eq!(to_sql(&Value::Null, ColumnKind::Blob), Ok(SqlValue::Null));
+        assert_eq!(to_sql(&json!(true), ColumnKind::Integer), Ok(SqlValue::Integer(1)));
+        assert_eq!(to_sql(&json!(2), ColumnKind::Real), Ok(SqlValue::Real(2.0)));
+        assert_eq!(to_sql(&json!(["x"]), ColumnKind::Text), Ok(SqlValue::Text("[\"x\"]".into())));
+    }
+
+    #[test]
+    fn validate_rejects_values_that_cannot_be_stored() {
+        let conn = codec_db();
+        let config = config();
+        let kinds = column_kinds(&conn, &config).unwrap();
+        let reject = |field: &str, value: Value| {
+            let mut data = json!({ "id": "c1" });
+            data[field] = value;
+            validate(&config, &kinds, &data).unwrap_err()
+        };
+
+        assert!(reject("meta", json!("{not json")).starts_with("meta: malformed JSON"));
+        assert!(reject("payload", json!("***")).starts_with("payload: invalid base64"));
+        assert_eq!(reject("payload", json!(42)), "payload: expected base64 string");
+        assert_eq!(reject("payload", json!([1, 2])), "payload: expected base64 string");
+
+        // 整数、实数、文本和未声明类型的列按 SQLite 的宽松规则接受任意标量
+        for field in ["count", "ratio", "label", "loose"] {
+            let data = json!({ "id": "c1", field: "text" });
+            assert!(validate(&config, &kinds, &data).is_ok(), "{}", field);
+        }
+        // 未提供的字段不检查
+        assert!(validate(&config, &kinds, &json!({ "id": "c1" })).is_ok());
+    }
+}
@@ -0,0 +1,427 @@
+//! 同步冲突队列
+//! 服务端记录已越过客户端的编辑基线、且无法自动合并时，两端副本都保存在 sync_conflicts 中，
+//! 由用户选择保留本机、保留远程或两者都保留
+
+use crate::hlc::{Hlc, HybridClock};
+use crate::note_revisions;
+use crate::sync_engine::{self, ApplyOutcome, SyncChange, SyncOp};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+
+/// 冲突副本标题后缀
+const CONFLICT_COPY_SUFFIX: &str = "（冲突副本）";
+
+/// 冲突解决方式
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    /// 保留服务端（本机）副本，并重新打版本号使其传播到各端
+    #[serde(rename = "keep_local")]
+    Local,
+    /// 采用推送方（远程）副本
+    #[serde(rename = "keep_remote")]
+    Remote,
+    /// 保留本机副本，远程副本另存为一条新的冲突副本记录
+    #[serde(rename = "keep_both")]
+    Both,
+}
+
+impl Resolution {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Resolution::Local => "keep_local",
+            Resolution::Remote => "keep_remote",
+            Resolution::Both => "keep_both",
+        }
+    }
+}
+
+/// 解决冲突失败；失败时冲突保持未解决
+#[derive(Debug)]
+pub enum ResolveError {
+    /// 要保留的副本没有写入（被拒绝或被判定为旧版本）
+    NotApplied(String),
+    Db(rusqlite::Error),
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResolveError::NotApplied(reason) => write!(f, "无法写入远程副本: {}", reason),
+            ResolveError::Db(e) => write!(f, "数据库错误: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ResolveError {}
+
+impl From<rusqlite::Error> for ResolveError {
+    fn from(e: rusqlite::Error) -> Self {
+        ResolveError::Db(e)
+    }
+}
+
+/// 冲突队列中的一条记录
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SyncConflict {
+    pub id: i64,
+    pub table_name: String,
+    pub pk: String,
+    /// 客户端编辑所基于的版本号
+    pub base_version: i64,
+    /// 检测到冲突时服务端记录的版本号
+    pub local_version: i64,
+    /// 服务端副本（检测时）
+    pub local_data: serde_json::Value,
+    /// 推送方的完整变更
+    pub remote_change: SyncChange,
+    /// 推送方设备 ID（来自 HLC）
+    pub remote_device: Option<String>,
+    /// open | resolved
+    pub status: String,
+    pub resolution: Option<String>,
+    pub created_at: String,
+    pub resolved_at: Option<String>,
+}
+
+/// 存入冲突：每个推送方设备的副本各占一条，不同设备的编辑不会互相覆盖；
+/// 同一设备对同一条记录再次推送冲突的编辑时，用它的最新副本更新该设备那条未解决的冲突
+pub fn record_conflict(
+    conn: &Connection,
+    table_name: &str,
+    pk: &str,
+    base_version: i64,
+    local_version: i64,
+    local_data: &serde_json::Value,
+    change: &SyncChange,
+) -> rusqlite::Result<i64> {
+    let remote_change = serde_json::to_string(change).unwrap_or_default();
+    let remote_device = change
+        .hlc
+        .as_deref()
+        .and_then(Hlc::parse)
+        .map(|h| h.device_id)
+        .filter(|d| !d.is_empty());
+    let now = sync_engine::now_iso();
+
+    let existing: Option<i64> = conn
+        .query_row(
+            "SELECT id FROM sync_conflicts WHERE table_name = ?1 AND pk = ?2 AND status = 'open' AND remote_device IS ?3",
+            params![table_name, pk, remote_device],
+            |row| row.get(0),
+        )
+        .optional()?;
+
+    if let Some(id) = existing {
+        conn.execute(
+            "UPDATE sync_conflicts SET base_version = ?1, local_version = ?2, local_data = ?3, remote_change = ?4, remote_device = ?5, created_at = ?6 WHERE id = ?7",
+            params![base_version, local_version, local_data.to_string(), remote_change, remote_device, now, id],
+        )?;
+        return Ok(id);
+    }
+
+    conn.execute(
+        "INSERT INTO sync_conflicts (table_name, pk, base_version, local_version, local_data, remote_change, remote_device, status, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, 'open', ?8)",
+        params![table_name, pk, base_version, local_version, local_data.to_string(), remote_change, remote_device, now],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+const SELECT_CONFLICT: &str = "SELECT id, table_name, pk, base_version, local_version, local_data, remote_change, remote_device, status, resolution, created_at, resolved_at FROM sync_conflicts";
+
+fn map_conflict(row: &rusqlite::Row) -> rusqlite::Result<SyncConflict> {
+    let local_data: String = row.get(5)?;
+    let remote_change: String = row.get(6)?;
+    let remote_change = serde_json::from_str(&remote_change).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(6, rusqlite::types::Type::Text, Box::new(e))
+    })?;
+
+    Ok(SyncConflict {
+        id: row.get(0)?,
+        table_name: row.get(1)?,
+        pk: row.get(2)?,
+        base_version: row.get(3)?,
+        local_version: row.get(4)?,
+        local_data: serde_json::from_str(&local_data).unwrap_or(serde_json::Value::Null),
+        remote_change,
+        remote_device: row.get(7)?,
+        status: row.get(8)?,
+        resolution: row.get(9)?,
+        created_at: row.get(10)?,
+        resolved_at: row.get(11)?,
+    })
+}
+
+/// 列出冲突（status 为空时返回全部），最新的在前
+pub fn list_conflicts(conn: &Connection, status: Option<&str>) -> rusqlite::Result<Vec<SyncConflict>> {
+    let query = format!(
+        "{} WHERE (?1 IS NULL OR status = ?1) ORDER BY created_at DESC, id DESC",
+        SELECT_CONFLICT
+    );
+    let mut stmt = conn.prepare(&query)?;
+    let rows = stmt.query_map(params![status], map_conflict)?;
+    rows.collect()
+}
+
+/// 获取单条冲突
+pub fn get_conflict(conn: &Connection, id: i64) -> rusqlite::Result<Option<SyncConflict>> {
+    let query = format!("{} WHERE id = ?1", SELECT_CONFLICT);
+    conn.query_row(&query, params![id], map_conflict).optional()
+}
+
+/// 解决冲突，返回更新后的冲突记录；冲突不存在或已解决时返回 None
+///
+/// 所有写入都会分配新版本号和新的 HLC，保证结果会传播到所有设备。
+/// 要保留的远程副本没有写入时返回 `ResolveError::NotApplied`，事务回滚，冲突保持未解决
+pub fn resolve_conflict(
+    conn: &mut Connection,
+    id: i64,
+    resolution: Resolution,
+    clock: &HybridClock,
+) -> Result<Option<SyncConflict>, ResolveError> {
+    let conflict = match get_conflict(conn, id)? {
+        Some(c) if c.status == "open" => c,
+        _ => return Ok(None),
+    };
+    let config = match sync_engine::get_table_config(&conflict.table_name) {
+        Some(c) => c,
+        None => return Ok(None),
+    };
+
+    let tx = conn.transaction()?;
+
+    // 新的写入必须排在两端副本之后
+    let current: Option<(Option<String>, Option<String>)> = tx
+        .query_row(
+            &format!("SELECT updated_at, hlc FROM {} WHERE {} = ?1", config.name, config.primary_key),
+            params![conflict.pk],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    if let Some((updated_at, hlc)) = &current {
+        clock.advance(&Hlc::effective(hlc.as_deref(), updated_at.as_deref().unwrap_or("")));
+    }
+
+    match resolution {
+        Resolution::Local => {
+            restamp_row(&tx, config, &conflict.pk, clock)?;
+        }
+        Resolution::Remote => {
+            let change = rewrite_remote(&conflict.remote_change, clock);
+            let revision_mark = sync_engine::revision_mark(&tx, &conflict.table_name, &conflict.pk)?;
+            ensure_applied(sync_engine::apply_table_change(&tx, &conflict.table_name, &change, clock)?)?;
+            // 远程副本以本机时钟写入，被覆盖的本机内容记为同步版本
+            if let Some(mark) = revision_mark {
+                note_revisions::relabel_since(&tx, &conflict.pk, mark, note_revisions::SOURCE_SYNC)?;
+            }
+        }
+        Resolution::Both => {
+            restamp_row(&tx, config, &conflict.pk, clock)?;
+
+            // 远程删除没有可保留的内容，等同于保留本机
+            if matches!(conflict.remote_change.op, SyncOp::Upsert) {
+                let mut change = rewrite_remote(&conflict.remote_change, clock);
+                let copy_pk = generate_uuid();
+                change.data[config.primary_key] = serde_json::json!(copy_pk);
+                if let Some(title_field) = config.title_field {
+                    let title = change.data.get(title_field).and_then(|v| v.as_str()).unwrap_or("");
+                    change.data[title_field] = serde_json::json!(format!("{}{}", title, CONFLICT_COPY_SUFFIX));
+                }
+                ensure_applied(sync_engine::apply_table_change(&tx, &conflict.table_name, &change, clock)?)?;
+                log::info!("[SyncConflict] #{} 远程副本另存为 {}", id, copy_pk);
+            }
+        }
+    }
+
+    tx.execute(
+        "UPDATE sync_conflicts SET status = 'resolved', resolution = ?1, resolved_at = ?2 WHERE id = ?3",
+        params![resolution.as_str(), sync_engine::now_iso(), id],
+    )?;
+    tx.commit()?;
+
+    Ok(get_conflict(conn, id)?)
+}
+
+/// 把远程副本改写为本机的一次新写入：新的 HLC 和时间，不带基线和版本向量
+/// （用户已明确选择保留它，不能再被判定为旧版本）
+fn rewrite_remote(remote: &SyncChange, clock: &HybridClock) -> SyncChange {
+    let mut change = remote.clone();
+    change.hlc = Some(clock.now().encode());
+    change.updated_at = sync_engine::now_iso();
+    change.base_version = None;
+    change.vv = None;
+    change
+}
+
+fn ensure_applied(outcome: ApplyOutcome) -> Result<(), ResolveError> {
+    match outcome {
+        ApplyOutcome::Applied | ApplyOutcome::Merged { .. } => Ok(()),
+        ApplyOutcome::Rejected(reason) => Err(ResolveError::NotApplied(reason)),
+        ApplyOutcome::Skipped => Err(ResolveError::NotApplied("local copy is newer".to_string())),
+        ApplyOutcome::Conflict(_) => Err(ResolveError::NotApplied("record changed again".to_string())),
+    }
+}
+
+/// 为现有记录分配新的 HLC（内容不变，版本号由触发器分配），使其在下一次拉取时覆盖各端副本
+fn restamp_row(
+    conn: &Connection,
+    config: &sync_engine::TableConfig,
+    pk: &str,
+    clock: &HybridClock,
+) -> rusqlite::Result<()> {
+    let query = format!(
+        "UPDATE {} SET version = 0, hlc = ?1, updated_at = ?2 WHERE {} = ?3",
+        config.name, config.primary_key
+    );
+    conn.execute(&query, params![clock.now().encode(), sync_engine::now_iso(), pk])?;
+    sync_engine::snapshot_merge_base(conn, config.name, config, pk)
+}
+
+/// 生成 UUID v4 格式的随机 ID
+fn generate_uuid() -> String {
+    let mut bytes = [0u8; 16];
+    for chunk in bytes.chunks_mut(8) {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_nanos());
+        chunk.copy_from_slice(&hasher.finish().to_le_bytes());
+    }
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync_engine::ChangeStatus;
+    use crate::test_support::mem_db;
+
+    /// 基于 base_version 修改 workflow_schemas（没有可合并字段，分叉的编辑进入冲突队列）
+    fn edit(name: &str, device: &str, base_version: Option<i64>) -> SyncChange {
+        let hlc = HybridClock::new(device.to_string()).now().encode();
+        serde_json::from_value(serde_json::json!({
+            "table": "workflow_schemas", "op": "upsert",
+            "data": {"uuid": "ws1", "name": name, "description": null, "fields": "[]"},
+            "version": 0, "updated_at": sync_engine::now_iso(), "deleted_at": null,
+            "hlc": hlc, "base_version": base_version
+        }))
+        .unwrap()
+    }
+
+    fn version(conn: &Connection) -> i64 {
+        conn.query_row("SELECT version FROM workflow_schemas WHERE uuid = 'ws1'", [], |row| row.get(0)).unwrap()
+    }
+
+    fn apply(conn: &Connection, change: &SyncChange, clock: &HybridClock) -> ApplyOutcome {
+        sync_engine::apply_table_change(conn, "workflow_schemas", change, clock).unwrap()
+    }
+
+    #[test]
+    fn resolution_wire_names_are_stable() {
+        for (resolution, name) in [(Resolution::Local, "keep_local"), (Resolution::Remote, "keep_remote"), (Resolution::Both, "keep_both")] {
+            assert_eq!(resolution.as_str(), name);
+            assert_eq!(serde_json::to_value(resolution).unwrap(), name);
+            assert_eq!(serde_json::from_value::<Resolution>(serde_json::json!(name)).unwrap(), resolution);
+        }
+    }
+
+    #[test]
+    fn keeps_one_conflict_per_device() {
+        let conn = mem_db();
+        let clock = HybridClock::new("server".to_string());
+        apply(&conn, &edit("v1", "server", None), &clock);
+        let base = version(&conn);
+        assert_eq!(apply(&conn, &edit("v2", "desktop", Some(base)), &clock), ApplyOutcome::Applied);
+
+        let ApplyOutcome::Conflict(a) = apply(&conn, &edit("from a", "phone-a", Some(base)), &clock) else {
+            panic!("expected conflict");
+        };
+        let ApplyOutcome::Conflict(b) = apply(&conn, &edit("from b", "phone-b", Some(base)), &clock) else {
+            panic!("expected conflict");
+        };
+        assert_ne!(a, b);
+        // 同一设备再次推送：更新它自己的那条
+        let ApplyOutcome::Conflict(a2) = apply(&conn, &edit("from a again", "phone-a", Some(base)), &clock) else {
+            panic!("expected conflict");
+        };
+        assert_eq!(a, a2);
+
+        let open = list_conflicts(&conn, Some("open")).unwrap();
+        let mut names: Vec<&str> = open.iter().map(|c| c.remote_change.data["name"].as_str().unwrap()).collect();
+        names.sort();
+        assert_eq!(names, vec!["from a again", "from b"]);
+    }
+
+    #[test]
+    fn keep_remote_that_cannot_be_written_stays_open() {
+        let mut conn = mem_db();
+        let clock = HybridClock::new("server".to_string());
+        apply(&conn, &edit("v1", "server", None), &clock);
+        let base = version(&conn);
+        apply(&conn, &edit("v2", "desktop", Some(base)), &clock);
+        let ApplyOutcome::Conflict(id) = apply(&conn, &edit("remote", "phone", Some(base)), &clock) else {
+            panic!("expected conflict");
+        };
+
+        // 存下的远程副本已无法写入（例如表结构变化后字段值不合法）
+        let mut broken = get_conflict(&conn, id).unwrap().unwrap().remote_change;
+        broken.data["fields"] = serde_json::json!("{not json");
+        conn.execute(
+            "UPDATE sync_conflicts SET remote_change = ?1 WHERE id = ?2",
+            params![serde_json::to_string(&broken).unwrap(), id],
+        )
+        .unwrap();
+
+        assert!(matches!(
+            resolve_conflict(&mut conn, id, Resolution::Remote, &clock),
+            Err(ResolveError::NotApplied(_))
+        ));
+        assert_eq!(get_conflict(&conn, id).unwrap().unwrap().status, "open");
+        let name: String = conn.query_row("SELECT name FROM workflow_schemas WHERE uuid = 'ws1'", [], |row| row.get(0)).unwrap();
+        assert_eq!(name, "v2");
+    }
+
+    #[test]
+    fn keep_remote_ignores_stale_version_vector() {
+        let mut conn = mem_db();
+        let clock = HybridClock::new("server".to_string());
+        apply(&conn, &edit("v1", "server", None), &clock);
+        let base = version(&conn);
+        apply(&conn, &edit("v2", "desktop", Some(base)), &clock);
+        let ApplyOutcome::Conflict(id) = apply(&conn, &edit("remote", "phone", Some(base)), &clock) else {
+            panic!("expected conflict");
+        };
+
+        let resolved = resolve_conflict(&mut conn, id, Resolution::Remote, &clock).unwrap().unwrap();
+        assert_eq!(resolved.status, "resolved");
+        let name: String = conn.query_row("SELECT name FROM workflow_schemas WHERE uuid = 'ws1'", [], |row| row.get(0)).unwrap();
+        assert_eq!(name, "remote");
+    }
+
+    /// 原子推送回滚后不再回报已被撤销的冲突 ID
+    #[test]
+    fn atomic_rollback_drops_conflict_ids() {
+        let mut conn = mem_db();
+        let clock = HybridClock::new("server".to_string());
+        apply(&conn, &edit("v1", "server", None), &clock);
+        let base = version(&conn);
+        apply(&conn, &edit("v2", "desktop", Some(base)), &clock);
+
+        let mut invalid = edit("bad", "phone", None);
+        invalid.data["uuid"] = serde_json::json!("ws2");
+        invalid.data["fields"] = serde_json::json!("{not json");
+        let changes = vec![edit("remote", "phone", Some(base)), invalid];
+        let report = sync_engine::apply_changes(&mut conn, &changes, None, &clock, true).unwrap();
+
+        assert!(!report.committed);
+        assert_eq!(report.outcomes[0].status, ChangeStatus::Aborted);
+        assert_eq!(report.outcomes[0].conflict_id, None);
+        assert_eq!(report.outcomes[1].status, ChangeStatus::Rejected);
+        assert!(list_conflicts(&conn, None).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,652 @@
+//! 成就数据的无冲突同步（CRDT）
+//! - user_stat_shards：每台设备一份统计分片，计数器按设备取最大值、汇总时求和（G-Counter）
+//! - user_points_log：只追加的积分流水，按 operation_id 去重
+//! - user_achievements：按成就取最高等级与最大进度
+//!
+//! 合并满足交换律、结合律和幂等性，同一批数据以任意顺序、重复多少次合并结果都相同；
+//! user_stats 与 user_achievement_profile 是派生数据，合并后从上述三张表重新计算，不参与同步
+
+use crate::sync_sequence::{BUMP_SQL, CURRENT_SQL};
+use rusqlite::types::ValueRef;
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// 参与 CRDT 同步的表及其内容列（版本号触发器只在这些列被写入时分配新版本号）
+const CRDT_TABLES: &[(&str, &[&str])] = &[
+    ("user_stat_shards", &["stat_type", "stat_value", "updated_at"]),
+    ("user_points_log", &["points", "exp", "reason"]),
+    ("user_achievements", &["level", "progress", "total_points", "total_exp", "unlocked_at", "updated_at"]),
+];
+
+/// 统计分片合并：last 类型按 (updated_at, 值) 取较新者，其余类型（counter/max/date）取数值较大者
+///
+/// 前端 useCrdtSync 使用相同的合并语句，修改时两边保持一致
+const MERGE_SHARD_SQL: &str = "
+    INSERT INTO user_stat_shards (user_id, stat_key, device_id, stat_type, stat_value, updated_at)
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+    ON CONFLICT(user_id, stat_key, device_id) DO UPDATE SET
+        stat_type = excluded.stat_type,
+        stat_value = excluded.stat_value,
+        updated_at = MAX(updated_at, excluded.updated_at)
+    WHERE (excluded.stat_type = 'last' AND (excluded.updated_at > updated_at
+            OR (excluded.updated_at = updated_at AND excluded.stat_value > stat_value)))
+       OR (excluded.stat_type != 'last' AND CAST(excluded.stat_value AS REAL) > CAST(stat_value AS REAL))";
+
+/// 成就合并：等级、进度、累计积分/经验取最大值，解锁时间取最早
+///
+/// 解锁时间为空（或 0，load_changes 对 NULL 的输出）的一端不参与比较，否则 MIN 会把已知的时间覆盖为 NULL
+const MERGE_ACHIEVEMENT_SQL: &str = "
+    INSERT INTO user_achievements
+        (user_id, achievement_key, level, progress, total_points, total_exp, unlocked_at, updated_at)
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6, NULLIF(?7, 0), ?8)
+    ON CONFLICT(user_id, achievement_key) DO UPDATE SET
+        level = MAX(level, excluded.level),
+        progress = MAX(progress, excluded.progress),
+        total_points = MAX(total_points, excluded.total_points),
+        total_exp = MAX(total_exp, excluded.total_exp),
+        unlocked_at = CASE WHEN unlocked_at IS NULL THEN excluded.unlocked_at
+                           WHEN excluded.unlocked_at IS NULL THEN unlocked_at
+                           ELSE MIN(unlocked_at, excluded.unlocked_at) END,
+        updated_at = MAX(updated_at, excluded.updated_at)
+    WHERE excluded.level > level OR excluded.progress > progress
+       OR excluded.total_points > total_points OR excluded.total_exp > total_exp
+       OR excluded.unlocked_at < unlocked_at OR (unlocked_at IS NULL AND excluded.unlocked_at IS NOT NULL)";
+
+/// 积分流水按 operation_id 去重，已有的流水不会被修改
+const MERGE_POINTS_SQL: &str = "
+    INSERT OR IGNORE INTO user_points_log
+        (user_id, operation_id, source_type, source_id, achievement_key, points, exp, reason, created_at, device_id)
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)";
+
+/// 某台设备上的一项统计
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StatShard {
+    pub user_id: i64,
+    pub stat_key: String,
+    pub device_id: String,
+    pub stat_type: String,
+    pub stat_value: String,
+    pub updated_at: i64,
+    #[serde(default)]
+    pub version: i64,
+}
+
+/// 一条积分流水
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PointsEntry {
+    pub user_id: i64,
+    pub operation_id: String,
+    pub source_type: String,
+    pub source_id: String,
+    pub achievement_key: Option<String>,
+    pub points: i64,
+    pub exp: i64,
+    pub reason: Option<String>,
+    pub created_at: i64,
+    pub device_id: Option<String>,
+    #[serde(default)]
+    pub version: i64,
+}
+
+/// 一项成就的解锁状态
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AchievementState {
+    pub user_id: i64,
+    pub achievement_key: String,
+    pub level: i64,
+    pub progress: i64,
+    pub total_points: i64,
+    pub total_exp: i64,
+    pub unlocked_at: i64,
+    pub updated_at: i64,
+    #[serde(default)]
+    pub version: i64,
+}
+
+/// 一批 CRDT 变更（/crdt/pull 返回、/crdt/push 接收）
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CrdtChanges {
+    #[serde(default)]
+    pub stats: Vec<StatShard>,
+    #[serde(default)]
+    pub points: Vec<PointsEntry>,
+    #[serde(default)]
+    pub achievements: Vec<AchievementState>,
+}
+
+/// 合并结果：每张表实际发生变化的记录数
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct CrdtReport {
+    pub stats: usize,
+    pub points: usize,
+    pub achievements: usize,
+}
+
+impl CrdtReport {
+    pub fn total(&self) -> usize {
+        self.stats + self.points + self.achievements
+    }
+}
+
+/// 安装版本号触发器，并为触发器安装前写入的记录分配版本号（由桌面端服务在启动时调用）
+pub fn install(conn: &mut Connection) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    for (table, columns) in CRDT_TABLES {
+        tx.execute_batch(&trigger_sql(table, columns))?;
+        // 触发器监听内容列，原样写回即可经由触发器逐条分配版本号
+        let stamped = tx.execute(
+            &format!(
+                "UPDATE {table} SET {col} = {col} WHERE version IS NULL OR version <= 0",
+                col = columns[0]
+            ),
+            [],
+        )?;
+        if stamped > 0 {
+            log::info!("[SyncCrdt] {} 表为 {} 条记录分配版本号", table, stamped);
+        }
+    }
+    tx.commit()
+}
+
+fn trigger_sql(table: &str, columns: &[&str]) -> String {
+    format!(
+        "DROP TRIGGER IF EXISTS sync_crdt_{table}_insert;
+         DROP TRIGGER IF EXISTS sync_crdt_{table}_update;
+
+         CREATE TRIGGER sync_crdt_{table}_insert AFTER INSERT ON {table}
+         BEGIN
+             {BUMP_SQL};
+             UPDATE {table} SET version = {CURRENT_SQL} WHERE rowid = NEW.rowid;
+         END;
+
+         -- synced_at 等客户端簿记列的写入不分配新版本号
+         CREATE TRIGGER sync_crdt_{table}_update AFTER UPDATE OF {columns} ON {table}
+         BEGIN
+             {BUMP_SQL};
+             UPDATE {table} SET version = {CURRENT_SQL} WHERE rowid = NEW.rowid;
+         END;",
+        columns = columns.join(", ")
+    )
+}
+
+/// 读取 since_version 之后变化的记录，每张表最多 limit 条
+///
+/// 任意一张表达到 limit 时返回下一页的 since_version（已返回的记录可能被重复下发，合并是幂等的）
+pub fn load_changes(conn: &Connection, since_version: i64, limit: usize) -> rusqlite::Result<(CrdtChanges, Option<i64>)> {
+    let mut stmt = conn.prepare(
+        "SELECT user_id, stat_key, device_id, stat_type, stat_value, updated_at, version
+         FROM user_stat_shards WHERE version > ?1 ORDER BY version LIMIT ?2",
+    )?;
+    let stats = stmt
+        .query_map(params![since_version, limit as i64], |row| {
+            Ok(StatShard {
+                user_id: row.get(0)?,
+                stat_key: row.get(1)?,
+                device_id: row.get(2)?,
+                stat_type: row.get(3)?,
+                stat_value: row.get(4)?,
+                updated_at: timestamp_ms(row.get_ref(5)?),
+                version: row.get(6)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let mut stmt = conn.prepare(
+        "SELECT user_id, operation_id, source_type, source_id, achievement_key, points, exp, reason,
+                created_at, device_id, version
+         FROM user_points_log WHERE version > ?1 ORDER BY version LIMIT ?2",
+    )?;
+    let points = stmt
+        .query_map(params![since_version, limit as i64], |row| {
+            Ok(PointsEntry {
+                user_id: row.get(0)?,
+                operation_id: row.get(1)?,
+                source_type: row.get(2)?,
+                source_id: row.get(3)?,
+                achievement_key: row.get(4)?,
+                points: row.get(5)?,
+                exp: row.get(6)?,
+                reason: row.get(7)?,
+                created_at: timestamp_ms(row.get_ref(8)?),
+                device_id: row.get(9)?,
+                version: row.get(10)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let mut stmt = conn.prepare(
+        "SELECT user_id, achievement_key, level, progress, total_points, total_exp, unlocked_at, updated_at, version
+         FROM user_achievements WHERE version > ?1 ORDER BY version LIMIT ?2",
+    )?;
+    let achievements = stmt
+        .query_map(params![since_version, limit as i64], |row| {
+            Ok(AchievementState {
+                user_id: row.get(0)?,
+                achievement_key: row.get(1)?,
+                level: row.get::<_, Option<i64>>(2)?.unwrap_or(1),
+                progress: row.get::<_, Option<i64>>(3)?.unwrap_or(0),
+                total_points: row.get::<_, Option<i64>>(4)?.unwrap_or(0),
+                total_exp: row.get::<_, Option<i64>>(5)?.unwrap_or(0),
+                unlocked_at: timestamp_ms(row.get_ref(6)?),
+                updated_at: timestamp_ms(row.get_ref(7)?),
+                version: row.get(8)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let next = [
+        (stats.len() >= limit).then(|| stats.last().map(|s| s.version)).flatten(),
+        (points.len() >= limit).then(|| points.last().map(|p| p.version)).flatten(),
+        (achievements.len() >= limit).then(|| achievements.last().map(|a| a.version)).flatten(),
+    ]
+    .into_iter()
+    .flatten()
+    .min();
+
+    Ok((CrdtChanges { stats, points, achievements }, next))
+}
+
+/// 在单个事务中合并一批变更，并重新计算受影响的统计与用户档案
+pub fn apply_changes(conn: &mut Connection, changes: &CrdtChanges) -> rusqlite::Result<CrdtReport> {
+    let tx = conn.transaction()?;
+    let mut report = CrdtReport::default();
+    let mut touched_stats = BTreeSet::new();
+    let mut touched_users = BTreeSet::new();
+
+    for shard in &changes.stats {
+        let changed = tx.execute(
+            MERGE_SHARD_SQL,
+            params![
+                shard.user_id,
+                shard.stat_key,
+                shard.device_id,
+                shard.stat_type,
+                shard.stat_value,
+                shard.updated_at
+            ],
+        )?;
+        if changed > 0 {
+            report.stats += 1;
+            touched_stats.insert((shard.user_id, shard.stat_key.clone()));
+        }
+    }
+
+    for entry in &changes.points {
+        let changed = tx.execute(
+            MERGE_POINTS_SQL,
+            params![
+                entry.user_id,
+                entry.operation_id,
+                entry.source_type,
+                entry.source_id,
+                entry.achievement_key,
+                entry.points,
+                entry.exp,
+                entry.reason,
+                entry.created_at,
+                entry.device_id
+            ],
+        )?;
+        if changed > 0 {
+            report.points += 1;
+            touched_users.insert(entry.user_id);
+        }
+    }
+
+    for a in &changes.achievements {
+        let changed = tx.execute(
+            MERGE_ACHIEVEMENT_SQL,
+            params![
+                a.user_id,
+                a.achievement_key,
+                a.level,
+                a.progress,
+                a.total_points,
+                a.total_exp,
+                a.unlocked_at,
+                a.updated_at
+            ],
+        )?;
+        if changed > 0 {
+            report.achievements += 1;
+            touched_users.insert(a.user_id);
+        }
+    }
+
+    for (user_id, stat_key) in &touched_stats {
+        rebuild_stat(&tx, *user_id, stat_key)?;
+    }
+    for user_id in &touched_users {
+        rebuild_profile(&tx, *user_id)?;
+    }
+
+    tx.commit()?;
+    Ok(report)
+}
+
+/// 从各设备的分片重新计算 user_stats 中的一项统计
+///
+/// counter 求和，max/date 取最大值，last 取最近一次写入的值
+pub fn rebuild_stat(conn: &Connection, user_id: i64, stat_key: &str) -> rusqlite::Result<()> {
+    let mut stmt = conn.prepare(
+        "SELECT stat_type, stat_value, updated_at FROM user_stat_shards
+         WHERE user_id = ?1 AND stat_key = ?2
+         ORDER BY updated_at, stat_value",
+    )?;
+    let shards = stmt
+        .query_map(params![user_id, stat_key], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, timestamp_ms(row.get_ref(2)?)))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    // 按 (updated_at, 值) 排序后最后一条即最近一次写入，其类型作为汇总类型
+    let Some((stat_type, latest_value, updated_at)) = shards.last().cloned() else {
+        return Ok(());
+    };
+    let numbers = shards.iter().map(|(_, v, _)| v.trim().parse::<f64>().unwrap_or(0.0));
+    let value = match stat_type.as_str() {
+        "last" => latest_value,
+        "max" | "date" => format_number(numbers.fold(f64::MIN, f64::max)),
+        _ => format_number(numbers.sum()),
+    };
+
+    conn.execute(
+        "INSERT INTO user_stats (user_id, stat_key, stat_value, stat_type, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5)
+         ON CONFLICT(user_id, stat_key) DO UPDATE SET
+             stat_value = excluded.stat_value,
+             stat_type = excluded.stat_type,
+             updated_at = excluded.updated_at",
+        params![user_id, stat_key, value, stat_type, updated_at],
+    )?;
+    Ok(())
+}
+
+/// 从积分流水和成就重新计算用户档案（与前端 usePointsSystem.updateProfile 的公式一致）
+pub fn rebuild_profile(conn: &Connection, user_id: i64) -> rusqlite::Result<()> {
+    let (total_points, total_exp): (i64, i64) = conn.query_row(
+        "SELECT COALESCE(SUM(points), 0), COALESCE(SUM(exp), 0) FROM user_points_log WHERE user_id = ?1",
+        params![user_id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    let achievements_count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM user_achievements WHERE user_id = ?1",
+        params![user_id],
+        |row| row.get(0),
+    )?;
+    // level = floor(sqrt(exp / 100)) + 1
+    let current_level = ((total_exp.max(0) as f64 / 100.0).sqrt().floor() as i64) + 1;
+
+    conn.execute(
+        "INSERT INTO user_achievement_profile
+             (user_id, total_points, total_exp, current_level, achievements_count, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+         ON CONFLICT(user_id) DO UPDATE SET
+             total_points = excluded.total_points,
+             total_exp = excluded.total_exp,
+             current_level = excluded.current_level,
+             achievements_count = excluded.achievements_count,
+             updated_at = excluded.updated_at",
+        params![
+            user_id,
+            total_points,
+            total_exp,
+            current_level,
+            achievements_count,
+            chrono::Utc::now().timestamp_millis()
+        ],
+    )?;
+    Ok(())
+}
+
+/// 整数值不带小数点，与前端 String(number) 的结果一致
+fn format_number(n: f64) -> String {
+    if n.fract() == 0.0 && n.abs() < 9.0e15 {
+        format!("{}", n as i64)
+    } else {
+        n.to_string()
+    }
+}
+
+/// 时间列统一为毫秒时间戳（前端写入 Date.now()，旧数据可能是 CURRENT_TIMESTAMP 文本）
+fn timestamp_ms(value: ValueRef<'_>) -> i64 {
+    match value {
+        ValueRef::Integer(n) => n,
+        ValueRef::Real(f) => f as i64,
+        ValueRef::Text(bytes) => {
+            let text = String::from_utf8_lossy(bytes);
+            text.trim().parse::<i64>().ok().unwrap_or_else(|| {
+                chrono::NaiveDateTime::parse_from_str(text.trim(), "%Y-%m-%d %H:%M:%S")
+                    .map(|dt| dt.and_utc().timestamp_millis())
+                    .unwrap_or(0)
+            })
+        }
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::mem_db;
+    use rusqlite::types::Value;
+
+    fn shard(device: &str, stat_type: &str, value: &str, updated_at: i64) -> StatShard {
+        StatShard {
+            user_id: 1,
+            stat_key: format!("{}_stat", stat_type),
+            device_id: device.into(),
+            stat_type: stat_type.into(),
+            stat_value: value.into(),
+            updated_at,
+            version: 0,
+        }
+    }
+
+    fn points(operation_id: &str, points: i64) -> PointsEntry {
+        PointsEntry {
+            user_id: 1,
+            operation_id: operation_id.into(),
+            source_type: "note".into(),
+            source_id: "n1".into(),
+            achievement_key: None,
+            points,
+            exp: points * 10,
+            reason: None,
+            created_at: 1_000,
+            device_id: Some("a".into()),
+            version: 0,
+        }
+    }
+
+    fn achievement(key: &str, level: i64, progress: i64, unlocked_at: i64, updated_at: i64) -> AchievementState {
+        AchievementState {
+            user_id: 1,
+            achievement_key: key.into(),
+            level,
+            progress,
+            total_points: level * 10,
+            total_exp: level * 100,
+            unlocked_at,
+            updated_at,
+            version: 0,
+        }
+    }
+
+    /// 同一份数据的三种来源混在一起：同一分片的新旧值、重复的流水、解锁时间缺失的成就
+    fn batch() -> Vec<CrdtChanges> {
+        let stats = vec![
+            shard("a", "counter", "3", 10),
+            shard("a", "counter", "5", 20),
+            shard("b", "counter", "2", 15),
+            shard("a", "max", "7", 10),
+            shard("b", "max", "9", 5),
+            shard("a", "last", "apple", 30),
+            shard("b", "last", "banana", 30),
+            shard("b", "last", "cherry", 10),
+        ];
+        let points = vec![points("op-1", 5), points("op-2", 3), points("op-1", 5)];
+        let achievements = vec![
+            achievement("first_note", 1, 1, 0, 100),
+            achievement("first_note", 1, 1, 500, 200),
+            achievement("first_note", 2, 3, 800, 300),
+            achievement("streak", 1, 4, 900, 100),
+            achievement("streak", 1, 2, 700, 150),
+        ];
+        stats
+            .into_iter()
+            .map(|s| CrdtChanges { stats: vec![s], ..Default::default() })
+            .chain(points.into_iter().map(|p| CrdtChanges { points: vec![p], ..Default::default() }))
+            .chain(achievements.into_iter().map(|a| CrdtChanges { achievements: vec![a], ..Default::default() }))
+            .collect()
+    }
+
+    fn merged(parts: &[CrdtChanges]) -> CrdtChanges {
+        let mut all = CrdtChanges::default();
+        for part in parts {
+            all.stats.extend(part.stats.iter().cloned());
+            all.points.extend(part.points.iter().cloned());
+            all.achievements.extend(part.achievements.iter().cloned());
+        }
+        all
+    }
+
+    fn crdt_db() -> Connection {
+        let mut conn = mem_db();
+        install(&mut conn).unwrap();
+        conn
+    }
+
+    fn rows(conn: &Connection, sql: &str) -> Vec<String> {
+        let mut stmt = conn.prepare(sql).unwrap();
+        let columns = stmt.column_count();
+        stmt.query_map([], |row| {
+            (0..columns)
+                .map(|i| row.get::<_, Value>(i).map(|v| format!("{:?}", v)))
+                .collect::<rusqlite::Result<Vec<_>>>()
+                .map(|values| values.join(", "))
+        })
+        .unwrap()
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .unwrap()
+    }
+
+    /// 合并结果（版本号与档案的 updated_at 取决于合并时机，不参与比较）
+    fn snapshot(conn: &Connection) -> Vec<String> {
+        [
+            "SELECT user_id, stat_key, device_id, stat_type, stat_value, updated_at FROM user_stat_shards
+             ORDER BY user_id, stat_key, device_id",
+            "SELECT operation_id, points, exp FROM user_points_log ORDER BY operation_id",
+            "SELECT achievement_key, level, progress, total_points, total_exp, unlocked_at, updated_at
+             FROM user_achievements ORDER BY achievement_key",
+            "SELECT stat_key, stat_value, stat_type, updated_at FROM user_stats ORDER BY stat_key",
+            "SELECT total_points, total_exp, current_level, achievements_count FROM user_achievement_profile",
+        ]
+        .iter()
+        .flat_map(|sql| rows(conn, sql))
+        .collect()
+    }
+
+    fn apply_all(conn: &mut Connection, parts: impl IntoIterator<Item = CrdtChanges>) {
+        for part in parts {
+            apply_changes(conn, &part).unwrap();
+        }
+    }
+
+    #[test]
+    fn merge_order_does_not_matter() {
+        let parts = batch();
+        let mut expected = crdt_db();
+        apply_all(&mut expected, parts.clone());
+        let expected = snapshot(&expected);
+
+        let mut reversed = crdt_db();
+        apply_all(&mut reversed, parts.iter().rev().cloned());
+        assert_eq!(snapshot(&reversed), expected);
+
+        let mut at_once = crdt_db();
+        apply_all(&mut at_once, [merged(&parts)]);
+        assert_eq!(snapshot(&at_once), expected);
+
+        // 奇偶交错，打乱同一记录的新旧版本
+        let mut interleaved = crdt_db();
+        apply_all(&mut interleaved, parts.iter().skip(1).step_by(2).cloned());
+        apply_all(&mut interleaved, parts.iter().step_by(2).cloned());
+        assert_eq!(snapshot(&interleaved), expected);
+
+        assert!(expected.iter().any(|row| row.contains("\"first_note\"") && row.contains("Integer(500)")));
+        assert!(expected.iter().any(|row| row.contains("\"counter_stat\"") && row.contains("Text(\"7\")")));
+    }
+
+    #[test]
+    fn merging_twice_changes_nothing() {
+        let all = merged(&batch());
+        let mut conn = crdt_db();
+        apply_changes(&mut conn, &all).unwrap();
+        let once = snapshot(&conn);
+
+        let report = apply_changes(&mut conn, &all).unwrap();
+        assert_eq!(report, CrdtReport::default());
+        assert_eq!(snapshot(&conn), once);
+    }
+
+    #[test]
+    fn relayed_merges_match_direct_merges() {
+        let parts = batch();
+        let (a, rest) = parts.split_at(4);
+        let (b, c) = rest.split_at(6);
+
+        // (A ∪ B) ∪ C：中转端先合并 A、B，再整批发给已有 C 的设备
+        let mut relay = crdt_db();
+        apply_all(&mut relay, [merged(a), merged(b)]);
+        let (relayed, next) = load_changes(&relay, 0, 1_000).unwrap();
+        assert_eq!(next, None);
+        let mut left = crdt_db();
+        apply_all(&mut left, [merged(c), relayed]);
+
+        // A ∪ (B ∪ C)
+        let mut relay = crdt_db();
+        apply_all(&mut relay, [merged(c), merged(b)]);
+        let (relayed, _) = load_changes(&relay, 0, 1_000).unwrap();
+        let mut right = crdt_db();
+        apply_all(&mut right, [merged(a), relayed]);
+
+        assert_eq!(snapshot(&left), snapshot(&right));
+    }
+
+    #[test]
+    fn missing_unlock_time_keeps_the_known_one() {
+        let mut conn = crdt_db();
+        conn.execute(
+            "INSERT INTO user_achievements (user_id, achievement_key, level, progress, unlocked_at, updated_at)
+             VALUES (1, 'first_note', 1, 1, NULL, 100)",
+            [],
+        )
+        .unwrap();
+        let unlocked_at = |conn: &Connection| -> Option<i64> {
+            conn.query_row(
+                "SELECT unlocked_at FROM user_achievements WHERE achievement_key = 'first_note'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+
+        let report = apply_changes(
+            &mut conn,
+            &CrdtChanges { achievements: vec![achievement("first_note", 1, 1, 500, 100)], ..Default::default() },
+        )
+        .unwrap();
+        assert_eq!(report.achievements, 1);
+        assert_eq!(unlocked_at(&conn), Some(500));
+
+        // 对端的 NULL 经 load_changes 输出为 0，不能覆盖已知的解锁时间
+        apply_changes(
+            &mut conn,
+            &CrdtChanges { achievements: vec![achievement("first_note", 2, 1, 0, 200)], ..Default::default() },
+        )
+        .unwrap();
+        assert_eq!(unlocked_at(&conn), Some(500));
+    }
+}
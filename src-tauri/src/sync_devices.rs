@@ -0,0 +1,270 @@
+//! 同步设备登记
+//! 客户端在每个同步请求中通过 X-Device-Id / X-Device-Name / X-App-Version 标识自己，
+//! 服务端记录最近访问时间与各设备已拉取、已推送到的版本号；移除的设备不能再同步
+
+use crate::sync_engine;
+use crate::sync_scopes::{self, SyncScope};
+use axum::http::HeaderMap;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+
+pub const DEVICE_ID_HEADER: &str = "x-device-id";
+pub const DEVICE_NAME_HEADER: &str = "x-device-name";
+pub const APP_VERSION_HEADER: &str = "x-app-version";
+
+/// 请求方设备信息（来自请求头）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub device_id: String,
+    pub name: Option<String>,
+    pub app_version: Option<String>,
+}
+
+impl DeviceInfo {
+    /// 从请求头读取；不带设备 ID 的旧客户端返回 None
+    pub fn from_headers(headers: &HeaderMap) -> Option<DeviceInfo> {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| percent_decode(v.trim()))
+                .filter(|v| !v.is_empty())
+        };
+        Some(DeviceInfo {
+            device_id: header(DEVICE_ID_HEADER)?,
+            name: header(DEVICE_NAME_HEADER),
+            app_version: header(APP_VERSION_HEADER),
+        })
+    }
+}
+
+/// 一台登记过的设备
+#[derive(Serialize, Debug, Clone)]
+pub struct SyncDevice {
+    pub device_id: String,
+    pub name: Option<String>,
+    pub app_version: Option<String>,
+    pub first_seen_at: Option<String>,
+    pub last_seen_at: Option<String>,
+    pub last_pulled_version: i64,
+    pub last_pushed_version: i64,
+    /// 落后服务端的版本数（服务端当前版本号 - 已拉取到的版本号）
+    pub versions_behind: i64,
+    /// 被移除的时间，非空时该设备的同步请求会被拒绝
+    pub revoked_at: Option<String>,
+    /// 同步范围，为空表示同步全部数据
+    pub scope: Option<SyncScope>,
+}
+
+/// 登记设备并刷新最近访问时间；设备已被移除时返回 false
+pub fn touch(conn: &Connection, info: &DeviceInfo) -> rusqlite::Result<bool> {
+    let now = sync_engine::now_iso();
+    conn.execute(
+        "INSERT INTO sync_devices (device_id, name, app_version, first_seen_at, last_seen_at)
+         VALUES (?1, ?2, ?3, ?4, ?4)
+         ON CONFLICT(device_id) DO UPDATE SET
+             name = COALESCE(excluded.name, name),
+             app_version = COALESCE(excluded.app_version, app_version),
+             last_seen_at = excluded.last_seen_at",
+        params![info.device_id, info.name, info.app_version, now],
+    )?;
+    let revoked: Option<String> = conn.query_row(
+        "SELECT revoked_at FROM sync_devices WHERE device_id = ?1",
+        params![info.device_id],
+        |row| row.get(0),
+    )?;
+    Ok(revoked.is_none())
+}
+
+/// 记录设备已拉取到的版本号（只前进不后退）
+pub fn record_pull(conn: &Connection, device_id: &str, version: i64) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE sync_devices SET last_pulled_version = MAX(COALESCE(last_pulled_version, 0), ?2) WHERE device_id = ?1",
+        params![device_id, version],
+    )?;
+    Ok(())
+}
+
+/// 记录设备推送后服务端的版本号（只前进不后退）
+pub fn record_push(conn: &Connection, device_id: &str, version: i64) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE sync_devices SET last_pushed_version = MAX(COALESCE(last_pushed_version, 0), ?2) WHERE device_id = ?1",
+        params![device_id, version],
+    )?;
+    Ok(())
+}
+
+/// 列出登记过的设备，最近访问的在前
+pub fn list(conn: &Connection, server_version: i64) -> rusqlite::Result<Vec<SyncDevice>> {
+    let mut stmt = conn.prepare(
+        "SELECT device_id, name, app_version, first_seen_at, last_seen_at,
+                COALESCE(last_pulled_version, 0), COALESCE(last_pushed_version, 0), revoked_at, scope
+         FROM sync_devices ORDER BY last_seen_at DESC",
+    )?;
+    let devices = stmt
+        .query_map([], |row| {
+            let last_pulled_version: i64 = row.get(5)?;
+            Ok(SyncDevice {
+                device_id: row.get(0)?,
+                name: row.get(1)?,
+                app_version: row.get(2)?,
+                first_seen_at: row.get(3)?,
+                last_seen_at: row.get(4)?,
+                last_pulled_version,
+                last_pushed_version: row.get(6)?,
+                versions_behind: (server_version - last_pulled_version).max(0),
+                revoked_at: row.get(7)?,
+                scope: sync_scopes::parse(row.get::<_, Option<String>>(8)?.as_deref()),
+            })
+        })?
+        .collect();
+    devices
+}
+
+/// 设备是否已登记且未被移除
+pub fn is_paired(conn: &Connection, device_id: &str) -> rusqlite::Result<bool> {
+    let revoked: Option<Option<String>> = conn
+        .query_row(
+            "SELECT revoked_at FROM sync_devices WHERE device_id = ?1",
+            params![device_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(matches!(revoked, Some(None)))
+}
+
+/// 移除设备：保留记录并拒绝它之后的同步请求；设备不存在时返回 false
+pub fn revoke(conn: &Connection, device_id: &str) -> rusqlite::Result<bool> {
+    let updated = conn.execute(
+        "UPDATE sync_devices SET revoked_at = COALESCE(revoked_at, ?2) WHERE device_id = ?1",
+        params![device_id, sync_engine::now_iso()],
+    )?;
+    if updated > 0 {
+        log::info!("[SyncDevices] 移除设备 {}", device_id);
+    }
+    Ok(updated > 0)
+}
+
+/// 按 encodeURIComponent 的规则编码请求头值（原生同步客户端使用）
+pub fn percent_encode(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for b in value.bytes() {
+        if b.is_ascii_alphanumeric() || b"-_.!~*'()".contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+/// 请求头只能是 ASCII，客户端用 encodeURIComponent 编码设备名
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && i + 2 < bytes.len()
+            && bytes[i + 1].is_ascii_hexdigit()
+            && bytes[i + 2].is_ascii_hexdigit()
+        {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("0");
+            out.push(u8::from_str_radix(hex, 16).unwrap_or(b'?'));
+            i += 3;
+            continue;
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::mem_db;
+    use axum::http::HeaderValue;
+
+    fn info(name: Option<&str>, app_version: Option<&str>) -> DeviceInfo {
+        DeviceInfo {
+            device_id: "phone".to_string(),
+            name: name.map(str::to_string),
+            app_version: app_version.map(str::to_string),
+        }
+    }
+
+    fn device(conn: &Connection) -> SyncDevice {
+        list(conn, 100).unwrap().into_iter().find(|d| d.device_id == "phone").expect("device registered")
+    }
+
+    #[test]
+    fn touch_upserts_and_keeps_known_fields() {
+        let conn = mem_db();
+        assert!(touch(&conn, &info(Some("我的手机"), Some("1.0.0"))).unwrap());
+        let first = device(&conn);
+        assert_eq!(first.name.as_deref(), Some("我的手机"));
+        assert_eq!(first.first_seen_at, first.last_seen_at);
+
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        assert!(touch(&conn, &info(None, Some("1.1.0"))).unwrap());
+        let second = device(&conn);
+        assert_eq!(second.name.as_deref(), Some("我的手机"));
+        assert_eq!(second.app_version.as_deref(), Some("1.1.0"));
+        assert_eq!(second.first_seen_at, first.first_seen_at);
+        assert!(second.last_seen_at > first.last_seen_at);
+        assert_eq!(list(&conn, 100).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn progress_only_moves_forward() {
+        let conn = mem_db();
+        touch(&conn, &info(None, None)).unwrap();
+        record_pull(&conn, "phone", 40).unwrap();
+        record_pull(&conn, "phone", 10).unwrap();
+        record_push(&conn, "phone", 25).unwrap();
+        record_push(&conn, "phone", 5).unwrap();
+        // 未登记的设备不会被创建
+        record_pull(&conn, "tablet", 10).unwrap();
+
+        let device = device(&conn);
+        assert_eq!((device.last_pulled_version, device.last_pushed_version), (40, 25));
+        assert_eq!(device.versions_behind, 60);
+        assert_eq!(list(&conn, 100).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn revoked_devices_stay_revoked() {
+        let conn = mem_db();
+        assert!(!is_paired(&conn, "phone").unwrap());
+        assert!(!revoke(&conn, "phone").unwrap());
+
+        touch(&conn, &info(None, None)).unwrap();
+        assert!(is_paired(&conn, "phone").unwrap());
+        assert!(revoke(&conn, "phone").unwrap());
+        let revoked_at = device(&conn).revoked_at;
+        assert!(revoked_at.is_some());
+
+        assert!(!touch(&conn, &info(Some("新名字"), None)).unwrap());
+        assert!(!is_paired(&conn, "phone").unwrap());
+        revoke(&conn, "phone").unwrap();
+        assert_eq!(device(&conn).revoked_at, revoked_at);
+    }
+
+    #[test]
+    fn headers_are_percent_decoded() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(DeviceInfo::from_headers(&headers), None);
+
+        let name = "张三的 iPhone (2)";
+        headers.insert(DEVICE_ID_HEADER, HeaderValue::from_static(" phone "));
+        headers.insert(DEVICE_NAME_HEADER, HeaderValue::from_str(&percent_encode(name)).unwrap());
+        headers.insert(APP_VERSION_HEADER, HeaderValue::from_static(""));
+        assert_eq!(
+            DeviceInfo::from_headers(&headers),
+            Some(DeviceInfo { device_id: "phone".into(), name: Some(name.into()), app_version: None })
+        );
+        assert_eq!(percent_encode(name), "%E5%BC%A0%E4%B8%89%E7%9A%84%20iPhone%20(2)");
+        assert_eq!(percent_decode("100%"), "100%");
+    }
+}
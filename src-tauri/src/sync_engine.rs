@@ -3,13 +3,32 @@
 
 use rusqlite::{params, Connection, OptionalExtension};
 use serde::{Deserialize, Serialize};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
 use chrono::Utc;
+use crate::hlc::{Hlc, HybridClock, MAX_DRIFT_MS};
+use crate::merge;
+use crate::note_revisions;
+use crate::sync_changelog;
+use crate::sync_codec::{self, ColumnKind};
+use crate::sync_conflicts;
+use crate::sync_registry;
+use crate::sync_scopes::{self, SyncScope};
+use crate::sync_settings;
+use crate::sync_vector::{self, Causality, VersionVector};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum SyncOp {
     Upsert,
+    /// 软删除：保留记录内容，只设置 deleted_at
     Delete,
+    /// 从回收站恢复：清除 deleted_at
+    Restore,
+    /// 记录移出了请求方设备的同步范围（只由服务端下发，只带主键）：删除本地副本，不算用户删除
+    ScopeOut,
+    /// 从回收站彻底清除（只带主键）：删除本地副本，不进回收站
+    Purge,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -20,53 +39,109 @@ pub struct SyncChange {
     pub version: i64,
     pub updated_at: String,
     pub deleted_at: Option<String>,
+    /// 写入时的混合逻辑时钟，用于冲突排序（旧客户端可能不携带）
+    #[serde(default)]
+    pub hlc: Option<String>,
+    /// 客户端编辑所基于的服务端版本号（用于三方合并，旧客户端不携带）
+    #[serde(default)]
+    pub base_version: Option<i64>,
+    /// 拉取时：自客户端 since_version 以来变化的字段（为空表示整条记录都是新的）
+    #[serde(default)]
+    pub changed_columns: Option<Vec<String>>,
+    /// 记录的版本向量，用于多主同步时判断因果关系（旧客户端不携带）
+    #[serde(default)]
+    pub vv: Option<VersionVector>,
 }
 
-/// 表配置定义
+/// 单条变更的应用结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApplyOutcome {
+    /// 已写入
+    Applied,
+    /// 与服务端的并发修改做了三方合并后写入
+    Merged { conflicted: bool },
+    /// 本地记录更新，跳过
+    Skipped,
+    /// 变更本身无效（不支持的表、缺少主键等）
+    Rejected(String),
+    /// 服务端记录已越过客户端的基线版本且无法自动合并，已存入冲突队列（冲突 ID）
+    Conflict(i64),
+}
+
+/// push 中每条变更的处理状态
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeStatus {
+    Applied,
+    Merged,
+    Skipped,
+    Rejected,
+    /// 已存入冲突队列，等待用户解决
+    Conflict,
+    /// 原子模式下因其他变更失败而整体回滚
+    Aborted,
+}
+
+/// push 中每条变更的结果，客户端据此只重试失败的记录
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChangeOutcome {
+    /// 在请求 changes 数组中的下标
+    pub index: usize,
+    pub table: String,
+    pub pk: Option<String>,
+    pub status: ChangeStatus,
+    pub reason: Option<String>,
+    /// 写入后分配的版本号（仅 applied / merged）
+    pub version: Option<i64>,
+    /// 冲突队列中的 ID（仅 conflict）
+    #[serde(default)]
+    pub conflict_id: Option<i64>,
+}
+
+/// 一批变更的应用结果
+#[derive(Debug, Clone)]
+pub struct ApplyReport {
+    pub outcomes: Vec<ChangeOutcome>,
+    /// 事务是否已提交（原子模式下有失败时为 false）
+    pub committed: bool,
+}
+
+impl ApplyReport {
+    /// 实际写入的变更数（applied + merged）
+    pub fn applied_count(&self) -> usize {
+        self.outcomes
+            .iter()
+            .filter(|o| matches!(o.status, ChangeStatus::Applied | ChangeStatus::Merged))
+            .count()
+    }
+}
+
+/// 表配置定义（通过 `SyncRegistry::register` 注册）
 pub struct TableConfig {
     pub name: &'static str,
     pub primary_key: &'static str,
     pub fields: &'static [&'static str],
     pub json_fields: &'static [&'static str],
+    /// 并发修改时做三方文本合并的字段（为空则按 HLC 整条覆盖）
+    pub merge_fields: &'static [&'static str],
+    /// 展示用的标题字段（冲突副本、列表展示）
+    pub title_field: Option<&'static str>,
 }
 
-/// 所有可同步的表配置（与前端 sync-tables.ts 对应）
-pub const SYNC_TABLES: &[TableConfig] = &[
-    TableConfig {
-        name: "notes",
-        primary_key: "uuid",
-        fields: &["uuid", "title", "content", "tags", "created_at", "updated_at", "deleted_at", "version"],
-        json_fields: &["tags"],
-    },
-    TableConfig {
-        name: "moments",
-        primary_key: "uuid",
-        fields: &["uuid", "content", "images", "tags", "created_at", "updated_at", "deleted_at", "version"],
-        json_fields: &["images", "tags"],
-    },
-    TableConfig {
-        name: "assets",
-        primary_key: "uuid",
-        fields: &["uuid", "url", "path", "filename", "size", "mime_type", "storage_type", "created_at", "updated_at", "deleted_at", "version"],
-        json_fields: &[],
-    },
-    TableConfig {
-        name: "workflows",
-        primary_key: "uuid",
-        fields: &["uuid", "name", "description", "steps", "schema_id", "type", "created_at", "updated_at", "deleted_at", "version"],
-        json_fields: &["steps"],
-    },
-    TableConfig {
-        name: "workflow_schemas",
-        primary_key: "uuid",
-        fields: &["uuid", "name", "description", "fields", "created_at", "updated_at", "deleted_at", "version"],
-        json_fields: &["fields"],
-    },
-];
+/// 同步元数据字段（不属于业务内容）
+const SYNC_META_FIELDS: &[&str] = &["version", "updated_at", "deleted_at", "hlc"];
+
+/// 每条记录保留的合并基线版本数
+const MAX_MERGE_BASES_PER_ROW: i64 = 10;
 
 /// 获取表配置
 pub fn get_table_config(table_name: &str) -> Option<&'static TableConfig> {
-    SYNC_TABLES.iter().find(|t| t.name == table_name)
+    sync_registry::registry().get(table_name)
+}
+
+/// 所有可同步的表名
+pub fn table_names() -> Vec<&'static str> {
+    sync_registry::registry().tables().iter().map(|t| t.name).collect()
 }
 
 /// 获取当前时间的 ISO 8601 字符串
@@ -89,10 +164,10 @@ pub fn max_version_for_table(conn: &Connection, table_name: &str) -> i64 {
     0
 }
 
-/// 获取所有表的全局最大版本号
+/// 获取所有表的全局最大版本号（包括变更日志中硬删除的版本号）
 pub fn max_version_all_tables(conn: &Connection) -> i64 {
-    let mut max = 0i64;
-    for table in SYNC_TABLES {
+    let mut max = max_version_for_table(conn, "sync_changelog");
+    for table in sync_registry::registry().tables() {
         let v = max_version_for_table(conn, table.name);
         if v > max {
             max = v;
@@ -101,177 +176,289 @@ pub fn max_version_all_tables(conn: &Connection) -> i64 {
     max
 }
 
-/// 升级表中 version <= 0 的记录（迁移的旧数据 + 本地未同步数据）
-fn upgrade_zero_versions(conn: &Connection, table_name: &str, config: &TableConfig) -> rusqlite::Result<()> {
-    // 获取当前最大版本号
-    let max_version = max_version_all_tables(conn);
-    
-    // 查询所有 version <= 0 且未删除的记录
-    // 对于 workflows 表，排除系统流
-    let where_clause = if table_name == "workflows" {
-        "version <= 0 AND deleted_at IS NULL AND (type IS NULL OR type = 'user' OR type NOT LIKE 'system:%')"
+/// 加载指定表的变更记录（从 sync_changelog 发现变更，包括硬删除）
+///
+/// 只读：版本号在写入时已由触发器分配。按 (version, 主键) 排序；`after_pk` 不为空时还会返回 version 等于 `since_version`
+/// 且主键大于 `after_pk` 的记录（跨表游标在同一版本号内续读）
+///
+/// `scope` 为请求方设备的同步范围：停用的表不返回任何变更，范围外的记录以 scope_out 返回
+pub fn load_table_changes(
+    conn: &Connection,
+    table_name: &str,
+    since_version: i64,
+    after_pk: Option<&str>,
+    limit: usize,
+    scope: Option<&SyncScope>,
+) -> rusqlite::Result<Vec<SyncChange>> {
+    let entries = read_table_changes(conn, table_name, since_version, after_pk, limit, scope)?;
+    Ok(entries.into_iter().filter_map(|(_, change)| change).collect())
+}
+
+/// 单表分页加载变更：返回的版本号不为空表示还有下一页，下一页以它作为 since_version
+///
+/// 是否还有下一页按读到的变更日志条数判断（不参与同步的记录也占一条），而不是按返回的变更数
+pub fn load_table_page(
+    conn: &Connection,
+    table_name: &str,
+    since_version: i64,
+    limit: usize,
+    scope: Option<&SyncScope>,
+) -> rusqlite::Result<(Vec<SyncChange>, Option<i64>)> {
+    let entries = read_table_changes(conn, table_name, since_version, None, limit, scope)?;
+    let next = if entries.len() >= limit {
+        entries.last().map(|(cursor, _)| cursor.version)
     } else {
-        "version <= 0 AND deleted_at IS NULL"
+        None
     };
-    
-    let query = format!(
-        "SELECT {} FROM {} WHERE {} ORDER BY created_at ASC",
-        config.primary_key, table_name, where_clause
-    );
-    
-    let mut stmt = conn.prepare(&query)?;
-    let mut rows = stmt.query([])?;
-    
-    let mut ids_to_upgrade: Vec<String> = Vec::new();
-    while let Some(row) = rows.next()? {
-        ids_to_upgrade.push(row.get(0)?);
-    }
-    drop(rows);
-    drop(stmt);
-    
-    if ids_to_upgrade.is_empty() {
-        return Ok(());
-    }
-    
-    log::info!("[SyncEngine] 升级 {} 表中 {} 条 version<=0 的记录", table_name, ids_to_upgrade.len());
-    
-    // 批量更新版本号
-    let mut current_version = max_version;
-    for id in ids_to_upgrade {
-        current_version += 1;
-        let update_sql = format!(
-            "UPDATE {} SET version = ?1, updated_at = ?2 WHERE {} = ?3",
-            table_name, config.primary_key
-        );
-        conn.execute(&update_sql, params![current_version, now_iso(), id])?;
-    }
-    
-    Ok(())
+    Ok((entries.into_iter().filter_map(|(_, change)| change).collect(), next))
 }
 
-/// 加载指定表的变更记录
-pub fn load_table_changes(
+/// 读取一张表的变更日志：每条日志返回它的游标位置，以及要下发的变更（不参与同步的记录为 None）
+fn read_table_changes(
     conn: &Connection,
     table_name: &str,
     since_version: i64,
+    after_pk: Option<&str>,
     limit: usize,
-) -> rusqlite::Result<Vec<SyncChange>> {
+    scope: Option<&SyncScope>,
+) -> rusqlite::Result<Vec<(PullCursor, Option<SyncChange>)>> {
     let config = match get_table_config(table_name) {
         Some(c) => c,
         None => return Ok(Vec::new()), // 不支持的表
     };
+    if scope.is_some_and(|s| !s.allows_table(table_name)) {
+        return Ok(Vec::new());
+    }
 
-    // 在查询前，先升级所有 version <= 0 的数据（迁移的旧数据 + 本地未同步数据）
-    upgrade_zero_versions(conn, table_name, config)?;
+    let entries = sync_changelog::latest_entries(conn, config, since_version, after_pk, limit)?;
+    let kinds = sync_codec::column_kinds(conn, config)?;
 
-    // 动态构建查询
-    let fields_str = config.fields.join(", ");
+    // 版本向量附在同步字段之后
     let query = format!(
-        "SELECT {} FROM {} WHERE version > ?1 ORDER BY version ASC LIMIT ?2",
-        fields_str, table_name
+        "SELECT {}, vv FROM {} WHERE {} = ?1",
+        config.fields.join(", "),
+        table_name,
+        config.primary_key
     );
-
     let mut stmt = conn.prepare(&query)?;
-    let mut rows = stmt.query(params![since_version as i64, limit as i64])?;
-    let mut changes = Vec::new();
+    let mut changes = Vec::with_capacity(entries.len());
 
-    while let Some(row) = rows.next()? {
-        // 通过字段名获取核心字段
-        let version_idx = config.fields.iter().position(|&f| f == "version").unwrap();
-        let updated_at_idx = config.fields.iter().position(|&f| f == "updated_at").unwrap();
-        let deleted_at_idx = config.fields.iter().position(|&f| f == "deleted_at").unwrap();
-        
-        let version: i64 = row.get(version_idx)?;
-        let updated_at: String = row.get(updated_at_idx).unwrap_or_else(|_| now_iso());
-        let deleted_at: Option<String> = row.get(deleted_at_idx).ok().flatten();
-
-        let op = if deleted_at.is_some() {
-            SyncOp::Delete
-        } else {
-            SyncOp::Upsert
-        };
+    for entry in entries {
+        let cursor = PullCursor { version: entry.version, table: table_name.to_string(), pk: entry.pk.clone() };
+        if entry.pk.trim().is_empty() {
+            log::warn!("[SyncEngine] Skip changelog entry with empty pk in table {}", table_name);
+            changes.push((cursor, None));
+            continue;
+        }
 
-        // 构建 data JSON
-        let mut data_map = serde_json::Map::new();
-        for (i, field_name) in config.fields.iter().enumerate() {
-            // 跳过 created_at（由数据库自动管理）
-            if *field_name == "created_at" {
+        // 硬删除：记录已不存在，只发送主键和删除时间（从回收站彻底清除的记录下发 purge）
+        if entry.op == sync_changelog::OP_DELETE {
+            let hlc = Hlc::effective(entry.hlc.as_deref(), &entry.created_at).encode();
+            let mut data_map = serde_json::Map::new();
+            data_map.insert(config.primary_key.to_string(), serde_json::json!(entry.pk));
+            let data = serde_json::Value::Object(data_map);
+            if sync_exclusion(conn, table_name, &data).is_some() {
+                changes.push((cursor, None));
                 continue;
             }
-            
-            let value: serde_json::Value = if *field_name == "version" {
-                serde_json::json!(version)
-            } else if *field_name == "updated_at" {
-                serde_json::json!(updated_at)
-            } else if *field_name == "deleted_at" {
-                serde_json::json!(deleted_at)
-            } else {
-                // 尝试获取字符串值
-                match row.get::<_, Option<String>>(i) {
-                    Ok(Some(s)) => serde_json::json!(s),
-                    Ok(None) => {
-                        // 可能是数字类型
-                        match row.get::<_, Option<i64>>(i) {
-                            Ok(Some(n)) => serde_json::json!(n),
-                            Ok(None) => serde_json::Value::Null,
-                            Err(_) => serde_json::Value::Null,
-                        }
-                    }
-                    Err(_) => {
-                        // 尝试作为数字
-                        match row.get::<_, Option<i64>>(i) {
-                            Ok(Some(n)) => serde_json::json!(n),
-                            Ok(None) => serde_json::Value::Null,
-                            Err(_) => serde_json::Value::Null,
-                        }
-                    }
-                }
-            };
-            
-            data_map.insert(field_name.to_string(), value);
+            changes.push((cursor, Some(SyncChange {
+                table: table_name.to_string(),
+                op: if entry.purged { SyncOp::Purge } else { SyncOp::Delete },
+                data,
+                version: entry.version,
+                updated_at: entry.created_at.clone(),
+                deleted_at: Some(entry.created_at),
+                hlc: Some(hlc),
+                base_version: None,
+                changed_columns: None,
+                vv: None,
+            })));
+            continue;
         }
 
-        // 检查 uuid 是否有效
-        if let Some(uuid_val) = data_map.get("uuid") {
-            if uuid_val.is_null() {
-                log::warn!("[SyncEngine] Skip record with null uuid in table {}", table_name);
-                continue;
-            }
-            if let Some(s) = uuid_val.as_str() {
-                if s.trim().is_empty() {
-                    log::warn!("[SyncEngine] Skip record with empty uuid in table {}", table_name);
-                    continue;
-                }
+        let mut rows = stmt.query(params![entry.pk])?;
+        let Some(row) = rows.next()? else {
+            changes.push((cursor, None));
+            continue;
+        };
+        let change = match row_to_change(table_name, config, &kinds, row)? {
+            Some(change) if sync_exclusion(conn, table_name, &change.data).is_some() => None,
+            Some(change) if scope.is_some_and(|s| !s.allows(table_name, &change.data)) => Some(sync_scopes::scope_out(
+                table_name,
+                &entry.pk,
+                change.version,
+                &change.updated_at,
+                change.hlc.as_deref(),
+            )),
+            Some(mut change) => {
+                change.changed_columns = sync_changelog::changed_columns_since(conn, table_name, &entry.pk, since_version)?;
+                let vv = VersionVector::parse(row.get::<_, Option<String>>(config.fields.len())?.as_deref());
+                change.vv = Some(vv).filter(|v| !v.is_empty());
+                Some(change)
             }
-        } else {
-             // 如果没有 uuid 字段（不应该发生，因为 config.fields 包含它），也跳过
-             log::warn!("[SyncEngine] Skip record missing uuid field in table {}", table_name);
-             continue;
-        }
-
-        changes.push(SyncChange {
-            table: table_name.to_string(),
-            op,
-            data: serde_json::Value::Object(data_map),
-            version,
-            updated_at,
-            deleted_at,
-        });
+            None => None,
+        };
+        changes.push((cursor, change));
     }
 
     Ok(changes)
 }
 
+/// 不参与同步的记录：系统工作流（各端内置）和非 synced 策略的设置项，返回原因
+fn sync_exclusion(conn: &Connection, table_name: &str, data: &serde_json::Value) -> Option<String> {
+    match table_name {
+        "workflows" => data
+            .get("type")
+            .and_then(|v| v.as_str())
+            .filter(|t| t.starts_with("system:"))
+            .map(|t| format!("system workflow {} is built in", t)),
+        "settings" => sync_settings::exclusion(conn, data),
+        _ => None,
+    }
+}
+
+/// 把一行记录转换为 SyncChange；主键为空时返回 None
+fn row_to_change(
+    table_name: &str,
+    config: &TableConfig,
+    kinds: &[ColumnKind],
+    row: &rusqlite::Row,
+) -> rusqlite::Result<Option<SyncChange>> {
+    // 通过字段名获取核心字段
+    let version_idx = config.fields.iter().position(|&f| f == "version").unwrap();
+    let updated_at_idx = config.fields.iter().position(|&f| f == "updated_at").unwrap();
+    let deleted_at_idx = config.fields.iter().position(|&f| f == "deleted_at").unwrap();
+    let hlc_idx = config.fields.iter().position(|&f| f == "hlc").unwrap();
+
+    let version: i64 = row.get(version_idx)?;
+    let updated_at: String = row.get(updated_at_idx).unwrap_or_else(|_| now_iso());
+    let deleted_at: Option<String> = row.get(deleted_at_idx).ok().flatten();
+    let stored_hlc: Option<String> = row.get(hlc_idx).ok().flatten();
+    let hlc = Hlc::effective(stored_hlc.as_deref(), &updated_at).encode();
+
+    let op = if deleted_at.is_some() {
+        SyncOp::Delete
+    } else {
+        SyncOp::Upsert
+    };
+
+    // 构建 data JSON
+    let mut data_map = serde_json::Map::new();
+    for (i, field_name) in config.fields.iter().enumerate() {
+        // 跳过 created_at（由数据库自动管理）
+        if *field_name == "created_at" {
+            continue;
+        }
+
+        let value: serde_json::Value = if *field_name == "version" {
+            serde_json::json!(version)
+        } else if *field_name == "updated_at" {
+            serde_json::json!(updated_at)
+        } else if *field_name == "deleted_at" {
+            serde_json::json!(deleted_at)
+        } else if *field_name == "hlc" {
+            serde_json::json!(hlc)
+        } else {
+            sync_codec::read_value(row, i, kinds[i])?
+        };
+
+        data_map.insert(field_name.to_string(), value);
+    }
+
+    // 检查主键是否有效
+    match data_map.get(config.primary_key) {
+        Some(v) if v.as_str().is_some_and(|s| !s.trim().is_empty()) => {}
+        _ => {
+            log::warn!("[SyncEngine] Skip record with empty {} in table {}", config.primary_key, table_name);
+            return Ok(None);
+        }
+    }
+
+    Ok(Some(SyncChange {
+        table: table_name.to_string(),
+        op,
+        data: serde_json::Value::Object(data_map),
+        version,
+        updated_at,
+        deleted_at,
+        hlc: Some(hlc),
+        base_version: None,
+        changed_columns: None,
+        vv: None,
+    }))
+}
+
+/// 跨表拉取的续读位置：上一页读到的最后一条变更日志的 (version, 表名, 主键)
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PullCursor {
+    pub version: i64,
+    pub table: String,
+    pub pk: String,
+}
+
+impl PullCursor {
+    /// 编码为不透明的 URL 安全字符串
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(format!("{}:{}:{}", self.version, self.table, self.pk))
+    }
+
+    pub fn decode(s: &str) -> Option<PullCursor> {
+        let raw = String::from_utf8(URL_SAFE_NO_PAD.decode(s.trim()).ok()?).ok()?;
+        let mut parts = raw.splitn(3, ':');
+        let version = parts.next()?.parse::<i64>().ok()?;
+        let table = parts.next()?.to_string();
+        let pk = parts.next()?.to_string();
+        Some(PullCursor { version, table, pk })
+    }
+}
+
+/// 跨表加载变更：所有同步表按 (version, 表名, 主键) 交错排序
+///
+/// `after` 为 None 时从 `since_version` 之后开始；返回的游标不为空表示还有下一页。`scope` 同 `load_table_changes`
+pub fn load_all_changes(
+    conn: &Connection,
+    since_version: i64,
+    after: Option<&PullCursor>,
+    limit: usize,
+    scope: Option<&SyncScope>,
+) -> rusqlite::Result<(Vec<SyncChange>, Option<PullCursor>)> {
+    // 按变更日志条目分页：不参与同步的记录也占位，避免整页都被过滤时误判为已读完
+    let mut entries = Vec::new();
+
+    for table in table_names() {
+        // 换算成每张表各自的起点：同一版本号内，表名排在游标之后的表从该版本号开始读
+        let (since, after_pk) = match after {
+            None => (since_version, None),
+            Some(c) if table > c.table.as_str() => (c.version - 1, None),
+            Some(c) if table == c.table.as_str() => (c.version, Some(c.pk.as_str())),
+            Some(c) => (c.version, None),
+        };
+        // 多读一条用于判断是否还有下一页
+        entries.extend(read_table_changes(conn, table, since, after_pk, limit + 1, scope)?);
+    }
+
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let next = if entries.len() > limit {
+        entries.truncate(limit);
+        entries.last().map(|(cursor, _)| cursor.clone())
+    } else {
+        None
+    };
+
+    Ok((entries.into_iter().filter_map(|(_, change)| change).collect(), next))
+}
+
 /// 应用变更到指定表
 pub fn apply_table_change(
     conn: &Connection,
     table_name: &str,
     change: &SyncChange,
-    new_version: i64,
-) -> rusqlite::Result<bool> {
+    clock: &HybridClock,
+) -> rusqlite::Result<ApplyOutcome> {
     let config = match get_table_config(table_name) {
         Some(c) => c,
-        None => return Ok(false), // 不支持的表
+        None => return Ok(ApplyOutcome::Rejected(format!("unsupported table: {}", table_name))), // 不支持的表
     };
 
     // 提取主键值
@@ -283,186 +470,652 @@ pub fn apply_table_change(
 
     if pk_value.is_empty() {
         log::warn!("Skip applying change for {} with empty primary key", table_name);
-        return Ok(false);
+        return Ok(ApplyOutcome::Rejected(format!("missing primary key {}", config.primary_key)));
+    }
+
+    if let Some(reason) = sync_exclusion(conn, table_name, &change.data) {
+        return Ok(ApplyOutcome::Rejected(reason));
+    }
+    if matches!(change.op, SyncOp::ScopeOut) {
+        return Ok(ApplyOutcome::Rejected("scope_out is only sent by the server".to_string()));
+    }
+
+    // 字段值必须能按列类型无损写入（JSON 合法、BLOB 为 base64）
+    let kinds = sync_codec::column_kinds(conn, config)?;
+    if !matches!(change.op, SyncOp::Delete | SyncOp::Purge) {
+        if let Err(reason) = sync_codec::validate(config, &kinds, &change.data) {
+            log::warn!("[SyncEngine] 拒绝 {} {}: {}", table_name, pk_value, reason);
+            return Ok(ApplyOutcome::Rejected(reason));
+        }
+    }
+
+    // 远程变更的 HLC（旧客户端不携带时由 updated_at 推导）
+    let remote_hlc = change_hlc(change);
+    if !clock.observe(&remote_hlc) {
+        return Ok(ApplyOutcome::Rejected(format!(
+            "hlc {} is more than {} ms ahead of this device",
+            remote_hlc.encode(),
+            MAX_DRIFT_MS
+        )));
     }
 
-    // 检查本地是否已有更新的记录（基于 updated_at）
+    // 检查本地是否已有更新的记录（基于 HLC 全序，不再直接比较时间字符串）
     let check_query = format!(
-        "SELECT updated_at FROM {} WHERE {} = ?1",
+        "SELECT updated_at, hlc, version FROM {} WHERE {} = ?1",
         table_name, config.primary_key
     );
     let mut stmt = conn.prepare(&check_query)?;
-    let existing: Option<String> = stmt.query_row(params![pk_value], |row| row.get(0)).optional()?;
-    
-    if let Some(local_updated_at) = existing {
-        // 如果本地时间 >= 远程时间，跳过
-        // 注意：这里假设时间格式一致（ISO 8601），可以直接字符串比较
-        if local_updated_at >= change.updated_at {
+    let existing: Option<(Option<String>, Option<String>, i64)> = stmt
+        .query_row(params![pk_value], |row| Ok((row.get(0)?, row.get(1)?, row.get::<_, Option<i64>>(2)?.unwrap_or(0))))
+        .optional()?;
+    let row_exists = existing.is_some();
+
+    // 两端都带版本向量时按因果关系判断，不依赖时钟
+    let remote_vv = change.vv.clone().unwrap_or_default();
+    let local_vv = if row_exists {
+        sync_vector::load(conn, config, pk_value)?
+    } else {
+        VersionVector::default()
+    };
+    let causality = (!local_vv.is_empty() && !remote_vv.is_empty()).then(|| remote_vv.compare(&local_vv));
+
+    if let Some((local_updated_at, local_hlc, local_version)) = existing {
+        let local_hlc = Hlc::effective(local_hlc.as_deref(), local_updated_at.as_deref().unwrap_or(""));
+
+        // 本地已包含这次写入
+        if matches!(causality, Some(Causality::Equal | Causality::Before)) {
+            return Ok(ApplyOutcome::Skipped);
+        }
+        // 远程包含本地的全部历史：直接覆盖，无需合并
+        let descends = causality == Some(Causality::After);
+
+        // 覆盖前保存当前版本的合并基线（兼容功能上线前已同步的旧记录）
+        if local_version > 0 {
+            snapshot_merge_base(conn, table_name, config, pk_value)?;
+        }
+
+        // 客户端声明了编辑基线：快进、三方合并或进入冲突队列（恢复操作不改内容，按 HLC 决定即可）
+        let base_version = change
+            .base_version
+            .filter(|v| *v > 0 && !descends && !matches!(change.op, SyncOp::Restore | SyncOp::Purge));
+        if let Some(base_version) = base_version {
+            if local_version != base_version {
+                let local_data = load_row_data(conn, table_name, config, pk_value)?.unwrap_or_default();
+
+                // 两端做了相同的修改（或都已删除），不算冲突
+                let local_deleted = local_data.get("deleted_at").is_some_and(|v| !v.is_null());
+                let remote_deleted = matches!(change.op, SyncOp::Delete | SyncOp::Purge);
+                if local_deleted == remote_deleted
+                    && (remote_deleted || same_content(config, &local_data, &change.data))
+                {
+                    return Ok(ApplyOutcome::Skipped);
+                }
+
+                if matches!(change.op, SyncOp::Upsert) && !config.merge_fields.is_empty() {
+                    let local_wins = local_hlc > remote_hlc;
+                    if let Some((merged, conflicted)) = merge_with_base(conn, table_name, config, pk_value, base_version, &change.data, local_wins)? {
+                        // 合并结果是一次新的写入，时间戳必须同时大于两端
+                        clock.advance(&local_hlc);
+                        let hlc = clock.now().encode();
+                        let revision_mark = revision_mark(conn, table_name, pk_value)?;
+                        upsert_row(conn, table_name, config, &kinds, pk_value, &merged, &now_iso(), &hlc)?;
+                        // 合并用的是本机时钟，触发器会记为本机编辑
+                        if let Some(mark) = revision_mark {
+                            note_revisions::relabel_since(conn, pk_value, mark, note_revisions::SOURCE_SYNC)?;
+                        }
+                        snapshot_merge_base(conn, table_name, config, pk_value)?;
+                        // 合并是本机的一次编辑：保留触发器写入的本机条目，再并入远程向量
+                        if !remote_vv.is_empty() {
+                            let stamped = sync_vector::load(conn, config, pk_value)?;
+                            sync_vector::store(conn, config, pk_value, &stamped.merge(&remote_vv))?;
+                        }
+                        return Ok(ApplyOutcome::Merged { conflicted });
+                    }
+                }
+
+                // 无法自动合并：保留两端副本，等待用户决定
+                let id = sync_conflicts::record_conflict(
+                    conn,
+                    table_name,
+                    pk_value,
+                    base_version,
+                    local_version,
+                    &serde_json::Value::Object(local_data),
+                    change,
+                )?;
+                log::info!(
+                    "[SyncEngine] 冲突入队 #{}: {} {} (base={}, server={})",
+                    id, table_name, pk_value, base_version, local_version
+                );
+                return Ok(ApplyOutcome::Conflict(id));
+            }
+        }
+
+        // 如果本地 HLC >= 远程 HLC，跳过
+        // （服务端版本恰好等于客户端基线时，客户端的修改在因果上更新，直接快进）
+        let fast_forward = descends || (change.base_version == Some(local_version) && local_version > 0);
+        if !fast_forward && local_hlc >= remote_hlc {
             log::debug!(
-                "Skip applying change for {} {}: local updated_at {} >= remote updated_at {}",
+                "Skip applying change for {} {}: local hlc {} >= remote hlc {}",
                 table_name,
                 pk_value,
-                local_updated_at,
-                change.updated_at
+                local_hlc.encode(),
+                remote_hlc.encode()
             );
-            return Ok(false); // 跳过旧版本
+            return Ok(ApplyOutcome::Skipped); // 跳过旧版本
+        }
+    } else if let Some(tombstone_hlc) = sync_changelog::tombstone_hlc(conn, table_name, pk_value)? {
+        // 记录已被硬删除：只有晚于删除的写入才能让它复活
+        if tombstone_hlc >= remote_hlc {
+            return Ok(ApplyOutcome::Skipped);
         }
     }
 
     // 使用客户端提供的 updated_at，如果没有则使用当前时间
     let updated_at = change.updated_at.clone();
+    let hlc = remote_hlc.encode();
 
     match change.op {
         SyncOp::Delete => {
             // 使用客户端提供的 deleted_at，如果没有则使用当前时间
             let deleted_at = change.deleted_at.clone().unwrap_or_else(|| now_iso());
+
+            // 本地没有该记录：只在变更日志中留下墓碑，不再插入空白占位记录
+            if !row_exists {
+                sync_changelog::record_tombstone(conn, table_name, pk_value, &hlc, &deleted_at)?;
+                return Ok(ApplyOutcome::Applied);
+            }
             
-            // 动态构建 DELETE 的 UPSERT 语句
-            let fields_except_created = config
-                .fields
-                .iter()
-                .filter(|f| **f != "created_at")
-                .copied()
-                .collect::<Vec<_>>();
-            
-            let placeholders = fields_except_created
-                .iter()
-                .enumerate()
-                .map(|(i, _)| format!("?{}", i + 1))
-                .collect::<Vec<_>>()
-                .join(", ");
-            
-            let update_set = fields_except_created
-                .iter()
-                .map(|f| format!("{} = excluded.{}", f, f))
-                .collect::<Vec<_>>()
-                .join(", ");
-
-            let insert_query = format!(
-                "INSERT INTO {} ({}) VALUES ({}) ON CONFLICT({}) DO UPDATE SET {}",
-                table_name,
-                fields_except_created.join(", "),
-                placeholders,
-                config.primary_key,
-                update_set
-            );
+            // 保留记录内容，只标记删除（可从回收站恢复）
+            set_deleted_at(conn, config, pk_value, Some(&deleted_at), &updated_at, &hlc)?;
+        }
+        SyncOp::Restore => {
+            if !row_exists {
+                // 记录已被彻底清除：客户端带了完整内容时按 upsert 重建，否则无从恢复
+                let has_content = change
+                    .data
+                    .as_object()
+                    .is_some_and(|m| m.keys().any(|k| k != config.primary_key));
+                if !has_content {
+                    return Ok(ApplyOutcome::Rejected("record not found".to_string()));
+                }
+                upsert_row(conn, table_name, config, &kinds, pk_value, &change.data, &updated_at, &hlc)?;
+                snapshot_merge_base(conn, table_name, config, pk_value)?;
+                if !remote_vv.is_empty() {
+                    sync_vector::store(conn, config, pk_value, &remote_vv)?;
+                }
+                return Ok(ApplyOutcome::Applied);
+            }
+            set_deleted_at(conn, config, pk_value, None, &updated_at, &hlc)?;
+        }
+        SyncOp::Upsert => {
+            upsert_row(conn, table_name, config, &kinds, pk_value, &change.data, &updated_at, &hlc)?;
+            snapshot_merge_base(conn, table_name, config, pk_value)?;
+        }
+        SyncOp::Purge => {
+            // 本地没有该记录：同样留下墓碑，保证继续传播给其他设备
+            if !row_exists {
+                let deleted_at = change.deleted_at.clone().unwrap_or_else(now_iso);
+                sync_changelog::record_tombstone(conn, table_name, pk_value, &hlc, &deleted_at)?;
+            } else {
+                purge_row(conn, config, pk_value)?;
+            }
+            sync_changelog::mark_purged(conn, table_name, pk_value, &hlc)?;
+            return Ok(ApplyOutcome::Applied);
+        }
+        // 已在开头拒绝
+        SyncOp::ScopeOut => {}
+    }
 
-            // 构建参数（所有字段都设为默认值，除了 pk、version、deleted_at、updated_at）
-            let mut params_vec: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
-            for field in &fields_except_created {
-                if *field == config.primary_key {
-                    params_vec.push(Box::new(pk_value));
-                } else if *field == "version" {
-                    params_vec.push(Box::new(new_version));
-                } else if *field == "deleted_at" {
-                    params_vec.push(Box::new(deleted_at.clone()));
-                } else if *field == "updated_at" {
-                    params_vec.push(Box::new(updated_at.clone()));
-                } else if config.json_fields.contains(field) {
-                    params_vec.push(Box::new("[]".to_string()));
-                } else {
-                    params_vec.push(Box::new("".to_string()));
+    // 转发的写入不算本机编辑：用合并后的向量替换触发器写入的本机条目
+    // （旧客户端不带向量时保留本机条目，由本机代为记录这次写入）
+    if !remote_vv.is_empty() {
+        sync_vector::store(conn, config, pk_value, &local_vv.merge(&remote_vv))?;
+    }
+
+    Ok(ApplyOutcome::Applied)
+}
+
+/// 物理删除记录及其合并基线（删除触发器记录硬删除，调用方再用 `mark_purged` 标记为彻底清除）
+pub(crate) fn purge_row(conn: &Connection, config: &TableConfig, pk_value: &str) -> rusqlite::Result<usize> {
+    let deleted = conn.execute(
+        &format!("DELETE FROM {} WHERE {} = ?1", config.name, config.primary_key),
+        params![pk_value],
+    )?;
+    conn.execute(
+        "DELETE FROM sync_merge_bases WHERE table_name = ?1 AND pk = ?2",
+        params![config.name, pk_value],
+    )?;
+    Ok(deleted)
+}
+
+/// 设置或清除记录的 deleted_at（版本号由触发器分配）
+pub(crate) fn set_deleted_at(
+    conn: &Connection,
+    config: &TableConfig,
+    pk_value: &str,
+    deleted_at: Option<&str>,
+    updated_at: &str,
+    hlc: &str,
+) -> rusqlite::Result<usize> {
+    conn.execute(
+        &format!(
+            "UPDATE {} SET deleted_at = ?1, updated_at = ?2, hlc = ?3, version = 0 WHERE {} = ?4",
+            config.name, config.primary_key
+        ),
+        params![deleted_at, updated_at, hlc, pk_value],
+    )
+}
+
+/// 在一个事务中应用一批变更（/push）
+///
+/// 每条变更使用独立的 SAVEPOINT：失败只回滚该条并记为 rejected。
+/// `atomic` 为 true 时任意一条被拒绝都会回滚整个事务（all-or-nothing）。
+/// 版本号由触发器在写入时分配，结果中回报的是写入后变更日志里的版本号。
+pub fn apply_changes(
+    conn: &mut Connection,
+    changes: &[SyncChange],
+    table_filter: Option<&str>,
+    clock: &HybridClock,
+    atomic: bool,
+) -> rusqlite::Result<ApplyReport> {
+    run_changes(conn, changes, table_filter, clock, atomic, false)
+}
+
+/// 预演一批变更（/push 的 dry run）：与 `apply_changes` 做完全相同的判断，最后回滚整个事务
+///
+/// 结果中的状态与原因即实际推送时将得到的结果；没有真正写入，因此不返回版本号和冲突 ID。
+/// 预演使用本机时钟的副本，观察到的远程时间戳不会推进本机时钟
+pub fn preview_changes(
+    conn: &mut Connection,
+    changes: &[SyncChange],
+    table_filter: Option<&str>,
+    clock: &HybridClock,
+    atomic: bool,
+) -> rusqlite::Result<ApplyReport> {
+    let scratch = clock.snapshot();
+    run_changes(conn, changes, table_filter, &scratch, atomic, true)
+}
+
+fn run_changes(
+    conn: &mut Connection,
+    changes: &[SyncChange],
+    table_filter: Option<&str>,
+    clock: &HybridClock,
+    atomic: bool,
+    dry_run: bool,
+) -> rusqlite::Result<ApplyReport> {
+    let mut tx = conn.transaction()?;
+    let mut outcomes = Vec::with_capacity(changes.len());
+
+    for (index, change) in changes.iter().enumerate() {
+        let pk = get_table_config(&change.table)
+            .and_then(|c| change.data.get(c.primary_key))
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string());
+        let mut outcome = ChangeOutcome {
+            index,
+            table: change.table.clone(),
+            pk,
+            status: ChangeStatus::Skipped,
+            reason: None,
+            version: None,
+            conflict_id: None,
+        };
+
+        // 如果请求中指定了表名，只处理该表
+        if let Some(t) = table_filter {
+            if change.table != t {
+                outcome.reason = Some(format!("not in requested table {}", t));
+                outcomes.push(outcome);
+                continue;
+            }
+        }
+
+        let sp = tx.savepoint()?;
+        match apply_table_change(&sp, &change.table, change, clock) {
+            Ok(ApplyOutcome::Applied) => {
+                sp.commit()?;
+                outcome.status = ChangeStatus::Applied;
+                outcome.version = written_version(&tx, &outcome)?;
+            }
+            Ok(ApplyOutcome::Merged { conflicted }) => {
+                sp.commit()?;
+                outcome.status = ChangeStatus::Merged;
+                outcome.version = written_version(&tx, &outcome)?;
+                if conflicted {
+                    outcome.reason = Some("overlapping edits kept with conflict markers".to_string());
                 }
             }
+            Ok(ApplyOutcome::Skipped) => {
+                outcome.status = ChangeStatus::Skipped;
+                outcome.reason = Some("server copy is newer".to_string());
+            }
+            Ok(ApplyOutcome::Rejected(reason)) => {
+                outcome.status = ChangeStatus::Rejected;
+                outcome.reason = Some(reason);
+            }
+            Ok(ApplyOutcome::Conflict(id)) => {
+                sp.commit()?;
+                outcome.status = ChangeStatus::Conflict;
+                outcome.reason = Some("server copy changed since base version".to_string());
+                outcome.conflict_id = Some(id);
+            }
+            Err(e) => {
+                // sp 在 drop 时自动回滚该条变更
+                log::error!("apply_table_change error for {}: {}", change.table, e);
+                outcome.status = ChangeStatus::Rejected;
+                outcome.reason = Some(e.to_string());
+            }
+        }
+        outcomes.push(outcome);
+    }
 
-            let params_refs: Vec<&dyn rusqlite::ToSql> = params_vec.iter().map(|b| b.as_ref()).collect();
-            conn.execute(&insert_query, params_refs.as_slice())?;
+    let aborted = atomic && outcomes.iter().any(|o| o.status == ChangeStatus::Rejected);
+    if aborted || dry_run {
+        // 回滚同时撤销了写入和冲突队列中的记录，不能再回报版本号和冲突 ID
+        tx.rollback()?;
+        for outcome in outcomes.iter_mut() {
+            if aborted && matches!(outcome.status, ChangeStatus::Applied | ChangeStatus::Merged | ChangeStatus::Conflict) {
+                outcome.status = ChangeStatus::Aborted;
+            }
+            outcome.version = None;
+            outcome.conflict_id = None;
         }
-        SyncOp::Upsert => {
-            // 动态构建 UPSERT 语句
-            let fields_except_created = config
-                .fields
-                .iter()
-                .filter(|f| **f != "created_at")
-                .copied()
-                .collect::<Vec<_>>();
-            
-            let placeholders = fields_except_created
-                .iter()
-                .enumerate()
-                .map(|(i, _)| format!("?{}", i + 1))
-                .collect::<Vec<_>>()
-                .join(", ");
-            
-            let update_set = fields_except_created
-                .iter()
-                .filter(|f| **f != config.primary_key) // 主键不更新
-                .map(|f| format!("{} = excluded.{}", f, f))
-                .collect::<Vec<_>>()
-                .join(", ");
-
-            let insert_query = format!(
-                "INSERT INTO {} ({}) VALUES ({}) ON CONFLICT({}) DO UPDATE SET {}",
-                table_name,
-                fields_except_created.join(", "),
-                placeholders,
-                config.primary_key,
-                update_set
-            );
+        return Ok(ApplyReport { outcomes, committed: false });
+    }
+
+    tx.commit()?;
+    Ok(ApplyReport { outcomes, committed: true })
+}
+
+/// 一条已写入的变更分配到的版本号
+fn written_version(conn: &Connection, outcome: &ChangeOutcome) -> rusqlite::Result<Option<i64>> {
+    match &outcome.pk {
+        Some(pk) => sync_changelog::latest_version(conn, &outcome.table, pk),
+        None => Ok(None),
+    }
+}
+
+/// 以 UPSERT 写入一条记录，data 中缺失的字段使用默认值；版本号由触发器分配
+#[allow(clippy::too_many_arguments)]
+fn upsert_row(
+    conn: &Connection,
+    table_name: &str,
+    config: &TableConfig,
+    kinds: &[ColumnKind],
+    pk_value: &str,
+    data: &serde_json::Value,
+    updated_at: &str,
+    hlc: &str,
+) -> rusqlite::Result<()> {
+    // 动态构建 UPSERT 语句
+    let fields_except_created = config
+        .fields
+        .iter()
+        .filter(|f| **f != "created_at")
+        .copied()
+        .collect::<Vec<_>>();
+    
+    let placeholders = fields_except_created
+        .iter()
+        .enumerate()
+        .map(|(i, _)| format!("?{}", i + 1))
+        .collect::<Vec<_>>()
+        .join(", ");
+    
+    let update_set = fields_except_created
+        .iter()
+        .filter(|f| **f != config.primary_key) // 主键不更新
+        .map(|f| format!("{} = excluded.{}", f, f))
+        .collect::<Vec<_>>()
+        .join(", ");
 
-            // 构建参数
-            let mut params_vec: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
-            for field in &fields_except_created {
-                if *field == config.primary_key {
-                    params_vec.push(Box::new(pk_value));
-                } else if *field == "version" {
-                    params_vec.push(Box::new(new_version));
-                } else if *field == "updated_at" {
-                    params_vec.push(Box::new(updated_at.clone()));
-                } else if *field == "deleted_at" {
-                    params_vec.push(Box::new(None::<String>));
+    let insert_query = format!(
+        "INSERT INTO {} ({}) VALUES ({}) ON CONFLICT({}) DO UPDATE SET {}",
+        table_name,
+        fields_except_created.join(", "),
+        placeholders,
+        config.primary_key,
+        update_set
+    );
+
+    // 构建参数
+    let mut params_vec: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
+    for field in &fields_except_created {
+        if *field == config.primary_key {
+            params_vec.push(Box::new(pk_value.to_string()));
+        } else if *field == "version" {
+            params_vec.push(Box::new(0i64));
+        } else if *field == "updated_at" {
+            params_vec.push(Box::new(updated_at.to_string()));
+        } else if *field == "hlc" {
+            params_vec.push(Box::new(hlc.to_string()));
+        } else if *field == "deleted_at" {
+            params_vec.push(Box::new(None::<String>));
+        } else {
+            // 从 data 中提取值，按列类型编码
+            let value = data.get(*field);
+            if let Some(v) = value {
+                let kind = config
+                    .fields
+                    .iter()
+                    .position(|f| f == field)
+                    .map_or(ColumnKind::Numeric, |i| kinds[i]);
+                let sql_value = sync_codec::to_sql(v, kind)
+                    .map_err(|reason| rusqlite::Error::ToSqlConversionFailure(format!("{}: {}", field, reason).into()))?;
+                params_vec.push(Box::new(sql_value));
+            } else {
+                // 字段不存在，使用默认值
+                // uuid 字段必须存在且非空
+                if *field == "uuid" {
+                    return Err(rusqlite::Error::InvalidQuery);
+                }
+                
+                if config.json_fields.contains(field) {
+                    params_vec.push(Box::new("[]".to_string()));
                 } else {
-                    // 从 change.data 中提取值
-                    let value = change.data.get(*field);
-                    if let Some(v) = value {
-                        if let Some(s) = v.as_str() {
-                            params_vec.push(Box::new(s.to_string()));
-                        } else if let Some(n) = v.as_i64() {
-                            params_vec.push(Box::new(n));
-                        } else if v.is_null() {
-                            params_vec.push(Box::new(None::<String>));
-                        } else {
-                            // JSON 对象或数组，转为字符串
-                            params_vec.push(Box::new(v.to_string()));
-                        }
-                    } else {
-                        // 字段不存在，使用默认值
-                        // uuid 字段必须存在且非空
-                        if *field == "uuid" {
-                            return Err(rusqlite::Error::InvalidQuery);
-                        }
-                        
-                        if config.json_fields.contains(field) {
-                            params_vec.push(Box::new("[]".to_string()));
-                        } else {
-                            params_vec.push(Box::new("".to_string()));
-                        }
-                    }
+                    params_vec.push(Box::new("".to_string()));
                 }
             }
+        }
+    }
+
+    let params_refs: Vec<&dyn rusqlite::ToSql> = params_vec.iter().map(|b| b.as_ref()).collect();
+    conn.execute(&insert_query, params_refs.as_slice())?;
+    Ok(())
+}
+
+/// 比较两份数据的业务字段是否一致（忽略版本号、时间戳等同步元数据）
+fn same_content(
+    config: &TableConfig,
+    local: &serde_json::Map<String, serde_json::Value>,
+    remote: &serde_json::Value,
+) -> bool {
+    config
+        .fields
+        .iter()
+        .filter(|f| !SYNC_META_FIELDS.contains(f) && **f != "created_at")
+        .all(|f| {
+            let json = config.json_fields.contains(f);
+            normalize(local.get(*f), json) == normalize(remote.get(*f), json)
+        })
+}
+
+/// 缺失、null 与空字符串视为相同；JSON 字段的字符串形式（旧客户端）先解析再比较
+fn normalize(value: Option<&serde_json::Value>, json: bool) -> serde_json::Value {
+    match value {
+        None | Some(serde_json::Value::Null) => serde_json::Value::String(String::new()),
+        Some(serde_json::Value::String(s)) if s.is_empty() => serde_json::Value::String(String::new()),
+        Some(serde_json::Value::String(s)) if json => serde_json::from_str(s).unwrap_or_else(|_| serde_json::json!(s)),
+        Some(v) => v.clone(),
+    }
+}
 
-            let params_refs: Vec<&dyn rusqlite::ToSql> = params_vec.iter().map(|b| b.as_ref()).collect();
-            conn.execute(&insert_query, params_refs.as_slice())?;
+/// 读取一条记录的全部同步字段
+pub(crate) fn load_row_data(
+    conn: &Connection,
+    table_name: &str,
+    config: &TableConfig,
+    pk_value: &str,
+) -> rusqlite::Result<Option<serde_json::Map<String, serde_json::Value>>> {
+    let query = format!(
+        "SELECT {} FROM {} WHERE {} = ?1",
+        config.fields.join(", "),
+        table_name,
+        config.primary_key
+    );
+    let kinds = sync_codec::column_kinds(conn, config)?;
+    conn.query_row(&query, params![pk_value], |row| {
+        let mut data = serde_json::Map::new();
+        for (i, field_name) in config.fields.iter().enumerate() {
+            data.insert(field_name.to_string(), sync_codec::read_value(row, i, kinds[i])?);
         }
+        Ok(data)
+    })
+    .optional()
+}
+
+/// 笔记写入前的最新历史版本 ID（非 notes 表为 None），写入后用于把本机时钟完成的同步写入改记为 sync
+pub(crate) fn revision_mark(conn: &Connection, table_name: &str, pk_value: &str) -> rusqlite::Result<Option<i64>> {
+    if table_name != "notes" {
+        return Ok(None);
     }
+    note_revisions::latest_id(conn, pk_value).map(Some)
+}
 
-    Ok(true)
+/// 以记录当前的版本号保存合并基线（仅 merge_fields 非空的表）
+/// 每条记录只保留最近 MAX_MERGE_BASES_PER_ROW 个版本
+pub(crate) fn snapshot_merge_base(
+    conn: &Connection,
+    table_name: &str,
+    config: &TableConfig,
+    pk_value: &str,
+) -> rusqlite::Result<()> {
+    if config.merge_fields.is_empty() {
+        return Ok(());
+    }
+    let data = match load_row_data(conn, table_name, config, pk_value)? {
+        Some(d) => d,
+        None => return Ok(()),
+    };
+    let version = match data.get("version").and_then(|v| v.as_i64()) {
+        Some(v) if v > 0 => v,
+        _ => return Ok(()),
+    };
+
+    let snapshot: serde_json::Map<String, serde_json::Value> = config
+        .merge_fields
+        .iter()
+        .map(|f| (f.to_string(), data.get(*f).cloned().unwrap_or(serde_json::Value::Null)))
+        .collect();
+
+    conn.execute(
+        "INSERT OR IGNORE INTO sync_merge_bases (table_name, pk, version, snapshot, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![table_name, pk_value, version, serde_json::Value::Object(snapshot).to_string(), now_iso()],
+    )?;
+    conn.execute(
+        "DELETE FROM sync_merge_bases WHERE table_name = ?1 AND pk = ?2 AND version NOT IN (
+            SELECT version FROM sync_merge_bases WHERE table_name = ?1 AND pk = ?2 ORDER BY version DESC LIMIT ?3
+        )",
+        params![table_name, pk_value, MAX_MERGE_BASES_PER_ROW],
+    )?;
+    Ok(())
+}
+
+/// 基于客户端的基线版本对 merge_fields 做三方合并，返回合并后的数据及是否写入了冲突标记
+///
+/// 其余字段取 HLC 较新的一端；找不到基线时返回 None，由调用方回退到 HLC 覆盖
+fn merge_with_base(
+    conn: &Connection,
+    table_name: &str,
+    config: &TableConfig,
+    pk_value: &str,
+    base_version: i64,
+    remote: &serde_json::Value,
+    local_wins: bool,
+) -> rusqlite::Result<Option<(serde_json::Value, bool)>> {
+    let base: Option<String> = conn
+        .query_row(
+            "SELECT snapshot FROM sync_merge_bases WHERE table_name = ?1 AND pk = ?2 AND version = ?3",
+            params![table_name, pk_value, base_version],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let base: serde_json::Value = match base.and_then(|s| serde_json::from_str(&s).ok()) {
+        Some(b) => b,
+        None => {
+            log::debug!("[SyncEngine] No merge base for {} {} @{}", table_name, pk_value, base_version);
+            return Ok(None);
+        }
+    };
+    let local = match load_row_data(conn, table_name, config, pk_value)? {
+        Some(d) => serde_json::Value::Object(d),
+        None => return Ok(None),
+    };
+
+    let text = |v: &serde_json::Value, f: &str| v.get(f).and_then(|x| x.as_str()).unwrap_or("").to_string();
+
+    let mut merged = if local_wins { local.clone() } else { remote.clone() };
+    let mut conflicted_fields = Vec::new();
+    for field in config.merge_fields {
+        let (b, l, r) = (text(&base, field), text(&local, field), text(remote, field));
+        let result = merge::merge3(&b, &l, &r);
+
+        // 单行字段（如标题）写冲突标记没有意义，重叠时取 HLC 较新的一端
+        let value = if result.conflicted && !(b.contains('\n') || l.contains('\n') || r.contains('\n')) {
+            if local_wins { l } else { r }
+        } else {
+            if result.conflicted {
+                conflicted_fields.push(*field);
+            }
+            result.text
+        };
+        merged[*field] = serde_json::json!(value);
+    }
+
+    log::info!(
+        "[SyncEngine] 三方合并 {} {} (base={}): conflicted={:?}",
+        table_name,
+        pk_value,
+        base_version,
+        conflicted_fields
+    );
+
+    Ok(Some((merged, !conflicted_fields.is_empty())))
+}
+
+/// 计算变更携带的 HLC：优先使用 SyncChange.hlc，其次 data.hlc，都没有时由 updated_at 推导
+fn change_hlc(change: &SyncChange) -> Hlc {
+    let stamped = change
+        .hlc
+        .as_deref()
+        .or_else(|| change.data.get("hlc").and_then(|v| v.as_str()));
+    Hlc::effective(stamped, &change.updated_at)
 }
 
 /// 获取指定表的所有记录元数据（用于智能合并）
+///
+/// `scope` 为请求方设备的同步范围：停用的表返回空列表，范围外的记录带 `scoped_out: true` 返回
 pub fn load_table_metadata(
     conn: &Connection,
     table_name: &str,
+    scope: Option<&SyncScope>,
 ) -> rusqlite::Result<Vec<serde_json::Value>> {
-    let _config = match get_table_config(table_name) {
+    let config = match get_table_config(table_name) {
         Some(c) => c,
         None => return Ok(Vec::new()),
     };
+    if scope.is_some_and(|s| !s.allows_table(table_name)) {
+        return Ok(Vec::new());
+    }
+    let category = if table_name == "settings" { "category" } else { "NULL" };
+    let tags = if config.fields.contains(&"tags") { "tags" } else { "NULL" };
 
-    // 查询元数据字段：uuid, version, updated_at, deleted_at
+    // 查询元数据字段：主键（以 uuid 返回）, version, updated_at, deleted_at, hlc
     let query = format!(
-        "SELECT uuid, version, updated_at, deleted_at FROM {} WHERE deleted_at IS NULL ORDER BY updated_at DESC",
-        table_name
+        "SELECT {}, version, updated_at, deleted_at, hlc, {}, {} FROM {} WHERE deleted_at IS NULL ORDER BY updated_at DESC",
+        config.primary_key, category, tags, table_name
     );
 
     let mut stmt = conn.prepare(&query)?;
@@ -474,15 +1127,29 @@ pub fn load_table_metadata(
         let version: i64 = row.get(1)?;
         let updated_at: String = row.get(2).unwrap_or_else(|_| now_iso());
         let deleted_at: Option<String> = row.get(3).ok().flatten();
+        let hlc: Option<String> = row.get(4).ok().flatten();
+        let hlc = Hlc::effective(hlc.as_deref(), &updated_at).encode();
+        let category: Option<String> = row.get(5).ok().flatten();
+        let tags: Option<String> = row.get(6).ok().flatten();
+
+        // 跳过不参与同步的记录
+        let key = serde_json::json!({ config.primary_key: uuid, "category": category });
+        if sync_exclusion(conn, table_name, &key).is_some() {
+            continue;
+        }
 
         // 跳过没有 uuid 的记录（旧数据）
         if let Some(uuid_value) = uuid {
-            let metadata = serde_json::json!({
+            let mut metadata = serde_json::json!({
                 "uuid": uuid_value,
                 "version": version,
                 "updated_at": updated_at,
                 "deleted_at": deleted_at,
+                "hlc": hlc,
             });
+            if scope.is_some_and(|s| !s.allows(table_name, &serde_json::json!({ "tags": tags, "updated_at": updated_at }))) {
+                metadata["scoped_out"] = serde_json::Value::Bool(true);
+            }
 
             metadata_list.push(metadata);
         }
@@ -490,3 +1157,321 @@ pub fn load_table_metadata(
 
     Ok(metadata_list)
 }
+
+/// 分页加载元数据（用于增量智能合并）：`since_version` 之后有变化的记录，按 (version, 主键) 排序
+///
+/// 与 `load_table_metadata` 不同，软删除的记录和硬删除留下的变更日志都会以墓碑返回（deleted_at 不为空），
+/// 客户端据此得知远程的删除。返回的游标不为空表示还有下一页；`scope` 同 `load_table_metadata`
+pub fn load_metadata_page(
+    conn: &Connection,
+    table_name: &str,
+    since_version: i64,
+    after: Option<&PullCursor>,
+    limit: usize,
+    scope: Option<&SyncScope>,
+) -> rusqlite::Result<(Vec<serde_json::Value>, Option<PullCursor>)> {
+    let config = match get_table_config(table_name) {
+        Some(c) => c,
+        None => return Ok((Vec::new(), None)),
+    };
+    if scope.is_some_and(|s| !s.allows_table(table_name)) {
+        return Ok((Vec::new(), None));
+    }
+    let category = if table_name == "settings" { "category" } else { "NULL" };
+    let tags = if config.fields.contains(&"tags") { "tags" } else { "NULL" };
+
+    let (since, after_pk) = match after {
+        Some(cursor) => (cursor.version, Some(cursor.pk.as_str())),
+        None => (since_version, None),
+    };
+    let entries = sync_changelog::latest_entries(conn, config, since, after_pk, limit)?;
+    let next = if entries.len() >= limit {
+        entries.last().map(|e| PullCursor { version: e.version, table: table_name.to_string(), pk: e.pk.clone() })
+    } else {
+        None
+    };
+
+    let query = format!(
+        "SELECT version, updated_at, deleted_at, hlc, {}, {} FROM {} WHERE {} = ?1",
+        category, tags, table_name, config.primary_key
+    );
+    let mut stmt = conn.prepare(&query)?;
+    let mut metadata_list = Vec::with_capacity(entries.len());
+
+    for entry in entries {
+        if entry.pk.trim().is_empty() {
+            continue;
+        }
+
+        // 硬删除：记录已不存在，删除时间取变更日志的时间
+        if entry.op == sync_changelog::OP_DELETE {
+            let key = serde_json::json!({ config.primary_key: entry.pk });
+            if sync_exclusion(conn, table_name, &key).is_some() {
+                continue;
+            }
+            metadata_list.push(serde_json::json!({
+                "uuid": entry.pk,
+                "version": entry.version,
+                "updated_at": entry.created_at,
+                "deleted_at": entry.created_at,
+                "hlc": Hlc::effective(entry.hlc.as_deref(), &entry.created_at).encode(),
+            }));
+            continue;
+        }
+
+        let row = stmt
+            .query_row(params![entry.pk], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, Option<String>>(1)?.unwrap_or_else(now_iso),
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                    row.get::<_, Option<String>>(4)?,
+                    row.get::<_, Option<String>>(5)?,
+                ))
+            })
+            .optional()?;
+        let Some((version, updated_at, deleted_at, hlc, category, tags)) = row else {
+            continue;
+        };
+
+        let key = serde_json::json!({ config.primary_key: entry.pk, "category": category });
+        if sync_exclusion(conn, table_name, &key).is_some() {
+            continue;
+        }
+
+        let mut metadata = serde_json::json!({
+            "uuid": entry.pk,
+            "version": version,
+            "updated_at": updated_at,
+            "deleted_at": deleted_at,
+            "hlc": Hlc::effective(hlc.as_deref(), &updated_at).encode(),
+        });
+        if scope.is_some_and(|s| !s.allows(table_name, &serde_json::json!({ "tags": tags, "updated_at": updated_at }))) {
+            metadata["scoped_out"] = serde_json::Value::Bool(true);
+        }
+        metadata_list.push(metadata);
+    }
+
+    Ok((metadata_list, next))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::mem_db;
+
+    fn insert_setting(conn: &Connection, key: &str, category: &str) {
+        conn.execute(
+            "INSERT INTO settings (key, value, category, updated_at) VALUES (?1, 'v', ?2, ?3)",
+            params![key, category, now_iso()],
+        )
+        .unwrap();
+    }
+
+    /// 整页都是不参与同步的记录时仍要返回续读游标，不能把之后的变更当作已读完
+    #[test]
+    fn pages_past_excluded_rows() {
+        let conn = mem_db();
+        for key in ["sidebar_open", "last_active_menu", "notes_active_tab", "local_a", "local_b"] {
+            insert_setting(&conn, key, "ui");
+        }
+        insert_setting(&conn, "custom_css", "ui");
+
+        let mut pulled = Vec::new();
+        let mut after: Option<PullCursor> = None;
+        let mut pages = 0;
+        loop {
+            let (changes, next) = load_all_changes(&conn, 0, after.as_ref(), 3, None).unwrap();
+            pulled.extend(changes.into_iter().map(|c| c.data["key"].as_str().unwrap().to_string()));
+            pages += 1;
+            match next {
+                Some(cursor) => after = Some(cursor),
+                None => break,
+            }
+        }
+        assert_eq!(pulled, vec!["custom_css".to_string()]);
+        assert!(pages > 1);
+
+        let (first, next) = load_table_page(&conn, "settings", 0, 3, None).unwrap();
+        assert!(first.is_empty());
+        let (second, _) = load_table_page(&conn, "settings", next.expect("more pages"), 3, None).unwrap();
+        assert_eq!(second.len(), 1);
+        assert_eq!(second[0].data["key"], "custom_css");
+    }
+
+    /// 分页读取元数据直到读完，返回 (主键, 是否墓碑) 列表
+    fn metadata_pages(conn: &Connection, since_version: i64, limit: usize) -> (Vec<(String, bool)>, usize) {
+        let mut items = Vec::new();
+        let mut after: Option<PullCursor> = None;
+        let mut pages = 0;
+        loop {
+            let (page, next) = load_metadata_page(conn, "notes", since_version, after.as_ref(), limit, None).unwrap();
+            items.extend(page.iter().map(|m| (m["uuid"].as_str().unwrap().to_string(), !m["deleted_at"].is_null())));
+            pages += 1;
+            match next {
+                Some(cursor) => after = Some(cursor),
+                None => return (items, pages),
+            }
+        }
+    }
+
+    /// 元数据按版本号分页，软删除和硬删除都以墓碑返回，增量读取只返回之后的变化
+    #[test]
+    fn metadata_pages_include_tombstones() {
+        let conn = mem_db();
+        for uuid in ["n1", "n2", "n3", "n4", "n5"] {
+            conn.execute("INSERT INTO notes (uuid, title) VALUES (?1, 't')", params![uuid]).unwrap();
+        }
+        let (items, pages) = metadata_pages(&conn, 0, 2);
+        assert_eq!(items.iter().map(|(pk, _)| pk.as_str()).collect::<Vec<_>>(), vec!["n1", "n2", "n3", "n4", "n5"]);
+        assert_eq!(pages, 3);
+
+        let since = crate::sync_sequence::current(&conn);
+        conn.execute("UPDATE notes SET deleted_at = ?1 WHERE uuid = 'n2'", params![now_iso()]).unwrap();
+        conn.execute("DELETE FROM notes WHERE uuid = 'n4'", []).unwrap();
+        conn.execute("UPDATE notes SET content = 'c' WHERE uuid = 'n5'", []).unwrap();
+
+        let (items, _) = metadata_pages(&conn, since, 2);
+        assert_eq!(
+            items,
+            vec![("n2".to_string(), true), ("n4".to_string(), true), ("n5".to_string(), false)]
+        );
+        // 全量元数据不含已删除的记录
+        let all = load_table_metadata(&conn, "notes", None).unwrap();
+        assert_eq!(all.len(), 3);
+        assert!(all.iter().all(|m| m["deleted_at"].is_null()));
+    }
+
+    fn schema_change(uuid: &str, hlc: &Hlc) -> SyncChange {
+        serde_json::from_value(serde_json::json!({
+            "table": "workflow_schemas", "op": "upsert",
+            "data": {"uuid": uuid, "name": uuid, "description": null, "fields": "[]"},
+            "version": 0, "updated_at": now_iso(), "deleted_at": null, "hlc": hlc.encode()
+        }))
+        .unwrap()
+    }
+
+    fn note_change(content: &str, hlc: &Hlc, base_version: Option<i64>) -> SyncChange {
+        serde_json::from_value(serde_json::json!({
+            "table": "notes", "op": "upsert",
+            "data": {"uuid": "n1", "title": "标题", "content": content, "tags": []},
+            "version": 0, "updated_at": now_iso(), "deleted_at": null, "hlc": hlc.encode(),
+            "base_version": base_version
+        }))
+        .unwrap()
+    }
+
+    /// 三方合并用本机时钟写入，被覆盖的内容仍记为同步版本
+    #[test]
+    fn merged_write_is_labelled_sync() {
+        let conn = mem_db();
+        let clock = HybridClock::load(&conn, &["notes"]);
+        let peer = |offset: i64| Hlc { physical: chrono::Utc::now().timestamp_millis() + offset, counter: 0, device_id: "peer".to_string() };
+
+        let outcome = apply_table_change(&conn, "notes", &note_change("a\nb\nc\n", &peer(0), None), &clock).unwrap();
+        assert_eq!(outcome, ApplyOutcome::Applied);
+        let base: i64 = conn.query_row("SELECT version FROM notes WHERE uuid = 'n1'", [], |row| row.get(0)).unwrap();
+
+        conn.execute("UPDATE notes SET content = 'A\nb\nc\n', hlc = ?1 WHERE uuid = 'n1'", params![clock.now().encode()])
+            .unwrap();
+        let outcome = apply_table_change(&conn, "notes", &note_change("a\nb\nC\n", &peer(1), Some(base)), &clock).unwrap();
+        assert_eq!(outcome, ApplyOutcome::Merged { conflicted: false });
+
+        let revisions = note_revisions::list(&conn, "n1").unwrap();
+        let sources: Vec<&str> = revisions.iter().map(|r| r.source.as_str()).collect();
+        assert_eq!(sources, vec![note_revisions::SOURCE_SYNC, note_revisions::SOURCE_EDIT]);
+        let content: String = conn.query_row("SELECT content FROM notes WHERE uuid = 'n1'", [], |row| row.get(0)).unwrap();
+        assert_eq!(content, "A\nb\nC\n");
+    }
+
+    /// 逐条回报结果：被拒绝的变更不影响同批其他变更，回报的版本号即写入后的版本号
+    #[test]
+    fn push_reports_each_change() {
+        let mut conn = mem_db();
+        let clock = HybridClock::new("server".to_string());
+        let now = Hlc { physical: chrono::Utc::now().timestamp_millis(), counter: 0, device_id: "peer".to_string() };
+        let stale = Hlc { physical: now.physical - 60_000, ..now.clone() };
+        let mut unknown = schema_change("x1", &now);
+        unknown.table = "no_such_table".to_string();
+        let changes = vec![
+            schema_change("ws1", &now),
+            unknown,
+            schema_change("ws1", &stale),
+            note_change("正文", &now, None),
+        ];
+
+        let report = apply_changes(&mut conn, &changes, Some("workflow_schemas"), &clock, false).unwrap();
+        assert!(report.committed);
+        let statuses: Vec<ChangeStatus> = report.outcomes.iter().map(|o| o.status).collect();
+        assert_eq!(statuses, vec![ChangeStatus::Applied, ChangeStatus::Skipped, ChangeStatus::Skipped, ChangeStatus::Skipped]);
+        assert_eq!(report.outcomes[2].reason.as_deref(), Some("server copy is newer"));
+        assert_eq!(report.outcomes[3].reason.as_deref(), Some("not in requested table workflow_schemas"));
+        assert_eq!(report.applied_count(), 1);
+        let version: i64 = conn.query_row("SELECT version FROM workflow_schemas WHERE uuid = 'ws1'", [], |row| row.get(0)).unwrap();
+        assert_eq!(report.outcomes[0].version, Some(version));
+
+        let report = apply_changes(&mut conn, &changes[3..], None, &clock, false).unwrap();
+        assert_eq!(report.outcomes[0].status, ChangeStatus::Applied);
+        let mut missing_pk = note_change("正文", &now, None);
+        missing_pk.data = serde_json::json!({ "title": "无主键" });
+        let report = apply_changes(&mut conn, &[missing_pk], None, &clock, false).unwrap();
+        assert!(report.committed);
+        assert_eq!(report.outcomes[0].status, ChangeStatus::Rejected);
+        assert!(report.outcomes[0].pk.is_none());
+    }
+
+    /// 原子模式下任意一条被拒绝，整批回滚，已写入的变更回报为 aborted
+    #[test]
+    fn atomic_push_rolls_back_on_rejection() {
+        let mut conn = mem_db();
+        let clock = HybridClock::new("server".to_string());
+        let now = Hlc { physical: chrono::Utc::now().timestamp_millis(), counter: 0, device_id: "peer".to_string() };
+        let mut unknown = schema_change("x1", &now);
+        unknown.table = "no_such_table".to_string();
+        let changes = vec![schema_change("ws1", &now), unknown];
+
+        let report = apply_changes(&mut conn, &changes, None, &clock, true).unwrap();
+        assert!(!report.committed);
+        assert_eq!(report.outcomes[0].status, ChangeStatus::Aborted);
+        assert_eq!(report.outcomes[0].version, None);
+        assert_eq!(report.outcomes[1].status, ChangeStatus::Rejected);
+        assert_eq!(report.applied_count(), 0);
+        let rows: i64 = conn.query_row("SELECT COUNT(*) FROM workflow_schemas", [], |row| row.get(0)).unwrap();
+        assert_eq!(rows, 0);
+        let logged: i64 = conn.query_row("SELECT COUNT(*) FROM sync_changelog", [], |row| row.get(0)).unwrap();
+        assert_eq!(logged, 0);
+
+        let report = apply_changes(&mut conn, &changes[..1], None, &clock, true).unwrap();
+        assert!(report.committed);
+        assert_eq!(report.outcomes[0].status, ChangeStatus::Applied);
+        assert!(report.outcomes[0].version.is_some());
+    }
+
+    /// 预演不写入、不回报版本号，也不推进本机时钟
+    #[test]
+    fn dry_run_leaves_clock_and_rows_untouched() {
+        let mut conn = mem_db();
+        let clock = HybridClock::new("server".to_string());
+        let ahead = Hlc { physical: chrono::Utc::now().timestamp_millis() + 60_000, counter: 0, device_id: "peer".to_string() };
+        let changes = vec![
+            schema_change("ws1", &ahead),
+            serde_json::from_value(serde_json::json!({
+                "table": "workflow_schemas", "op": "upsert", "data": {},
+                "version": 0, "updated_at": now_iso(), "deleted_at": null
+            }))
+            .unwrap(),
+        ];
+
+        for atomic in [false, true] {
+            let report = preview_changes(&mut conn, &changes, None, &clock, atomic).unwrap();
+            assert!(!report.committed);
+            assert!(report.outcomes.iter().all(|o| o.version.is_none() && o.conflict_id.is_none()));
+            let expected = if atomic { ChangeStatus::Aborted } else { ChangeStatus::Applied };
+            assert_eq!(report.outcomes[0].status, expected);
+        }
+        assert!(clock.now() < ahead);
+        let rows: i64 = conn.query_row("SELECT COUNT(*) FROM workflow_schemas", [], |row| row.get(0)).unwrap();
+        assert_eq!(rows, 0);
+    }
+}
@@ -0,0 +1,240 @@
+//! 墓碑回收
+//! 软删除记录和变更日志中的硬删除超过保留期后被物理清除，
+//! 同时记录低水位版本号：since_version 低于它的客户端可能错过了删除，需要全量对账。
+//! 每次回收还会把每条记录的变更日志压缩为至多两条，避免日志随编辑次数无限增长
+
+use crate::hlc::parse_timestamp_ms;
+use crate::sync_changelog;
+use crate::sync_engine;
+use crate::sync_registry;
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+
+/// 默认墓碑保留天数（可通过 sync_meta 的 tombstone_retention_days 覆盖）
+pub const DEFAULT_RETENTION_DAYS: i64 = 90;
+
+const RETENTION_DAYS_KEY: &str = "tombstone_retention_days";
+const LOW_WATER_MARK_KEY: &str = "tombstone_low_water_mark";
+
+/// 一次回收的结果
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GcReport {
+    /// 清除的软删除记录数
+    pub purged_rows: usize,
+    /// 清除的变更日志条数（硬删除墓碑 + 已被取代的旧日志）
+    pub purged_log_entries: usize,
+    /// 回收后的低水位版本号
+    pub low_water_mark: i64,
+}
+
+/// 当前低水位版本号：已清除的墓碑中最大的版本号，0 表示从未回收
+pub fn low_water_mark(conn: &Connection) -> i64 {
+    read_meta(conn, LOW_WATER_MARK_KEY).unwrap_or(0)
+}
+
+/// 客户端的增量游标是否早于低水位（since_version 为 0 的全量拉取不受影响）
+pub fn requires_resync(conn: &Connection, since_version: i64) -> bool {
+    since_version > 0 && since_version < low_water_mark(conn)
+}
+
+/// 墓碑保留天数
+pub fn retention_days(conn: &Connection) -> i64 {
+    read_meta(conn, RETENTION_DAYS_KEY)
+        .filter(|d| *d > 0)
+        .unwrap_or(DEFAULT_RETENTION_DAYS)
+}
+
+/// 清除早于保留期的墓碑和已被取代的旧日志，并推进低水位
+pub fn purge_tombstones(conn: &mut Connection, retention_days: i64) -> rusqlite::Result<GcReport> {
+    let cutoff_ms = Utc::now().timestamp_millis() - retention_days * 24 * 60 * 60 * 1000;
+    let expired = |ts: Option<&str>| ts.and_then(parse_timestamp_ms).is_some_and(|ms| ms < cutoff_ms);
+
+    let tx = conn.transaction()?;
+    let mut report = GcReport::default();
+    let mut max_purged = 0i64;
+
+    for config in sync_registry::registry().tables() {
+        // 1. 过期的软删除记录（只处理已分配版本号、已对外发布的记录）
+        let mut stmt = tx.prepare(&format!(
+            "SELECT {}, deleted_at, version FROM {} WHERE deleted_at IS NOT NULL AND version > 0",
+            config.primary_key, config.name
+        ))?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?, row.get::<_, i64>(2)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        drop(stmt);
+
+        for (pk, deleted_at, version) in rows {
+            if !expired(deleted_at.as_deref()) {
+                continue;
+            }
+            tx.execute(&format!("DELETE FROM {} WHERE {} = ?1", config.name, config.primary_key), params![pk])?;
+            // 删除触发器刚写入的日志也一并清除：该记录的删除早已发布过
+            report.purged_log_entries += purge_record_log(&tx, config.name, &pk)?;
+            max_purged = max_purged.max(version);
+            report.purged_rows += 1;
+        }
+
+        // 2. 过期的硬删除墓碑（变更日志中最新一条为 delete）
+        let mut stmt = tx.prepare(
+            "SELECT pk, created_at, version FROM sync_changelog l
+             WHERE table_name = ?1 AND op = 'delete' AND version > 0
+               AND seq = (SELECT MAX(seq) FROM sync_changelog m WHERE m.table_name = l.table_name AND m.pk = l.pk)",
+        )?;
+        let tombstones = stmt
+            .query_map(params![config.name], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?, row.get::<_, i64>(2)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        drop(stmt);
+
+        for (pk, created_at, version) in tombstones {
+            if !expired(created_at.as_deref()) {
+                continue;
+            }
+            report.purged_log_entries += purge_record_log(&tx, config.name, &pk)?;
+            max_purged = max_purged.max(version);
+        }
+    }
+
+    // 3. 已被同一记录更新日志取代的旧日志（每次回收都清理，否则每次自动保存都会留下一条日志）
+    report.purged_log_entries += compact_log(&tx)?;
+
+    let low_water_mark = low_water_mark(&tx).max(max_purged);
+    if low_water_mark > 0 {
+        tx.execute(
+            "INSERT OR REPLACE INTO sync_meta (key, value, updated_at) VALUES (?1, ?2, ?3)",
+            params![LOW_WATER_MARK_KEY, low_water_mark.to_string(), sync_engine::now_iso()],
+        )?;
+    }
+
+    tx.commit()?;
+    report.low_water_mark = low_water_mark;
+    Ok(report)
+}
+
+/// 压缩变更日志：每条记录只保留最新一条（决定增量拉取是否返回该记录）和最近一次 insert
+/// （游标早于它的客户端需要整条记录）。其间被取代的 update 只贡献 changed_columns，先并入最新一条，
+/// 保证游标落在其间的客户端仍能拿到全部变化的字段；最新一条为删除时只保留删除
+fn compact_log(conn: &Connection) -> rusqlite::Result<usize> {
+    const SAME_ROW: &str = "m.table_name = sync_changelog.table_name AND m.pk = sync_changelog.pk";
+
+    conn.execute(
+        &format!(
+            "UPDATE sync_changelog SET changed_columns = (
+                 SELECT json_group_array(DISTINCT c.value)
+                 FROM sync_changelog m, json_each(m.changed_columns) c
+                 WHERE {SAME_ROW} AND m.op = ?1 AND m.seq <= sync_changelog.seq
+                   AND m.seq > COALESCE((SELECT MAX(i.seq) FROM sync_changelog i
+                                         WHERE i.table_name = m.table_name AND i.pk = m.pk AND i.op = ?2), 0)
+             )
+             WHERE op = ?1
+               AND seq = (SELECT MAX(seq) FROM sync_changelog m WHERE {SAME_ROW})
+               AND EXISTS (SELECT 1 FROM sync_changelog m WHERE {SAME_ROW} AND m.op = ?1 AND m.seq < sync_changelog.seq)"
+        ),
+        params![sync_changelog::OP_UPDATE, sync_changelog::OP_INSERT],
+    )?;
+    conn.execute(
+        &format!(
+            "DELETE FROM sync_changelog
+             WHERE seq < (SELECT MAX(seq) FROM sync_changelog m WHERE {SAME_ROW})
+               AND NOT (
+                   op = ?1
+                   AND seq = (SELECT MAX(seq) FROM sync_changelog m WHERE {SAME_ROW} AND m.op = ?1)
+                   AND (SELECT op FROM sync_changelog m WHERE {SAME_ROW} ORDER BY seq DESC LIMIT 1) != ?2
+               )"
+        ),
+        params![sync_changelog::OP_INSERT, sync_changelog::OP_DELETE],
+    )
+}
+
+/// 清除一条记录的全部变更日志和合并基线
+fn purge_record_log(conn: &Connection, table_name: &str, pk: &str) -> rusqlite::Result<usize> {
+    conn.execute(
+        "DELETE FROM sync_merge_bases WHERE table_name = ?1 AND pk = ?2",
+        params![table_name, pk],
+    )?;
+    conn.execute(
+        "DELETE FROM sync_changelog WHERE table_name = ?1 AND pk = ?2",
+        params![table_name, pk],
+    )
+}
+
+fn read_meta(conn: &Connection, key: &str) -> Option<i64> {
+    conn.query_row("SELECT value FROM sync_meta WHERE key = ?1", params![key], |row| row.get::<_, String>(0))
+        .optional()
+        .ok()
+        .flatten()
+        .and_then(|v| v.trim().parse::<i64>().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::mem_db;
+
+    fn log_rows(conn: &Connection) -> i64 {
+        conn.query_row("SELECT COUNT(*) FROM sync_changelog WHERE table_name = 'notes'", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    /// 没有删除（低水位不动）时，反复更新后每条记录的日志仍不超过两条
+    #[test]
+    fn log_stays_bounded_without_deletes() {
+        let mut conn = mem_db();
+        conn.execute(
+            "INSERT INTO notes (uuid, title, content, tags, updated_at) VALUES ('n1', '标题', '0', '[]', '2024-01-01T00:00:00Z')",
+            [],
+        )
+        .unwrap();
+        let first: i64 = conn.query_row("SELECT version FROM notes WHERE uuid = 'n1'", [], |row| row.get(0)).unwrap();
+        conn.execute("UPDATE notes SET title = '新标题' WHERE uuid = 'n1'", []).unwrap();
+        let second: i64 = conn.query_row("SELECT version FROM notes WHERE uuid = 'n1'", [], |row| row.get(0)).unwrap();
+
+        for round in 0..3 {
+            for i in 0..20 {
+                conn.execute("UPDATE notes SET content = ?1 WHERE uuid = 'n1'", params![format!("{}-{}", round, i)])
+                    .unwrap();
+            }
+            let report = purge_tombstones(&mut conn, DEFAULT_RETENTION_DAYS).unwrap();
+            assert_eq!(report.low_water_mark, 0);
+            // insert + 最新一条 update
+            assert_eq!(log_rows(&conn), 2);
+        }
+
+        // 被压缩掉的日志的字段仍计入 changed_columns（游标落在其间的客户端拿到并集）；
+        // 游标早于 insert 的客户端拿到整条记录
+        for since in [first, second] {
+            let mut columns = sync_changelog::changed_columns_since(&conn, "notes", "n1", since).unwrap().unwrap();
+            columns.sort();
+            assert_eq!(columns, vec!["content".to_string(), "title".to_string()]);
+        }
+        assert_eq!(sync_changelog::changed_columns_since(&conn, "notes", "n1", first - 1).unwrap(), None);
+    }
+
+    /// 最新一条为删除时只保留删除
+    #[test]
+    fn deleted_rows_keep_only_the_tombstone() {
+        let mut conn = mem_db();
+        conn.execute(
+            "INSERT INTO notes (uuid, title, content, tags, updated_at) VALUES ('n1', '标题', '0', '[]', '2024-01-01T00:00:00Z')",
+            [],
+        )
+        .unwrap();
+        conn.execute("UPDATE notes SET content = '1' WHERE uuid = 'n1'", []).unwrap();
+        conn.execute("DELETE FROM notes WHERE uuid = 'n1'", []).unwrap();
+        purge_tombstones(&mut conn, DEFAULT_RETENTION_DAYS).unwrap();
+
+        let ops: Vec<String> = conn
+            .prepare("SELECT op FROM sync_changelog WHERE table_name = 'notes'")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(ops, vec![sync_changelog::OP_DELETE.to_string()]);
+    }
+}
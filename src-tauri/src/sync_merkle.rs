@@ -0,0 +1,352 @@
+//! 范围哈希对账（Merkle 树）
+//! 替代 /metadata 的全量元数据：两端按同样的规则把一张表的未删除记录分桶并计算桶哈希，
+//! 客户端只向下展开哈希不同的桶，差异很少时几轮请求即可定位，与表的大小基本无关。
+//!
+//! - 桶键：主键的 64 位哈希（16 位十六进制），任意主键（包括 settings 的 key）都均匀分布
+//! - 桶：桶键的十六进制前缀，每层 16 个子桶；根桶为空前缀
+//! - 叶子摘要：`主键 \n updated_at` 的 64 位哈希（与 /metadata 对比时以 updated_at 判断是否相同一致）
+//! - 桶哈希：桶内叶子摘要的异或，与记录顺序无关
+//!
+//! 记录数不超过 `LEAF_LIMIT` 的桶直接返回其中记录的元数据（与 /metadata 的条目相同），不再展开
+
+use crate::sync_scopes::SyncScope;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// 记录数不超过该值的桶直接返回记录
+pub const LEAF_LIMIT: usize = 64;
+
+/// 单次请求最多展开的桶数
+pub const MAX_PREFIXES: usize = 1024;
+
+/// 缓存的索引个数上限（表数 × 不同的同步范围），超出时整体清空
+const CACHE_LIMIT: usize = 64;
+
+/// 桶键长度（64 位哈希的十六进制位数），也是前缀的最大长度
+const KEY_LEN: usize = 16;
+
+const HEX: &[u8; 16] = b"0123456789abcdef";
+
+/// FNV-1a 64 位哈希，再经 MurmurHash3 的 fmix64 打散
+/// （FNV 对 `n1`、`n2` 这类短而相近的主键高位几乎不变，不打散会全部落进同一个桶）
+///
+/// 前端 utils/merkle.ts 中有相同的实现，两端必须一致（tests/fixtures/merkle-parity.json 固定了两端的结果）
+pub fn hash64(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+fn hex(value: u64) -> String {
+    format!("{:016x}", value)
+}
+
+/// 前缀只能由小写十六进制字符组成，且不超过桶键长度
+pub fn is_valid_prefix(prefix: &str) -> bool {
+    prefix.len() <= KEY_LEN && prefix.bytes().all(|b| HEX.contains(&b))
+}
+
+/// POST /merkle 请求体
+#[derive(Deserialize, Debug, Clone)]
+pub struct MerkleRequest {
+    pub table: String,
+    /// 要展开的桶（前缀），空串为根桶
+    pub prefixes: Vec<String>,
+}
+
+/// 子桶摘要（只返回非空的子桶）
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MerkleBucket {
+    pub prefix: String,
+    pub count: usize,
+    pub hash: String,
+}
+
+/// 一个桶的展开结果：记录较多时返回子桶，较少时直接返回记录
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MerkleNode {
+    pub prefix: String,
+    pub count: usize,
+    pub hash: String,
+    #[serde(default)]
+    pub children: Vec<MerkleBucket>,
+    #[serde(default)]
+    pub leaves: Option<Vec<Value>>,
+}
+
+struct Entry {
+    key: String,
+    digest: u64,
+    meta: Value,
+}
+
+/// 一张表的哈希索引（按桶键排序，任意前缀对应一段连续区间）
+pub struct MerkleIndex {
+    entries: Vec<Entry>,
+}
+
+impl MerkleIndex {
+    /// 由 `load_table_metadata` 的结果构建
+    pub fn build(metadata: Vec<Value>) -> MerkleIndex {
+        let mut entries: Vec<Entry> = metadata
+            .into_iter()
+            .filter_map(|meta| {
+                let uuid = meta["uuid"].as_str()?;
+                let updated_at = meta["updated_at"].as_str().unwrap_or("");
+                Some(Entry {
+                    key: hex(hash64(uuid.as_bytes())),
+                    digest: hash64(format!("{}\n{}", uuid, updated_at).as_bytes()),
+                    meta,
+                })
+            })
+            .collect();
+        entries.sort_by(|a, b| a.key.cmp(&b.key));
+        MerkleIndex { entries }
+    }
+
+    fn range(&self, prefix: &str) -> &[Entry] {
+        let start = self.entries.partition_point(|e| e.key.as_str() < prefix);
+        let len = self.entries[start..].partition_point(|e| e.key.starts_with(prefix));
+        &self.entries[start..start + len]
+    }
+
+    /// 桶内记录数与桶哈希
+    pub fn summary(&self, prefix: &str) -> (usize, String) {
+        let range = self.range(prefix);
+        (range.len(), hex(range.iter().fold(0, |acc, e| acc ^ e.digest)))
+    }
+
+    /// 桶内记录的元数据
+    pub fn rows(&self, prefix: &str) -> Vec<Value> {
+        self.range(prefix).iter().map(|e| e.meta.clone()).collect()
+    }
+
+    /// 展开一个桶
+    pub fn node(&self, prefix: &str) -> MerkleNode {
+        let (count, hash) = self.summary(prefix);
+        if count <= LEAF_LIMIT || prefix.len() >= KEY_LEN {
+            return MerkleNode {
+                prefix: prefix.to_string(),
+                count,
+                hash,
+                children: Vec::new(),
+                leaves: Some(self.rows(prefix)),
+            };
+        }
+        let children = HEX
+            .iter()
+            .map(|c| format!("{}{}", prefix, *c as char))
+            .filter_map(|child| {
+                let (count, hash) = self.summary(&child);
+                (count > 0).then_some(MerkleBucket { prefix: child, count, hash })
+            })
+            .collect();
+        MerkleNode {
+            prefix: prefix.to_string(),
+            count,
+            hash,
+            children,
+            leaves: None,
+        }
+    }
+}
+
+/// /merkle 的索引缓存：一次对账要请求多轮，每轮都重新加载整表元数据会使总开销随轮数成倍增长。
+/// 以表名和同步范围为键，全局版本号序列变化（有新的写入）后重建
+#[derive(Default)]
+pub struct MerkleCache {
+    entries: Mutex<HashMap<String, (i64, Arc<MerkleIndex>)>>,
+}
+
+impl MerkleCache {
+    /// 取出 `sequence` 时的索引，没有缓存或已过期时调用 `build` 重建
+    ///
+    /// `sequence` 应在加载元数据之前读取：期间有写入时索引比键新，下一次请求会再重建
+    pub fn get_or_build<E>(
+        &self,
+        table: &str,
+        scope: Option<&SyncScope>,
+        sequence: i64,
+        build: impl FnOnce() -> Result<MerkleIndex, E>,
+    ) -> Result<Arc<MerkleIndex>, E> {
+        let scope = scope.and_then(|s| serde_json::to_string(s).ok()).unwrap_or_default();
+        let key = format!("{}\n{}", table, scope);
+        if let Some((cached, index)) = self.lock().get(&key) {
+            if *cached == sequence {
+                return Ok(index.clone());
+            }
+        }
+
+        let index = Arc::new(build()?);
+        let mut entries = self.lock();
+        if entries.len() >= CACHE_LIMIT && !entries.contains_key(&key) {
+            entries.clear();
+        }
+        entries.insert(key, (sequence, index.clone()));
+        Ok(index)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, (i64, Arc<MerkleIndex>)>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// 客户端对账过程：`pending` 为下一轮要请求的桶，把对端返回的结果交给 `feed`，直到没有待请求的桶
+///
+/// 结束后 `remote` / `local_rows` 为两端哈希不同的桶中的记录，哈希相同的桶中的记录两端完全一致，可直接跳过
+pub struct Reconciler<'a> {
+    local: &'a MerkleIndex,
+    pending: Vec<String>,
+    /// 对端在差异桶中的记录
+    pub remote: Vec<Value>,
+    /// 本地在差异桶中的记录
+    pub local_rows: Vec<Value>,
+    /// 已完成的请求轮数
+    pub rounds: usize,
+}
+
+impl<'a> Reconciler<'a> {
+    pub fn new(local: &'a MerkleIndex) -> Reconciler<'a> {
+        Reconciler {
+            local,
+            pending: vec![String::new()],
+            remote: Vec::new(),
+            local_rows: Vec::new(),
+            rounds: 0,
+        }
+    }
+
+    /// 下一轮要展开的桶，为空表示对账完成
+    pub fn pending(&self) -> &[String] {
+        &self.pending
+    }
+
+    /// 处理一轮展开结果（`pending` 分多次请求时合并后一次传入）
+    pub fn feed(&mut self, nodes: Vec<MerkleNode>) {
+        self.rounds += 1;
+        let mut next = Vec::new();
+        for node in nodes {
+            let (count, hash) = self.local.summary(&node.prefix);
+            if count == node.count && hash == node.hash {
+                continue;
+            }
+            if let Some(leaves) = node.leaves {
+                self.remote.extend(leaves);
+                self.local_rows.extend(self.local.rows(&node.prefix));
+                continue;
+            }
+            for c in HEX.iter() {
+                let prefix = format!("{}{}", node.prefix, *c as char);
+                let (count, hash) = self.local.summary(&prefix);
+                match node.children.iter().find(|b| b.prefix == prefix) {
+                    // 对端没有这个桶：本地记录都是本地独有的，无需再请求
+                    None if count > 0 => self.local_rows.extend(self.local.rows(&prefix)),
+                    None => {}
+                    Some(bucket) if bucket.count == count && bucket.hash == hash => {}
+                    Some(_) => next.push(prefix),
+                }
+            }
+        }
+        self.pending = next;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 与前端 tests/merkle.test.ts 共用的固定数据，两端的哈希和分桶结果必须与之一致
+    const PARITY_FIXTURE: &str = include_str!("../../tests/fixtures/merkle-parity.json");
+
+    fn fixture() -> Value {
+        serde_json::from_str(PARITY_FIXTURE).unwrap()
+    }
+
+    fn fixture_index(fixture: &Value) -> MerkleIndex {
+        MerkleIndex::build(fixture["records"].as_array().unwrap().clone())
+    }
+
+    #[test]
+    fn matches_the_shared_parity_fixture() {
+        let fixture = fixture();
+        for (text, expected) in fixture["hashes"].as_object().unwrap() {
+            assert_eq!(hex(hash64(text.as_bytes())), expected.as_str().unwrap(), "hash64({:?})", text);
+        }
+        assert_eq!(LEAF_LIMIT as u64, fixture["leaf_limit"].as_u64().unwrap());
+
+        let root = fixture_index(&fixture).node("");
+        let expected = &fixture["root"];
+        assert_eq!(root.count as u64, expected["count"].as_u64().unwrap());
+        assert_eq!(root.hash, expected["hash"].as_str().unwrap());
+        assert!(root.leaves.is_none());
+        let children: Vec<MerkleBucket> = serde_json::from_value(expected["children"].clone()).unwrap();
+        assert_eq!(root.children, children);
+    }
+
+    #[test]
+    fn small_buckets_return_leaves() {
+        let fixture = fixture();
+        let index = fixture_index(&fixture);
+        let bucket = &index.node("").children[0];
+        let node = index.node(&bucket.prefix);
+        assert!(node.children.is_empty());
+        assert_eq!(node.leaves.map(|l| l.len()), Some(bucket.count));
+    }
+
+    #[test]
+    fn reconciler_finds_only_differing_records() {
+        let fixture = fixture();
+        let records = fixture["records"].as_array().unwrap().clone();
+        let remote = MerkleIndex::build(records.clone());
+
+        let mut changed = records;
+        changed[0]["updated_at"] = serde_json::json!("2025-01-01T00:00:00.000Z");
+        changed.push(serde_json::json!({"uuid": "local-only", "updated_at": ""}));
+        let local = MerkleIndex::build(changed);
+
+        let mut reconciler = Reconciler::new(&local);
+        while !reconciler.pending().is_empty() {
+            let nodes = reconciler.pending().iter().map(|p| remote.node(p)).collect();
+            reconciler.feed(nodes);
+        }
+        let uuids = |rows: &[Value]| -> Vec<String> {
+            let mut uuids: Vec<String> = rows.iter().map(|r| r["uuid"].as_str().unwrap().to_string()).collect();
+            uuids.sort();
+            uuids
+        };
+        assert!(uuids(&reconciler.local_rows).contains(&"n0".to_string()));
+        assert!(uuids(&reconciler.local_rows).contains(&"local-only".to_string()));
+        assert!(uuids(&reconciler.remote).contains(&"n0".to_string()));
+        assert!(reconciler.local_rows.len() < 20);
+    }
+
+    #[test]
+    fn cache_rebuilds_only_when_the_sequence_changes() {
+        let cache = MerkleCache::default();
+        let mut builds = 0;
+        let mut get = |sequence: i64, scope: Option<&SyncScope>| {
+            cache
+                .get_or_build("notes", scope, sequence, || {
+                    builds += 1;
+                    Ok::<_, ()>(MerkleIndex::build(Vec::new()))
+                })
+                .unwrap()
+        };
+        get(1, None);
+        get(1, None);
+        get(2, None);
+        let scoped = SyncScope { exclude_tags: vec!["私密".to_string()], ..Default::default() };
+        get(2, Some(&scoped));
+        get(2, None);
+        assert_eq!(builds, 3);
+    }
+}
@@ -0,0 +1,123 @@
+//! 同步预览（dry run）
+//! 在真正合并之前查看将会发生什么，例如重装后的手机第一次同步：
+//!
+//! - `/push?dry_run=true`：每条变更照常经过 `apply_table_change` 的判断，最后回滚整个事务，
+//!   返回每条变更将会得到的结果（见 `sync_engine::preview_changes`）
+//! - `/pull?dry_run=true`：按表统计将会拉取到的变更数与记录标题，不返回变更内容，也不更新设备的拉取进度
+//!
+//! 预览不写入数据库，也不记入同步会话
+
+use crate::sync_engine::{self, PullCursor, SyncOp};
+use crate::sync_gc;
+use crate::sync_scopes::SyncScope;
+use crate::sync_sequence;
+use crate::sync_vector::VersionVector;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// 每张表最多列出的记录数
+pub const PREVIEW_ITEMS_PER_TABLE: usize = 50;
+
+/// 每次从 SQLite 读取的变更数
+const PAGE_SIZE: usize = 500;
+
+/// 将会拉取到的一条记录
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PreviewItem {
+    pub pk: Option<String>,
+    /// 标题字段的值（表未配置 title_field 时为空）
+    pub title: Option<String>,
+    pub op: SyncOp,
+    pub deleted: bool,
+    pub updated_at: String,
+}
+
+/// 一张表将会拉取到的变更
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TablePreview {
+    pub table: String,
+    pub count: usize,
+    /// 其中的删除数
+    pub deletes: usize,
+    /// 按版本号顺序的前 `PREVIEW_ITEMS_PER_TABLE` 条
+    pub items: Vec<PreviewItem>,
+}
+
+/// /pull?dry_run=true 响应
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PullPreview {
+    pub tables: Vec<TablePreview>,
+    pub total: usize,
+    pub server_version: i64,
+    /// since_version 早于低水位：实际拉取时需要从 0 全量对账
+    pub resync_required: bool,
+    pub low_water_mark: i64,
+}
+
+/// 统计 since_version 之后将会拉取到的变更；`table` 只看一张表，`knowledge` 为客户端已见过的写入，
+/// `scope` 为请求方设备的同步范围（移出范围的记录计为删除）
+pub fn preview_pull(
+    conn: &Connection,
+    since_version: i64,
+    table: Option<&str>,
+    knowledge: Option<&VersionVector>,
+    scope: Option<&SyncScope>,
+) -> rusqlite::Result<PullPreview> {
+    let mut preview = PullPreview {
+        server_version: sync_sequence::current(conn),
+        low_water_mark: sync_gc::low_water_mark(conn),
+        ..Default::default()
+    };
+    if sync_gc::requires_resync(conn, since_version) {
+        preview.resync_required = true;
+        return Ok(preview);
+    }
+
+    // 与实际拉取一致：从 0 全量拉取时不按 knowledge 过滤
+    let knowledge = knowledge.filter(|_| since_version > 0);
+    let mut tables: BTreeMap<String, TablePreview> = BTreeMap::new();
+    let mut after: Option<PullCursor> = None;
+    loop {
+        let (changes, next) = sync_engine::load_all_changes(conn, since_version, after.as_ref(), PAGE_SIZE, scope)?;
+        for change in changes {
+            if table.is_some_and(|t| t != change.table) {
+                continue;
+            }
+            if let (Some(knowledge), Some(vv)) = (knowledge, &change.vv) {
+                if vv.seen_by(knowledge) {
+                    continue;
+                }
+            }
+            let config = sync_engine::get_table_config(&change.table);
+            let text = |field: Option<&str>| field.and_then(|f| change.data.get(f)).and_then(|v| v.as_str()).map(|s| s.to_string());
+            let deleted = change.deleted_at.is_some() || matches!(change.op, SyncOp::Delete | SyncOp::ScopeOut | SyncOp::Purge);
+
+            let entry = tables.entry(change.table.clone()).or_insert_with(|| TablePreview {
+                table: change.table.clone(),
+                ..Default::default()
+            });
+            entry.count += 1;
+            if deleted {
+                entry.deletes += 1;
+            }
+            if entry.items.len() < PREVIEW_ITEMS_PER_TABLE {
+                entry.items.push(PreviewItem {
+                    pk: text(config.map(|c| c.primary_key)),
+                    title: text(config.and_then(|c| c.title_field)),
+                    op: change.op.clone(),
+                    deleted,
+                    updated_at: change.updated_at.clone(),
+                });
+            }
+        }
+        match next {
+            Some(cursor) => after = Some(cursor),
+            None => break,
+        }
+    }
+
+    preview.total = tables.values().map(|t| t.count).sum();
+    preview.tables = tables.into_values().collect();
+    Ok(preview)
+}
@@ -0,0 +1,238 @@
+//! 同步协议版本与能力协商
+//! 同步接口挂在 `/v1/...` 下（不带前缀的旧路由保留给旧版本客户端）。
+//! 客户端先调用 `POST /v1/handshake` 交换协议版本、各同步表的字段列表和可选能力，
+//! 之后每个 /v1 请求都带上 `X-Sync-Protocol` 请求头。
+//!
+//! 双方版本不兼容或同步表结构冲突时返回类型化的拒绝（`Refusal`），而不是让对方写入无法解析的数据
+
+use crate::sync_engine;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// 当前协议版本，请求/响应格式有不兼容的变化时递增
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// 仍然接受的最低协议版本
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// 客户端声明自身协议版本的请求头
+pub const PROTOCOL_HEADER: &str = "x-sync-protocol";
+
+/// 本机支持的可选能力
+pub const CAPABILITIES: &[&str] = &[
+    // 请求/响应体 gzip、zstd 压缩
+    "compression",
+    // /pull/stream NDJSON 流式拉取
+    "stream_pull",
+    // /merkle 范围哈希对账
+    "merkle",
+    // /metadata 分页与墓碑
+    "metadata_pages",
+    // /assets 资源文件传输
+    "assets",
+    // 版本向量与 knowledge
+    "version_vectors",
+    // /crdt 成就数据合并
+    "crdt",
+    // 按设备的同步范围与 scope_out 变更
+    "scopes",
+];
+
+/// 一张同步表的结构
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TableFields {
+    pub name: String,
+    pub primary_key: String,
+    pub fields: Vec<String>,
+}
+
+/// POST /v1/handshake 请求体
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HandshakeRequest {
+    pub protocol_version: u32,
+    /// 客户端能接受的最低协议版本（缺省为 protocol_version）
+    #[serde(default)]
+    pub min_protocol_version: Option<u32>,
+    #[serde(default)]
+    pub app_version: Option<String>,
+    #[serde(default)]
+    pub tables: Vec<TableFields>,
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+}
+
+/// 两端字段不一致的同步表（可以同步，但对方不认识的字段会被忽略）
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TableWarning {
+    pub table: String,
+    /// 客户端发送、本机会忽略的字段
+    pub unknown_fields: Vec<String>,
+    /// 本机有、客户端不会发送的字段
+    pub missing_fields: Vec<String>,
+}
+
+/// 握手成功的响应
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HandshakeResponse {
+    /// 协商后双方使用的协议版本
+    pub protocol_version: u32,
+    pub server_protocol_version: u32,
+    pub min_protocol_version: u32,
+    /// 服务端软件版本
+    pub server_version: String,
+    pub tables: Vec<TableFields>,
+    /// 双方都支持的能力
+    pub capabilities: Vec<String>,
+    pub warnings: Vec<TableWarning>,
+}
+
+/// 拒绝原因
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RefusalCode {
+    /// 客户端协议版本低于本机接受的最低版本，需要升级客户端
+    ClientTooOld,
+    /// 客户端要求的最低协议版本高于本机，需要升级本机
+    ServerTooOld,
+    /// 缺少 X-Sync-Protocol 请求头或无法解析
+    MissingProtocol,
+    /// 同一张表两端的主键不同，无法安全合并
+    SchemaMismatch,
+}
+
+/// 类型化的拒绝，随 success = false 的响应返回
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Refusal {
+    pub code: RefusalCode,
+    pub message: String,
+    pub server_protocol_version: u32,
+    pub min_protocol_version: u32,
+}
+
+impl Refusal {
+    fn new(code: RefusalCode, message: String) -> Refusal {
+        Refusal {
+            code,
+            message,
+            server_protocol_version: PROTOCOL_VERSION,
+            min_protocol_version: MIN_PROTOCOL_VERSION,
+        }
+    }
+}
+
+/// 检查客户端的协议版本区间 `[client_min, client]`，返回双方使用的版本
+pub fn check_version(client: u32, client_min: u32) -> Result<u32, Refusal> {
+    if client < MIN_PROTOCOL_VERSION {
+        return Err(Refusal::new(
+            RefusalCode::ClientTooOld,
+            format!("客户端同步协议 v{} 过旧，请升级到支持 v{} 及以上的版本", client, MIN_PROTOCOL_VERSION),
+        ));
+    }
+    if client_min > PROTOCOL_VERSION {
+        return Err(Refusal::new(
+            RefusalCode::ServerTooOld,
+            format!("客户端要求同步协议 v{} 及以上，本机只支持到 v{}，请升级桌面端", client_min, PROTOCOL_VERSION),
+        ));
+    }
+    Ok(client.min(PROTOCOL_VERSION))
+}
+
+/// 解析 X-Sync-Protocol 请求头（只声明一个版本，视为客户端最低版本也是它）
+pub fn check_header(value: Option<&str>) -> Result<u32, Refusal> {
+    let version = value
+        .and_then(|v| v.trim().parse::<u32>().ok())
+        .ok_or_else(|| Refusal::new(RefusalCode::MissingProtocol, "缺少同步协议版本，请先调用 /v1/handshake".to_string()))?;
+    check_version(version, version.min(PROTOCOL_VERSION))
+}
+
+/// 不带前缀的旧路由：携带协议请求头时照常校验；不携带的旧客户端只能读取，
+/// 写入（/push 等）返回类型化的拒绝，避免按旧格式写入无法解析的数据
+pub fn check_legacy(value: Option<&str>, write: bool) -> Result<(), Refusal> {
+    if value.is_some() {
+        return check_header(value).map(|_| ());
+    }
+    if write {
+        return Err(Refusal::new(
+            RefusalCode::ClientTooOld,
+            format!("旧版客户端不能写入，请升级到支持同步协议 v{} 的版本", MIN_PROTOCOL_VERSION),
+        ));
+    }
+    Ok(())
+}
+
+/// 本机注册的同步表结构
+pub fn server_tables() -> Vec<TableFields> {
+    sync_engine::table_names()
+        .into_iter()
+        .filter_map(sync_engine::get_table_config)
+        .map(|config| TableFields {
+            name: config.name.to_string(),
+            primary_key: config.primary_key.to_string(),
+            fields: config.fields.iter().map(|f| f.to_string()).collect(),
+        })
+        .collect()
+}
+
+/// 处理握手：校验版本与表结构，协商能力
+pub fn negotiate(request: &HandshakeRequest) -> Result<HandshakeResponse, Refusal> {
+    let protocol_version = check_version(
+        request.protocol_version,
+        request.min_protocol_version.unwrap_or(request.protocol_version),
+    )?;
+
+    let tables = server_tables();
+    let mut warnings = Vec::new();
+    for theirs in &request.tables {
+        // 本机没有的表：客户端不会收到，推送时会被逐条拒绝
+        let Some(ours) = tables.iter().find(|t| t.name == theirs.name) else {
+            continue;
+        };
+        if ours.primary_key != theirs.primary_key {
+            return Err(Refusal::new(
+                RefusalCode::SchemaMismatch,
+                format!(
+                    "同步表 {} 的主键不一致（本机 {}，客户端 {}）",
+                    ours.name, ours.primary_key, theirs.primary_key
+                ),
+            ));
+        }
+        let our_fields: HashSet<&String> = ours.fields.iter().collect();
+        let their_fields: HashSet<&String> = theirs.fields.iter().collect();
+        let unknown_fields: Vec<String> = theirs.fields.iter().filter(|f| !our_fields.contains(f)).cloned().collect();
+        let missing_fields: Vec<String> = ours.fields.iter().filter(|f| !their_fields.contains(f)).cloned().collect();
+        if !unknown_fields.is_empty() || !missing_fields.is_empty() {
+            warnings.push(TableWarning { table: ours.name.clone(), unknown_fields, missing_fields });
+        }
+    }
+
+    // 客户端未声明能力时视为旧客户端，返回本机支持的全部能力供其参考
+    let capabilities = CAPABILITIES
+        .iter()
+        .filter(|c| request.capabilities.is_empty() || request.capabilities.iter().any(|r| r == *c))
+        .map(|c| c.to_string())
+        .collect();
+
+    Ok(HandshakeResponse {
+        protocol_version,
+        server_protocol_version: PROTOCOL_VERSION,
+        min_protocol_version: MIN_PROTOCOL_VERSION,
+        server_version: env!("CARGO_PKG_VERSION").to_string(),
+        tables,
+        capabilities,
+        warnings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_routes_refuse_writes_without_protocol() {
+        assert!(check_legacy(None, false).is_ok());
+        assert_eq!(check_legacy(None, true).unwrap_err().code, RefusalCode::ClientTooOld);
+        assert!(check_legacy(Some("1"), true).is_ok());
+        assert_eq!(check_legacy(Some("abc"), false).unwrap_err().code, RefusalCode::MissingProtocol);
+        assert_eq!(check_legacy(Some("0"), false).unwrap_err().code, RefusalCode::ClientTooOld);
+    }
+}
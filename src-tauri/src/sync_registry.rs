@@ -0,0 +1,373 @@
+//! 同步表注册表
+//! 启动时通过 `SyncRegistry::register` 注册可同步的表，并用 `PRAGMA table_info` 校验实际表结构，
+//! 取代手工维护的静态表列表；`/state` 会返回注册的表结构供前端核对
+
+use crate::sync_engine::TableConfig;
+use rusqlite::Connection;
+use serde::Serialize;
+use std::fmt;
+use std::sync::OnceLock;
+
+/// 每张同步表都必须具备的同步元数据列
+const REQUIRED_SYNC_COLUMNS: &[&str] = &["version", "updated_at", "deleted_at", "hlc"];
+
+/// 进程内唯一的注册表，`install` 之前访问时使用内置表
+static REGISTRY: OnceLock<SyncRegistry> = OnceLock::new();
+
+/// 注册或校验失败的原因
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SchemaError {
+    /// 同名表重复注册
+    DuplicateTable { table: String },
+    /// 配置自身不一致（如 json_fields 不在 fields 中）
+    InvalidConfig { table: String, detail: String },
+    /// 数据库中不存在该表
+    MissingTable { table: String },
+    /// 配置中的字段在表中不存在
+    MissingColumns { table: String, columns: Vec<String> },
+    /// 主键列不存在，或既不是 PRIMARY KEY 也没有单列 UNIQUE 约束（UPSERT 依赖它）
+    MissingPrimaryKey { table: String, column: String },
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaError::DuplicateTable { table } => write!(f, "table {} is registered twice", table),
+            SchemaError::InvalidConfig { table, detail } => write!(f, "invalid config for {}: {}", table, detail),
+            SchemaError::MissingTable { table } => write!(f, "table {} does not exist", table),
+            SchemaError::MissingColumns { table, columns } => {
+                write!(f, "table {} is missing columns: {}", table, columns.join(", "))
+            }
+            SchemaError::MissingPrimaryKey { table, column } => {
+                write!(f, "table {} has no PRIMARY KEY or UNIQUE constraint on {}", table, column)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+/// 表中的一列（来自 PRAGMA table_info）
+#[derive(Debug, Clone, Serialize)]
+pub struct ColumnInfo {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub column_type: String,
+}
+
+/// 一张注册表的结构及校验结果（/state 返回）
+#[derive(Debug, Clone, Serialize)]
+pub struct TableSchema {
+    pub name: &'static str,
+    pub primary_key: &'static str,
+    pub fields: &'static [&'static str],
+    pub json_fields: &'static [&'static str],
+    pub merge_fields: &'static [&'static str],
+    /// 数据库中的实际列
+    pub columns: Vec<ColumnInfo>,
+    pub errors: Vec<SchemaError>,
+}
+
+impl TableSchema {
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// 可同步表的注册表
+#[derive(Default)]
+pub struct SyncRegistry {
+    tables: Vec<TableConfig>,
+}
+
+impl SyncRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 注册一张表；拒绝重名以及字段引用不一致的配置
+    pub fn register(&mut self, config: TableConfig) -> Result<&mut Self, SchemaError> {
+        if self.get(config.name).is_some() {
+            return Err(SchemaError::DuplicateTable { table: config.name.to_string() });
+        }
+        check_config(&config)?;
+        self.tables.push(config);
+        Ok(self)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&TableConfig> {
+        self.tables.iter().find(|t| t.name == name)
+    }
+
+    pub fn tables(&self) -> &[TableConfig] {
+        &self.tables
+    }
+
+    /// 用 PRAGMA table_info 校验所有注册表，返回每张表的结构与错误
+    pub fn describe(&self, conn: &Connection) -> Vec<TableSchema> {
+        self.tables.iter().map(|config| describe_table(conn, config)).collect()
+    }
+
+    /// 校验所有注册表，任意一张表不匹配时返回全部错误
+    pub fn validate(&self, conn: &Connection) -> Result<Vec<TableSchema>, Vec<SchemaError>> {
+        let schema = self.describe(conn);
+        let errors: Vec<SchemaError> = schema.iter().flat_map(|t| t.errors.iter().cloned()).collect();
+        if errors.is_empty() {
+            Ok(schema)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// 设为进程内的注册表；只能设置一次，重复设置返回 false
+    pub fn install(self) -> bool {
+        REGISTRY.set(self).is_ok()
+    }
+
+    /// 内置的可同步表
+    pub fn with_builtin_tables() -> Self {
+        let mut registry = Self::new();
+        for config in builtin_tables() {
+            if let Err(e) = registry.register(config) {
+                log::error!("[SyncRegistry] 内置表配置错误: {}", e);
+            }
+        }
+        registry
+    }
+}
+
+/// 当前生效的注册表
+pub fn registry() -> &'static SyncRegistry {
+    REGISTRY.get_or_init(SyncRegistry::with_builtin_tables)
+}
+
+/// 校验配置内部的字段引用
+fn check_config(config: &TableConfig) -> Result<(), SchemaError> {
+    let invalid = |detail: String| SchemaError::InvalidConfig { table: config.name.to_string(), detail };
+
+    if !config.fields.contains(&config.primary_key) {
+        return Err(invalid(format!("primary key {} is not in fields", config.primary_key)));
+    }
+    for column in REQUIRED_SYNC_COLUMNS {
+        if !config.fields.contains(column) {
+            return Err(invalid(format!("sync column {} is not in fields", column)));
+        }
+    }
+    for (kind, list) in [("json_fields", config.json_fields), ("merge_fields", config.merge_fields)] {
+        if let Some(f) = list.iter().find(|f| !config.fields.contains(f)) {
+            return Err(invalid(format!("{} entry {} is not in fields", kind, f)));
+        }
+    }
+    if let Some(f) = config.title_field.filter(|f| !config.fields.contains(f)) {
+        return Err(invalid(format!("title_field {} is not in fields", f)));
+    }
+    Ok(())
+}
+
+fn describe_table(conn: &Connection, config: &TableConfig) -> TableSchema {
+    let mut schema = TableSchema {
+        name: config.name,
+        primary_key: config.primary_key,
+        fields: config.fields,
+        json_fields: config.json_fields,
+        merge_fields: config.merge_fields,
+        columns: Vec::new(),
+        errors: Vec::new(),
+    };
+
+    let columns = match table_columns(conn, config.name) {
+        Ok(columns) => columns,
+        Err(e) => {
+            log::error!("[SyncRegistry] 读取 {} 表结构失败: {}", config.name, e);
+            Vec::new()
+        }
+    };
+    if columns.is_empty() {
+        schema.errors.push(SchemaError::MissingTable { table: config.name.to_string() });
+        return schema;
+    }
+
+    let missing: Vec<String> = config
+        .fields
+        .iter()
+        .filter(|f| !columns.iter().any(|(c, _)| c.name == **f))
+        .map(|f| f.to_string())
+        .collect();
+    let pk_missing = missing.iter().any(|f| f == config.primary_key);
+    if !missing.is_empty() {
+        schema.errors.push(SchemaError::MissingColumns { table: config.name.to_string(), columns: missing });
+    }
+
+    let pk_declared = columns.iter().any(|(c, pk)| c.name == config.primary_key && *pk);
+    if pk_missing || !(pk_declared || has_unique_index(conn, config.name, config.primary_key)) {
+        schema.errors.push(SchemaError::MissingPrimaryKey {
+            table: config.name.to_string(),
+            column: config.primary_key.to_string(),
+        });
+    }
+
+    schema.columns = columns.into_iter().map(|(c, _)| c).collect();
+    schema
+}
+
+/// 读取表的列及其是否为（单列）主键
+fn table_columns(conn: &Connection, table: &str) -> rusqlite::Result<Vec<(ColumnInfo, bool)>> {
+    let mut stmt = conn.prepare("SELECT name, type, pk FROM pragma_table_info(?1)")?;
+    let rows = stmt.query_map([table], |row| {
+        let pk: i64 = row.get(2)?;
+        Ok((ColumnInfo { name: row.get(0)?, column_type: row.get(1)? }, pk == 1))
+    })?;
+    rows.collect()
+}
+
+/// 是否存在只包含该列的 UNIQUE 索引
+fn has_unique_index(conn: &Connection, table: &str, column: &str) -> bool {
+    let query = "SELECT COUNT(*) FROM pragma_index_list(?1) AS il
+                 WHERE il.\"unique\" = 1
+                   AND (SELECT COUNT(*) FROM pragma_index_info(il.name)) = 1
+                   AND (SELECT name FROM pragma_index_info(il.name)) = ?2";
+    conn.query_row(query, [table, column], |row| row.get::<_, i64>(0))
+        .map(|n| n > 0)
+        .unwrap_or(false)
+}
+
+/// 内置的可同步表（与前端 sync-tables.ts 对应）
+fn builtin_tables() -> Vec<TableConfig> {
+    vec![
+        TableConfig {
+            name: "notes",
+            primary_key: "uuid",
+            fields: &["uuid", "title", "content", "tags", "created_at", "updated_at", "deleted_at", "version", "hlc"],
+            json_fields: &["tags"],
+            merge_fields: &["title", "content"],
+            title_field: Some("title"),
+        },
+        TableConfig {
+            name: "moments",
+            primary_key: "uuid",
+            fields: &["uuid", "content", "images", "tags", "created_at", "updated_at", "deleted_at", "version", "hlc"],
+            json_fields: &["images", "tags"],
+            merge_fields: &[],
+            title_field: None,
+        },
+        TableConfig {
+            name: "assets",
+            primary_key: "uuid",
+            fields: &["uuid", "url", "path", "filename", "size", "mime_type", "storage_type", "created_at", "updated_at", "deleted_at", "version", "hlc"],
+            json_fields: &[],
+            merge_fields: &[],
+            title_field: Some("filename"),
+        },
+        TableConfig {
+            name: "workflows",
+            primary_key: "uuid",
+            fields: &["uuid", "name", "description", "steps", "schema_id", "type", "created_at", "updated_at", "deleted_at", "version", "hlc"],
+            json_fields: &["steps"],
+            merge_fields: &[],
+            title_field: Some("name"),
+        },
+        TableConfig {
+            name: "workflow_schemas",
+            primary_key: "uuid",
+            fields: &["uuid", "name", "description", "fields", "created_at", "updated_at", "deleted_at", "version", "hlc"],
+            json_fields: &["fields"],
+            merge_fields: &[],
+            title_field: Some("name"),
+        },
+        // 只同步 synced 策略的键，见 sync_settings
+        TableConfig {
+            name: "settings",
+            primary_key: "key",
+            fields: &["key", "value", "category", "created_at", "updated_at", "deleted_at", "version", "hlc"],
+            json_fields: &[],
+            merge_fields: &[],
+            title_field: Some("key"),
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::mem_db;
+
+    const FIELDS: &[&str] = &["uuid", "body", "updated_at", "deleted_at", "version", "hlc"];
+
+    fn config(name: &'static str) -> TableConfig {
+        TableConfig {
+            name,
+            primary_key: "uuid",
+            fields: FIELDS,
+            json_fields: &[],
+            merge_fields: &[],
+            title_field: None,
+        }
+    }
+
+    fn create(conn: &Connection, name: &str, uuid_column: &str) {
+        conn.execute_batch(&format!(
+            "CREATE TABLE {name} ({uuid_column}, body TEXT, updated_at TEXT, deleted_at TEXT, version INTEGER, hlc TEXT)"
+        ))
+        .unwrap();
+    }
+
+    #[test]
+    fn builtin_tables_match_the_migrated_schema() {
+        let conn = mem_db();
+        let schema = SyncRegistry::with_builtin_tables().validate(&conn).expect("builtin tables are valid");
+        assert_eq!(schema.len(), SyncRegistry::with_builtin_tables().tables().len());
+        assert!(schema.iter().all(|t| t.is_valid() && !t.columns.is_empty()));
+    }
+
+    #[test]
+    fn register_rejects_duplicates_and_inconsistent_configs() {
+        let mut registry = SyncRegistry::new();
+        registry.register(config("items")).unwrap();
+        assert_eq!(
+            registry.register(config("items")).err(),
+            Some(SchemaError::DuplicateTable { table: "items".into() })
+        );
+
+        let invalid = |config: TableConfig| match SyncRegistry::new().register(config) {
+            Err(SchemaError::InvalidConfig { detail, .. }) => detail,
+            other => panic!("expected InvalidConfig, got {:?}", other.err()),
+        };
+        assert!(invalid(TableConfig { primary_key: "id", ..config("a") }).contains("primary key id"));
+        assert!(invalid(TableConfig { fields: &["uuid", "updated_at", "deleted_at", "version"], ..config("a") })
+            .contains("sync column hlc"));
+        assert!(invalid(TableConfig { json_fields: &["tags"], ..config("a") }).contains("json_fields entry tags"));
+        assert!(invalid(TableConfig { merge_fields: &["title"], ..config("a") }).contains("merge_fields entry title"));
+        assert!(invalid(TableConfig { title_field: Some("title"), ..config("a") }).contains("title_field title"));
+    }
+
+    #[test]
+    fn validate_reports_schema_mismatches() {
+        let conn = mem_db();
+        create(&conn, "keyed", "uuid TEXT PRIMARY KEY");
+        create(&conn, "unique_keyed", "uuid TEXT UNIQUE");
+        create(&conn, "unkeyed", "uuid TEXT");
+        conn.execute_batch("CREATE TABLE partial (uuid TEXT PRIMARY KEY, updated_at TEXT, version INTEGER)").unwrap();
+
+        let mut registry = SyncRegistry::new();
+        for name in ["keyed", "unique_keyed", "unkeyed", "partial", "absent"] {
+            registry.register(config(name)).unwrap();
+        }
+        let errors = registry.validate(&conn).unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                SchemaError::MissingPrimaryKey { table: "unkeyed".into(), column: "uuid".into() },
+                SchemaError::MissingColumns {
+                    table: "partial".into(),
+                    columns: vec!["body".into(), "deleted_at".into(), "hlc".into()],
+                },
+                SchemaError::MissingTable { table: "absent".into() },
+            ]
+        );
+
+        let schema = registry.describe(&conn);
+        assert!(schema[0].is_valid() && schema[1].is_valid());
+        assert_eq!(schema[0].columns.len(), FIELDS.len());
+    }
+}
@@ -0,0 +1,451 @@
+//! 设备同步范围
+//! 每台配对设备可以只同步一部分数据（例如手机不需要已归档的研究笔记）：按标签包含 / 排除、启用的同步表、
+//! 笔记的最长未修改天数。范围只决定下发给该设备的数据（/pull、/metadata、/merkle），设备推送的写入照常接受。
+//!
+//! 记录移出范围时，设备收到只带主键的 `scope_out` 变更（元数据中为 `scoped_out: true`）：
+//! 删除本地副本，但这不是用户删除，不会作为删除同步回来。移出范围的三种情况：
+//! - 记录有新的写入（例如打上了排除的标签）：`load_table_changes` 把它下发为 scope_out
+//! - 笔记随时间变旧：拉取 notes 时补发上次检查以来超过最长天数的笔记（`aged_out`）
+//! - 范围本身被修改：各表标记为待对账，设备下一次增量拉取收到 resync_required，从 0 重新拉取
+//!
+//! 停用的表整张不再下发，也不发送 scope_out
+
+use crate::hlc::{parse_timestamp_ms, Hlc};
+use crate::sync_engine::{self, SyncChange, SyncOp};
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+
+/// 一台设备的同步范围；各项为空表示不限制
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncScope {
+    /// 只同步带有其中任一标签的记录（对有 tags 字段的表生效）
+    #[serde(default)]
+    pub include_tags: Vec<String>,
+    /// 带有其中任一标签的记录不同步，优先于 include_tags
+    #[serde(default)]
+    pub exclude_tags: Vec<String>,
+    /// 启用的同步表，为空表示全部
+    #[serde(default)]
+    pub tables: Option<Vec<String>>,
+    /// 超过该天数未修改的笔记不同步
+    #[serde(default)]
+    pub max_note_age_days: Option<i64>,
+}
+
+impl SyncScope {
+    /// 不限制任何数据
+    pub fn is_unrestricted(&self) -> bool {
+        self.include_tags.is_empty()
+            && self.exclude_tags.is_empty()
+            && self.tables.is_none()
+            && self.max_note_age_days.is_none_or(|d| d <= 0)
+    }
+
+    pub fn allows_table(&self, table: &str) -> bool {
+        self.tables.as_ref().is_none_or(|tables| tables.iter().any(|t| t == table))
+    }
+
+    /// 记录是否在范围内；`data` 需要带上 tags（有该字段的表）与 updated_at
+    pub fn allows(&self, table: &str, data: &Value) -> bool {
+        if !self.allows_table(table) || !self.allows_tags(table, data) {
+            return false;
+        }
+        if table == "notes" {
+            if let Some(cutoff) = self.age_cutoff_ms(Utc::now().timestamp_millis()) {
+                let updated_at = data.get("updated_at").and_then(|v| v.as_str()).and_then(parse_timestamp_ms);
+                if updated_at.is_some_and(|ms| ms < cutoff) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    fn allows_tags(&self, table: &str, data: &Value) -> bool {
+        if !has_tags(table) {
+            return true;
+        }
+        let tags = tags_of(data.get("tags"));
+        if tags.iter().any(|t| self.exclude_tags.contains(t)) {
+            return false;
+        }
+        self.include_tags.is_empty() || tags.iter().any(|t| self.include_tags.contains(t))
+    }
+
+    /// 在 now_ms 时刻，updated_at 早于返回值的笔记超出范围
+    fn age_cutoff_ms(&self, now_ms: i64) -> Option<i64> {
+        self.max_note_age_days.filter(|d| *d > 0).map(|d| now_ms - d * DAY_MS)
+    }
+}
+
+fn has_tags(table: &str) -> bool {
+    sync_engine::get_table_config(table).is_some_and(|c| c.fields.contains(&"tags"))
+}
+
+/// 读取标签：同步数据中为 JSON 数组，数据库中为 JSON 字符串
+fn tags_of(value: Option<&Value>) -> Vec<String> {
+    let parsed;
+    let value = match value {
+        Some(Value::String(raw)) => {
+            parsed = serde_json::from_str::<Value>(raw).unwrap_or(Value::Null);
+            &parsed
+        }
+        Some(v) => v,
+        None => return Vec::new(),
+    };
+    value
+        .as_array()
+        .map(|tags| tags.iter().filter_map(|t| t.as_str()).map(|t| t.trim().to_string()).collect())
+        .unwrap_or_default()
+}
+
+/// 只带主键的 scope_out 变更
+pub fn scope_out(table: &str, pk: &str, version: i64, updated_at: &str, hlc: Option<&str>) -> SyncChange {
+    let primary_key = sync_engine::get_table_config(table).map(|c| c.primary_key).unwrap_or("uuid");
+    SyncChange {
+        table: table.to_string(),
+        op: SyncOp::ScopeOut,
+        data: serde_json::json!({ primary_key: pk }),
+        version,
+        updated_at: updated_at.to_string(),
+        deleted_at: None,
+        hlc: Some(Hlc::effective(hlc, updated_at).encode()),
+        base_version: None,
+        changed_columns: None,
+        vv: None,
+    }
+}
+
+/// scope_out 变更在 /metadata 中的形式
+pub fn scope_out_metadata(change: &SyncChange) -> Value {
+    let pk = sync_engine::get_table_config(&change.table)
+        .and_then(|c| change.data.get(c.primary_key))
+        .cloned()
+        .unwrap_or(Value::Null);
+    serde_json::json!({
+        "uuid": pk,
+        "version": change.version,
+        "updated_at": change.updated_at,
+        "deleted_at": null,
+        "hlc": change.hlc,
+        "scoped_out": true,
+    })
+}
+
+/// 读取设备的同步范围；未设置或不限制时返回 None
+pub fn load(conn: &Connection, device_id: &str) -> rusqlite::Result<Option<SyncScope>> {
+    let raw: Option<Option<String>> = conn
+        .query_row("SELECT scope FROM sync_devices WHERE device_id = ?1", params![device_id], |row| row.get(0))
+        .optional()?;
+    Ok(parse(raw.flatten().as_deref()))
+}
+
+/// 解析存储的范围 JSON；不限制的范围视为未设置
+pub fn parse(raw: Option<&str>) -> Option<SyncScope> {
+    raw.and_then(|s| serde_json::from_str::<SyncScope>(s).ok())
+        .filter(|scope| !scope.is_unrestricted())
+}
+
+/// 设置设备的同步范围（None 或不限制的范围表示同步全部数据）；设备不存在时返回 false
+///
+/// 所有表标记为待对账：设备下一次增量拉取会被要求从 0 重新拉取，以收到新进入范围的记录和移出范围的 scope_out
+pub fn save(conn: &Connection, device_id: &str, scope: Option<&SyncScope>) -> rusqlite::Result<bool> {
+    let scope = scope.filter(|s| !s.is_unrestricted());
+    if !device_exists(conn, device_id)? {
+        return Ok(false);
+    }
+    if load(conn, device_id)?.as_ref() == scope {
+        return Ok(true);
+    }
+    let raw = scope.map(|s| serde_json::to_string(s).unwrap_or_default());
+    let pending = serde_json::to_string(&sync_engine::table_names()).unwrap_or_else(|_| "[]".to_string());
+    let updated = conn.execute(
+        "UPDATE sync_devices SET scope = ?2, scope_pending = ?3, scope_checked_at = NULL WHERE device_id = ?1",
+        params![device_id, raw, pending],
+    )?;
+    if updated > 0 {
+        log::info!("[SyncScopes] 设备 {} 的同步范围已更新: {}", device_id, raw.as_deref().unwrap_or("全部"));
+    }
+    Ok(updated > 0)
+}
+
+fn device_exists(conn: &Connection, device_id: &str) -> rusqlite::Result<bool> {
+    conn.query_row("SELECT 1 FROM sync_devices WHERE device_id = ?1", params![device_id], |_| Ok(()))
+        .optional()
+        .map(|row| row.is_some())
+}
+
+/// 请求方设备的同步范围；设备未知或读取失败时不限制
+pub fn for_device(conn: &Connection, device_id: Option<&str>) -> Option<SyncScope> {
+    let device_id = device_id?;
+    match load(conn, device_id) {
+        Ok(scope) => scope,
+        Err(e) => {
+            log::warn!("[SyncScopes] 读取设备 {} 的同步范围失败: {}", device_id, e);
+            None
+        }
+    }
+}
+
+/// 设备开始一次拉取（第一页）：从 0 拉取时清除待对账标记；增量拉取笔记时返回本次变旧的笔记（scope_out）
+///
+/// `table` 为空表示跨表拉取
+pub fn begin_pull(
+    conn: &Connection,
+    device_id: &str,
+    scope: Option<&SyncScope>,
+    table: Option<&str>,
+    since_version: i64,
+) -> Vec<SyncChange> {
+    if since_version <= 0 {
+        if let Err(e) = mark_resynced(conn, device_id, table) {
+            log::warn!("[SyncScopes] 清除待对账标记失败: {}", e);
+        }
+    }
+    let Some(scope) = scope else {
+        return Vec::new();
+    };
+    if table.is_some_and(|t| t != "notes") || !scope.allows_table("notes") {
+        return Vec::new();
+    }
+    match aged_out(conn, device_id, scope) {
+        // 从 0 拉取时范围外的笔记已经以 scope_out 返回
+        Ok(changes) if since_version > 0 => changes,
+        Ok(_) => Vec::new(),
+        Err(e) => {
+            log::warn!("[SyncScopes] 检查变旧的笔记失败: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+fn pending_tables(conn: &Connection, device_id: &str) -> rusqlite::Result<Vec<String>> {
+    let raw: Option<Option<String>> = conn
+        .query_row("SELECT scope_pending FROM sync_devices WHERE device_id = ?1", params![device_id], |row| row.get(0))
+        .optional()?;
+    Ok(raw.flatten().and_then(|s| serde_json::from_str(&s).ok()).unwrap_or_default())
+}
+
+/// 范围修改后设备尚未重新拉取：增量拉取需要改为从 0 全量拉取（`table` 为空表示跨表拉取）
+pub fn requires_resync(conn: &Connection, device_id: &str, table: Option<&str>, since_version: i64) -> bool {
+    if since_version <= 0 {
+        return false;
+    }
+    match pending_tables(conn, device_id) {
+        Ok(pending) => match table {
+            Some(table) => pending.iter().any(|t| t == table),
+            None => !pending.is_empty(),
+        },
+        Err(e) => {
+            log::warn!("[SyncScopes] 读取待对账的表失败: {}", e);
+            false
+        }
+    }
+}
+
+/// 设备已开始从 0 重新拉取，清除待对账标记（`table` 为空表示全部表）
+pub fn mark_resynced(conn: &Connection, device_id: &str, table: Option<&str>) -> rusqlite::Result<()> {
+    let mut pending = pending_tables(conn, device_id)?;
+    if pending.is_empty() {
+        return Ok(());
+    }
+    pending.retain(|t| table.is_some_and(|table| table != t));
+    let raw = (!pending.is_empty()).then(|| serde_json::to_string(&pending).unwrap_or_default());
+    conn.execute(
+        "UPDATE sync_devices SET scope_pending = ?2 WHERE device_id = ?1",
+        params![device_id, raw],
+    )?;
+    Ok(())
+}
+
+/// 上次检查以来超过最长天数的笔记，以 scope_out 返回，并记下本次检查时间
+///
+/// 首次检查只记录时间（更早变旧的笔记由设置范围后的全量拉取处理）
+pub fn aged_out(conn: &Connection, device_id: &str, scope: &SyncScope) -> rusqlite::Result<Vec<SyncChange>> {
+    let now_ms = Utc::now().timestamp_millis();
+    let Some(cutoff) = scope.age_cutoff_ms(now_ms) else {
+        return Ok(Vec::new());
+    };
+    let checked_at: Option<Option<String>> = conn
+        .query_row("SELECT scope_checked_at FROM sync_devices WHERE device_id = ?1", params![device_id], |row| row.get(0))
+        .optional()?;
+    conn.execute(
+        "UPDATE sync_devices SET scope_checked_at = ?2 WHERE device_id = ?1",
+        params![device_id, sync_engine::now_iso()],
+    )?;
+    let Some(previous_cutoff) = checked_at
+        .flatten()
+        .as_deref()
+        .and_then(parse_timestamp_ms)
+        .and_then(|ms| scope.age_cutoff_ms(ms))
+    else {
+        return Ok(Vec::new());
+    };
+
+    let mut stmt = conn.prepare("SELECT uuid, version, updated_at, hlc, tags FROM notes WHERE deleted_at IS NULL")?;
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, Option<String>>(0)?,
+            row.get::<_, Option<i64>>(1)?.unwrap_or(0),
+            row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+            row.get::<_, Option<String>>(3)?,
+            row.get::<_, Option<String>>(4)?,
+        ))
+    })?;
+
+    let mut changes = Vec::new();
+    for row in rows {
+        let (uuid, version, updated_at, hlc, tags) = row?;
+        let Some(uuid) = uuid.filter(|u| !u.trim().is_empty()) else {
+            continue;
+        };
+        let Some(updated_ms) = parse_timestamp_ms(&updated_at) else {
+            continue;
+        };
+        // 只补发本次才变旧的笔记；因标签本来就不在范围内的，设备从未收到过
+        if updated_ms < previous_cutoff || updated_ms >= cutoff {
+            continue;
+        }
+        if !scope.allows_tags("notes", &serde_json::json!({ "tags": tags })) {
+            continue;
+        }
+        changes.push(scope_out("notes", &uuid, version, &updated_at, hlc.as_deref()));
+    }
+    Ok(changes)
+}
+
+/// 应用对端下发的 scope_out：删除本地副本及其变更日志，不留下删除墓碑（不会作为删除同步出去）
+pub fn drop_local(conn: &Connection, change: &SyncChange) -> rusqlite::Result<bool> {
+    let Some(config) = sync_engine::get_table_config(&change.table) else {
+        return Ok(false);
+    };
+    let Some(pk) = change.data.get(config.primary_key).and_then(|v| v.as_str()) else {
+        return Ok(false);
+    };
+    let deleted = conn.execute(
+        &format!("DELETE FROM {} WHERE {} = ?1", config.name, config.primary_key),
+        params![pk],
+    )?;
+    conn.execute(
+        "DELETE FROM sync_changelog WHERE table_name = ?1 AND pk = ?2",
+        params![config.name, pk],
+    )?;
+    conn.execute(
+        "DELETE FROM sync_merge_bases WHERE table_name = ?1 AND pk = ?2",
+        params![config.name, pk],
+    )?;
+    Ok(deleted > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::mem_db;
+    use serde_json::json;
+
+    fn days_ago(days: i64) -> String {
+        (Utc::now() - chrono::Duration::days(days)).to_rfc3339()
+    }
+
+    fn note(tags: &[&str], updated_at: &str) -> Value {
+        json!({ "uuid": "n1", "tags": tags, "updated_at": updated_at })
+    }
+
+    #[test]
+    fn tags_filter_records_and_exclusion_wins() {
+        let scope = SyncScope {
+            include_tags: vec!["work".into()],
+            exclude_tags: vec!["private".into()],
+            ..Default::default()
+        };
+        let now = days_ago(0);
+        assert!(scope.allows("notes", &note(&["work"], &now)));
+        assert!(!scope.allows("notes", &note(&["work", "private"], &now)));
+        assert!(!scope.allows("notes", &note(&["personal"], &now)));
+        assert!(!scope.allows("notes", &note(&[], &now)));
+        // 数据库中的 tags 是 JSON 字符串
+        assert!(scope.allows("moments", &json!({ "tags": "[\" work \"]", "updated_at": now })));
+        // 没有 tags 字段的表不受标签限制
+        assert!(scope.allows("assets", &json!({ "uuid": "a1", "updated_at": now })));
+
+        let exclude_only = SyncScope { exclude_tags: vec!["private".into()], ..Default::default() };
+        assert!(exclude_only.allows("notes", &note(&[], &now)));
+        assert!(!exclude_only.allows("notes", &note(&["private"], &now)));
+    }
+
+    #[test]
+    fn table_allow_list_limits_tables() {
+        let scope = SyncScope { tables: Some(vec!["notes".into()]), ..Default::default() };
+        assert!(scope.allows_table("notes"));
+        assert!(!scope.allows_table("moments"));
+        assert!(!scope.allows("moments", &json!({ "tags": [], "updated_at": days_ago(0) })));
+        assert!(SyncScope::default().allows_table("moments"));
+        assert!(!scope.is_unrestricted());
+    }
+
+    #[test]
+    fn notes_older_than_the_cut_off_are_out_of_scope() {
+        let scope = SyncScope { max_note_age_days: Some(7), ..Default::default() };
+        assert!(scope.allows("notes", &note(&[], &days_ago(1))));
+        assert!(!scope.allows("notes", &note(&[], &days_ago(10))));
+        // 只对笔记生效；无法解析的时间不按年龄排除
+        assert!(scope.allows("moments", &json!({ "tags": [], "updated_at": days_ago(10) })));
+        assert!(scope.allows("notes", &note(&[], "")));
+
+        let disabled = SyncScope { max_note_age_days: Some(0), ..Default::default() };
+        assert!(disabled.is_unrestricted());
+        assert!(disabled.allows("notes", &note(&[], &days_ago(1000))));
+    }
+
+    #[test]
+    fn saving_a_scope_requires_a_resync_that_scopes_records_out() {
+        let conn = mem_db();
+        conn.execute("INSERT INTO sync_devices (device_id) VALUES ('phone')", []).unwrap();
+        for (uuid, tags) in [("n1", "[\"work\"]"), ("n2", "[\"private\"]")] {
+            conn.execute(
+                "INSERT INTO notes (uuid, title, content, tags, updated_at) VALUES (?1, 't', 'c', ?2, ?3)",
+                params![uuid, tags, sync_engine::now_iso()],
+            )
+            .unwrap();
+        }
+        let pulled = crate::sync_sequence::current(&conn);
+
+        let scope = SyncScope { exclude_tags: vec!["private".into()], ..Default::default() };
+        assert!(!save(&conn, "tablet", Some(&scope)).unwrap());
+        assert!(save(&conn, "phone", Some(&scope)).unwrap());
+        assert_eq!(load(&conn, "phone").unwrap(), Some(scope.clone()));
+        assert!(requires_resync(&conn, "phone", Some("notes"), pulled));
+        assert!(requires_resync(&conn, "phone", None, pulled));
+        assert!(!requires_resync(&conn, "phone", Some("notes"), 0));
+
+        // 设备从 0 重新拉取 notes：只清除该表的标记，跨表拉取仍需对账
+        assert!(begin_pull(&conn, "phone", Some(&scope), Some("notes"), 0).is_empty());
+        assert!(!requires_resync(&conn, "phone", Some("notes"), pulled));
+        assert!(requires_resync(&conn, "phone", Some("moments"), pulled));
+        assert!(requires_resync(&conn, "phone", None, pulled));
+
+        let changes = sync_engine::load_table_changes(&conn, "notes", 0, None, 100, Some(&scope)).unwrap();
+        let op = |uuid: &str| {
+            changes.iter().find(|c| c.data.get("uuid").and_then(|v| v.as_str()) == Some(uuid)).map(|c| c.op.clone())
+        };
+        assert!(matches!(op("n1"), Some(SyncOp::Upsert)));
+        assert!(matches!(op("n2"), Some(SyncOp::ScopeOut)));
+        let scoped_out = changes.iter().find(|c| matches!(c.op, SyncOp::ScopeOut)).unwrap();
+        assert_eq!(scoped_out.data, json!({ "uuid": "n2" }));
+
+        begin_pull(&conn, "phone", Some(&scope), None, 0);
+        assert!(!requires_resync(&conn, "phone", None, pulled));
+
+        // 保存相同的范围不再要求对账
+        assert!(save(&conn, "phone", Some(&scope)).unwrap());
+        assert!(!requires_resync(&conn, "phone", None, pulled));
+        // 清除范围同样要求对账，以收回之前移出范围的记录
+        assert!(save(&conn, "phone", None).unwrap());
+        assert_eq!(load(&conn, "phone").unwrap(), None);
+        assert!(requires_resync(&conn, "phone", Some("notes"), pulled));
+    }
+}
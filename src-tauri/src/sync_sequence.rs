@@ -0,0 +1,97 @@
+//! 持久化版本号序列
+//! sync_sequence 表保存全局版本号，变更日志触发器在每次写入同步表时从这里分配版本号，
+//! 界面（tauri-plugin-sql）和同步写入共用同一个序列，重启后无需从 MAX(version) 重新推算
+
+use crate::sync_engine;
+use rusqlite::{params, Connection, OptionalExtension};
+
+/// 序列名
+const VERSION_SEQUENCE: &str = "version";
+
+/// 序列加一（触发器和 Rust 代码共用）
+pub const BUMP_SQL: &str = "UPDATE sync_sequence SET value = value + 1 WHERE name = 'version'";
+
+/// 序列当前值（用作触发器中的子查询）
+pub const CURRENT_SQL: &str = "(SELECT value FROM sync_sequence WHERE name = 'version')";
+
+/// 首次安装时用已有数据中的最大版本号初始化序列，之后不再重新推算
+pub fn seed(conn: &Connection) -> rusqlite::Result<()> {
+    let seeded = conn.execute(
+        "INSERT OR IGNORE INTO sync_sequence (name, value) VALUES (?1, ?2)",
+        params![VERSION_SEQUENCE, sync_engine::max_version_all_tables(conn)],
+    )?;
+    if seeded > 0 {
+        log::info!("[SyncSequence] 初始化版本号序列: {}", current(conn));
+    }
+    Ok(())
+}
+
+/// 当前版本号（已分配的最大版本号），序列未初始化时回退到扫描各表
+pub fn current(conn: &Connection) -> i64 {
+    conn.query_row(
+        "SELECT value FROM sync_sequence WHERE name = ?1",
+        params![VERSION_SEQUENCE],
+        |row| row.get::<_, i64>(0),
+    )
+    .optional()
+    .ok()
+    .flatten()
+    .unwrap_or_else(|| sync_engine::max_version_all_tables(conn))
+}
+
+/// 分配下一个版本号（用于不经过同步表触发器的写入，例如墓碑）
+pub fn next(conn: &Connection) -> rusqlite::Result<i64> {
+    conn.execute(BUMP_SQL, [])?;
+    conn.query_row(
+        "SELECT value FROM sync_sequence WHERE name = ?1",
+        params![VERSION_SEQUENCE],
+        |row| row.get(0),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync_changelog;
+    use crate::test_support::{mem_db, migrated_db};
+
+    fn note_version(conn: &Connection, uuid: &str) -> i64 {
+        conn.query_row("SELECT version FROM notes WHERE uuid = ?1", params![uuid], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn seeds_once_from_existing_versions() {
+        let conn = migrated_db();
+        conn.execute("INSERT INTO notes (uuid, title, version) VALUES ('n1', 't', 7)", []).unwrap();
+        conn.execute("INSERT INTO moments (uuid, content, version) VALUES ('m1', 'c', 12)", []).unwrap();
+        // 序列未初始化时回退到扫描各表
+        assert_eq!(current(&conn), 12);
+
+        seed(&conn).unwrap();
+        assert_eq!(current(&conn), 12);
+        conn.execute("UPDATE notes SET version = 100 WHERE uuid = 'n1'", []).unwrap();
+        seed(&conn).unwrap();
+        assert_eq!(current(&conn), 12);
+    }
+
+    #[test]
+    fn triggers_and_next_share_the_sequence() {
+        let mut conn = mem_db();
+        conn.execute("INSERT INTO notes (uuid, title) VALUES ('n1', 't')", []).unwrap();
+        let first = note_version(&conn, "n1");
+        assert_eq!(current(&conn), first);
+
+        let tombstone = next(&conn).unwrap();
+        assert_eq!(tombstone, first + 1);
+        conn.execute("INSERT INTO notes (uuid, title) VALUES ('n2', 't')", []).unwrap();
+        assert_eq!(note_version(&conn, "n2"), tombstone + 1);
+
+        // 重新安装（重启）不会从 MAX(version) 重新推算，已分配的版本号不会被复用
+        conn.execute("DELETE FROM notes WHERE uuid = 'n2'", []).unwrap();
+        let before = current(&conn);
+        sync_changelog::install(&mut conn).unwrap();
+        assert_eq!(current(&conn), before);
+        conn.execute("INSERT INTO notes (uuid, title) VALUES ('n3', 't')", []).unwrap();
+        assert!(note_version(&conn, "n3") > before);
+    }
+}
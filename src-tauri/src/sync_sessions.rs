@@ -0,0 +1,400 @@
+//! 同步会话日志
+//! 每次同步都在 sync_sessions 中留下一条会话记录：对端、各表的变更数、传输字节数、耗时与错误。
+//!
+//! - 服务端（本机被其他设备同步）：同一台设备连续的 /pull、/push 请求归入同一个会话，
+//!   与上一次请求间隔超过 `SESSION_IDLE_SECS` 时开始新的会话
+//! - 客户端（`sync_with_peer` 主动同步另一台桌面端）：每次同步记录为一个会话，对端为其地址
+//!
+//! pulled / pushed 均以发起同步的一方（客户端）的视角计数，与 /pull、/push 对应。
+//! 每次更新会话后通过 `sync:session` 事件把会话与本次请求的统计发给前端
+
+use crate::sync_engine::{self, ChangeOutcome, ChangeStatus};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// 同一设备两次请求的间隔超过该秒数时开始新的会话
+pub const SESSION_IDLE_SECS: i64 = 120;
+
+/// 最多保留的会话数，更早的会话在写入新会话时删除
+pub const MAX_SESSIONS: i64 = 1000;
+
+/// 本机在会话中的角色
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionRole {
+    /// 对端通过 HTTP 服务同步本机
+    Server,
+    /// 本机主动同步对端
+    Client,
+}
+
+impl SessionRole {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SessionRole::Server => "server",
+            SessionRole::Client => "client",
+        }
+    }
+
+    fn parse(value: &str) -> SessionRole {
+        match value {
+            "client" => SessionRole::Client,
+            _ => SessionRole::Server,
+        }
+    }
+}
+
+/// 一张表在会话中的变更数
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TableCounts {
+    pub pulled: usize,
+    pub pushed: usize,
+    /// 进入冲突队列的变更数
+    pub conflicts: usize,
+    /// 被拒绝的变更数
+    pub rejected: usize,
+}
+
+impl TableCounts {
+    fn add(&mut self, other: &TableCounts) {
+        self.pulled += other.pulled;
+        self.pushed += other.pushed;
+        self.conflicts += other.conflicts;
+        self.rejected += other.rejected;
+    }
+}
+
+/// 一次请求（或一次客户端同步）的统计，合并进所属的会话
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Exchange {
+    pub tables: BTreeMap<String, TableCounts>,
+    /// 本机收到的字节数
+    pub bytes_in: u64,
+    /// 本机发出的字节数
+    pub bytes_out: u64,
+    pub duration_ms: u64,
+    pub error: Option<String>,
+}
+
+impl Exchange {
+    /// 记录一条拉取的变更
+    pub fn add_pulled(&mut self, table: &str) {
+        self.tables.entry(table.to_string()).or_default().pulled += 1;
+    }
+
+    /// 按写入结果统计变更；`pulled` 为 true 时计入拉取，否则计入推送
+    pub fn add_outcomes(&mut self, outcomes: &[ChangeOutcome], pulled: bool) {
+        count_outcomes(&mut self.tables, outcomes, pulled);
+    }
+
+    /// 各表变更数之和
+    pub fn totals(&self) -> TableCounts {
+        sum(&self.tables)
+    }
+}
+
+/// 按表累计写入结果（会话日志与原生客户端的同步报告共用）
+pub fn count_outcomes(tables: &mut BTreeMap<String, TableCounts>, outcomes: &[ChangeOutcome], pulled: bool) {
+    for outcome in outcomes {
+        let counts = tables.entry(outcome.table.clone()).or_default();
+        match outcome.status {
+            ChangeStatus::Applied | ChangeStatus::Merged if pulled => counts.pulled += 1,
+            ChangeStatus::Applied | ChangeStatus::Merged => counts.pushed += 1,
+            ChangeStatus::Conflict => counts.conflicts += 1,
+            ChangeStatus::Rejected => counts.rejected += 1,
+            ChangeStatus::Skipped | ChangeStatus::Aborted => {}
+        }
+    }
+}
+
+fn sum(tables: &BTreeMap<String, TableCounts>) -> TableCounts {
+    let mut totals = TableCounts::default();
+    for counts in tables.values() {
+        totals.add(counts);
+    }
+    totals
+}
+
+/// 一条同步会话
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SyncSession {
+    pub id: i64,
+    pub role: SessionRole,
+    /// 服务端会话为对端设备 ID（不带设备标识的旧客户端为空），客户端会话为对端地址
+    pub peer_id: Option<String>,
+    /// 对端设备名（来自 sync_devices）
+    pub peer_name: Option<String>,
+    pub started_at: String,
+    /// 最近一次请求结束的时间
+    pub ended_at: String,
+    /// 会话内各请求的处理耗时之和
+    pub duration_ms: u64,
+    /// 会话内的请求数
+    pub requests: i64,
+    pub pulled: usize,
+    pub pushed: usize,
+    pub conflicts: usize,
+    pub rejected: usize,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub tables: BTreeMap<String, TableCounts>,
+    pub errors: Vec<String>,
+    /// ok | error
+    pub status: String,
+}
+
+/// sync:session 事件：更新后的会话与触发本次更新的请求
+#[derive(Serialize, Debug, Clone)]
+pub struct SessionEvent {
+    pub session: SyncSession,
+    pub exchange: Exchange,
+}
+
+/// 会话列表的一页
+#[derive(Serialize, Debug, Clone)]
+pub struct SessionPage {
+    pub items: Vec<SyncSession>,
+    /// 下一页的 before 参数，为空表示没有更早的会话
+    pub next_before: Option<i64>,
+}
+
+const SELECT_SESSION: &str = "SELECT s.id, s.role, s.peer_id, d.name, s.started_at, s.ended_at, s.duration_ms, s.requests, s.bytes_in, s.bytes_out, s.tables, s.errors FROM sync_sessions s LEFT JOIN sync_devices d ON d.device_id = s.peer_id";
+
+fn map_session(row: &rusqlite::Row) -> rusqlite::Result<SyncSession> {
+    let tables: String = row.get(10)?;
+    let errors: String = row.get(11)?;
+    let tables: BTreeMap<String, TableCounts> = serde_json::from_str(&tables).unwrap_or_default();
+    let errors: Vec<String> = serde_json::from_str(&errors).unwrap_or_default();
+    let totals = sum(&tables);
+
+    Ok(SyncSession {
+        id: row.get(0)?,
+        role: SessionRole::parse(&row.get::<_, String>(1)?),
+        peer_id: row.get(2)?,
+        peer_name: row.get(3)?,
+        started_at: row.get(4)?,
+        ended_at: row.get(5)?,
+        duration_ms: row.get::<_, i64>(6)?.max(0) as u64,
+        requests: row.get(7)?,
+        pulled: totals.pulled,
+        pushed: totals.pushed,
+        conflicts: totals.conflicts,
+        rejected: totals.rejected,
+        bytes_in: row.get::<_, i64>(8)?.max(0) as u64,
+        bytes_out: row.get::<_, i64>(9)?.max(0) as u64,
+        status: if errors.is_empty() { "ok" } else { "error" }.to_string(),
+        tables,
+        errors,
+    })
+}
+
+/// 把一次请求合并进对端最近的会话；间隔超过 `SESSION_IDLE_SECS` 时开始新的会话
+pub fn record(conn: &Connection, role: SessionRole, peer_id: Option<&str>, exchange: &Exchange) -> rusqlite::Result<SyncSession> {
+    let latest: Option<(i64, String)> = conn
+        .query_row(
+            "SELECT id, ended_at FROM sync_sessions WHERE role = ?1 AND peer_id IS ?2 ORDER BY id DESC LIMIT 1",
+            params![role.as_str(), peer_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+
+    let recent = latest.filter(|(_, ended_at)| {
+        DateTime::parse_from_rfc3339(ended_at)
+            .map(|t| (Utc::now() - t.with_timezone(&Utc)).num_seconds() <= SESSION_IDLE_SECS)
+            .unwrap_or(false)
+    });
+    match recent {
+        Some((id, _)) => merge(conn, id, exchange),
+        None => insert(conn, role, peer_id, exchange),
+    }
+}
+
+/// 新建一条会话
+pub fn insert(conn: &Connection, role: SessionRole, peer_id: Option<&str>, exchange: &Exchange) -> rusqlite::Result<SyncSession> {
+    let now = Utc::now();
+    let started_at = now - chrono::Duration::milliseconds(exchange.duration_ms as i64);
+    let errors: Vec<&String> = exchange.error.iter().collect();
+    conn.execute(
+        "INSERT INTO sync_sessions (role, peer_id, started_at, ended_at, duration_ms, requests, bytes_in, bytes_out, tables, errors)
+         VALUES (?1, ?2, ?3, ?4, ?5, 1, ?6, ?7, ?8, ?9)",
+        params![
+            role.as_str(),
+            peer_id,
+            started_at.to_rfc3339(),
+            now.to_rfc3339(),
+            exchange.duration_ms as i64,
+            exchange.bytes_in as i64,
+            exchange.bytes_out as i64,
+            serde_json::to_string(&exchange.tables).unwrap_or_else(|_| "{}".to_string()),
+            serde_json::to_string(&errors).unwrap_or_else(|_| "[]".to_string()),
+        ],
+    )?;
+    let id = conn.last_insert_rowid();
+    conn.execute("DELETE FROM sync_sessions WHERE id <= ?1", params![id - MAX_SESSIONS])?;
+    load(conn, id)
+}
+
+fn merge(conn: &Connection, id: i64, exchange: &Exchange) -> rusqlite::Result<SyncSession> {
+    let session = load(conn, id)?;
+    let mut tables = session.tables;
+    for (table, counts) in &exchange.tables {
+        tables.entry(table.clone()).or_default().add(counts);
+    }
+    let mut errors = session.errors;
+    errors.extend(exchange.error.iter().cloned());
+
+    conn.execute(
+        "UPDATE sync_sessions SET ended_at = ?1, duration_ms = duration_ms + ?2, requests = requests + 1,
+             bytes_in = bytes_in + ?3, bytes_out = bytes_out + ?4, tables = ?5, errors = ?6
+         WHERE id = ?7",
+        params![
+            sync_engine::now_iso(),
+            exchange.duration_ms as i64,
+            exchange.bytes_in as i64,
+            exchange.bytes_out as i64,
+            serde_json::to_string(&tables).unwrap_or_else(|_| "{}".to_string()),
+            serde_json::to_string(&errors).unwrap_or_else(|_| "[]".to_string()),
+            id,
+        ],
+    )?;
+    load(conn, id)
+}
+
+fn load(conn: &Connection, id: i64) -> rusqlite::Result<SyncSession> {
+    conn.query_row(&format!("{} WHERE s.id = ?1", SELECT_SESSION), params![id], map_session)
+}
+
+/// 查看单条会话
+pub fn get(conn: &Connection, id: i64) -> rusqlite::Result<Option<SyncSession>> {
+    load(conn, id).optional()
+}
+
+/// 按时间倒序分页列出会话；before 为上一页的 next_before，peer_id 只看某个对端
+pub fn list(conn: &Connection, before: Option<i64>, limit: usize, peer_id: Option<&str>) -> rusqlite::Result<SessionPage> {
+    let limit = limit.clamp(1, 200);
+    let mut stmt = conn.prepare(&format!(
+        "{} WHERE (?1 IS NULL OR s.id < ?1) AND (?2 IS NULL OR s.peer_id = ?2) ORDER BY s.id DESC LIMIT ?3",
+        SELECT_SESSION
+    ))?;
+    let mut items = stmt
+        .query_map(params![before, peer_id, limit as i64 + 1], map_session)?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let next_before = if items.len() > limit {
+        items.truncate(limit);
+        items.last().map(|s| s.id)
+    } else {
+        None
+    };
+    Ok(SessionPage { items, next_before })
+}
+
+/// JSON 序列化后的字节数（响应体压缩前的大小）
+pub fn json_size<T: Serialize>(value: &T) -> u64 {
+    serde_json::to_vec(value).map(|v| v.len() as u64).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::mem_db;
+
+    fn outcome(table: &str, status: ChangeStatus) -> ChangeOutcome {
+        ChangeOutcome {
+            index: 0,
+            table: table.to_string(),
+            pk: Some("n1".to_string()),
+            status,
+            reason: None,
+            version: None,
+            conflict_id: None,
+        }
+    }
+
+    fn exchange(pulled: usize, bytes_out: u64, error: Option<&str>) -> Exchange {
+        let mut exchange = Exchange { bytes_out, duration_ms: 5, error: error.map(str::to_string), ..Default::default() };
+        for _ in 0..pulled {
+            exchange.add_pulled("notes");
+        }
+        exchange
+    }
+
+    #[test]
+    fn outcomes_are_counted_per_table() {
+        let mut exchange = Exchange::default();
+        let outcomes = [
+            outcome("notes", ChangeStatus::Applied),
+            outcome("notes", ChangeStatus::Merged),
+            outcome("notes", ChangeStatus::Conflict),
+            outcome("moments", ChangeStatus::Rejected),
+            outcome("moments", ChangeStatus::Skipped),
+            outcome("moments", ChangeStatus::Aborted),
+        ];
+        exchange.add_outcomes(&outcomes, false);
+        exchange.add_outcomes(&outcomes[..1], true);
+
+        assert_eq!(exchange.tables["notes"], TableCounts { pulled: 1, pushed: 2, conflicts: 1, rejected: 0 });
+        assert_eq!(exchange.tables["moments"], TableCounts { rejected: 1, ..Default::default() });
+        assert_eq!(exchange.totals(), TableCounts { pulled: 1, pushed: 2, conflicts: 1, rejected: 1 });
+    }
+
+    #[test]
+    fn consecutive_requests_share_a_session() {
+        let conn = mem_db();
+        let first = record(&conn, SessionRole::Server, Some("phone"), &exchange(2, 100, None)).unwrap();
+        let second = record(&conn, SessionRole::Server, Some("phone"), &exchange(1, 50, Some("网络中断"))).unwrap();
+        assert_eq!(second.id, first.id);
+        assert_eq!((second.requests, second.pulled, second.bytes_out, second.duration_ms), (2, 3, 150, 10));
+        assert_eq!(second.errors, vec!["网络中断".to_string()]);
+        assert_eq!(second.status, "error");
+
+        // 其他对端、其他角色各自开始会话
+        let tablet = record(&conn, SessionRole::Server, Some("tablet"), &exchange(1, 0, None)).unwrap();
+        let client = record(&conn, SessionRole::Client, Some("phone"), &exchange(1, 0, None)).unwrap();
+        assert!(tablet.id != first.id && client.id != first.id);
+        assert_eq!(tablet.status, "ok");
+
+        // 空闲超过 SESSION_IDLE_SECS 后开始新的会话
+        let idle = (Utc::now() - chrono::Duration::seconds(SESSION_IDLE_SECS + 1)).to_rfc3339();
+        conn.execute("UPDATE sync_sessions SET ended_at = ?1 WHERE id = ?2", params![idle, first.id]).unwrap();
+        let third = record(&conn, SessionRole::Server, Some("phone"), &exchange(1, 0, None)).unwrap();
+        assert_ne!(third.id, first.id);
+        assert_eq!(get(&conn, first.id).unwrap().map(|s| s.requests), Some(2));
+    }
+
+    #[test]
+    fn list_pages_by_id_and_filters_by_peer() {
+        let conn = mem_db();
+        conn.execute("INSERT INTO sync_devices (device_id, name) VALUES ('phone', '我的手机')", []).unwrap();
+        for peer in ["phone", "tablet", "phone", "tablet", "phone"] {
+            insert(&conn, SessionRole::Server, Some(peer), &exchange(1, 0, None)).unwrap();
+        }
+
+        let first = list(&conn, None, 2, None).unwrap();
+        assert_eq!(first.items.iter().map(|s| s.id).collect::<Vec<_>>(), vec![5, 4]);
+        let second = list(&conn, first.next_before, 2, None).unwrap();
+        assert_eq!(second.items.iter().map(|s| s.id).collect::<Vec<_>>(), vec![3, 2]);
+        let last = list(&conn, second.next_before, 2, None).unwrap();
+        assert_eq!(last.items.len(), 1);
+        assert_eq!(last.next_before, None);
+
+        let phone = list(&conn, None, 10, Some("phone")).unwrap();
+        assert_eq!(phone.items.iter().map(|s| s.id).collect::<Vec<_>>(), vec![5, 3, 1]);
+        assert!(phone.items.iter().all(|s| s.peer_name.as_deref() == Some("我的手机")));
+        assert_eq!(get(&conn, 99).unwrap().map(|s| s.id), None);
+    }
+
+    #[test]
+    fn old_sessions_are_pruned() {
+        let conn = mem_db();
+        for _ in 0..MAX_SESSIONS + 5 {
+            insert(&conn, SessionRole::Client, None, &Exchange::default()).unwrap();
+        }
+        let (count, oldest): (i64, i64) = conn
+            .query_row("SELECT COUNT(*), MIN(id) FROM sync_sessions", [], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap();
+        assert_eq!((count, oldest), (MAX_SESSIONS, 6));
+    }
+}
@@ -0,0 +1,200 @@
+//! 设置同步策略
+//! settings 表按键名决定是否参与同步：synced 在各设备间同步，device-local 只属于本机，
+//! secret 为凭据（如 COS 密钥），无论如何都不会离开本机
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+
+/// 单个设置键的同步策略
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SettingPolicy {
+    Synced,
+    DeviceLocal,
+    Secret,
+}
+
+/// 键名包含这些片段时一律视为凭据（兜底，避免新增的凭据被误同步）
+const SECRET_MARKERS: &[&str] = &["secret", "token", "password", "credential", "api_key"];
+
+/// 按键名指定的策略，优先于分类
+const KEY_POLICIES: &[(&str, SettingPolicy)] = &[
+    ("secret_id", SettingPolicy::Secret),
+    ("secret_key", SettingPolicy::Secret),
+    ("default_storage_provider", SettingPolicy::Synced),
+    ("custom_css", SettingPolicy::Synced),
+    ("sidebar_open", SettingPolicy::DeviceLocal),
+    ("last_active_menu", SettingPolicy::DeviceLocal),
+    ("notes_active_tab", SettingPolicy::DeviceLocal),
+];
+
+/// 按 category 列的默认策略；未列出的分类（包括 general）默认只属于本机
+const CATEGORY_POLICIES: &[(&str, SettingPolicy)] = &[
+    ("cos", SettingPolicy::Synced),
+    ("editor", SettingPolicy::Synced),
+    ("sync", SettingPolicy::DeviceLocal),
+    ("ui", SettingPolicy::DeviceLocal),
+];
+
+/// 计算一个设置键的策略；category 未知时（例如只带主键的硬删除）只按键名判断
+pub fn policy(key: &str, category: Option<&str>) -> SettingPolicy {
+    let lower = key.to_ascii_lowercase();
+    if SECRET_MARKERS.iter().any(|m| lower.contains(m)) {
+        return SettingPolicy::Secret;
+    }
+    if let Some((_, p)) = KEY_POLICIES.iter().find(|(k, _)| *k == key) {
+        return *p;
+    }
+    category
+        .and_then(|c| CATEGORY_POLICIES.iter().find(|(name, _)| *name == c))
+        .map(|(_, p)| *p)
+        .unwrap_or(SettingPolicy::DeviceLocal)
+}
+
+/// 本机记录过的分类（setting_categories 由触发器维护，记录被硬删除后仍保留）；本机从未有过该键时返回 None
+fn stored_category(conn: &Connection, key: &str) -> Option<Option<String>> {
+    conn.query_row("SELECT category FROM setting_categories WHERE key = ?1", params![key], |row| row.get(0))
+        .optional()
+        .unwrap_or_else(|e| {
+            log::warn!("[SyncSettings] 读取 {} 的分类失败: {}", key, e);
+            None
+        })
+}
+
+/// 一条 settings 记录不参与同步时返回原因
+///
+/// 分类以本机记录为准：推送方携带的 category 不能把本机 device-local 的键改为 synced，
+/// 只带主键的硬删除也按原来的分类判断；本机从未有过该键时才使用记录自带的分类
+pub fn exclusion(conn: &Connection, data: &serde_json::Value) -> Option<String> {
+    let key = data.get("key").and_then(|v| v.as_str()).unwrap_or("");
+    let category = match stored_category(conn, key) {
+        Some(stored) => stored,
+        None => data.get("category").and_then(|v| v.as_str()).map(|s| s.to_string()),
+    };
+    match policy(key, category.as_deref()) {
+        SettingPolicy::Synced => None,
+        SettingPolicy::DeviceLocal => Some(format!("setting {} is device-local", key)),
+        SettingPolicy::Secret => Some(format!("setting {} is secret and never synced", key)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hlc::HybridClock;
+    use crate::sync_engine::{self, ApplyOutcome, SyncChange};
+    use crate::test_support::mem_db;
+
+    fn change(op: &str, data: serde_json::Value) -> SyncChange {
+        serde_json::from_value(serde_json::json!({
+            "table": "settings", "op": op, "data": data,
+            "version": 0, "updated_at": sync_engine::now_iso(), "deleted_at": null,
+            "hlc": HybridClock::new("peer".to_string()).now().encode()
+        }))
+        .unwrap()
+    }
+
+    fn insert(conn: &Connection, key: &str, value: &str, category: &str) {
+        conn.execute(
+            "INSERT INTO settings (key, value, category) VALUES (?1, ?2, ?3)",
+            params![key, value, category],
+        )
+        .unwrap();
+    }
+
+    fn value(conn: &Connection, key: &str) -> Option<String> {
+        conn.query_row("SELECT value FROM settings WHERE key = ?1", params![key], |row| row.get(0))
+            .optional()
+            .unwrap()
+    }
+
+    #[test]
+    fn pushed_category_cannot_override_local_policy() {
+        let conn = mem_db();
+        let clock = HybridClock::new("server".to_string());
+        insert(&conn, "sync_server_address", "http://10.0.0.2:8080", "sync");
+
+        let pushed = change("upsert", serde_json::json!({"key": "sync_server_address", "value": "http://evil", "category": "cos"}));
+        let outcome = sync_engine::apply_table_change(&conn, "settings", &pushed, &clock).unwrap();
+        assert!(matches!(outcome, ApplyOutcome::Rejected(_)));
+        assert_eq!(value(&conn, "sync_server_address").as_deref(), Some("http://10.0.0.2:8080"));
+    }
+
+    #[test]
+    fn deletes_use_the_stored_category() {
+        let conn = mem_db();
+        let clock = HybridClock::new("server".to_string());
+        insert(&conn, "bucket", "notes-1250000000", "cos");
+        insert(&conn, "sidebar_open", "true", "ui");
+
+        let synced = change("delete", serde_json::json!({"key": "bucket"}));
+        let outcome = sync_engine::apply_table_change(&conn, "settings", &synced, &clock).unwrap();
+        assert!(matches!(outcome, ApplyOutcome::Applied), "{:?}", outcome);
+
+        let local = change("delete", serde_json::json!({"key": "sidebar_open"}));
+        let outcome = sync_engine::apply_table_change(&conn, "settings", &local, &clock).unwrap();
+        assert!(matches!(outcome, ApplyOutcome::Rejected(_)));
+    }
+
+    #[test]
+    fn registry_survives_hard_delete() {
+        let conn = mem_db();
+        insert(&conn, "bucket", "notes-1250000000", "cos");
+        conn.execute("DELETE FROM settings WHERE key = 'bucket'", []).unwrap();
+
+        assert_eq!(exclusion(&conn, &serde_json::json!({"key": "bucket"})), None);
+        assert!(exclusion(&conn, &serde_json::json!({"key": "unknown_key"})).is_some());
+        assert_eq!(exclusion(&conn, &serde_json::json!({"key": "unknown_key", "category": "editor"})), None);
+    }
+
+    #[test]
+    fn key_policies_override_categories() {
+        assert_eq!(policy("cos_api_key", Some("cos")), SettingPolicy::Secret);
+        assert_eq!(policy("GitHub_Token", Some("editor")), SettingPolicy::Secret);
+        assert_eq!(policy("secret_id", None), SettingPolicy::Secret);
+        assert_eq!(policy("custom_css", Some("ui")), SettingPolicy::Synced);
+        assert_eq!(policy("sidebar_open", Some("editor")), SettingPolicy::DeviceLocal);
+        assert_eq!(policy("font_size", Some("editor")), SettingPolicy::Synced);
+        assert_eq!(policy("font_size", Some("general")), SettingPolicy::DeviceLocal);
+        assert_eq!(policy("font_size", None), SettingPolicy::DeviceLocal);
+    }
+
+    #[test]
+    fn triggers_track_inserts_category_changes_and_renames() {
+        let conn = mem_db();
+        insert(&conn, "font_size", "14", "editor");
+        assert_eq!(stored_category(&conn, "font_size"), Some(Some("editor".to_string())));
+
+        conn.execute("UPDATE settings SET category = 'ui' WHERE key = 'font_size'", []).unwrap();
+        assert_eq!(stored_category(&conn, "font_size"), Some(Some("ui".to_string())));
+
+        // 只改值不影响记录的分类
+        conn.execute("UPDATE settings SET value = '16' WHERE key = 'font_size'", []).unwrap();
+        assert_eq!(stored_category(&conn, "font_size"), Some(Some("ui".to_string())));
+
+        conn.execute("UPDATE settings SET key = 'editor_font_size' WHERE key = 'font_size'", []).unwrap();
+        assert_eq!(stored_category(&conn, "editor_font_size"), Some(Some("ui".to_string())));
+        assert_eq!(stored_category(&conn, "font_size"), Some(Some("ui".to_string())));
+        assert_eq!(stored_category(&conn, "never_set"), None);
+    }
+
+    #[test]
+    fn migration_backfills_existing_settings() {
+        let conn = Connection::open_in_memory().unwrap();
+        let migrations = crate::migrations();
+        for migration in migrations.iter().filter(|m| m.version < 19) {
+            conn.execute_batch(migration.sql).unwrap();
+        }
+        insert(&conn, "bucket", "notes-1250000000", "cos");
+        insert(&conn, "sidebar_open", "true", "ui");
+        for migration in migrations.iter().filter(|m| m.version >= 19) {
+            conn.execute_batch(migration.sql).unwrap();
+        }
+
+        assert_eq!(stored_category(&conn, "bucket"), Some(Some("cos".to_string())));
+        assert_eq!(stored_category(&conn, "sidebar_open"), Some(Some("ui".to_string())));
+        conn.execute("DELETE FROM settings", []).unwrap();
+        assert_eq!(exclusion(&conn, &serde_json::json!({"key": "bucket"})), None);
+        assert!(exclusion(&conn, &serde_json::json!({"key": "sidebar_open", "category": "cos"})).is_some());
+    }
+}
@@ -0,0 +1,260 @@
+//! 流式拉取（NDJSON）
+//! /pull/stream 跨表返回变更，每行一个 JSON 对象，边从 SQLite 分页读取边写出，
+//! 服务端和客户端都不需要把整个结果集放在内存里。
+//!
+//! 行格式（按 type 区分）：
+//! - `{"type":"change", ...SyncChange}`：一条变更
+//! - `{"type":"end", ...}`：最后一行，携带服务端版本号和续读游标；没有 end 行说明连接中途断开
+
+use crate::sync_engine::{self, PullCursor, SyncChange};
+use crate::sync_gc;
+use crate::sync_scopes::SyncScope;
+use crate::sync_sequence;
+use crate::sync_vector::VersionVector;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// NDJSON 的 Content-Type
+pub const CONTENT_TYPE: &str = "application/x-ndjson";
+
+/// 每次从 SQLite 读取的变更数
+const PAGE_SIZE: usize = 200;
+
+/// 流中的一行
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PullStreamItem {
+    Change(SyncChange),
+    End(StreamSummary),
+}
+
+/// 流的最后一行
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct StreamSummary {
+    /// 已写出的变更数
+    pub count: usize,
+    /// 达到 limit 时的续读游标（与 /pull 的 next_cursor 相同），为空表示已拉取完
+    pub next_cursor: Option<String>,
+    pub server_version: i64,
+    /// since_version 早于低水位或设备的同步范围已修改：没有写出任何变更，客户端需要从 0 全量对账
+    pub resync_required: bool,
+    pub low_water_mark: i64,
+    /// 按请求方设备的同步范围过滤过：对端的 knowledge 不能代表客户端持有的数据
+    #[serde(default)]
+    pub scoped: bool,
+    /// 各表写出的变更数（只在本机统计，不写入流）
+    #[serde(skip)]
+    pub tables: BTreeMap<String, usize>,
+}
+
+/// 流式拉取的参数
+#[derive(Debug, Clone, Default)]
+pub struct StreamRequest {
+    pub since_version: i64,
+    pub after: Option<PullCursor>,
+    /// 客户端已见过的写入，不再发送
+    pub knowledge: Option<VersionVector>,
+    /// 最多写出的变更数，为空表示不限
+    pub limit: Option<usize>,
+    /// 请求方设备的同步范围
+    pub scope: Option<SyncScope>,
+    /// 设备的同步范围修改后尚未重新拉取
+    pub scope_resync: bool,
+}
+
+/// 把一行编码为 NDJSON（带换行）
+pub fn encode_line(item: &PullStreamItem) -> Vec<u8> {
+    let mut line = serde_json::to_vec(item).unwrap_or_default();
+    line.push(b'\n');
+    line
+}
+
+/// 分页读取变更并逐行交给 `send`；`send` 返回 false（客户端已断开）时停止读取
+///
+/// `scoped_out` 为额外下发的 scope_out 变更（本次变旧的笔记），写在最前面且不计入 limit。
+/// 返回写出的汇总，最后一行 end 也已经交给 `send`
+pub fn write_changes(
+    conn: &Connection,
+    request: &StreamRequest,
+    scoped_out: Vec<SyncChange>,
+    mut send: impl FnMut(Vec<u8>) -> bool,
+) -> rusqlite::Result<StreamSummary> {
+    let mut summary = StreamSummary {
+        server_version: sync_sequence::current(conn),
+        low_water_mark: sync_gc::low_water_mark(conn),
+        scoped: request.scope.is_some(),
+        ..Default::default()
+    };
+
+    if request.after.is_none() && (request.scope_resync || sync_gc::requires_resync(conn, request.since_version)) {
+        summary.resync_required = true;
+        send(encode_line(&PullStreamItem::End(summary.clone())));
+        return Ok(summary);
+    }
+
+    for change in scoped_out {
+        let table = change.table.clone();
+        if !send(encode_line(&PullStreamItem::Change(change))) {
+            return Ok(summary);
+        }
+        *summary.tables.entry(table).or_default() += 1;
+    }
+
+    let mut after = request.after.clone();
+    loop {
+        let remaining = request.limit.map(|l| l.saturating_sub(summary.count));
+        let page_size = remaining.map_or(PAGE_SIZE, |r| r.min(PAGE_SIZE));
+        if page_size == 0 {
+            break;
+        }
+        let (changes, next) = sync_engine::load_all_changes(conn, request.since_version, after.as_ref(), page_size, request.scope.as_ref())?;
+        for change in changes {
+            if let (Some(knowledge), Some(vv)) = (&request.knowledge, &change.vv) {
+                if vv.seen_by(knowledge) {
+                    continue;
+                }
+            }
+            let table = change.table.clone();
+            if !send(encode_line(&PullStreamItem::Change(change))) {
+                log::info!("[SyncStream] 客户端已断开，停止写出（已写出 {} 条）", summary.count);
+                return Ok(summary);
+            }
+            summary.count += 1;
+            *summary.tables.entry(table).or_default() += 1;
+        }
+        match next {
+            Some(cursor) => after = Some(cursor),
+            None => {
+                after = None;
+                break;
+            }
+        }
+    }
+
+    // 达到 limit 时还有未读完的变更
+    summary.next_cursor = after.map(|c| c.encode());
+    send(encode_line(&PullStreamItem::End(summary.clone())));
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync_scopes;
+    use crate::sync_vector;
+    use crate::test_support::mem_db;
+
+    fn seed(conn: &Connection) {
+        for uuid in ["n1", "n2", "n3"] {
+            conn.execute("INSERT INTO notes (uuid, title) VALUES (?1, 't')", [uuid]).unwrap();
+        }
+        for uuid in ["m1", "m2"] {
+            conn.execute("INSERT INTO moments (uuid, content) VALUES (?1, 'c')", [uuid]).unwrap();
+        }
+    }
+
+    /// 运行一次流式拉取，返回解析后的各行
+    fn stream(conn: &Connection, request: &StreamRequest, scoped_out: Vec<SyncChange>) -> Vec<PullStreamItem> {
+        let mut body = Vec::new();
+        write_changes(conn, request, scoped_out, |line| {
+            body.extend(line);
+            true
+        })
+        .unwrap();
+        let text = String::from_utf8(body).unwrap();
+        assert!(text.ends_with('\n'));
+        text.lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+    }
+
+    fn split(items: Vec<PullStreamItem>) -> (Vec<String>, StreamSummary) {
+        let mut pks = Vec::new();
+        let mut end = None;
+        for item in items {
+            assert!(end.is_none(), "end must be the last line");
+            match item {
+                PullStreamItem::Change(change) => pks.push(change.data["uuid"].as_str().unwrap().to_string()),
+                PullStreamItem::End(summary) => end = Some(summary),
+            }
+        }
+        (pks, end.expect("stream ends with an end line"))
+    }
+
+    #[test]
+    fn streams_every_change_then_an_end_line() {
+        let conn = mem_db();
+        seed(&conn);
+        let (pks, end) = split(stream(&conn, &StreamRequest::default(), Vec::new()));
+        assert_eq!(pks, vec!["n1", "n2", "n3", "m1", "m2"]);
+        assert_eq!(end.count, 5);
+        assert_eq!(end.next_cursor, None);
+        assert_eq!(end.server_version, sync_sequence::current(&conn));
+        assert!(!end.resync_required && !end.scoped);
+    }
+
+    #[test]
+    fn limit_returns_a_cursor_to_resume_from() {
+        let conn = mem_db();
+        seed(&conn);
+        let mut request = StreamRequest { limit: Some(2), ..Default::default() };
+        let mut pulled = Vec::new();
+        loop {
+            let (pks, end) = split(stream(&conn, &request, Vec::new()));
+            assert!(pks.len() <= 2);
+            pulled.extend(pks);
+            match end.next_cursor {
+                Some(cursor) => request.after = Some(PullCursor::decode(&cursor).unwrap()),
+                None => break,
+            }
+        }
+        assert_eq!(pulled, vec!["n1", "n2", "n3", "m1", "m2"]);
+    }
+
+    #[test]
+    fn skips_changes_the_client_has_seen() {
+        let conn = mem_db();
+        seed(&conn);
+        let replica = sync_vector::replica_id(&conn).unwrap();
+        let n2: i64 = conn.query_row("SELECT version FROM notes WHERE uuid = 'n2'", [], |row| row.get(0)).unwrap();
+        let mut knowledge = VersionVector::default();
+        knowledge.set(&replica, n2);
+
+        let request = StreamRequest { knowledge: Some(knowledge), ..Default::default() };
+        let (pks, end) = split(stream(&conn, &request, Vec::new()));
+        assert_eq!(pks, vec!["n3", "m1", "m2"]);
+        assert_eq!(end.count, 3);
+    }
+
+    #[test]
+    fn scoped_out_changes_come_first_and_resync_sends_only_the_end() {
+        let conn = mem_db();
+        seed(&conn);
+        let scope = sync_scopes::SyncScope { tables: Some(vec!["notes".into()]), ..Default::default() };
+        let aged = sync_scopes::scope_out("notes", "old", 1, &sync_engine::now_iso(), None);
+        let request = StreamRequest { scope: Some(scope), since_version: 1, ..Default::default() };
+        let (pks, end) = split(stream(&conn, &request, vec![aged]));
+        assert_eq!(pks, vec!["old", "n2", "n3"]);
+        assert_eq!(end.count, 2);
+        assert!(end.scoped);
+
+        let request = StreamRequest { since_version: 1, scope_resync: true, ..Default::default() };
+        let (pks, end) = split(stream(&conn, &request, Vec::new()));
+        assert!(pks.is_empty());
+        assert!(end.resync_required);
+    }
+
+    #[test]
+    fn stops_when_the_client_disconnects() {
+        let conn = mem_db();
+        seed(&conn);
+        let mut lines = 0;
+        let summary = write_changes(&conn, &StreamRequest::default(), Vec::new(), |_| {
+            lines += 1;
+            lines < 2
+        })
+        .unwrap();
+        // 第二行写出失败后不再读取，也不写 end 行
+        assert_eq!(lines, 2);
+        assert_eq!(summary.count, 1);
+    }
+}
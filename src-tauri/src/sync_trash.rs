@@ -0,0 +1,173 @@
+//! 回收站
+//! 软删除的记录保留完整内容，可在任意设备上恢复或彻底清除；
+//! 恢复是普通写入，清除是带 purged 标记的硬删除，都经变更日志同步到其他设备（其他设备同样彻底清除）
+
+use crate::hlc::HybridClock;
+use crate::sync_changelog;
+use crate::sync_engine::{self, TableConfig};
+use crate::sync_registry;
+use rusqlite::{params, Connection};
+use serde::Serialize;
+
+/// 回收站中的一条记录
+#[derive(Serialize, Debug, Clone)]
+pub struct TrashItem {
+    pub table_name: String,
+    pub pk: String,
+    /// 标题字段的值（表未配置 title_field 时为空）
+    pub title: Option<String>,
+    pub deleted_at: String,
+    pub version: i64,
+}
+
+/// 列出回收站中的记录，按删除时间倒序；`table` 为空时包含所有同步表
+pub fn list_trash(conn: &Connection, table: Option<&str>) -> rusqlite::Result<Vec<TrashItem>> {
+    let mut items = Vec::new();
+    for config in sync_registry::registry().tables() {
+        if table.is_some_and(|t| t != config.name) {
+            continue;
+        }
+        let title = config.title_field.unwrap_or("NULL");
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {pk}, {title}, deleted_at, version FROM {table} WHERE deleted_at IS NOT NULL",
+            pk = config.primary_key,
+            table = config.name
+        ))?;
+        let rows = stmt.query_map([], |row| {
+            Ok(TrashItem {
+                table_name: config.name.to_string(),
+                pk: row.get(0)?,
+                title: row.get::<_, Option<String>>(1).ok().flatten(),
+                deleted_at: row.get(2)?,
+                version: row.get::<_, Option<i64>>(3)?.unwrap_or(0),
+            })
+        })?;
+        for item in rows {
+            items.push(item?);
+        }
+    }
+    items.sort_by(|a, b| b.deleted_at.cmp(&a.deleted_at));
+    Ok(items)
+}
+
+/// 从回收站恢复；记录不存在或未被删除时返回 false
+pub fn restore(conn: &Connection, table: &str, pk: &str, clock: &HybridClock) -> rusqlite::Result<bool> {
+    let Some(config) = trashed(conn, table, pk)? else {
+        return Ok(false);
+    };
+    sync_engine::set_deleted_at(conn, config, pk, None, &sync_engine::now_iso(), &clock.now().encode())?;
+    log::info!("[SyncTrash] 恢复 {} {}", table, pk);
+    Ok(true)
+}
+
+/// 彻底清除回收站中的记录（删除日志标记为 purged，其他设备收到后同样彻底清除）
+pub fn purge(conn: &mut Connection, table: &str, pk: &str, clock: &HybridClock) -> rusqlite::Result<bool> {
+    let tx = conn.transaction()?;
+    let Some(config) = trashed(&tx, table, pk)? else {
+        return Ok(false);
+    };
+    sync_engine::purge_row(&tx, config, pk)?;
+    sync_changelog::mark_purged(&tx, config.name, pk, &clock.now().encode())?;
+    tx.commit()?;
+    log::info!("[SyncTrash] 彻底清除 {} {}", table, pk);
+    Ok(true)
+}
+
+/// 记录存在且已被删除时返回所属表的配置
+fn trashed(conn: &Connection, table: &str, pk: &str) -> rusqlite::Result<Option<&'static TableConfig>> {
+    let Some(config) = sync_engine::get_table_config(table) else {
+        return Ok(None);
+    };
+    let count: i64 = conn.query_row(
+        &format!(
+            "SELECT COUNT(*) FROM {} WHERE {} = ?1 AND deleted_at IS NOT NULL",
+            config.name, config.primary_key
+        ),
+        params![pk],
+        |row| row.get(0),
+    )?;
+    Ok((count > 0).then_some(config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync_engine::{ApplyOutcome, SyncOp};
+    use crate::test_support::mem_db;
+
+    /// 插入一条笔记并移入回收站
+    fn trashed_note(conn: &Connection, clock: &HybridClock) {
+        conn.execute(
+            "INSERT INTO notes (uuid, title, content, tags, updated_at, hlc) VALUES ('n1', '标题', '正文', '[]', ?1, ?2)",
+            params![sync_engine::now_iso(), clock.now().encode()],
+        )
+        .unwrap();
+        let config = sync_engine::get_table_config("notes").unwrap();
+        let now = sync_engine::now_iso();
+        sync_engine::set_deleted_at(conn, config, "n1", Some(&now), &now, &clock.now().encode()).unwrap();
+    }
+
+    /// (op, changed_columns, purged)
+    fn latest_log(conn: &Connection) -> (String, Option<String>, bool) {
+        conn.query_row(
+            "SELECT op, changed_columns, purged FROM sync_changelog WHERE table_name = 'notes' AND pk = 'n1' ORDER BY seq DESC LIMIT 1",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn restore_is_logged_as_an_update() {
+        let conn = mem_db();
+        let clock = HybridClock::new("local".to_string());
+        trashed_note(&conn, &clock);
+        assert_eq!(list_trash(&conn, None).unwrap().len(), 1);
+        let before = sync_changelog::latest_version(&conn, "notes", "n1").unwrap().unwrap();
+
+        assert!(restore(&conn, "notes", "n1", &clock).unwrap());
+        assert!(!restore(&conn, "notes", "n1", &clock).unwrap());
+        assert!(list_trash(&conn, Some("notes")).unwrap().is_empty());
+        assert_eq!(latest_log(&conn), ("update".to_string(), Some("[\"deleted_at\"]".to_string()), false));
+        assert!(sync_changelog::latest_version(&conn, "notes", "n1").unwrap().unwrap() > before);
+
+        let changes = sync_engine::load_table_changes(&conn, "notes", before, None, 10, None).unwrap();
+        assert_eq!(changes.len(), 1);
+        assert!(matches!(changes[0].op, SyncOp::Upsert));
+        assert!(changes[0].deleted_at.is_none());
+    }
+
+    #[test]
+    fn purge_propagates_as_a_purge() {
+        let mut conn = mem_db();
+        let clock = HybridClock::new("local".to_string());
+        trashed_note(&conn, &clock);
+        let before = sync_changelog::latest_version(&conn, "notes", "n1").unwrap().unwrap();
+        // 对端回收站中的同一条记录（清除之前删除）
+        let peer = mem_db();
+        let peer_clock = HybridClock::new("peer".to_string());
+        trashed_note(&peer, &peer_clock);
+        std::thread::sleep(std::time::Duration::from_millis(2));
+
+        assert!(purge(&mut conn, "notes", "n1", &clock).unwrap());
+        assert!(!purge(&mut conn, "notes", "n1", &clock).unwrap());
+        assert!(list_trash(&conn, None).unwrap().is_empty());
+        assert_eq!(latest_log(&conn), ("delete".to_string(), None, true));
+
+        let changes = sync_engine::load_table_changes(&conn, "notes", before, None, 10, None).unwrap();
+        assert_eq!(changes.len(), 1);
+        assert!(matches!(changes[0].op, SyncOp::Purge));
+        assert_eq!(changes[0].data, serde_json::json!({"uuid": "n1"}));
+
+        // 对端回收站中的副本被彻底清除，并继续以 purge 传播
+        let outcome = sync_engine::apply_table_change(&peer, "notes", &changes[0], &peer_clock).unwrap();
+        assert_eq!(outcome, ApplyOutcome::Applied);
+        let rows: i64 = peer.query_row("SELECT COUNT(*) FROM notes", [], |row| row.get(0)).unwrap();
+        assert_eq!(rows, 0);
+        assert_eq!(latest_log(&peer), ("delete".to_string(), None, true));
+
+        // 重复收到时按墓碑跳过
+        let outcome = sync_engine::apply_table_change(&peer, "notes", &changes[0], &peer_clock).unwrap();
+        assert_eq!(outcome, ApplyOutcome::Skipped);
+    }
+}
@@ -0,0 +1,261 @@
+//! 版本向量（多主同步）
+//! 每个副本（安装了 ZotePad 的设备，ID 为 sync_meta 中的 device_id）各自维护版本号序列，
+//! 版本号只在本机有意义，用作拉取游标；跨副本的因果关系由记录上的版本向量 vv 判断：
+//! `{副本 ID: 该副本写入这条记录时的本地版本号}`。
+//!
+//! - 本机编辑：触发器把本机条目设为新分配的版本号
+//! - 应用远程变更：vv 取两端逐项最大值，不产生本机条目（转发不算编辑）
+//! - 远程 vv 支配本地：直接覆盖（不受时钟偏差影响）；被本地支配或相等：跳过；并发：回退到 HLC / 三方合并
+//!
+//! sync_knowledge 记录本机已完整接收到的各副本版本号，拉取时发给对端，
+//! 对端据此跳过本机已见过的记录，任意两个节点可以按任意顺序同步并最终一致
+
+use crate::sync_engine::TableConfig;
+use crate::sync_sequence::{self, CURRENT_SQL};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+/// 本机副本 ID（用作触发器中的子查询）
+pub const REPLICA_SQL: &str = "COALESCE((SELECT value FROM sync_meta WHERE key = 'device_id'), 'local')";
+
+/// 触发器中为本机编辑打上的版本向量：本机条目设为当前版本号
+pub fn stamp_sql() -> String {
+    format!("json_set(COALESCE(NEW.vv, '{{}}'), '$.\"' || {REPLICA_SQL} || '\"', {CURRENT_SQL})")
+}
+
+/// 两个版本向量的因果关系（以 self 为主语）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Causality {
+    Equal,
+    /// self 是 other 的祖先（other 更新）
+    Before,
+    /// self 包含了 other 的全部历史（self 更新）
+    After,
+    /// 各自有对方没见过的写入
+    Concurrent,
+}
+
+/// 版本向量：副本 ID -> 版本号
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct VersionVector(BTreeMap<String, i64>);
+
+impl VersionVector {
+    /// 解析数据库中的 JSON；为空或格式错误时返回空向量（旧数据）
+    pub fn parse(raw: Option<&str>) -> VersionVector {
+        raw.and_then(|s| serde_json::from_str(s).ok()).unwrap_or_default()
+    }
+
+    pub fn encode(&self) -> String {
+        serde_json::to_string(&self.0).unwrap_or_else(|_| "{}".to_string())
+    }
+
+    /// 编码为 URL 安全的查询参数
+    pub fn encode_param(&self) -> String {
+        URL_SAFE_NO_PAD.encode(self.encode())
+    }
+
+    pub fn decode_param(s: &str) -> Option<VersionVector> {
+        let raw = String::from_utf8(URL_SAFE_NO_PAD.decode(s.trim()).ok()?).ok()?;
+        serde_json::from_str(&raw).ok()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn get(&self, replica: &str) -> i64 {
+        self.0.get(replica).copied().unwrap_or(0)
+    }
+
+    pub fn set(&mut self, replica: &str, version: i64) {
+        self.0.insert(replica.to_string(), version);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &i64)> {
+        self.0.iter()
+    }
+
+    /// 逐项取最大值
+    pub fn merge(&self, other: &VersionVector) -> VersionVector {
+        let mut merged = self.clone();
+        for (replica, version) in &other.0 {
+            let entry = merged.0.entry(replica.clone()).or_insert(0);
+            *entry = (*entry).max(*version);
+        }
+        merged
+    }
+
+    pub fn compare(&self, other: &VersionVector) -> Causality {
+        let mut ordering = Ordering::Equal;
+        for replica in self.0.keys().chain(other.0.keys()) {
+            match (self.get(replica).cmp(&other.get(replica)), ordering) {
+                (Ordering::Equal, _) => {}
+                (o, Ordering::Equal) => ordering = o,
+                (o, current) if o != current => return Causality::Concurrent,
+                _ => {}
+            }
+        }
+        match ordering {
+            Ordering::Equal => Causality::Equal,
+            Ordering::Less => Causality::Before,
+            Ordering::Greater => Causality::After,
+        }
+    }
+
+    /// 每一项都不超过 knowledge：持有 knowledge 的副本已经见过这次写入
+    pub fn seen_by(&self, knowledge: &VersionVector) -> bool {
+        !self.is_empty() && self.0.iter().all(|(replica, version)| *version <= knowledge.get(replica))
+    }
+}
+
+/// 本机副本 ID
+pub fn replica_id(conn: &Connection) -> rusqlite::Result<String> {
+    conn.query_row(&format!("SELECT {}", REPLICA_SQL), [], |row| row.get(0))
+}
+
+/// 读取一条记录的版本向量
+pub fn load(conn: &Connection, config: &TableConfig, pk_value: &str) -> rusqlite::Result<VersionVector> {
+    let raw: Option<Option<String>> = conn
+        .query_row(
+            &format!("SELECT vv FROM {} WHERE {} = ?1", config.name, config.primary_key),
+            params![pk_value],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(VersionVector::parse(raw.flatten().as_deref()))
+}
+
+/// 写入记录的版本向量（vv 不在同步字段中，不会触发版本号分配）
+pub fn store(conn: &Connection, config: &TableConfig, pk_value: &str, vv: &VersionVector) -> rusqlite::Result<()> {
+    conn.execute(
+        &format!("UPDATE {} SET vv = ?1 WHERE {} = ?2", config.name, config.primary_key),
+        params![vv.encode(), pk_value],
+    )?;
+    Ok(())
+}
+
+/// 为尚无版本向量的已有记录补上本机条目（视为本机写入），返回补录条数
+pub fn backfill(conn: &Connection, config: &TableConfig) -> rusqlite::Result<usize> {
+    conn.execute(
+        &format!(
+            "UPDATE {} SET vv = json_object({}, version) WHERE vv IS NULL AND version > 0",
+            config.name, REPLICA_SQL
+        ),
+        [],
+    )
+}
+
+/// 本机已完整接收到的各副本版本号（本机条目为当前版本号）
+pub fn knowledge(conn: &Connection) -> rusqlite::Result<VersionVector> {
+    let mut stmt = conn.prepare("SELECT replica_id, counter FROM sync_knowledge")?;
+    let mut vv = VersionVector::default();
+    for row in stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))? {
+        let (replica, counter) = row?;
+        vv.set(&replica, counter);
+    }
+    vv.set(&replica_id(conn)?, sync_sequence::current(conn));
+    Ok(vv)
+}
+
+/// 完整拉取对端后合并对端的 knowledge（只前进不后退，忽略本机条目）
+pub fn observe(conn: &Connection, remote: &VersionVector) -> rusqlite::Result<()> {
+    let own = replica_id(conn)?;
+    for (replica, counter) in remote.iter().filter(|(r, _)| **r != own) {
+        conn.execute(
+            "INSERT INTO sync_knowledge (replica_id, counter, updated_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT(replica_id) DO UPDATE SET
+                 counter = MAX(counter, excluded.counter),
+                 updated_at = excluded.updated_at",
+            params![replica, counter, crate::sync_engine::now_iso()],
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync_engine::get_table_config;
+    use crate::test_support::mem_db;
+
+    fn vv(entries: &[(&str, i64)]) -> VersionVector {
+        let mut vv = VersionVector::default();
+        for (replica, version) in entries {
+            vv.set(replica, *version);
+        }
+        vv
+    }
+
+    #[test]
+    fn compare_orders_by_causality() {
+        let a = vv(&[("a", 2), ("b", 1)]);
+        assert_eq!(a.compare(&a.clone()), Causality::Equal);
+        assert_eq!(a.compare(&vv(&[("a", 3), ("b", 1)])), Causality::Before);
+        assert_eq!(a.compare(&vv(&[("a", 2)])), Causality::After);
+        assert_eq!(a.compare(&vv(&[("a", 1), ("b", 2)])), Causality::Concurrent);
+        // 缺失的条目按 0 比较
+        assert_eq!(vv(&[("a", 1)]).compare(&vv(&[("b", 1)])), Causality::Concurrent);
+        assert_eq!(vv(&[("a", 0)]).compare(&VersionVector::default()), Causality::Equal);
+        assert_eq!(VersionVector::default().compare(&a), Causality::Before);
+
+        let merged = a.merge(&vv(&[("a", 1), ("c", 4)]));
+        assert_eq!(merged, vv(&[("a", 2), ("b", 1), ("c", 4)]));
+        assert_eq!(merged.compare(&a), Causality::After);
+    }
+
+    #[test]
+    fn seen_by_requires_every_entry_to_be_known() {
+        let knowledge = vv(&[("a", 5), ("b", 3)]);
+        assert!(vv(&[("a", 5)]).seen_by(&knowledge));
+        assert!(vv(&[("a", 2), ("b", 3)]).seen_by(&knowledge));
+        assert!(!vv(&[("a", 6)]).seen_by(&knowledge));
+        assert!(!vv(&[("a", 1), ("c", 1)]).seen_by(&knowledge));
+        // 没有版本向量的旧记录总是下发
+        assert!(!VersionVector::default().seen_by(&knowledge));
+    }
+
+    #[test]
+    fn encodings_round_trip() {
+        let a = vv(&[("设备-1", 7), ("b", 1)]);
+        assert_eq!(VersionVector::decode_param(&a.encode_param()), Some(a.clone()));
+        assert_eq!(VersionVector::parse(Some(&a.encode())), a);
+        assert_eq!(VersionVector::parse(Some("not json")), VersionVector::default());
+        assert_eq!(VersionVector::parse(None), VersionVector::default());
+        assert_eq!(VersionVector::decode_param("%%%"), None);
+    }
+
+    #[test]
+    fn local_writes_stamp_the_own_entry() {
+        let conn = mem_db();
+        conn.execute("INSERT INTO sync_meta (key, value) VALUES ('device_id', 'desk')", []).unwrap();
+        conn.execute("INSERT INTO notes (uuid, title) VALUES ('n1', 't')", []).unwrap();
+        let notes = get_table_config("notes").unwrap();
+        let version: i64 = conn.query_row("SELECT version FROM notes WHERE uuid = 'n1'", [], |row| row.get(0)).unwrap();
+        assert_eq!(load(&conn, notes, "n1").unwrap(), vv(&[("desk", version)]));
+
+        // 应用远程变更时写入合并后的向量，本机再次编辑只推进本机条目
+        store(&conn, notes, "n1", &vv(&[("desk", version), ("phone", 9)])).unwrap();
+        conn.execute("UPDATE notes SET content = 'c' WHERE uuid = 'n1'", []).unwrap();
+        let edited = sync_sequence::current(&conn);
+        assert_eq!(load(&conn, notes, "n1").unwrap(), vv(&[("desk", edited), ("phone", 9)]));
+    }
+
+    #[test]
+    fn knowledge_only_moves_forward() {
+        let conn = mem_db();
+        conn.execute("INSERT INTO sync_meta (key, value) VALUES ('device_id', 'desk')", []).unwrap();
+        observe(&conn, &vv(&[("phone", 8), ("desk", 999)])).unwrap();
+        observe(&conn, &vv(&[("phone", 3), ("tablet", 2)])).unwrap();
+
+        let known = knowledge(&conn).unwrap();
+        assert_eq!(known.get("phone"), 8);
+        assert_eq!(known.get("tablet"), 2);
+        // 本机条目始终是本机当前版本号，不受对端影响
+        assert_eq!(known.get("desk"), sync_sequence::current(&conn));
+    }
+}
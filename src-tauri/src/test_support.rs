@@ -0,0 +1,21 @@
+//! 测试辅助：执行全部迁移并安装变更日志触发器的内存数据库
+
+use crate::sync_changelog;
+use rusqlite::Connection;
+
+/// 与应用数据库结构相同的内存数据库
+pub fn mem_db() -> Connection {
+    let mut conn = migrated_db();
+    sync_changelog::install(&mut conn).expect("install changelog triggers");
+    conn
+}
+
+/// 只执行迁移、尚未安装触发器的内存数据库（相当于同步服务首次启动前的数据库）
+pub fn migrated_db() -> Connection {
+    let conn = Connection::open_in_memory().expect("open in-memory db");
+    for migration in crate::migrations() {
+        conn.execute_batch(migration.sql)
+            .unwrap_or_else(|e| panic!("migration {} ({}) failed: {}", migration.version, migration.description, e));
+    }
+    conn
+}
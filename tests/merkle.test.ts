@@ -0,0 +1,40 @@
+/**
+ * 范围哈希对账的两端一致性检查
+ * 与服务端 src-tauri/src/sync_merkle.rs 的测试共用 fixtures/merkle-parity.json：
+ * 哈希、分桶或桶哈希规则任一端改动后两边都会失败
+ */
+
+import type { MerkleBucket, MerkleNode, MerkleRecord } from '../app/utils/merkle.ts'
+import assert from 'node:assert/strict'
+import { readFileSync } from 'node:fs'
+import { test } from 'node:test'
+import { hash64, MerkleIndex, reconcile } from '../app/utils/merkle.ts'
+
+interface ParityFixture {
+  leaf_limit: number
+  hashes: Record<string, string>
+  records: MerkleRecord[]
+  root: MerkleBucket & { children: MerkleBucket[] }
+}
+
+const fixture: ParityFixture = JSON.parse(readFileSync(new URL('./fixtures/merkle-parity.json', import.meta.url), 'utf8'))
+
+test('hash64 与服务端一致', () => {
+  for (const [text, expected] of Object.entries(fixture.hashes))
+    assert.equal(hash64(text), expected, `hash64(${JSON.stringify(text)})`)
+})
+
+test('根桶和一级子桶与服务端一致', () => {
+  const index = new MerkleIndex(fixture.records)
+  assert.deepEqual(index.summary(''), { count: fixture.root.count, hash: fixture.root.hash })
+  assert.ok(fixture.root.count > fixture.leaf_limit)
+  for (const bucket of fixture.root.children)
+    assert.deepEqual(index.summary(bucket.prefix), { count: bucket.count, hash: bucket.hash })
+})
+
+test('与服务端的根节点对账时没有差异', async () => {
+  const index = new MerkleIndex(fixture.records)
+  const root: MerkleNode<MerkleRecord> = { ...fixture.root, leaves: null }
+  const result = await reconcile(index, async prefixes => prefixes.map(() => root))
+  assert.deepEqual(result, { local: [], remote: [], rounds: 1 })
+})